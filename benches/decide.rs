@@ -0,0 +1,179 @@
+//! Compares `decide` with `decide_batch`, which orders egresses and compiles
+//! app patterns once per batch, and with `decide_egress`, which skips
+//! building reasons and looks rules up in a `CompiledConfig` built once.
+//!
+//! Run with `cargo bench --bench decide`. Besides criterion's timings, the
+//! allocations each function makes per flow are counted and printed first;
+//! for `decide_batch` the allocations of an empty batch are counted apart,
+//! as they are made once however many flows the batch holds.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use policy_router_rs::policy::{
+    config::AppConfig,
+    engine::{CompiledConfig, decide, decide_batch, decide_egress},
+};
+
+/// The system allocator, counting every allocation.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// `(process, domain)` flows hitting every rule stage.
+const FLOWS: &[(Option<&str>, Option<&str>)] = &[
+    (Some("zen.exe"), Some("www.youtube.com")),
+    (Some("zen.exe"), Some("chatgpt.com")),
+    (
+        Some(r"C:\Program Files\Zen\zen.exe"),
+        Some("unknown.example"),
+    ),
+    (Some("bad.exe"), Some("youtube.com")),
+    (None, Some("ads.blocked.example")),
+    (Some("curl.exe"), Some("10.1.2.3")),
+    (None, Some("mail.example.ru")),
+    (Some("other.exe"), None),
+    (Some("python3.12"), None),
+];
+
+fn config() -> AppConfig {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "vpn"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.proxy]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.direct]
+type = "direct"
+
+[egress.block]
+type = "block"
+
+[rules.domain]
+vpn = ["chatgpt.com", "openai.com", "discord.com"]
+proxy = ["youtube.com", "googlevideo.com", "ytimg.com"]
+direct = ["ru", "example.ru"]
+block = ["blocked.example"]
+
+[rules.app]
+vpn = ["zen.exe", '/^python3\.\d+$/', "*-updater.exe"]
+proxy = ["curl.exe"]
+direct = ["ciadpi.exe"]
+block = ["bad.exe"]
+
+[rules.cidr]
+direct = ["10.0.0.0/8"]
+"#;
+    let cfg = toml::from_str::<AppConfig>(toml).expect("bench config TOML must parse");
+    cfg.validate().expect("bench config must validate");
+    cfg
+}
+
+/// Allocations `f` makes.
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+/// `total` allocations averaged over all of [`FLOWS`].
+fn per_flow(total: usize) -> f64 {
+    #[allow(
+        clippy::cast_precision_loss,
+        reason = "allocation counts stay far below 2^52"
+    )]
+    let per_flow = total as f64 / FLOWS.len() as f64;
+    per_flow
+}
+
+/// Allocations `f` makes per flow, averaged over all of [`FLOWS`].
+fn allocations_per_flow(f: impl Fn(Option<&str>, Option<&str>)) -> f64 {
+    per_flow(allocations(|| {
+        for &(process, domain) in FLOWS {
+            f(process, domain);
+        }
+    }))
+}
+
+fn bench_decide(c: &mut Criterion) {
+    let cfg = config();
+    let compiled = CompiledConfig::new(&cfg);
+
+    let full = allocations_per_flow(|process, domain| {
+        black_box(decide(&cfg, process, domain));
+    });
+    let batch_setup = allocations(|| {
+        black_box(decide_batch(&cfg, &[]));
+    });
+    let batch = per_flow(
+        allocations(|| {
+            black_box(decide_batch(&cfg, FLOWS));
+        }) - batch_setup,
+    );
+    let egress_only = allocations_per_flow(|process, domain| {
+        black_box(decide_egress(&cfg, &compiled, process, domain));
+    });
+    eprintln!(
+        "allocations per flow: decide {full:.1}, decide_batch {batch:.1} \
+         (plus {batch_setup} per batch), decide_egress {egress_only:.1}"
+    );
+
+    let mut group = c.benchmark_group("decide");
+    group.bench_function("decide", |b| {
+        b.iter(|| {
+            for &(process, domain) in FLOWS {
+                black_box(decide(&cfg, black_box(process), black_box(domain)));
+            }
+        });
+    });
+    group.bench_function("decide_batch", |b| {
+        b.iter(|| black_box(decide_batch(&cfg, black_box(FLOWS))));
+    });
+    group.bench_function("decide_egress", |b| {
+        b.iter(|| {
+            for &(process, domain) in FLOWS {
+                black_box(decide_egress(
+                    &cfg,
+                    &compiled,
+                    black_box(process),
+                    black_box(domain),
+                ));
+            }
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_decide);
+criterion_main!(benches);
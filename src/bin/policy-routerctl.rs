@@ -1,7 +1,29 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{
+    Parser, Subcommand, ValueEnum,
+    builder::{PossibleValuesParser, TypedValueParser},
+};
 use interprocess::local_socket::{Stream, prelude::*};
-use policy_router_rs::ipc::{ExplainRequest, Request, Response, SOCKET_ENV_VAR, client_roundtrip};
+use policy_router_rs::{
+    ipc::{
+        DecisionInfo, DecisionSource, EgressRefsResponse, ErrorCode, Event, ExplainRequest,
+        ExplainResponse, HelloResponse, HistoryEntry, HistoryRequest, PriorityResponse,
+        ReloadDiffResponse, Request, Response, SOCKET_ENV_VAR, StatusResponse, ValidateResponse,
+        client_hello, client_roundtrip, client_stream, read_json_line, write_json_line,
+    },
+    policy::{
+        config::AppConfig,
+        engine::{self, CompiledConfig, DecideInput},
+    },
+};
 use serde::Serialize;
 
 #[derive(Debug, Parser)]
@@ -27,51 +49,388 @@ struct Cli {
 enum OutputFormat {
     Text,
     Json,
+    /// One YAML document per response, each opened with `---`.
+    Yaml,
 }
 
 #[derive(Debug, Subcommand)]
 enum Cmd {
     Status,
-    Reload,
+    Reload {
+        /// Report what the reload would change without applying it.
+        #[arg(long)]
+        dry_run: bool,
+        /// Send this config file's contents for the daemon to load instead of
+        /// its own config file; needs `policy-routerd --allow-ipc-reload`.
+        #[arg(long, value_name = "PATH", conflicts_with = "dry_run")]
+        from: Option<PathBuf>,
+    },
     Stop,
     Diagnostics,
     Explain {
         #[arg(long)]
         process: Option<String>,
+        /// Full command line, matched by `name::substring` app patterns.
+        #[arg(long)]
+        cmdline: Option<String>,
         #[arg(long)]
         domain: Option<String>,
+        /// Destination port, matched by `rules.port`.
+        #[arg(long)]
+        port: Option<u16>,
+        /// List every matching domain rule, not just the winner.
+        #[arg(long)]
+        verbose: bool,
+        /// Exit non-zero unless the resolved egress equals this id.
+        #[arg(long)]
+        assert_egress: Option<String>,
+        /// Exit non-zero unless the decision source equals this value.
+        #[arg(long, value_parser = decision_source_parser())]
+        assert_source: Option<DecisionSource>,
+        /// Explain every `{"process":..,"domain":..}` JSON line of this file.
+        #[arg(long, conflicts_with_all = ["process", "cmdline", "domain", "port", "assert_egress", "assert_source"])]
+        batch: Option<PathBuf>,
+    },
+    History {
+        /// Keep streaming new decisions after printing the recent history.
+        #[arg(long)]
+        follow: bool,
+    },
+    /// List every file that contributed to the effective config.
+    ConfigFiles,
+    /// Check the on-disk config without applying it.
+    Validate,
+    /// Print the rule table of the live config, grouped by egress.
+    Rules,
+    /// Print daemon counters in Prometheus text format.
+    Metrics,
+    /// Print the live config as TOML, passwords redacted.
+    GetConfig,
+    /// List the app, domain and cidr rules routing to an egress.
+    EgressRefs {
+        egress: String,
+    },
+    /// Count how often each rule decided a flow since start or the last
+    /// reload.
+    RuleStats {
+        /// Only list rules that never matched.
+        #[arg(long)]
+        unused: bool,
+    },
+    /// Measure the round trip of a request the daemon answers without
+    /// touching its config.
+    Ping,
+    /// Print the local process owning a socket address such as
+    /// `127.0.0.1:50123`.
+    Whois {
+        addr: String,
+    },
+    /// Print the order in which the live config tries app and domain rule
+    /// egresses.
+    Priority,
+    /// Redraw diagnostics and status until Ctrl+C, reconnecting whenever the
+    /// daemon goes away.
+    Watch {
+        #[arg(long, default_value_t = 1000)]
+        interval_ms: u64,
+    },
+    /// Load and validate a config file offline; exits 1 if it is invalid.
+    Test {
+        #[arg(long)]
+        config: PathBuf,
+    },
+    /// Decide every flow of `--inputs` offline under two configs and list
+    /// the flows whose egress differs.
+    DiffDecisions {
+        #[arg(long)]
+        old: PathBuf,
+        #[arg(long)]
+        new: PathBuf,
+        /// One `{"process":..,"domain":..}` JSON object per line, as for
+        /// `explain --batch`.
+        #[arg(long)]
+        inputs: PathBuf,
     },
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    if let Some(res) = run_unconnected(&cli) {
+        return res;
+    }
+
     let name = resolve_ipc_socket(cli.socket.as_deref())?;
     let mut conn = Stream::connect(name).context("failed to connect to policy-routerd")?;
+    let daemon = client_hello(&mut conn)?;
+
+    if let Cmd::Explain {
+        batch: Some(path),
+        verbose,
+        ..
+    } = &cli.cmd
+    {
+        return explain_batch(&mut conn, &daemon, path, *verbose, cli.format);
+    }
 
+    let mut assertions = ExplainAssertions::default();
+    let mut unused_only = false;
     let req = match cli.cmd {
         Cmd::Status => Request::Status,
-        Cmd::Reload => Request::Reload,
+        Cmd::Reload { dry_run, from } => reload_request(dry_run, from.as_deref())?,
         Cmd::Stop => Request::Stop,
         Cmd::Diagnostics => Request::Diagnostics,
-        Cmd::Explain { process, domain } => Request::Explain(ExplainRequest { process, domain }),
+        Cmd::Explain {
+            process,
+            cmdline,
+            domain,
+            port,
+            verbose,
+            assert_egress,
+            assert_source,
+            batch: _,
+        } => {
+            assertions = ExplainAssertions {
+                egress: assert_egress,
+                source: assert_source,
+            };
+            Request::Explain(ExplainRequest {
+                process,
+                cmdline,
+                domain,
+                port,
+                verbose,
+            })
+        }
+        Cmd::History { follow } => Request::History(HistoryRequest { follow }),
+        Cmd::ConfigFiles => Request::ConfigFiles,
+        Cmd::Validate => Request::ValidateConfig,
+        Cmd::Rules => Request::ListRules,
+        Cmd::Metrics => Request::Metrics,
+        Cmd::GetConfig => Request::GetConfig,
+        Cmd::EgressRefs { egress } => Request::EgressRefs { egress },
+        Cmd::RuleStats { unused } => {
+            unused_only = unused;
+            Request::RuleStats
+        }
+        Cmd::Ping => Request::Ping,
+        Cmd::Whois { addr } => Request::WhoIs { addr },
+        Cmd::Priority => Request::Priority,
+        Cmd::Test { .. } | Cmd::Watch { .. } | Cmd::DiffDecisions { .. } => {
+            unreachable!("handled before connecting")
+        }
     };
 
-    let resp = client_roundtrip(&mut conn, &req)?;
+    if matches!(req, Request::History(HistoryRequest { follow: true })) {
+        return follow_history(&mut conn, &daemon, &req, cli.format, cli.quiet);
+    }
+
+    let sent = Instant::now();
+    let mut resp = with_version_hint(client_roundtrip(&mut conn, &req), &daemon)?;
+    let rtt = sent.elapsed();
+    if let Response::OkRuleStats(stats) = &mut resp
+        && unused_only
+    {
+        stats.rules.retain(|rule| rule.never_matched);
+    }
 
-    let res = match cli.format {
-        OutputFormat::Text => print_text(&resp, cli.quiet),
-        OutputFormat::Json => print_json(&resp),
-    };
+    let res = print_response(&resp, rtt, cli.format, cli.quiet);
 
-    if matches!(resp, Response::Err(_)) {
+    if let Response::Err(e) = &resp {
+        print_version_note(&daemon);
         // Deterministic non-zero exit for scripted usage.
-        std::process::exit(2);
+        std::process::exit(error_exit_code(e.code));
+    }
+
+    if matches!(
+        resp,
+        Response::OkValidate(ValidateResponse { ok: false, .. })
+    ) {
+        std::process::exit(1);
+    }
+
+    if let Response::OkExplain(x) = &resp {
+        let failures = assertions.check(&x.decision);
+        if !failures.is_empty() {
+            for failure in &failures {
+                eprintln!("assertion failed: {failure}");
+            }
+            std::process::exit(1);
+        }
     }
 
     res
 }
 
+/// Prints `resp` in `format`; a pong becomes a [`PingReport`] carrying the
+/// measured `rtt`.
+fn print_response(resp: &Response, rtt: Duration, format: OutputFormat, quiet: bool) -> Result<()> {
+    if let Response::OkPong { server_time_ms } = *resp {
+        return print_ping(
+            &PingReport {
+                rtt_ms: rtt.as_secs_f64() * 1000.0,
+                server_time_ms,
+            },
+            format,
+        );
+    }
+    match format {
+        OutputFormat::Text => print_text(resp, quiet),
+        OutputFormat::Json => print_json(resp),
+        OutputFormat::Yaml => print_yaml(resp),
+    }
+}
+
+/// Runs the commands that do not share the single connection `main` opens;
+/// `None` for every other command.
+fn run_unconnected(cli: &Cli) -> Option<Result<()>> {
+    match &cli.cmd {
+        // These run without the daemon, so they must not touch the socket.
+        Cmd::Test { config } => Some(test_config(config, cli.format)),
+        Cmd::DiffDecisions { old, new, inputs } => {
+            Some(diff_decisions(old, new, inputs, cli.format))
+        }
+        Cmd::Watch { interval_ms } => Some(watch(
+            cli.socket.as_deref(),
+            Duration::from_millis(*interval_ms),
+            cli.format,
+        )),
+        _ => None,
+    }
+}
+
+/// Process exit status for a daemon error, distinct per [`ErrorCode`]; 1 stays
+/// reserved for failed validation and assertions.
+const fn error_exit_code(code: ErrorCode) -> i32 {
+    match code {
+        ErrorCode::Internal => 2,
+        ErrorCode::InvalidRequest => 3,
+        ErrorCode::UnsupportedVersion => 4,
+        ErrorCode::Busy => 5,
+        ErrorCode::ConfigNotFound => 6,
+        ErrorCode::ConfigInvalid => 7,
+        ErrorCode::UnknownEgress => 8,
+        ErrorCode::Unauthorized => 9,
+    }
+}
+
+/// Expected explain outcome requested via `--assert-*` flags.
+#[derive(Debug, Default)]
+struct ExplainAssertions {
+    egress: Option<String>,
+    source: Option<DecisionSource>,
+}
+
+impl ExplainAssertions {
+    /// Returns a human readable diff line for every unmet expectation.
+    fn check(&self, decision: &DecisionInfo) -> Vec<String> {
+        let mut failures = Vec::new();
+
+        if let Some(expected) = &self.egress
+            && *expected != decision.egress
+        {
+            failures.push(format!(
+                "egress: expected '{expected}', got '{}'",
+                decision.egress
+            ));
+        }
+
+        if let Some(expected) = self.source
+            && expected != decision.source
+        {
+            failures.push(format!(
+                "source: expected '{}', got '{}'",
+                expected.as_str(),
+                decision.source.as_str()
+            ));
+        }
+
+        failures
+    }
+}
+
+/// Accepts the wire name of every [`DecisionSource`] for `--assert-source`.
+fn decision_source_parser() -> impl TypedValueParser<Value = DecisionSource> {
+    PossibleValuesParser::new(DecisionSource::ALL.map(DecisionSource::as_str)).try_map(|name| {
+        DecisionSource::ALL
+            .into_iter()
+            .find(|source| source.as_str() == name)
+            .ok_or("unknown decision source")
+    })
+}
+
+/// Outcome of `policy-routerctl ping`.
+#[derive(Debug, Serialize)]
+struct PingReport {
+    /// Round trip of the ping request as the client measured it.
+    rtt_ms: f64,
+    server_time_ms: u64,
+}
+
+fn print_ping(report: &PingReport, format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Text => {
+            println!("rtt_ms: {:.3}", report.rtt_ms);
+            println!("server_time_ms: {}", report.server_time_ms);
+        }
+        OutputFormat::Json => {
+            let s = serde_json::to_string_pretty(report)
+                .context("failed to serialize report as JSON")?;
+            println!("{s}");
+        }
+        OutputFormat::Yaml => print_yaml(report)?,
+    }
+    Ok(())
+}
+
+/// Outcome of `policy-routerctl test`.
+#[derive(Debug, Serialize)]
+struct ConfigTestReport {
+    valid: bool,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+/// Loads `path` as the daemon would, which includes validation, and reports
+/// errors or lints. Exits 1 if the config is invalid.
+fn test_config(path: &Path, format: OutputFormat) -> Result<()> {
+    let report = match AppConfig::load_from_path(path) {
+        Ok(cfg) => ConfigTestReport {
+            valid: true,
+            errors: Vec::new(),
+            warnings: cfg.lints(),
+        },
+        Err(err) => ConfigTestReport {
+            valid: false,
+            errors: vec![format!("{err:#}")],
+            warnings: Vec::new(),
+        },
+    };
+
+    match format {
+        OutputFormat::Text => {
+            println!("valid: {}", report.valid);
+            for error in &report.errors {
+                println!("error: {error}");
+            }
+            for warning in &report.warnings {
+                println!("warning: {warning}");
+            }
+        }
+        OutputFormat::Json => {
+            let s = serde_json::to_string_pretty(&report)
+                .context("failed to serialize report as JSON")?;
+            println!("{s}");
+        }
+        OutputFormat::Yaml => print_yaml(&report)?,
+    }
+
+    if !report.valid {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
 fn resolve_ipc_socket(
     cli_socket: Option<&str>,
 ) -> Result<interprocess::local_socket::Name<'static>> {
@@ -81,6 +440,263 @@ fn resolve_ipc_socket(
     Ok(name)
 }
 
+/// Explains every non-empty JSON line of `path` over one connection.
+fn explain_batch(
+    conn: &mut Stream,
+    daemon: &HelloResponse,
+    path: &Path,
+    verbose: bool,
+    format: OutputFormat,
+) -> Result<()> {
+    let mut inputs = read_batch(path)?;
+    for (_, req) in &mut inputs {
+        req.verbose |= verbose;
+    }
+
+    let reqs: Vec<Request> = inputs
+        .iter()
+        .map(|(_, req)| Request::Explain(req.clone()))
+        .collect();
+    let resps = with_version_hint(client_stream(conn, &reqs), daemon)?;
+
+    match format {
+        OutputFormat::Json => {
+            let s = serde_json::to_string_pretty(&resps)
+                .context("failed to serialize responses as JSON")?;
+            println!("{s}");
+        }
+        OutputFormat::Yaml => print_yaml(&resps)?,
+        OutputFormat::Text => {
+            for ((line, req), resp) in inputs.iter().zip(&resps) {
+                print_batch_line(*line, req, resp)?;
+            }
+        }
+    }
+
+    if let Some(code) = resps.iter().find_map(|resp| match resp {
+        Response::Err(e) => Some(e.code),
+        _ => None,
+    }) {
+        print_version_note(daemon);
+        std::process::exit(error_exit_code(code));
+    }
+
+    Ok(())
+}
+
+/// The request for `reload`: a dry run, a plain reload, or one sending the
+/// config at `from`.
+fn reload_request(dry_run: bool, from: Option<&Path>) -> Result<Request> {
+    Ok(match from {
+        Some(path) => Request::ReloadFrom {
+            toml: std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read config {}", path.display()))?,
+        },
+        None if dry_run => Request::ReloadDiff,
+        None => Request::Reload,
+    })
+}
+
+/// Parses every non-empty JSON line of `path` with its line number.
+fn read_batch(path: &Path) -> Result<Vec<(usize, ExplainRequest)>> {
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read batch file {}", path.display()))?;
+
+    let mut inputs = Vec::new();
+    for (index, line) in raw.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let req: ExplainRequest = serde_json::from_str(line)
+            .with_context(|| format!("invalid batch entry on line {}", index + 1))?;
+        inputs.push((index + 1, req));
+    }
+    Ok(inputs)
+}
+
+/// A flow `diff-decisions` routes differently under the new config.
+#[derive(Debug, Serialize)]
+struct DecisionChange {
+    #[serde(skip)]
+    line: usize,
+    process: Option<String>,
+    domain: Option<String>,
+    /// Egress chosen under the old config.
+    old: String,
+    /// Egress chosen under the new config.
+    new: String,
+}
+
+/// Decides every flow of `inputs` under the `old` and `new` configs with the
+/// library engine and prints the flows whose egress changed.
+fn diff_decisions(old: &Path, new: &Path, inputs: &Path, format: OutputFormat) -> Result<()> {
+    let old_cfg = AppConfig::load_from_path(old)
+        .with_context(|| format!("failed to load old config {}", old.display()))?;
+    let new_cfg = AppConfig::load_from_path(new)
+        .with_context(|| format!("failed to load new config {}", new.display()))?;
+    let old_compiled = CompiledConfig::new(&old_cfg);
+    let new_compiled = CompiledConfig::new(&new_cfg);
+
+    let mut changes = Vec::new();
+    for (line, req) in read_batch(inputs)? {
+        let input = DecideInput {
+            process_name: req.process.as_deref(),
+            cmdline: req.cmdline.as_deref(),
+            domain: req.domain.as_deref(),
+            port: req.port,
+            ..DecideInput::default()
+        };
+        let old = engine::decide_compiled(&old_cfg, Some(&old_compiled), &input).egress;
+        let new = engine::decide_compiled(&new_cfg, Some(&new_compiled), &input).egress;
+        if old != new {
+            changes.push(DecisionChange {
+                line,
+                process: req.process,
+                domain: req.domain,
+                old: old.to_string(),
+                new: new.to_string(),
+            });
+        }
+    }
+
+    match format {
+        OutputFormat::Json => {
+            let s = serde_json::to_string_pretty(&changes)
+                .context("failed to serialize changes as JSON")?;
+            println!("{s}");
+        }
+        OutputFormat::Yaml => print_yaml(&changes)?,
+        OutputFormat::Text => {
+            for change in &changes {
+                println!(
+                    "#{} process={} domain={}: {} -> {}",
+                    change.line,
+                    change.process.as_deref().unwrap_or("-"),
+                    change.domain.as_deref().unwrap_or("-"),
+                    change.old,
+                    change.new
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_batch_line(line: usize, req: &ExplainRequest, resp: &Response) -> Result<()> {
+    let process = req.process.as_deref().unwrap_or("-");
+    let domain = req.domain.as_deref().unwrap_or("-");
+    match resp {
+        Response::OkExplain(x) => println!(
+            "#{line} process={process} domain={domain} -> {} ({})",
+            x.decision.egress,
+            fmt_snake_case(&x.decision.source)?,
+        ),
+        Response::Err(e) => println!(
+            "#{line} process={process} domain={domain} error: {}",
+            e.message
+        ),
+        other => println!("#{line} unexpected response: {other:?}"),
+    }
+    Ok(())
+}
+
+fn follow_history(
+    conn: &mut Stream,
+    daemon: &HelloResponse,
+    req: &Request,
+    format: OutputFormat,
+    quiet: bool,
+) -> Result<()> {
+    write_json_line(&mut *conn, req)?;
+
+    let mut reader = BufReader::new(&*conn);
+    loop {
+        if reader
+            .fill_buf()
+            .context("failed to read from policy-routerd")?
+            .is_empty()
+        {
+            return Ok(());
+        }
+
+        let resp: Response = with_version_hint(read_json_line(&mut reader), daemon)?;
+        match format {
+            OutputFormat::Text => print_text(&resp, quiet)?,
+            OutputFormat::Json => print_json(&resp)?,
+            OutputFormat::Yaml => print_yaml(&resp)?,
+        }
+    }
+}
+
+/// Polls diagnostics and status every `interval`, redrawing the terminal in
+/// text format, until Ctrl+C.
+fn watch(socket: Option<&str>, interval: Duration, format: OutputFormat) -> Result<()> {
+    let (stop_tx, stop_rx) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = stop_tx.send(());
+    })
+    .context("failed to set Ctrl+C handler")?;
+
+    let mut conn = None;
+    loop {
+        let polled = poll_watch(&mut conn, socket);
+        if matches!(format, OutputFormat::Text) {
+            // Clear the screen and move the cursor home.
+            print!("\x1b[2J\x1b[H");
+        }
+        match polled {
+            Ok(resps) => {
+                for resp in &resps {
+                    match format {
+                        OutputFormat::Text => print_text(resp, false)?,
+                        OutputFormat::Json => print_json(resp)?,
+                        OutputFormat::Yaml => print_yaml(resp)?,
+                    }
+                }
+            }
+            Err(err) => {
+                conn = None;
+                eprintln!("disconnected, retrying: {err:#}");
+            }
+        }
+
+        if stop_rx.recv_timeout(interval) != Err(mpsc::RecvTimeoutError::Timeout) {
+            return Ok(());
+        }
+    }
+}
+
+/// Sends one round of watch requests, connecting first if `conn` is empty.
+fn poll_watch(conn: &mut Option<Stream>, socket: Option<&str>) -> Result<[Response; 2]> {
+    let stream = if let Some(stream) = conn {
+        stream
+    } else {
+        let name = resolve_ipc_socket(socket)?;
+        let mut stream = Stream::connect(name).context("failed to connect to policy-routerd")?;
+        client_hello(&mut stream)?;
+        conn.insert(stream)
+    };
+    Ok([
+        client_roundtrip(stream, &Request::Diagnostics)?,
+        client_roundtrip(stream, &Request::Status)?,
+    ])
+}
+
+/// Points at the protocol version skew when talking to the daemon fails.
+fn with_version_hint<T>(res: Result<T>, daemon: &HelloResponse) -> Result<T> {
+    match daemon.mismatch_hint() {
+        Some(hint) => res.context(hint),
+        None => res,
+    }
+}
+
+/// An error response may just mean the daemon does not know the request.
+fn print_version_note(daemon: &HelloResponse) {
+    if let Some(hint) = daemon.mismatch_hint() {
+        eprintln!("note: {hint}");
+    }
+}
+
 fn fmt_snake_case<T: Serialize>(value: &T) -> Result<String> {
     let raw = serde_json::to_string(value).context("failed to serialize enum")?;
     Ok(raw.trim_matches('"').to_string())
@@ -92,43 +708,35 @@ fn print_json(resp: &Response) -> Result<()> {
     Ok(())
 }
 
+/// Prints `value` as a YAML document, so consecutive calls form a valid
+/// multi-document stream.
+fn print_yaml<T: Serialize>(value: &T) -> Result<()> {
+    let s = serde_yaml::to_string(value).context("failed to serialize output as YAML")?;
+    print!("---\n{s}");
+    Ok(())
+}
+
 fn print_text(resp: &Response, quiet: bool) -> Result<()> {
     match resp {
-        Response::OkStatus(s) => {
-            println!("uptime_ms: {}", s.uptime_ms);
-            println!("config_path: {}", s.config_path);
-            println!("egress:");
-            for e in &s.egress {
-                println!("  - id: {}", e.id);
-                println!("    kind: {}", e.kind);
-                if let Some(ep) = &e.endpoint {
-                    println!("    endpoint: {ep}",);
-                }
-            }
+        Response::Hello(x) => {
+            println!(
+                "protocol: v{} (min supported v{})",
+                x.version, x.min_supported
+            );
         }
+        Response::OkStatus(s) => print_status(s),
         Response::OkReload => {
             if !quiet {
                 println!("reloaded: true");
             }
         }
+        Response::OkReloadDiff(d) => print_reload_diff(d),
         Response::OkStop => {
             if !quiet {
                 println!("stopping: true");
             }
         }
-        Response::OkExplain(x) => {
-            println!("egress: {}", x.decision.egress);
-            println!("source: {}", fmt_snake_case(&x.decision.source)?);
-            if let Some(rule_egress) = &x.decision.rule_egress {
-                println!("rule_egress: {rule_egress}");
-            }
-            if let Some(m) = &x.decision.matcher {
-                println!("matcher:");
-                println!("  type: {}", fmt_snake_case(&m.kind)?);
-                println!("  pattern: {}", m.pattern);
-            }
-            println!("reason: {}", x.decision.reason);
-        }
+        Response::OkExplain(x) => print_explain(x)?,
         Response::OkDiagnostics(d) => {
             println!("uptime_ms: {}", d.uptime_ms);
             println!("config_path: {}", d.config_path);
@@ -138,6 +746,65 @@ fn print_text(resp: &Response, quiet: bool) -> Result<()> {
             println!("ipc_requests: {}", d.ipc_requests);
             println!("reload_ok: {}", d.reload_ok);
             println!("reload_err: {}", d.reload_err);
+            println!("config_generation: {}", d.config_generation);
+            println!("dropped_rules: {}", d.dropped_rules);
+            if !d.lints.is_empty() {
+                println!("lints:");
+                for lint in &d.lints {
+                    println!("  - {lint}");
+                }
+            }
+        }
+        Response::OkHistory(h) => {
+            for entry in &h.entries {
+                print_history_entry(entry)?;
+            }
+        }
+        Response::OkValidate(v) => {
+            println!("ok: {}", v.ok);
+            for error in &v.errors {
+                println!("error: {error}");
+            }
+        }
+        Response::OkConfigFiles(c) => {
+            for file in &c.files {
+                println!("{file}");
+            }
+        }
+        Response::OkMetrics(m) => {
+            print!("{}", m.text);
+        }
+        Response::OkConfig(c) => {
+            print!("{}", c.toml);
+        }
+        Response::OkRules(r) => {
+            print_rule_group("app", &r.app);
+            print_rule_group("domain", &r.domain);
+            print_rule_group("cidr", &r.cidr);
+            print_rule_group("port", &r.port);
+        }
+        Response::OkEgressRefs(r) => print_egress_refs(r),
+        Response::OkRuleStats(r) => {
+            for rule in &r.rules {
+                println!(
+                    "{} {} {} -> {}",
+                    rule.hits, rule.kind, rule.pattern, rule.egress
+                );
+            }
+        }
+        Response::OkPong { server_time_ms } => {
+            println!("server_time_ms: {server_time_ms}");
+        }
+        Response::OkWhoIs(r) => match &r.process {
+            Some(process) => {
+                println!("pid: {}", process.pid);
+                println!("exe: {}", process.exe);
+            }
+            None => println!("process: none"),
+        },
+        Response::OkPriority(r) => print_priority(r),
+        Response::Event(Event::DecisionMade(entry)) => {
+            print_history_entry(entry)?;
         }
         Response::Err(e) => {
             println!("error: {}", e.message);
@@ -146,3 +813,246 @@ fn print_text(resp: &Response, quiet: bool) -> Result<()> {
 
     Ok(())
 }
+
+fn print_status(s: &StatusResponse) {
+    println!("uptime_ms: {}", s.uptime_ms);
+    println!("config_path: {}", s.config_path);
+    println!("config_generation: {}", s.config_generation);
+    println!("egress:");
+    for e in &s.egress {
+        println!("  - id: {}", e.id);
+        println!("    kind: {}", e.kind);
+        if let Some(ep) = &e.endpoint {
+            println!("    endpoint: {ep}");
+        }
+        if let Some(interface) = &e.interface {
+            println!("    interface: {interface}");
+        }
+        if e.auth {
+            println!("    auth: true");
+        }
+        if !e.groups.is_empty() {
+            println!("    groups: {}", e.groups.join(", "));
+        }
+        if let Some(description) = &e.description {
+            println!("    description: {description}");
+        }
+        if let Some(healthy) = e.healthy {
+            println!("    healthy: {healthy}");
+        }
+        if let Some(checked) = e.last_checked_ms {
+            println!("    last_checked_ms: {checked}");
+        }
+    }
+}
+
+fn print_explain(x: &ExplainResponse) -> Result<()> {
+    match (&x.decision.kind, &x.decision.endpoint) {
+        (Some(kind), Some(endpoint)) => {
+            println!("egress: {} -> {kind} {endpoint}", x.decision.egress);
+        }
+        (Some(kind), None) => println!("egress: {} -> {kind}", x.decision.egress),
+        (None, _) => println!("egress: {}", x.decision.egress),
+    }
+    println!("source: {}", fmt_snake_case(&x.decision.source)?);
+    if let Some(rule_egress) = &x.decision.rule_egress {
+        println!("rule_egress: {rule_egress}");
+    }
+    if let Some(group) = &x.decision.group {
+        println!("group: {group}");
+    }
+    if let Some(rank) = x.decision.rank {
+        println!("rank: {rank}");
+        println!("tie_broken: {}", x.decision.tie_broken);
+    }
+    if let Some(m) = &x.decision.matcher {
+        println!("matcher:");
+        println!("  type: {}", fmt_snake_case(&m.kind)?);
+        match m.index {
+            Some(index) => println!("  pattern: {} (index {index})", m.pattern),
+            None => println!("  pattern: {}", m.pattern),
+        }
+    }
+    if let Some(description) = &x.decision.description {
+        println!("description: {description}");
+    }
+    if !x.decision.matchers.is_empty() {
+        println!("matchers:");
+        for m in &x.decision.matchers {
+            let rank = m.rank.map_or_else(|| "-".to_owned(), |r| r.to_string());
+            let index = m.index.map_or_else(String::new, |i| format!("index {i}, "));
+            println!(
+                "  - {} {} {} ({index}rank {rank})",
+                m.egress.as_deref().unwrap_or("-"),
+                fmt_snake_case(&m.kind)?,
+                m.pattern
+            );
+        }
+    }
+    println!("reason: {}", x.decision.reason);
+    println!("reason_code: {}", x.decision.reason_code);
+    if !x.decision.trace.is_empty() {
+        println!("trace:");
+        for step in &x.decision.trace {
+            let outcome = if step.matched { "matched" } else { "no match" };
+            let pattern = step
+                .pattern
+                .as_deref()
+                .map_or_else(String::new, |p| format!(" '{p}'"));
+            println!(
+                "  - {} {}: {outcome}{pattern}",
+                fmt_snake_case(&step.rule)?,
+                step.egress
+            );
+        }
+    }
+    if let Some(client) = &x.client {
+        println!("client: pid={} exe={}", client.pid, client.exe);
+    }
+    Ok(())
+}
+
+fn print_reload_diff(d: &ReloadDiffResponse) {
+    if d.added_egress.is_empty()
+        && d.removed_egress.is_empty()
+        && d.changed_endpoints.is_empty()
+        && d.added_rules.is_empty()
+        && d.removed_rules.is_empty()
+    {
+        println!("no changes");
+        return;
+    }
+    for id in &d.added_egress {
+        println!("+ egress {id}");
+    }
+    for id in &d.removed_egress {
+        println!("- egress {id}");
+    }
+    for change in &d.changed_endpoints {
+        println!(
+            "~ egress {} endpoint: {} -> {}",
+            change.egress,
+            change.old.as_deref().unwrap_or("-"),
+            change.new.as_deref().unwrap_or("-"),
+        );
+    }
+    for rule in &d.added_rules {
+        println!("+ {} {}: {}", rule.kind, rule.egress, rule.pattern);
+    }
+    for rule in &d.removed_rules {
+        println!("- {} {}: {}", rule.kind, rule.egress, rule.pattern);
+    }
+}
+
+/// One line per stage, in the order a decision tries them.
+fn print_priority(r: &PriorityResponse) {
+    for (stage, ids) in [
+        ("block_app", &r.block_app),
+        ("block_domain", &r.block_domain),
+        ("domain", &r.domain),
+        ("app", &r.app),
+    ] {
+        if ids.is_empty() {
+            println!("{stage}: none");
+        } else {
+            println!("{stage}: {}", ids.join(" > "));
+        }
+    }
+}
+
+fn print_egress_refs(r: &EgressRefsResponse) {
+    println!("egress: {}", r.egress);
+    println!("default: {}", r.default);
+    for (kind, patterns) in [
+        ("app", &r.app),
+        ("domain", &r.domain),
+        ("cidr", &r.cidr),
+        ("port", &r.port),
+    ] {
+        if !patterns.is_empty() {
+            println!("{kind}:");
+            for pattern in patterns {
+                println!("  {pattern}");
+            }
+        }
+    }
+}
+
+fn print_rule_group(kind: &str, rules: &BTreeMap<String, Vec<String>>) {
+    for (egress, patterns) in rules {
+        println!("{kind} {egress}:");
+        for pattern in patterns {
+            println!("  {pattern}");
+        }
+    }
+}
+
+fn print_history_entry(entry: &HistoryEntry) -> Result<()> {
+    println!(
+        "#{} +{}ms process={} domain={} -> {} ({})",
+        entry.seq,
+        entry.uptime_ms,
+        entry.process.as_deref().unwrap_or("-"),
+        entry.domain.as_deref().unwrap_or("-"),
+        entry.egress,
+        fmt_snake_case(&entry.source)?,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decision(egress: &str, source: DecisionSource) -> DecisionInfo {
+        serde_json::from_value(serde_json::json!({
+            "egress": egress,
+            "reason": "test",
+            "source": source,
+        }))
+        .expect("decision must deserialize")
+    }
+
+    #[test]
+    fn explain_assertions_report_each_mismatch() {
+        let assertions = ExplainAssertions {
+            egress: Some("vpn".to_owned()),
+            source: Some(DecisionSource::DomainRule),
+        };
+
+        assert_eq!(
+            assertions.check(&decision("vpn", DecisionSource::DomainRule)),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            assertions.check(&decision("direct", DecisionSource::DomainRule)),
+            ["egress: expected 'vpn', got 'direct'"]
+        );
+        assert_eq!(
+            assertions.check(&decision("vpn", DecisionSource::AppRule)),
+            ["source: expected 'domain_rule', got 'app_rule'"]
+        );
+    }
+
+    #[test]
+    fn assert_source_accepts_every_decision_source() {
+        for source in DecisionSource::ALL {
+            let cli = Cli::try_parse_from([
+                "policy-routerctl",
+                "explain",
+                "--assert-source",
+                source.as_str(),
+            ])
+            .expect("every source must parse");
+            let Cmd::Explain { assert_source, .. } = cli.cmd else {
+                panic!("expected the explain command");
+            };
+            assert_eq!(assert_source, Some(source));
+        }
+
+        assert!(
+            Cli::try_parse_from(["policy-routerctl", "explain", "--assert-source", "nope"])
+                .is_err()
+        );
+    }
+}
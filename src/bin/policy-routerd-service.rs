@@ -0,0 +1,110 @@
+//! `policy-routerd` as a Windows service.
+//!
+//! Takes the same arguments as `policy-routerd`, set in the service's binary
+//! path. Without `--log-file`, logs go to `policy-routerd.log` next to the
+//! executable since a service has no console.
+
+#[cfg(windows)]
+#[path = "policy-routerd.rs"]
+#[allow(dead_code, reason = "the console entry point is unused here")]
+mod daemon;
+
+#[cfg(windows)]
+mod service {
+    use std::{ffi::OsString, sync::Arc, time::Duration};
+
+    use anyhow::{Context, Result};
+    use clap::Parser;
+    use tracing::error;
+    use windows_service::{
+        define_windows_service,
+        service::{
+            ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+            ServiceType,
+        },
+        service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+        service_dispatcher,
+    };
+
+    use super::daemon::{self, Cli};
+
+    const SERVICE_NAME: &str = "policy-routerd";
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    pub fn main() -> Result<()> {
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+            .context("failed to connect to the service control manager")
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        let mut cli = Cli::parse();
+        if cli.log_file.is_none() {
+            cli.log_file = std::env::current_exe()
+                .ok()
+                .map(|exe| exe.with_file_name("policy-routerd.log"));
+        }
+        // Without logging there is nowhere to report failures; keep running.
+        let _ = daemon::init_logging(&cli);
+
+        let mut status_handle = None;
+        let result = daemon::run(cli, |state| {
+            let state = Arc::clone(state);
+            let handle =
+                service_control_handler::register(SERVICE_NAME, move |control| match control {
+                    ServiceControl::Stop | ServiceControl::Shutdown => {
+                        state.request_stop();
+                        ServiceControlHandlerResult::NoError
+                    }
+                    ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                    _ => ServiceControlHandlerResult::NotImplemented,
+                })
+                .context("failed to register service control handler")?;
+            set_state(handle, ServiceState::Running, 0)?;
+            status_handle = Some(handle);
+            Ok(())
+        });
+
+        let exit_code = match result {
+            Ok(()) => 0,
+            Err(err) => {
+                error!(error = %format!("{err:#}"), "service failed");
+                1
+            }
+        };
+        if let Some(handle) = status_handle
+            && let Err(err) = set_state(handle, ServiceState::Stopped, exit_code)
+        {
+            error!(error = %format!("{err:#}"), "failed to report service stop");
+        }
+    }
+
+    fn set_state(handle: ServiceStatusHandle, state: ServiceState, exit_code: u32) -> Result<()> {
+        handle
+            .set_service_status(ServiceStatus {
+                service_type: ServiceType::OWN_PROCESS,
+                current_state: state,
+                controls_accepted: if state == ServiceState::Running {
+                    ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+                } else {
+                    ServiceControlAccept::empty()
+                },
+                exit_code: ServiceExitCode::Win32(exit_code),
+                checkpoint: 0,
+                wait_hint: Duration::default(),
+                process_id: None,
+            })
+            .context("failed to set service status")
+    }
+}
+
+#[cfg(windows)]
+fn main() -> anyhow::Result<()> {
+    service::main()
+}
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("policy-routerd-service only runs on Windows; use policy-routerd instead");
+    std::process::exit(1);
+}
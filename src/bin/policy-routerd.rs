@@ -1,35 +1,58 @@
 use std::{
-    io::{self, BufReader},
+    collections::{BTreeMap, VecDeque},
+    io::{self, BufRead, BufReader},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    num::NonZeroUsize,
     path::{Path, PathBuf},
     sync::{
-        Arc,
-        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         mpsc,
     },
     thread,
     time::{Duration, Instant},
 };
 
-use anyhow::{Context, Result};
-use arc_swap::ArcSwap;
-use clap::Parser;
+use anyhow::{Context, Result, bail};
+use clap::{Args, Parser, ValueEnum};
 use interprocess::local_socket::{
     GenericNamespaced, ListenerNonblockingMode, ListenerOptions, prelude::*,
 };
+use lru::LruCache;
 use notify::{Event, EventKind, RecursiveMode, Watcher};
 use policy_router_rs::{
     ipc::{
-        DecisionInfo, DecisionSource, DiagnosticsResponse, ErrorResponse, MatcherInfo, MatcherKind,
-        Request, Response, SOCKET_ENV_VAR, StatusResponse, read_json_line, write_json_line,
+        ClientInfo, ConfigFilesResponse, ConfigResponse, DecisionInfo, DecisionSource,
+        DiagnosticsResponse, EgressRefsResponse, EndpointChangeInfo, ErrorCode, ErrorResponse,
+        HelloRequest, HelloResponse, HistoryEntry, HistoryResponse, LineTooLong, MAX_LINE_BYTES,
+        MIN_SUPPORTED_PROTOCOL_VERSION, MatcherInfo, MatcherKind, MetricsResponse,
+        PROTOCOL_VERSION, PriorityResponse, ReloadDiffResponse, Request, Response, RuleChangeInfo,
+        RuleStat, RuleStatsResponse, RulesResponse, SOCKET_ENV_VAR, StatusResponse, TraceRule,
+        TraceStep, ValidateResponse, WhoIsResponse, read_json_line_limited,
+        set_stream_read_timeout, set_stream_timeout, write_json_line,
     },
-    policy::{config::AppConfig, engine},
+    platform::{PeerUser, ProcessLookup, Protocol, process_is_alive, process_lookup},
+    policy::{
+        config::{
+            AppConfig, AppPattern, CidrPattern, DomainPattern, EgressId, EgressKind, PortPattern,
+            Restrict, Security, UnknownEgressError,
+        },
+        diff::{ConfigDiff, RuleChange, RuleKind},
+        engine::{self, DecideInput},
+    },
+    router::{LiveConfig, PolicyRouter},
 };
+use serde::Serialize;
 use tracing::{info, warn};
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::{EnvFilter, fmt::writer::BoxMakeWriter, util::SubscriberInitExt};
+
+#[cfg(feature = "tokio")]
+#[path = "policy-routerd/async_ipc.rs"]
+mod async_ipc;
 
 #[derive(Debug, Parser)]
 #[command(name = "policy-routerd")]
-struct Cli {
+pub struct Cli {
     #[arg(long, default_value = "config.toml")]
     config: PathBuf,
 
@@ -38,97 +61,1073 @@ struct Cli {
 
     #[arg(long, default_value = "info")]
     log_level: String,
+
+    /// Only log warnings and errors unless `RUST_LOG` says otherwise.
+    #[arg(long)]
+    quiet: bool,
+
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Append logs to this file instead of stdout.
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
+
+    /// Append one JSON line per explained decision to this file.
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+
+    /// Reload automatically when a config file changes on disk.
+    #[arg(long)]
+    watch: bool,
+
+    /// Accept `reload_from` requests, which replace the live config with TOML
+    /// sent over IPC instead of the config file.
+    #[arg(long)]
+    allow_ipc_reload: bool,
+
+    /// Write the daemon pid here while running; refuses to start if it names
+    /// a live process.
+    #[arg(long)]
+    pid_file: Option<PathBuf>,
+
+    /// Drop IPC clients that stay silent or stop reading for this long.
+    #[arg(long, default_value_t = 5000, value_parser = clap::value_parser!(u64).range(1..))]
+    client_timeout_ms: u64,
+
+    /// Threads serving IPC connections; defaults to the number of CPUs.
+    #[arg(long)]
+    max_workers: Option<NonZeroUsize>,
+
+    /// Accepted connections that may wait for a busy worker; beyond this
+    /// they are refused with an error.
+    #[arg(long, default_value_t = 64)]
+    max_queued: usize,
+
+    /// `history --follow` clients streamed at once, each on its own thread
+    /// rather than a worker; beyond this they are refused with an error.
+    #[arg(long, default_value_t = 16)]
+    max_followers: usize,
+
+    /// Longest pause between polls for new connections while idle, in
+    /// milliseconds; polling speeds up again right after a connection.
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u64).range(1..=1000))]
+    accept_poll_ms: u64,
+
+    /// Explain decisions remembered per normalized process and domain until
+    /// the next reload; 0 disables the cache.
+    #[arg(long, default_value_t = 1024)]
+    decision_cache_size: usize,
+
+    /// How often to TCP-connect to each proxy egress endpoint and report
+    /// whether it is reachable in `status`; 0 disables the checks.
+    #[arg(long, default_value_t = 0)]
+    health_interval_ms: u64,
+
+    #[command(flatten)]
+    precheck: Precheck,
+
+    /// Longest request line accepted, in bytes; a longer one is answered with
+    /// an error and the connection closed.
+    #[arg(long, default_value_t = MAX_LINE_BYTES)]
+    max_request_bytes: usize,
+
+    /// How IPC connections are served.
+    #[arg(long, value_enum, default_value_t = Runtime::Sync)]
+    runtime: Runtime,
+
+    /// Also serve IPC over TCP on `addr:port`, or on loopback given only a
+    /// port. TCP clients have no peer credentials, so `security.allow_control`
+    /// treats them as unidentified.
+    #[arg(long, value_name = "ADDR:PORT", value_parser = parse_tcp_addr)]
+    tcp: Option<SocketAddr>,
+}
+
+/// The one-off reachability check of proxy egress endpoints at startup.
+#[derive(Debug, Args)]
+struct Precheck {
+    /// TCP-connect to each proxy egress endpoint once at startup and log
+    /// which are reachable.
+    #[arg(long = "precheck-egress", id = "precheck_egress")]
+    enabled: bool,
+
+    /// Refuse to start when `--precheck-egress` finds an endpoint down.
+    #[arg(long = "precheck-strict", requires = "precheck_egress")]
+    strict: bool,
+}
+
+/// Parses `--tcp`: a bare port binds loopback, anything else must be a full
+/// socket address.
+fn parse_tcp_addr(raw: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = raw.parse::<u16>() {
+        return Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port));
+    }
+    raw.parse()
+        .map_err(|_| format!("'{raw}' is neither a port nor an address:port"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Runtime {
+    /// A pool of `--max-workers` threads, each serving one connection.
+    Sync,
+    /// One tokio task per connection on `--max-workers` runtime threads;
+    /// needs the `tokio` feature.
+    Async,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    /// One JSON object per event, with fields as top-level keys.
+    Json,
 }
 
 #[derive(Debug)]
-struct State {
-    started_at: Instant,
-    config_path: PathBuf,
+pub struct State {
+    router: PolicyRouter,
     socket: String,
-    cfg: ArcSwap<AppConfig>,
+    /// Longest request line accepted from a client.
+    max_request_bytes: usize,
     running: AtomicBool,
     ipc_requests: std::sync::atomic::AtomicU64,
     reload_ok: std::sync::atomic::AtomicU64,
     reload_err: std::sync::atomic::AtomicU64,
+    accept_errors: AtomicU64,
+    /// Connections refused because every worker was busy and the queue full.
+    refused_connections: AtomicU64,
+    /// Explain decisions per source, indexed like [`DecisionSource::ALL`].
+    decisions: [AtomicU64; DecisionSource::ALL.len()],
+    history: DecisionHistory,
+    /// `--audit-log`, when given and openable.
+    audit: Option<AuditLog>,
+    /// `--allow-ipc-reload`.
+    allow_ipc_reload: bool,
+    decision_cache: DecisionCache,
+    rule_hits: RuleHits,
+    /// Last health check per egress id, written by the health check thread.
+    health: Mutex<BTreeMap<String, HealthCheck>>,
+    /// Connection worker and follower threads, joined on shutdown.
+    workers: Mutex<Vec<thread::JoinHandle<()>>>,
+    /// `--max-followers`.
+    max_followers: usize,
+    /// `history --follow` clients currently streamed.
+    followers: AtomicUsize,
+}
+
+const HISTORY_CAPACITY: usize = 256;
+
+/// Append-only `--audit-log` of explained decisions, one [`AuditRecord`] per
+/// line.
+#[derive(Debug)]
+struct AuditLog {
+    file: Mutex<io::BufWriter<std::fs::File>>,
+}
+
+/// One line of the audit log. Its fields are a stable schema: they are only
+/// ever added to, never renamed or removed.
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    /// Wall-clock time of the decision, RFC 3339 in UTC.
+    timestamp: String,
+    /// The IPC client that asked, when its process could be resolved.
+    client: Option<&'a ClientInfo>,
+    process: Option<&'a str>,
+    domain: Option<&'a str>,
+    egress: &'a str,
+    reason_code: &'a str,
+}
+
+impl AuditLog {
+    /// Opens `path` for appending; a file that cannot be opened only disables
+    /// auditing, with a warning.
+    fn open(path: &Path) -> Option<Self> {
+        match std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+        {
+            Ok(file) => Some(Self {
+                file: Mutex::new(io::BufWriter::new(file)),
+            }),
+            Err(err) => {
+                warn!(path = %path.display(), error = %err, "failed to open audit log; auditing disabled");
+                None
+            }
+        }
+    }
+
+    /// Appends `record` as one line and flushes it.
+    fn record(&self, record: &AuditRecord<'_>) {
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        let written = serde_json::to_writer(&mut *file, record)
+            .map_err(io::Error::from)
+            .and_then(|()| io::Write::write_all(&mut *file, b"\n"))
+            .and_then(|()| io::Write::flush(&mut *file));
+        if let Err(err) = written {
+            warn!(error = %err, "failed to write audit log");
+        }
+    }
+}
+
+/// Bounded log of recent decisions plus live subscribers for `history --follow`.
+#[derive(Debug, Default)]
+struct DecisionHistory {
+    /// One lock for numbering, buffering and broadcasting, so entries reach
+    /// the buffer and every subscriber in `seq` order.
+    inner: Mutex<HistoryLog>,
+}
+
+#[derive(Debug, Default)]
+struct HistoryLog {
+    next_seq: u64,
+    entries: VecDeque<HistoryEntry>,
+    subscribers: Vec<mpsc::Sender<HistoryEntry>>,
+}
+
+impl DecisionHistory {
+    fn record(&self, mut entry: HistoryEntry) {
+        let Ok(mut log) = self.inner.lock() else {
+            return;
+        };
+        entry.seq = log.next_seq;
+        log.next_seq += 1;
+
+        if log.entries.len() == HISTORY_CAPACITY {
+            log.entries.pop_front();
+        }
+        log.entries.push_back(entry.clone());
+        log.subscribers.retain(|tx| tx.send(entry.clone()).is_ok());
+    }
+
+    fn snapshot(&self) -> Vec<HistoryEntry> {
+        self.inner
+            .lock()
+            .map_or_else(|_| Vec::new(), |log| log.entries.iter().cloned().collect())
+    }
+
+    /// Subscribes and takes the snapshot at once, so every later decision
+    /// reaches the subscription and none already in the snapshot does.
+    fn follow(&self) -> (mpsc::Receiver<HistoryEntry>, Vec<HistoryEntry>) {
+        let (tx, rx) = mpsc::channel();
+        let entries = self.inner.lock().map_or_else(
+            |_| Vec::new(),
+            |mut log| {
+                log.subscribers.push(tx);
+                log.entries.iter().cloned().collect()
+            },
+        );
+        (rx, entries)
+    }
+}
+
+/// Outcome of the last connect attempt to an egress endpoint.
+#[derive(Debug, Clone, Copy)]
+struct HealthCheck {
+    healthy: bool,
+    /// Daemon uptime at the check.
+    checked_ms: u64,
+}
+
+type FlowKey = (Option<String>, Option<String>, Option<u16>);
+
+/// Recent explain decisions keyed by [`engine::flow_key`], dropped as soon as
+/// a reload swaps in a config of another generation.
+#[derive(Debug)]
+struct DecisionCache {
+    /// `None` when the cache is disabled.
+    inner: Option<Mutex<CachedDecisions>>,
+}
+
+#[derive(Debug)]
+struct CachedDecisions {
+    /// Generation of the config the entries were decided with.
+    generation: u64,
+    entries: LruCache<FlowKey, engine::Decision>,
+}
+
+impl DecisionCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            inner: NonZeroUsize::new(capacity).map(|capacity| {
+                Mutex::new(CachedDecisions {
+                    generation: 0,
+                    entries: LruCache::new(capacity),
+                })
+            }),
+        }
+    }
+
+    /// Decides `input` with `cfg`, reusing an earlier decision for the same
+    /// flow and config generation.
+    fn decide(&self, cfg: &LiveConfig, input: &DecideInput<'_>) -> engine::Decision {
+        let decide = || engine::decide_compiled(cfg, Some(cfg.compiled()), input);
+        // Command lines and schedules make decisions depend on more than the
+        // flow key.
+        let Some(cache) = self.inner.as_ref().filter(|_| {
+            input.cmdline.is_none() && cfg.egress.values().all(|spec| spec.active.is_none())
+        }) else {
+            return decide();
+        };
+
+        let key = engine::flow_key(cfg, input);
+        if let Ok(mut cached) = cache.lock() {
+            if cached.generation != cfg.generation() {
+                cached.entries.clear();
+                cached.generation = cfg.generation();
+            }
+            if let Some(decision) = cached.entries.get(&key) {
+                return decision.clone();
+            }
+        }
+
+        let decision = decide();
+        if let Ok(mut cached) = cache.lock()
+            && cached.generation == cfg.generation()
+        {
+            cached.entries.put(key, decision.clone());
+        }
+        decision
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.inner
+            .as_ref()
+            .and_then(|cache| cache.lock().ok())
+            .map_or(0, |cached| cached.entries.len())
+    }
+}
+
+/// Rule table, the egress or group a pattern is listed under, and the
+/// pattern's index there.
+type RuleKey = (RuleKind, EgressId, usize);
+
+/// How often each rule decided an explained flow, for the config generation
+/// the counts were taken with.
+#[derive(Debug, Default)]
+struct RuleHits {
+    inner: Mutex<RuleHitCounts>,
+}
+
+#[derive(Debug, Default)]
+struct RuleHitCounts {
+    generation: u64,
+    hits: BTreeMap<RuleKey, u64>,
+}
+
+impl RuleHits {
+    /// Counts the rule behind `reason`, decided with config `generation`;
+    /// a newer generation starts the counts over.
+    fn record(&self, generation: u64, reason: &engine::DecisionReason) {
+        let Some(key) = rule_key(reason) else {
+            return;
+        };
+        let Ok(mut counts) = self.inner.lock() else {
+            return;
+        };
+        if generation > counts.generation {
+            counts.hits.clear();
+            counts.generation = generation;
+        }
+        if generation == counts.generation {
+            *counts.hits.entry(key).or_default() += 1;
+        }
+    }
+
+    /// Hits of every rule of `cfg`.
+    fn stats(&self, cfg: &LiveConfig) -> RuleStatsResponse {
+        let counts = self.inner.lock().ok();
+        let hits = |key: &RuleKey| {
+            counts
+                .as_ref()
+                .filter(|counts| counts.generation == cfg.generation())
+                .and_then(|counts| counts.hits.get(key).copied())
+                .unwrap_or(0)
+        };
+
+        let mut rules = Vec::new();
+        let mut push = |kind: RuleKind, egress: &EgressId, index: usize, pattern: &str| {
+            let hits = hits(&(kind, egress.clone(), index));
+            rules.push(RuleStat {
+                kind: kind.as_str().to_owned(),
+                egress: egress.to_string(),
+                pattern: pattern.to_owned(),
+                hits,
+                never_matched: hits == 0,
+            });
+        };
+        for (egress, patterns) in &cfg.rules.app {
+            for (index, pattern) in patterns.iter().enumerate() {
+                push(RuleKind::App, egress, index, pattern.as_str());
+            }
+        }
+        for (egress, patterns) in &cfg.rules.domain {
+            for (index, pattern) in patterns.iter().enumerate() {
+                push(RuleKind::Domain, egress, index, pattern.as_str());
+            }
+        }
+        for (egress, patterns) in &cfg.rules.cidr {
+            for (index, pattern) in patterns.iter().enumerate() {
+                push(RuleKind::Cidr, egress, index, pattern.as_str());
+            }
+        }
+        for (egress, patterns) in &cfg.rules.port {
+            for (index, pattern) in patterns.iter().enumerate() {
+                push(RuleKind::Port, egress, index, pattern.as_str());
+            }
+        }
+
+        RuleStatsResponse { rules }
+    }
+}
+
+/// The rule that decided a flow; a rule targeting a group is listed under
+/// the group, not the member it picked.
+fn rule_key(reason: &engine::DecisionReason) -> Option<RuleKey> {
+    let (kind, egress, group, index) = match reason {
+        engine::DecisionReason::BlockByApp {
+            egress,
+            group,
+            index,
+            ..
+        }
+        | engine::DecisionReason::AppRule {
+            egress,
+            group,
+            index,
+            ..
+        } => (RuleKind::App, egress, group, index),
+        engine::DecisionReason::BlockByDomain {
+            egress,
+            group,
+            index,
+            ..
+        }
+        | engine::DecisionReason::DomainRule {
+            egress,
+            group,
+            index,
+            ..
+        } => (RuleKind::Domain, egress, group, index),
+        engine::DecisionReason::CidrRule {
+            egress,
+            group,
+            index,
+            ..
+        } => (RuleKind::Cidr, egress, group, index),
+        engine::DecisionReason::PortRule {
+            egress,
+            group,
+            index,
+            ..
+        } => (RuleKind::Port, egress, group, index),
+        engine::DecisionReason::Default { .. } | engine::DecisionReason::DefaultBlock { .. } => {
+            return None;
+        }
+    };
+    Some((kind, group.as_ref().unwrap_or(egress).clone(), *index))
+}
+
+impl State {
+    /// Makes the accept loop exit and the daemon shut down.
+    pub fn request_stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    init_logging(&cli)?;
+
+    run(cli, |state| {
+        let state = Arc::clone(state);
+        ctrlc::set_handler(move || state.request_stop()).context("failed to set Ctrl+C handler")
+    })
+}
 
+/// Logs to `--log-file` when given, stdout otherwise, in `--log-format`.
+///
+/// # Errors
+///
+/// Returns an error if the log file cannot be opened.
+pub fn init_logging(cli: &Cli) -> Result<()> {
     let filter =
-        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(cli.log_level.clone()));
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter(cli)));
+
+    let writer = match &cli.log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("failed to open log file {}", path.display()))?;
+            BoxMakeWriter::new(Mutex::new(file))
+        }
+        None => BoxMakeWriter::new(io::stdout),
+    };
+
+    log_subscriber(cli.log_format, filter, writer, cli.log_file.is_none()).init();
+    Ok(())
+}
 
-    tracing_subscriber::fmt()
+/// The filter used when `RUST_LOG` is unset: `warn` under `--quiet`,
+/// `--log-level` otherwise.
+fn default_filter(cli: &Cli) -> &str {
+    if cli.quiet { "warn" } else { &cli.log_level }
+}
+
+fn log_subscriber(
+    format: LogFormat,
+    filter: EnvFilter,
+    writer: BoxMakeWriter,
+    ansi: bool,
+) -> Box<dyn tracing::Subscriber + Send + Sync> {
+    let builder = tracing_subscriber::fmt()
         .with_env_filter(filter)
         .with_target(false)
         .with_level(true)
-        .init();
+        .with_ansi(ansi)
+        .with_writer(writer);
 
-    let cfg = AppConfig::load_from_path(&cli.config)?;
+    match format {
+        LogFormat::Text => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(builder.json().flatten_event(true).finish()),
+    }
+}
+
+/// Runs the daemon until [`State::request_stop`] is called.
+///
+/// `install_shutdown` runs once the state exists, to hook whatever signals
+/// should stop the daemon.
+///
+/// # Errors
+///
+/// Returns an error if the config cannot be loaded, the shutdown hook or
+/// pid file fails, or the IPC listener cannot be created.
+pub fn run(cli: Cli, install_shutdown: impl FnOnce(&Arc<State>) -> Result<()>) -> Result<()> {
+    let router = PolicyRouter::from_path(cli.config)?;
 
     let socket_label = resolve_socket_label(cli.socket.as_deref());
 
     let state = Arc::new(State {
-        started_at: Instant::now(),
-        config_path: cli.config,
+        router,
         socket: socket_label,
-        cfg: ArcSwap::from_pointee(cfg),
+        max_request_bytes: cli.max_request_bytes,
         running: AtomicBool::new(true),
         ipc_requests: std::sync::atomic::AtomicU64::new(0),
         reload_ok: std::sync::atomic::AtomicU64::new(0),
         reload_err: std::sync::atomic::AtomicU64::new(0),
+        accept_errors: AtomicU64::new(0),
+        refused_connections: AtomicU64::new(0),
+        decisions: Default::default(),
+        history: DecisionHistory::default(),
+        audit: cli.audit_log.as_deref().and_then(AuditLog::open),
+        allow_ipc_reload: cli.allow_ipc_reload,
+        decision_cache: DecisionCache::new(cli.decision_cache_size),
+        rule_hits: RuleHits::default(),
+        health: Mutex::new(BTreeMap::new()),
+        workers: Mutex::new(Vec::new()),
+        max_followers: cli.max_followers,
+        followers: AtomicUsize::new(0),
+    });
+
+    install_shutdown(&state)?;
+
+    if cli.precheck.enabled {
+        precheck_egress(&state, cli.precheck.strict)?;
+    }
+
+    // Claim the pid file before touching the socket of a possibly running
+    // daemon.
+    let pid_file = cli.pid_file.as_deref().map(PidFile::create).transpose()?;
+
+    let (name, fs_socket_path) = resolve_ipc_socket(cli.socket.as_deref())?;
+    cleanup_fs_socket(fs_socket_path.as_ref());
+
+    let workers = cli.max_workers.map_or_else(
+        || thread::available_parallelism().map_or(4, NonZeroUsize::get),
+        NonZeroUsize::get,
+    );
+    let listener = IpcListener::bind(cli.runtime, name, workers)?;
+    let tcp_listener = cli.tcp.map(bind_tcp).transpose()?;
+
+    let watcher_handle = cli.watch.then(|| spawn_config_watcher(Arc::clone(&state)));
+    let health_handle = (cli.health_interval_ms > 0).then(|| {
+        spawn_health_checker(
+            Arc::clone(&state),
+            Duration::from_millis(cli.health_interval_ms),
+        )
     });
 
-    ctrlc::set_handler({
+    let client_timeout = Duration::from_millis(cli.client_timeout_ms);
+    let accept_poll = Duration::from_millis(cli.accept_poll_ms);
+    let tcp_handle = tcp_listener.map(|listener| {
         let state = Arc::clone(&state);
-        move || {
-            state.running.store(false, Ordering::SeqCst);
+        let max_queued = cli.max_queued;
+        thread::spawn(move || {
+            serve_tcp(
+                &state,
+                &listener,
+                workers,
+                max_queued,
+                client_timeout,
+                accept_poll,
+            );
+        })
+    });
+    info!(workers, runtime = ?cli.runtime, "started");
+    match listener {
+        IpcListener::Sync(listener) => {
+            serve_sync(
+                &state,
+                &listener,
+                workers,
+                cli.max_queued,
+                client_timeout,
+                accept_poll,
+            );
         }
-    })
-    .context("failed to set Ctrl+C handler")?;
+        #[cfg(feature = "tokio")]
+        IpcListener::Async(server) => server.serve(&state, client_timeout),
+    }
 
-    let (name, fs_socket_path) = resolve_ipc_socket(cli.socket.as_deref())?;
     cleanup_fs_socket(fs_socket_path.as_ref());
+    drop(pid_file);
+
+    if let Some(handle) = watcher_handle
+        && let Err(err) = handle.join()
+    {
+        warn!(error = ?err, "config watcher thread join failed");
+    }
+    if let Some(handle) = health_handle
+        && let Err(err) = handle.join()
+    {
+        warn!(error = ?err, "health check thread join failed");
+    }
+    if let Some(handle) = tcp_handle
+        && let Err(err) = handle.join()
+    {
+        warn!(error = ?err, "tcp listener thread join failed");
+    }
 
-    let listener = ListenerOptions::new()
-        .name(name)
-        .nonblocking(ListenerNonblockingMode::Accept)
-        .create_sync()
-        .context("failed to create IPC listener")?;
+    Ok(())
+}
+
+/// The IPC listener of the selected [`Runtime`].
+enum IpcListener {
+    Sync(interprocess::local_socket::Listener),
+    #[cfg(feature = "tokio")]
+    Async(async_ipc::Server),
+}
 
-    let watcher_handle = spawn_config_watcher(Arc::clone(&state));
+impl IpcListener {
+    fn bind(
+        runtime: Runtime,
+        name: interprocess::local_socket::Name<'static>,
+        workers: usize,
+    ) -> Result<Self> {
+        match runtime {
+            Runtime::Sync => ListenerOptions::new()
+                .name(name)
+                .nonblocking(ListenerNonblockingMode::Accept)
+                .create_sync()
+                .context("failed to create IPC listener")
+                .map(Self::Sync),
+            #[cfg(feature = "tokio")]
+            Runtime::Async => async_ipc::Server::bind(name, workers).map(Self::Async),
+            #[cfg(not(feature = "tokio"))]
+            Runtime::Async => {
+                let _ = workers;
+                bail!("--runtime async needs policy-routerd built with the `tokio` feature")
+            }
+        }
+    }
+}
 
-    info!("started");
+/// Accepts connections until the daemon stops, handing them to a pool of
+/// `workers` threads, then drains the pool.
+fn serve_sync(
+    state: &Arc<State>,
+    listener: &interprocess::local_socket::Listener,
+    workers: usize,
+    max_queued: usize,
+    client_timeout: Duration,
+    accept_poll: Duration,
+) {
+    let queue = spawn_workers(state, workers, max_queued, client_timeout);
+    let mut backoff = AcceptBackoff::new(accept_poll);
 
     while state.running.load(Ordering::SeqCst) {
-        match listener.accept() {
-            Ok(conn) => {
-                let state = Arc::clone(&state);
-                thread::spawn(move || {
-                    if let Err(e) = handle_conn(&state, conn) {
-                        warn!(error = %format!("{e:#}"), "ipc error");
-                    }
-                });
+        enqueue(state, listener.accept(), &queue, &mut backoff);
+    }
+
+    info!("stopping");
+
+    // Workers exit once the queued connections are served.
+    drop(queue);
+    let (drained, abandoned) = drain_workers(state, DRAIN_TIMEOUT);
+    if abandoned == 0 {
+        info!(drained, "drained connection workers");
+    } else {
+        warn!(
+            drained,
+            abandoned, "connection workers still busy after drain timeout"
+        );
+    }
+}
+
+/// Binds the `--tcp` listener, warning when it is reachable from other hosts.
+fn bind_tcp(addr: SocketAddr) -> Result<TcpListener> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("failed to bind tcp listener {addr}"))?;
+    listener
+        .set_nonblocking(true)
+        .context("failed to make tcp listener non-blocking")?;
+    if addr.ip().is_loopback() {
+        info!(%addr, "serving ipc over tcp");
+    } else {
+        warn!(
+            %addr,
+            "serving ipc over tcp on a NON-LOOPBACK address: any host that can reach it can \
+             query the policy, and stop or reload the daemon unless security.allow_control is set"
+        );
+    }
+    Ok(listener)
+}
+
+/// Accepts `--tcp` connections until the daemon stops, serving them on their
+/// own pool of `workers` threads.
+fn serve_tcp(
+    state: &Arc<State>,
+    listener: &TcpListener,
+    workers: usize,
+    max_queued: usize,
+    client_timeout: Duration,
+    accept_poll: Duration,
+) {
+    let queue = spawn_workers(state, workers, max_queued, client_timeout);
+    let mut backoff = AcceptBackoff::new(accept_poll);
+
+    while state.running.load(Ordering::SeqCst) {
+        // Accepted sockets may inherit the listener's non-blocking mode.
+        let accepted = listener
+            .accept()
+            .and_then(|(conn, _)| conn.set_nonblocking(false).map(|()| conn));
+        enqueue(state, accepted, &queue, &mut backoff);
+    }
+}
+
+/// Pause between polls of a non-blocking listener: it starts short after a
+/// connection and doubles while nothing arrives, up to `--accept-poll-ms`.
+#[derive(Debug)]
+struct AcceptBackoff {
+    max: Duration,
+    next: Duration,
+}
+
+impl AcceptBackoff {
+    const MIN: Duration = Duration::from_millis(1);
+
+    const fn new(max: Duration) -> Self {
+        Self { max, next: max }
+    }
+
+    /// Polls quickly again, as more connections tend to follow one.
+    fn reset(&mut self) {
+        self.next = Self::MIN.min(self.max);
+    }
+
+    /// The pause after an empty poll.
+    fn idle(&mut self) -> Duration {
+        let pause = self.next;
+        self.next = (pause * 2).min(self.max);
+        pause
+    }
+}
+
+/// Queues an accepted connection for the workers, refusing it when the queue
+/// is full; backs off when nothing was waiting or accepting failed.
+fn enqueue<C: IpcConn>(
+    state: &State,
+    accepted: io::Result<C>,
+    queue: &mpsc::SyncSender<C>,
+    backoff: &mut AcceptBackoff,
+) {
+    match accepted {
+        Ok(conn) => {
+            backoff.reset();
+            if let Err(mpsc::TrySendError::Full(conn) | mpsc::TrySendError::Disconnected(conn)) =
+                queue.try_send(conn)
+            {
+                refuse_connection(state, &conn);
             }
-            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                thread::sleep(Duration::from_millis(20));
+        }
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+            thread::sleep(backoff.idle());
+        }
+        Err(e) => {
+            state.accept_errors.fetch_add(1, Ordering::Relaxed);
+            warn!(error = %e, "accept error");
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+/// A pid file owned by this daemon, removed on drop.
+#[derive(Debug)]
+struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    fn create(path: &Path) -> Result<Self> {
+        if let Ok(raw) = std::fs::read_to_string(path)
+            && let Ok(pid) = raw.trim().parse::<u32>()
+            && pid != std::process::id()
+            && process_is_alive(pid)
+        {
+            bail!(
+                "pid file {} names running process {pid}; is policy-routerd already running?",
+                path.display()
+            );
+        }
+
+        std::fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("failed to write pid file {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!(error = %err, path = %self.path.display(), "failed to remove pid file");
+        }
+    }
+}
+
+/// How long shutdown waits for in-flight connections to finish.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a refused client gets to take its error response.
+const REFUSE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Starts `workers` threads serving accepted connections one at a time.
+///
+/// The returned queue holds up to `capacity` connections waiting for a free
+/// worker. Dropping it lets the workers exit once the queue is empty.
+fn spawn_workers<C: IpcConn>(
+    state: &Arc<State>,
+    workers: usize,
+    capacity: usize,
+    client_timeout: Duration,
+) -> mpsc::SyncSender<C> {
+    let (tx, rx) = mpsc::sync_channel::<C>(capacity);
+    let rx = Arc::new(Mutex::new(rx));
+    for _ in 0..workers {
+        let handle = thread::spawn({
+            let state = Arc::clone(state);
+            let rx = Arc::clone(&rx);
+            move || {
+                loop {
+                    // Hold the lock only while waiting, not while serving.
+                    let next = match rx.lock() {
+                        Ok(rx) => rx.recv(),
+                        Err(_) => break,
+                    };
+                    let Ok(conn) = next else { break };
+
+                    if let Err(e) = conn.set_timeout(Some(client_timeout)) {
+                        warn!(error = %format!("{e:#}"), "failed to set ipc client timeout");
+                    }
+                    match conn.serve(&state) {
+                        Ok(Served::Closed) => {}
+                        Ok(Served::Follow) => spawn_follower(&state, conn),
+                        Err(e) => warn!(error = %format!("{e:#}"), "ipc error"),
+                    }
+                }
             }
-            Err(e) => {
-                warn!(error = %e, "accept error");
-                thread::sleep(Duration::from_millis(50));
+        });
+        track_worker(state, handle);
+    }
+    tx
+}
+
+/// Streams history to a `history --follow` client on a thread of its own, so
+/// it does not hold a worker, unless `--max-followers` are already streamed.
+fn spawn_follower<C: IpcConn>(state: &Arc<State>, conn: C) {
+    if state.followers.fetch_add(1, Ordering::SeqCst) >= state.max_followers {
+        state.followers.fetch_sub(1, Ordering::SeqCst);
+        state.refused_connections.fetch_add(1, Ordering::Relaxed);
+        warn!("too many history followers; refusing follow");
+        refuse(
+            &conn,
+            "policy-routerd is busy: too many clients follow history (see --max-followers); try again",
+        );
+        return;
+    }
+
+    let handle = thread::spawn({
+        let state = Arc::clone(state);
+        move || {
+            if let Err(e) = follow_history(&state, &conn) {
+                warn!(error = %format!("{e:#}"), "ipc error");
             }
+            state.followers.fetch_sub(1, Ordering::SeqCst);
         }
+    });
+    track_worker(state, handle);
+}
+
+/// Answers a connection that found every worker busy and the queue full with
+/// an error, then drops it.
+fn refuse_connection(state: &State, conn: &impl IpcConn) {
+    state.refused_connections.fetch_add(1, Ordering::Relaxed);
+    warn!("all ipc workers busy; refusing connection");
+    refuse(
+        conn,
+        "policy-routerd is busy: all workers are serving connections; try again",
+    );
+}
+
+/// Sends a [`ErrorCode::Busy`] error with `message`, waiting at most
+/// [`REFUSE_TIMEOUT`] for the client to take it.
+fn refuse(conn: &impl IpcConn, message: &str) {
+    let resp = Response::Err(ErrorResponse {
+        code: ErrorCode::Busy,
+        message: message.to_owned(),
+    });
+    if let Err(e) = conn
+        .set_timeout(Some(REFUSE_TIMEOUT))
+        .and_then(|()| conn.send(&resp))
+    {
+        warn!(error = %format!("{e:#}"), "failed to refuse ipc connection");
     }
+}
 
-    info!("stopping");
+/// A connection the workers serve: the local socket or `--tcp`.
+trait IpcConn: Send + 'static {
+    /// Bounds every read and write on the connection by `timeout`.
+    fn set_timeout(&self, timeout: Option<Duration>) -> Result<()>;
 
-    cleanup_fs_socket(fs_socket_path.as_ref());
+    /// Answers requests until the client closes its end or asks to follow
+    /// history.
+    fn serve(&self, state: &State) -> Result<Served>;
 
-    if let Err(err) = watcher_handle.join() {
-        warn!(error = ?err, "config watcher thread join failed");
+    /// Writes one response line.
+    fn send(&self, resp: &Response) -> Result<()>;
+
+    /// Whether the client closed its end, waiting at most `wait` for it to
+    /// say so. Anything the client sends instead is discarded.
+    fn peer_closed(&self, wait: Duration) -> bool;
+}
+
+/// How a connection left [`serve_lines`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Served {
+    /// The client closed its end, or the connection was dropped on an error.
+    Closed,
+    /// The client asked to follow history; nothing was answered yet.
+    Follow,
+}
+
+/// Reads one chunk from `conn` to tell a closed peer (end of stream or a
+/// reset) from a quiet one (timeout).
+fn probe_closed<S>(conn: &S) -> bool
+where
+    for<'a> &'a S: io::Read,
+{
+    let mut buf = [0_u8; 512];
+    match io::Read::read(&mut &*conn, &mut buf) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => !matches!(
+            e.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+        ),
     }
+}
 
-    Ok(())
+impl IpcConn for interprocess::local_socket::Stream {
+    fn set_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        set_stream_timeout(self, timeout)
+    }
+
+    fn serve(&self, state: &State) -> Result<Served> {
+        handle_conn(state, self)
+    }
+
+    fn send(&self, resp: &Response) -> Result<()> {
+        write_json_line(self, resp)
+    }
+
+    /// Named pipes have no read timeout to wait with, so on Windows a closed
+    /// peer only shows up on the next write.
+    fn peer_closed(&self, wait: Duration) -> bool {
+        cfg!(unix) && set_stream_read_timeout(self, Some(wait)).is_ok() && probe_closed(self)
+    }
+}
+
+impl IpcConn for TcpStream {
+    fn set_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.set_read_timeout(timeout)
+            .context("failed to set read timeout")?;
+        self.set_write_timeout(timeout)
+            .context("failed to set write timeout")
+    }
+
+    fn serve(&self, state: &State) -> Result<Served> {
+        serve_lines(state, self, &lookup_tcp_peer(self))
+    }
+
+    fn send(&self, resp: &Response) -> Result<()> {
+        write_json_line(self, resp)
+    }
+
+    fn peer_closed(&self, wait: Duration) -> bool {
+        self.set_read_timeout(Some(wait)).is_ok() && probe_closed(self)
+    }
+}
+
+/// Remembers a worker thread, forgetting ones that already finished.
+fn track_worker(state: &State, handle: thread::JoinHandle<()>) {
+    if let Ok(mut workers) = state.workers.lock() {
+        workers.retain(|handle| !handle.is_finished());
+        workers.push(handle);
+    }
+}
+
+/// Joins worker threads until `timeout` elapses; returns how many were
+/// joined and how many were still running.
+fn drain_workers(state: &State, timeout: Duration) -> (usize, usize) {
+    let mut pending = state
+        .workers
+        .lock()
+        .map_or_else(|_| Vec::new(), |mut workers| std::mem::take(&mut *workers));
+    let deadline = Instant::now() + timeout;
+
+    let mut drained = 0;
+    while !pending.is_empty() && Instant::now() < deadline {
+        let (finished, running): (Vec<_>, Vec<_>) = pending
+            .into_iter()
+            .partition(thread::JoinHandle::is_finished);
+        for handle in finished {
+            if handle.join().is_err() {
+                warn!("connection worker panicked");
+            }
+            drained += 1;
+        }
+        pending = running;
+        if !pending.is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    (drained, pending.len())
 }
 
 fn resolve_ipc_socket(
@@ -161,6 +1160,109 @@ fn cleanup_fs_socket(path: Option<&PathBuf>) {
     }
 }
 
+/// Upper bound on one endpoint connect attempt of a health check.
+const HEALTH_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn spawn_health_checker(state: Arc<State>, interval: Duration) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        while state.running.load(Ordering::SeqCst) {
+            check_egress_health(&state);
+
+            let next = Instant::now() + interval;
+            while state.running.load(Ordering::SeqCst) && Instant::now() < next {
+                thread::sleep(Duration::from_millis(100).min(interval));
+            }
+        }
+    })
+}
+
+/// Connects once to every egress endpoint of the live config that a health
+/// check applies to, replacing the recorded results.
+fn check_egress_health(state: &State) {
+    let checks = probe_egress_endpoints(state);
+    if let Ok(mut health) = state.health.lock() {
+        *health = checks;
+    }
+}
+
+/// Runs one health check before serving, logging which endpoints answer.
+///
+/// # Errors
+///
+/// With `strict`, returns an error naming every unreachable egress.
+fn precheck_egress(state: &State, strict: bool) -> Result<()> {
+    let checks = probe_egress_endpoints(state);
+    let mut down = Vec::new();
+    for (id, check) in &checks {
+        if check.healthy {
+            info!(egress = %id, "egress endpoint reachable");
+        } else {
+            down.push(id.as_str());
+        }
+    }
+    let down = down.join(", ");
+    if let Ok(mut health) = state.health.lock() {
+        *health = checks;
+    }
+
+    if strict && !down.is_empty() {
+        bail!(
+            "egress endpoints unreachable at startup: {down}; refusing to start (--precheck-strict)"
+        );
+    }
+    Ok(())
+}
+
+/// Connects once to every egress endpoint of the live config that a health
+/// check applies to; direct, block and tun egresses are skipped.
+fn probe_egress_endpoints(state: &State) -> BTreeMap<String, HealthCheck> {
+    let cfg = state.router.config();
+    let mut checks = BTreeMap::new();
+    for (id, spec) in &cfg.egress {
+        if !matches!(
+            spec.kind,
+            EgressKind::Socks5 | EgressKind::Singbox | EgressKind::Http
+        ) {
+            continue;
+        }
+        let Some((host, port)) = spec.endpoint_host_port() else {
+            continue;
+        };
+        let healthy = endpoint_reachable(&host, port);
+        if !healthy {
+            warn!(egress = %id, host, port, "egress endpoint unreachable");
+        }
+        checks.insert(
+            id.to_string(),
+            HealthCheck {
+                healthy,
+                checked_ms: u64::try_from(state.router.uptime().as_millis()).unwrap_or(u64::MAX),
+            },
+        );
+    }
+    checks
+}
+
+fn endpoint_reachable(host: &str, port: u16) -> bool {
+    (host, port).to_socket_addrs().is_ok_and(|mut addrs| {
+        addrs.any(|addr| TcpStream::connect_timeout(&addr, HEALTH_CONNECT_TIMEOUT).is_ok())
+    })
+}
+
+/// Status of the live config with the latest health check of each egress.
+fn build_status(state: &State) -> StatusResponse {
+    let mut status = state.router.status();
+    if let Ok(health) = state.health.lock() {
+        for egress in &mut status.egress {
+            if let Some(check) = health.get(&egress.id) {
+                egress.healthy = Some(check.healthy);
+                egress.last_checked_ms = Some(check.checked_ms);
+            }
+        }
+    }
+    status
+}
+
 fn spawn_config_watcher(state: Arc<State>) -> thread::JoinHandle<()> {
     thread::spawn(move || {
         if let Err(err) = run_config_watcher(&state) {
@@ -176,11 +1278,27 @@ fn run_config_watcher(state: &Arc<State>) -> Result<()> {
     })
     .context("failed to create config watcher")?;
 
-    let config_dir = state.config_path.parent().unwrap_or_else(|| Path::new("."));
-
-    watcher
-        .watch(config_dir, RecursiveMode::NonRecursive)
-        .with_context(|| format!("failed to watch config directory {}", config_dir.display()))?;
+    let mut config_dirs: Vec<PathBuf> = state
+        .router
+        .config()
+        .source_files
+        .iter()
+        .map(|path| {
+            path.parent()
+                .unwrap_or_else(|| Path::new("."))
+                .to_path_buf()
+        })
+        .collect();
+    config_dirs.sort();
+    config_dirs.dedup();
+
+    for config_dir in &config_dirs {
+        watcher
+            .watch(config_dir, RecursiveMode::NonRecursive)
+            .with_context(|| {
+                format!("failed to watch config directory {}", config_dir.display())
+            })?;
+    }
 
     let debounce = Duration::from_millis(350);
     let mut last_event: Option<Instant> = None;
@@ -188,7 +1306,7 @@ fn run_config_watcher(state: &Arc<State>) -> Result<()> {
     while state.running.load(Ordering::SeqCst) {
         match rx.recv_timeout(Duration::from_millis(100)) {
             Ok(Ok(event)) => {
-                if should_reload_event(&event, &state.config_path) {
+                if should_reload_event(&event, &state.router.config().source_files) {
                     last_event = Some(Instant::now());
                 }
             }
@@ -215,27 +1333,250 @@ fn run_config_watcher(state: &Arc<State>) -> Result<()> {
     Ok(())
 }
 
-fn should_reload_event(event: &Event, config_path: &Path) -> bool {
+fn should_reload_event(event: &Event, config_files: &[PathBuf]) -> bool {
     matches!(
         &event.kind,
         EventKind::Modify(_) | EventKind::Create(_) | EventKind::Remove(_) | EventKind::Any
-    ) && event.paths.iter().any(|path| path == config_path)
+    ) && event.paths.iter().any(|path| config_files.contains(path))
+}
+
+fn handle_conn(state: &State, conn: &interprocess::local_socket::Stream) -> Result<Served> {
+    serve_lines(state, conn, &lookup_peer(conn))
+}
+
+/// Answers request lines from `conn` until the client closes its end or asks
+/// to follow history.
+fn serve_lines<S>(state: &State, conn: &S, peer: &Peer) -> Result<Served>
+where
+    for<'a> &'a S: io::Read + io::Write,
+{
+    let mut reader = BufReader::new(conn);
+
+    // One request per line until the client closes its end.
+    while !reader.fill_buf()?.is_empty() {
+        let req = read_json_line_limited(&mut reader, state.max_request_bytes);
+        // The rest of an oversized line is still unread; answer and hang up.
+        let overran = req.as_ref().is_err_and(anyhow::Error::is::<LineTooLong>);
+        match dispatch(state, req, peer) {
+            Some(resp) => write_json_line(conn, &resp)?,
+            None => return Ok(Served::Follow),
+        }
+        if overran {
+            break;
+        }
+    }
+
+    Ok(Served::Closed)
 }
 
-fn handle_conn(state: &Arc<State>, mut conn: interprocess::local_socket::Stream) -> Result<()> {
-    let req: Request = read_json_line(BufReader::new(&mut conn))?;
+/// Answers one request line, whatever runtime serves the connection.
+///
+/// Returns `None` when the client asked to follow history; the caller then
+/// streams decisions until the client leaves or the daemon stops.
+fn dispatch(state: &State, req: Result<Request>, peer: &Peer) -> Option<Response> {
+    let req = match req {
+        Ok(req) => req,
+        Err(err) => {
+            return Some(Response::Err(ErrorResponse {
+                code: ErrorCode::InvalidRequest,
+                message: format!("invalid request: {err:#}"),
+            }));
+        }
+    };
 
     state
         .ipc_requests
         .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 
-    let resp = handle_request(state.as_ref(), req);
-    write_json_line(&mut conn, &resp)?;
+    if let Some(resp) =
+        refuse_unauthorized(&state.router.config().security, &req, peer.user.as_ref())
+    {
+        return Some(resp);
+    }
+
+    if let Request::History(x) = &req
+        && x.follow
+    {
+        return None;
+    }
+
+    Some(handle_request(state, req, peer.client.as_ref()))
+}
+
+/// The error answering `req` unless `security` lets `user` send it. An
+/// unknown user is only let through requests `security` does not guard.
+fn refuse_unauthorized(
+    security: &Security,
+    req: &Request,
+    user: Option<&PeerUser>,
+) -> Option<Response> {
+    let allowed = security.allow_control.as_ref()?;
+    let guarded = match req {
+        Request::Hello(_) | Request::Ping => false,
+        Request::Stop | Request::Reload | Request::ReloadFrom { .. } => true,
+        _ => security.restrict == Restrict::All,
+    };
+    if !guarded || user.is_some_and(|user| allowed.iter().any(|entry| user_matches(user, entry))) {
+        return None;
+    }
+
+    let who = user.map_or_else(
+        || "an unidentified user".to_string(),
+        |user| format!("user '{}'", user.name.as_deref().unwrap_or(&user.id)),
+    );
+    warn!(client = %who, "refusing unauthorized ipc request");
+    Some(Response::Err(ErrorResponse {
+        code: ErrorCode::Unauthorized,
+        message: format!("{who} is not allowed to send this request (see security.allow_control)"),
+    }))
+}
+
+/// Whether an `allow_control` entry names `user`, by id or by account name;
+/// Windows account names ignore case.
+fn user_matches(user: &PeerUser, entry: &str) -> bool {
+    let entry = entry.trim();
+    user.id == entry
+        || user.name.as_deref().is_some_and(|name| {
+            if cfg!(windows) {
+                name.eq_ignore_ascii_case(entry)
+            } else {
+                name == entry
+            }
+        })
+}
+
+/// Who is on the other end of a connection.
+#[derive(Debug, Default)]
+struct Peer {
+    client: Option<ClientInfo>,
+    user: Option<PeerUser>,
+}
+
+/// Resolves the process and user on the other end of `conn`; a failed lookup
+/// only leaves them unknown.
+fn lookup_peer(conn: &interprocess::local_socket::Stream) -> Peer {
+    let lookup = peer_lookup();
+    let client = client_info(lookup.lookup_ipc_peer(conn));
+    let user = lookup.lookup_ipc_peer_user(conn).unwrap_or_else(|err| {
+        warn!(error = %format!("{err:#}"), "failed to resolve IPC client user");
+        None
+    });
+    Peer { client, user }
+}
+
+/// Resolves the process behind a TCP client on this host by its socket
+/// address; remote clients and the user of any client stay unknown.
+fn lookup_tcp_peer(conn: &TcpStream) -> Peer {
+    let client = conn
+        .peer_addr()
+        .ok()
+        .filter(|addr| addr.ip().is_loopback())
+        .and_then(|addr| client_info(peer_lookup().lookup_client_process(addr, Protocol::Tcp)));
+    Peer { client, user: None }
+}
+
+fn peer_lookup() -> &'static dyn ProcessLookup {
+    static LOOKUP: OnceLock<Box<dyn ProcessLookup>> = OnceLock::new();
+    LOOKUP.get_or_init(process_lookup).as_ref()
+}
+
+fn client_info(
+    found: Result<Option<policy_router_rs::platform::ProcessInfo>>,
+) -> Option<ClientInfo> {
+    match found {
+        Ok(info) => info.map(|info| ClientInfo {
+            pid: info.pid,
+            exe: info.exe,
+        }),
+        Err(err) => {
+            warn!(error = %format!("{err:#}"), "failed to resolve IPC client process");
+            None
+        }
+    }
+}
+
+/// Resolves the local process owning the TCP or UDP socket bound to `addr`.
+fn whois(addr: &str) -> Response {
+    let addr: SocketAddr = match addr.trim().parse() {
+        Ok(addr) => addr,
+        Err(err) => {
+            return Response::Err(ErrorResponse {
+                code: ErrorCode::InvalidRequest,
+                message: format!("invalid socket address '{addr}': {err}"),
+            });
+        }
+    };
+    match peer_lookup().lookup_client_process(addr, Protocol::Any) {
+        Ok(info) => Response::OkWhoIs(WhoIsResponse {
+            process: info.map(|info| ClientInfo {
+                pid: info.pid,
+                exe: info.exe,
+            }),
+        }),
+        Err(err) => Response::Err(ErrorResponse {
+            code: ErrorCode::Internal,
+            message: format!("failed to resolve the owner of {addr}: {err:#}"),
+        }),
+    }
+}
+
+/// How long a follower waits for a decision before checking on the client.
+const FOLLOW_POLL: Duration = Duration::from_millis(100);
+
+/// How long a follower waits for a quiet client to show it left.
+const FOLLOW_PROBE: Duration = Duration::from_millis(5);
+
+/// Streams decisions to `conn` until the client leaves or the daemon stops.
+fn follow_history(state: &State, conn: &impl IpcConn) -> Result<()> {
+    let (rx, snapshot, mut next_seq) = start_follow(state);
+    conn.send(&snapshot)?;
+
+    while state.running.load(Ordering::SeqCst) {
+        match rx.recv_timeout(FOLLOW_POLL) {
+            Ok(entry) => {
+                if let Some(event) = history_event(entry, &mut next_seq) {
+                    conn.send(&event)?;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if conn.peer_closed(FOLLOW_PROBE) {
+                    break;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
     Ok(())
 }
 
-fn handle_request(state: &State, req: Request) -> Response {
+/// Subscribes to new decisions; returns the subscription, the snapshot to
+/// send first and the sequence number the next event must reach.
+fn start_follow(state: &State) -> (mpsc::Receiver<HistoryEntry>, Response, u64) {
+    let (rx, entries) = state.history.follow();
+    let next_seq = entries.last().map_or(0, |entry| entry.seq + 1);
+    (
+        rx,
+        Response::OkHistory(HistoryResponse { entries }),
+        next_seq,
+    )
+}
+
+/// The event announcing `entry`, unless it was already part of the snapshot
+/// that started the follow.
+fn history_event(entry: HistoryEntry, next_seq: &mut u64) -> Option<Response> {
+    if entry.seq < *next_seq {
+        return None;
+    }
+    *next_seq = entry.seq + 1;
+    Some(Response::Event(policy_router_rs::ipc::Event::DecisionMade(
+        entry,
+    )))
+}
+
+fn handle_request(state: &State, req: Request, client: Option<&ClientInfo>) -> Response {
     match req {
+        Request::Hello(x) => hello(&x),
         Request::Status => Response::OkStatus(build_status(state)),
         Request::Reload => match reload_config(state) {
             Ok(()) => {
@@ -245,99 +1586,441 @@ fn handle_request(state: &State, req: Request) -> Response {
             Err(e) => {
                 warn!(error = %format!("{e:#}"), "reload failed");
                 Response::Err(ErrorResponse {
-                    message: format!("reload failed for {}: {:#}", state.config_path.display(), e),
+                    code: config_error_code(&e),
+                    message: format!(
+                        "reload failed for {}: {:#}",
+                        state.router.config_path().display(),
+                        e
+                    ),
                 })
             }
         },
+        Request::ReloadFrom { toml } => reload_from(state, &toml),
+        Request::ReloadDiff => match reload_diff(state) {
+            Ok(diff) => Response::OkReloadDiff(diff),
+            Err(err) => Response::Err(ErrorResponse {
+                code: config_error_code(&err),
+                message: format!(
+                    "dry-run reload failed for {}: {err:#}",
+                    state.router.config_path().display()
+                ),
+            }),
+        },
         Request::Stop => {
-            state.running.store(false, Ordering::SeqCst);
+            state.request_stop();
             info!("stop requested");
             Response::OkStop
         }
-        Request::Explain(x) => handle_explain(state, &x),
+        Request::Explain(x) => handle_explain(state, &x, client),
         Request::Diagnostics => Response::OkDiagnostics(build_diagnostics(state)),
+        Request::History(_) => Response::OkHistory(HistoryResponse {
+            entries: state.history.snapshot(),
+        }),
+        Request::ConfigFiles => Response::OkConfigFiles(build_config_files(state)),
+        Request::ValidateConfig => Response::OkValidate(validate_config(state)),
+        Request::ListRules => Response::OkRules(build_rules(state)),
+        Request::Metrics => Response::OkMetrics(MetricsResponse {
+            text: render_metrics(state),
+        }),
+        Request::EgressRefs { egress } => build_egress_refs(state, &egress),
+        Request::RuleStats => Response::OkRuleStats(state.rule_hits.stats(&state.router.config())),
+        Request::Ping => Response::OkPong {
+            server_time_ms: u64::try_from(state.router.uptime().as_millis()).unwrap_or(u64::MAX),
+        },
+        Request::WhoIs { addr } => whois(&addr),
+        Request::Priority => Response::OkPriority(build_priority(state)),
+        Request::GetConfig => match build_config(state) {
+            Ok(config) => Response::OkConfig(config),
+            Err(err) => Response::Err(ErrorResponse {
+                code: ErrorCode::Internal,
+                message: format!("{err:#}"),
+            }),
+        },
     }
 }
 
-fn build_status(state: &State) -> StatusResponse {
-    let cfg = state.cfg.load();
-    let egress = cfg
-        .egress
+/// Classifies a failed config load: a missing file, an undeclared egress, or
+/// anything else wrong with the config.
+fn config_error_code(err: &anyhow::Error) -> ErrorCode {
+    for cause in err.chain() {
+        if cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+        {
+            return ErrorCode::ConfigNotFound;
+        }
+        if cause.is::<UnknownEgressError>() {
+            return ErrorCode::UnknownEgress;
+        }
+    }
+    ErrorCode::ConfigInvalid
+}
+
+/// Answers the handshake, refusing clients older than the oldest supported
+/// protocol version.
+#[allow(
+    clippy::absurd_extreme_comparisons,
+    reason = "the supported floor stays 0 until a breaking protocol change"
+)]
+fn hello(req: &HelloRequest) -> Response {
+    if req.version < MIN_SUPPORTED_PROTOCOL_VERSION {
+        return Response::Err(ErrorResponse {
+            code: ErrorCode::UnsupportedVersion,
+            message: format!(
+                "policy-routerctl speaks protocol v{} but policy-routerd needs at least v{MIN_SUPPORTED_PROTOCOL_VERSION}; upgrade policy-routerctl",
+                req.version
+            ),
+        });
+    }
+    Response::Hello(HelloResponse {
+        version: PROTOCOL_VERSION,
+        min_supported: MIN_SUPPORTED_PROTOCOL_VERSION,
+    })
+}
+
+/// Serializes the config live at call time, so a concurrent reload yields
+/// either the old or the new config, never a mix.
+fn build_config(state: &State) -> Result<ConfigResponse> {
+    let mut cfg = AppConfig::clone(&state.router.config());
+    cfg.redact_secrets();
+    Ok(ConfigResponse {
+        toml: cfg.to_toml()?,
+    })
+}
+
+fn validate_config(state: &State) -> ValidateResponse {
+    match state.router.load_config() {
+        Ok(_) => ValidateResponse {
+            ok: true,
+            errors: Vec::new(),
+        },
+        Err(err) => ValidateResponse {
+            ok: false,
+            errors: vec![format!("{err:#}")],
+        },
+    }
+}
+
+fn build_rules(state: &State) -> RulesResponse {
+    let cfg = state.router.config();
+    RulesResponse {
+        app: rule_table(&cfg.rules.app, AppPattern::as_str),
+        domain: rule_table(&cfg.rules.domain, DomainPattern::as_str),
+        cidr: rule_table(&cfg.rules.cidr, CidrPattern::as_str),
+        port: rule_table(&cfg.rules.port, PortPattern::as_str),
+    }
+}
+
+fn rule_table<T>(
+    rules: &BTreeMap<EgressId, Vec<T>>,
+    as_str: fn(&T) -> &str,
+) -> BTreeMap<String, Vec<String>> {
+    rules
         .iter()
-        .map(|(id, spec)| policy_router_rs::ipc::EgressInfo {
-            id: id.to_string(),
-            kind: spec.kind.to_string(),
-            endpoint: spec.endpoint.clone(),
+        .map(|(id, patterns)| {
+            let patterns = patterns.iter().map(|p| as_str(p).to_owned()).collect();
+            (id.to_string(), patterns)
         })
-        .collect::<Vec<_>>();
+        .collect()
+}
+
+fn build_priority(state: &State) -> PriorityResponse {
+    let cfg = state.router.config();
+    let order = engine::rule_priority(&cfg);
+    let ids = |ids: Vec<&EgressId>| ids.into_iter().map(ToString::to_string).collect();
+    PriorityResponse {
+        block_app: ids(order.block_app),
+        block_domain: ids(order.block_domain),
+        app: ids(order.app),
+        domain: ids(order.domain),
+    }
+}
+
+/// Lists the rules routing to `egress`; an alias stands for its target.
+fn build_egress_refs(state: &State, egress: &str) -> Response {
+    let cfg = state.router.config();
+    let id = EgressId(egress.to_owned());
+    let id = cfg.aliases.get(&id).cloned().unwrap_or(id);
+    if !cfg.egress.contains_key(&id) && !cfg.egress_group.contains_key(&id) {
+        return Response::Err(ErrorResponse {
+            code: ErrorCode::UnknownEgress,
+            message: format!("unknown egress id '{egress}'"),
+        });
+    }
 
-    StatusResponse {
-        uptime_ms: u64::try_from(state.started_at.elapsed().as_millis()).unwrap_or(u64::MAX),
-        config_path: state.config_path.display().to_string(),
-        egress,
+    Response::OkEgressRefs(EgressRefsResponse {
+        default: cfg.defaults.egress == id,
+        app: rule_patterns(&cfg.rules.app, &id, AppPattern::as_str),
+        domain: rule_patterns(&cfg.rules.domain, &id, DomainPattern::as_str),
+        cidr: rule_patterns(&cfg.rules.cidr, &id, CidrPattern::as_str),
+        port: rule_patterns(&cfg.rules.port, &id, PortPattern::as_str),
+        egress: id.to_string(),
+    })
+}
+
+fn rule_patterns<T>(
+    rules: &BTreeMap<EgressId, Vec<T>>,
+    id: &EgressId,
+    as_str: fn(&T) -> &str,
+) -> Vec<String> {
+    rules
+        .get(id)
+        .into_iter()
+        .flatten()
+        .map(|p| as_str(p).to_owned())
+        .collect()
+}
+
+fn build_config_files(state: &State) -> ConfigFilesResponse {
+    let cfg = state.router.config();
+    ConfigFilesResponse {
+        files: cfg
+            .source_files
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect(),
+    }
+}
+
+fn render_metrics(state: &State) -> String {
+    use std::fmt::Write;
+
+    let counters = [
+        ("ipc_requests", "IPC requests parsed.", &state.ipc_requests),
+        ("reload_ok", "Successful config reloads.", &state.reload_ok),
+        ("reload_err", "Failed config reloads.", &state.reload_err),
+        ("accept_errors", "Failed IPC accepts.", &state.accept_errors),
+        (
+            "refused_connections",
+            "IPC connections refused while every worker was busy.",
+            &state.refused_connections,
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, help, value) in counters {
+        let _ = writeln!(out, "# HELP policy_router_{name}_total {help}");
+        let _ = writeln!(out, "# TYPE policy_router_{name}_total counter");
+        let _ = writeln!(
+            out,
+            "policy_router_{name}_total {}",
+            value.load(Ordering::Relaxed)
+        );
+    }
+
+    out.push_str("# HELP policy_router_decisions_total Explain decisions by source.\n");
+    out.push_str("# TYPE policy_router_decisions_total counter\n");
+    for (source, value) in DecisionSource::ALL.iter().zip(&state.decisions) {
+        let _ = writeln!(
+            out,
+            "policy_router_decisions_total{{source=\"{}\"}} {}",
+            source.as_str(),
+            value.load(Ordering::Relaxed)
+        );
     }
+
+    out
 }
 
 fn build_diagnostics(state: &State) -> DiagnosticsResponse {
-    let uptime_ms = u64::try_from(state.started_at.elapsed().as_millis()).unwrap_or(u64::MAX);
+    let uptime_ms = u64::try_from(state.router.uptime().as_millis()).unwrap_or(u64::MAX);
 
-    let cfg = state.cfg.load();
+    let cfg = state.router.config();
 
     DiagnosticsResponse {
         uptime_ms,
-        config_path: state.config_path.display().to_string(),
+        config_path: state.router.config_source(),
         socket: state.socket.clone(),
         egress_count: cfg.egress.len(),
         running: state.running.load(Ordering::SeqCst),
         ipc_requests: state.ipc_requests.load(std::sync::atomic::Ordering::SeqCst),
         reload_ok: state.reload_ok.load(std::sync::atomic::Ordering::SeqCst),
         reload_err: state.reload_err.load(std::sync::atomic::Ordering::SeqCst),
+        config_generation: cfg.generation(),
+        dropped_rules: cfg.dropped_rules,
+        lints: cfg.lints(),
     }
 }
 
 fn reload_config(state: &State) -> Result<()> {
-    let next = match AppConfig::load_from_path(&state.config_path)
-        .with_context(|| format!("failed to load config {}", state.config_path.display()))
-    {
-        Ok(cfg) => cfg,
+    count_reload(state, state.router.reload())
+}
+
+fn count_reload(state: &State, result: Result<()>) -> Result<()> {
+    let counter = if result.is_ok() {
+        &state.reload_ok
+    } else {
+        &state.reload_err
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    result
+}
+
+/// Makes config text sent over IPC live, if `--allow-ipc-reload` was given.
+fn reload_from(state: &State, raw: &str) -> Response {
+    if !state.allow_ipc_reload {
+        return Response::Err(ErrorResponse {
+            code: ErrorCode::Unauthorized,
+            message: "reloading config over ipc is disabled; start policy-routerd with \
+                      --allow-ipc-reload"
+                .to_string(),
+        });
+    }
+
+    match count_reload(state, state.router.reload_from_str(raw)) {
+        Ok(()) => {
+            info!("reloaded config from ipc");
+            Response::OkReload
+        }
         Err(err) => {
-            state.reload_err.fetch_add(1, Ordering::Relaxed);
-            return Err(err);
+            warn!(error = %format!("{err:#}"), "reload from ipc failed");
+            Response::Err(ErrorResponse {
+                code: config_error_code(&err),
+                message: format!("reload from ipc failed: {err:#}"),
+            })
         }
-    };
+    }
+}
 
-    state.cfg.store(Arc::new(next));
-    state.reload_ok.fetch_add(1, Ordering::Relaxed);
-    Ok(())
+/// Loads the on-disk config and reports how it differs from the live one,
+/// leaving the live config in place.
+fn reload_diff(state: &State) -> Result<ReloadDiffResponse> {
+    let next = state.router.load_config()?;
+    let diff = ConfigDiff::between(&state.router.config(), &next);
+
+    let rule_change = |change: RuleChange| RuleChangeInfo {
+        kind: change.kind.as_str().to_string(),
+        egress: change.egress.to_string(),
+        pattern: change.pattern,
+    };
+    Ok(ReloadDiffResponse {
+        added_egress: diff.added_egress.iter().map(ToString::to_string).collect(),
+        removed_egress: diff
+            .removed_egress
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
+        changed_endpoints: diff
+            .changed_endpoints
+            .into_iter()
+            .map(|change| EndpointChangeInfo {
+                egress: change.egress.to_string(),
+                old: change.old,
+                new: change.new,
+            })
+            .collect(),
+        added_rules: diff.added_rules.into_iter().map(rule_change).collect(),
+        removed_rules: diff.removed_rules.into_iter().map(rule_change).collect(),
+    })
 }
 
-fn handle_explain(state: &State, req: &policy_router_rs::ipc::ExplainRequest) -> Response {
-    let decision = explain(state, req.process.as_deref(), req.domain.as_deref());
-    Response::OkExplain(decision)
+fn handle_explain(
+    state: &State,
+    req: &policy_router_rs::ipc::ExplainRequest,
+    client: Option<&ClientInfo>,
+) -> Response {
+    // Without an explicit process, explain the caller itself.
+    let process = req
+        .process
+        .as_deref()
+        .or_else(|| client.map(|c| c.exe.as_str()));
+    let input = DecideInput {
+        process_name: process,
+        cmdline: req.cmdline.as_deref(),
+        domain: req.domain.as_deref(),
+        port: req.port,
+        now: Some(chrono::Local::now().naive_local()),
+    };
+    let mut decision = explain(state, &input, req.verbose);
+    if let Some(audit) = &state.audit {
+        audit.record(&AuditRecord {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            client,
+            process,
+            domain: req.domain.as_deref(),
+            egress: &decision.decision.egress,
+            reason_code: &decision.decision.reason_code,
+        });
+    }
+    decision.client = client.cloned();
+    Response::OkExplain(Box::new(decision))
 }
 
 fn explain(
     state: &State,
-    process: Option<&str>,
-    domain: Option<&str>,
+    input: &DecideInput<'_>,
+    verbose: bool,
 ) -> policy_router_rs::ipc::ExplainResponse {
-    let decision = {
-        let cfg = state.cfg.load();
-        engine::decide(&cfg, process, domain)
+    let DecideInput {
+        process_name: process,
+        domain,
+        ..
+    } = *input;
+    let (decision, matchers, trace, spec, description) = {
+        let cfg = state.router.config();
+        let (decision, trace) = if verbose {
+            engine::decide_traced(&cfg, input)
+        } else {
+            (state.decision_cache.decide(&cfg, input), Vec::new())
+        };
+        let matchers = match domain {
+            Some(d) if verbose => engine::domain_matches(&cfg, d)
+                .into_iter()
+                .map(|m| map_domain_match(&cfg, m))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let spec = cfg.egress.get(&decision.egress).cloned();
+        if spec.is_none() {
+            warn!(egress = %decision.egress, "decided egress is missing from the config");
+        }
+        state.rule_hits.record(cfg.generation(), &decision.reason);
+        let description = rule_key(&decision.reason)
+            .and_then(|(kind, egress, index)| match kind {
+                RuleKind::App => cfg.rules.app_description(&egress, index),
+                RuleKind::Domain => cfg.rules.domain_description(&egress, index),
+                RuleKind::Cidr | RuleKind::Port => None,
+            })
+            .map(str::to_owned);
+        (decision, matchers, trace, spec, description)
     };
 
     let source = map_source(&decision.reason);
+    state.decisions[source as usize].fetch_add(1, Ordering::Relaxed);
     let rule_egress = Some(map_rule_egress(&decision.reason));
-    let matcher = map_matcher(&decision.reason);
+    let mut matcher = map_matcher(&decision.reason);
+    let rank = decision.priority.map(|p| p.rank);
+    if let Some(m) = matcher.as_mut() {
+        m.rank = rank;
+    }
+
+    state.history.record(HistoryEntry {
+        seq: 0,
+        uptime_ms: u64::try_from(state.router.uptime().as_millis()).unwrap_or(u64::MAX),
+        process: process.map(str::to_owned),
+        domain: domain.map(str::to_owned),
+        egress: decision.egress.to_string(),
+        source,
+    });
 
     policy_router_rs::ipc::ExplainResponse {
         decision: DecisionInfo {
             egress: decision.egress.to_string(),
             reason: decision.reason.to_human(),
+            reason_code: decision.reason.reason_code().to_owned(),
+            kind: spec.as_ref().map(|spec| spec.kind.to_string()),
+            endpoint: spec.and_then(|spec| spec.endpoint),
             source,
             rule_egress,
+            group: decision.reason.group().map(ToString::to_string),
             matcher,
+            description,
+            matchers,
+            rank,
+            tie_broken: decision.priority.is_some_and(|p| p.tie_broken),
+            trace: trace.into_iter().map(map_trace_step).collect(),
         },
+        client: None,
     }
 }
 
@@ -347,7 +2030,26 @@ const fn map_source(reason: &engine::DecisionReason) -> DecisionSource {
         engine::DecisionReason::BlockByDomain { .. } => DecisionSource::BlockDomain,
         engine::DecisionReason::AppRule { .. } => DecisionSource::AppRule,
         engine::DecisionReason::DomainRule { .. } => DecisionSource::DomainRule,
+        engine::DecisionReason::CidrRule { .. } => DecisionSource::CidrRule,
+        engine::DecisionReason::PortRule { .. } => DecisionSource::PortRule,
         engine::DecisionReason::Default { .. } => DecisionSource::Default,
+        engine::DecisionReason::DefaultBlock { .. } => DecisionSource::DefaultBlock,
+    }
+}
+
+fn map_trace_step(step: engine::TraceStep) -> TraceStep {
+    TraceStep {
+        egress: step.egress.to_string(),
+        rule: match step.rule {
+            engine::TraceRule::BlockApp => TraceRule::BlockApp,
+            engine::TraceRule::BlockDomain => TraceRule::BlockDomain,
+            engine::TraceRule::Port => TraceRule::Port,
+            engine::TraceRule::Cidr => TraceRule::Cidr,
+            engine::TraceRule::Domain => TraceRule::Domain,
+            engine::TraceRule::App => TraceRule::App,
+        },
+        matched: step.matched,
+        pattern: step.pattern,
     }
 }
 
@@ -357,31 +2059,68 @@ fn map_rule_egress(reason: &engine::DecisionReason) -> String {
         | engine::DecisionReason::BlockByDomain { egress, .. }
         | engine::DecisionReason::AppRule { egress, .. }
         | engine::DecisionReason::DomainRule { egress, .. }
-        | engine::DecisionReason::Default { egress } => egress.to_string(),
+        | engine::DecisionReason::CidrRule { egress, .. }
+        | engine::DecisionReason::PortRule { egress, .. }
+        | engine::DecisionReason::Default { egress }
+        | engine::DecisionReason::DefaultBlock { egress } => egress.to_string(),
     }
 }
 
 fn map_matcher(reason: &engine::DecisionReason) -> Option<MatcherInfo> {
     match reason {
-        engine::DecisionReason::BlockByApp { pattern, .. }
-        | engine::DecisionReason::AppRule { pattern, .. } => Some(MatcherInfo {
+        engine::DecisionReason::BlockByApp { pattern, index, .. }
+        | engine::DecisionReason::AppRule { pattern, index, .. } => Some(MatcherInfo {
             kind: MatcherKind::Exact,
             pattern: pattern.clone(),
+            index: Some(*index),
+            egress: None,
+            rank: None,
         }),
         engine::DecisionReason::BlockByDomain {
             pattern,
+            index,
             match_kind,
             ..
         }
         | engine::DecisionReason::DomainRule {
             pattern,
+            index,
             match_kind,
             ..
         } => Some(MatcherInfo {
             kind: map_matcher_kind(*match_kind),
             pattern: pattern.clone(),
+            index: Some(*index),
+            egress: None,
+            rank: None,
+        }),
+        engine::DecisionReason::CidrRule { pattern, index, .. } => Some(MatcherInfo {
+            kind: MatcherKind::Cidr,
+            pattern: pattern.clone(),
+            index: Some(*index),
+            egress: None,
+            rank: None,
+        }),
+        engine::DecisionReason::PortRule { pattern, index, .. } => Some(MatcherInfo {
+            kind: MatcherKind::Port,
+            pattern: pattern.clone(),
+            index: Some(*index),
+            egress: None,
+            rank: None,
         }),
-        engine::DecisionReason::Default { .. } => None,
+        engine::DecisionReason::Default { .. } | engine::DecisionReason::DefaultBlock { .. } => {
+            None
+        }
+    }
+}
+
+fn map_domain_match(cfg: &AppConfig, m: engine::DomainMatch) -> MatcherInfo {
+    MatcherInfo {
+        kind: map_matcher_kind(m.match_kind),
+        pattern: m.pattern,
+        index: Some(m.index),
+        rank: engine::egress_rank(cfg, &m.egress),
+        egress: Some(m.egress.to_string()),
     }
 }
 
@@ -389,6 +2128,7 @@ const fn map_matcher_kind(match_kind: engine::MatchKind) -> MatcherKind {
     match match_kind {
         engine::MatchKind::Exact => MatcherKind::Exact,
         engine::MatchKind::Suffix => MatcherKind::Suffix,
+        engine::MatchKind::Wildcard => MatcherKind::Wildcard,
     }
 }
 
@@ -396,6 +2136,8 @@ const fn map_matcher_kind(match_kind: engine::MatchKind) -> MatcherKind {
 mod tests {
     use std::fs;
 
+    use policy_router_rs::{ipc::read_json_line, policy::config::REDACTED};
+
     use super::*;
 
     fn write_file(path: &PathBuf, contents: &str) {
@@ -406,8 +2148,7 @@ mod tests {
         let pid = std::process::id();
         let nanos = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_nanos())
-            .unwrap_or(0);
+            .map_or(0, |d| d.as_nanos());
 
         std::env::temp_dir().join(format!("policy-router-{tag}-{pid}-{nanos}.toml"))
     }
@@ -421,15 +2162,914 @@ mod tests {
 
     fn make_state(config_path: PathBuf, cfg: AppConfig) -> State {
         State {
-            started_at: Instant::now(),
-            config_path,
+            router: PolicyRouter::with_config(config_path, cfg),
             socket: "test.sock".to_owned(),
-            cfg: ArcSwap::from_pointee(cfg),
+            max_request_bytes: MAX_LINE_BYTES,
             running: AtomicBool::new(true),
             ipc_requests: std::sync::atomic::AtomicU64::new(0),
             reload_ok: std::sync::atomic::AtomicU64::new(0),
             reload_err: std::sync::atomic::AtomicU64::new(0),
+            accept_errors: AtomicU64::new(0),
+            refused_connections: AtomicU64::new(0),
+            decisions: Default::default(),
+            history: DecisionHistory::default(),
+            audit: None,
+            allow_ipc_reload: false,
+            decision_cache: DecisionCache::new(16),
+            rule_hits: RuleHits::default(),
+            health: Mutex::new(BTreeMap::new()),
+            workers: Mutex::new(Vec::new()),
+            max_followers: 4,
+            followers: AtomicUsize::new(0),
+        }
+    }
+
+    #[test]
+    fn hello_reports_protocol_versions() {
+        use policy_router_rs::ipc::HelloRequest;
+
+        let state = make_state(PathBuf::from("config.toml"), load_example_config());
+        let req = Request::Hello(HelloRequest {
+            version: PROTOCOL_VERSION,
+        });
+
+        match handle_request(&state, req, None) {
+            Response::Hello(x) => {
+                assert_eq!(x.version, PROTOCOL_VERSION);
+                assert_eq!(x.min_supported, MIN_SUPPORTED_PROTOCOL_VERSION);
+            }
+            other => panic!("unexpected response: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn conn_serves_requests_until_eof_and_survives_bad_lines() {
+        use std::io::Write;
+
+        use policy_router_rs::ipc::{ExplainRequest, client_stream, socket_name_with_override};
+
+        let sock = tmp_path("keepalive").with_extension("sock");
+        let sock_raw = sock.display().to_string();
+        let (name, _) = socket_name_with_override(Some(&sock_raw)).expect("socket name");
+        let listener = ListenerOptions::new()
+            .name(name.clone())
+            .create_sync()
+            .expect("create listener");
+
+        let state = Arc::new(make_state(
+            PathBuf::from("config.toml"),
+            load_example_config(),
+        ));
+        let server = thread::spawn({
+            let state = Arc::clone(&state);
+            move || {
+                let conn = listener.accept().expect("accept");
+                handle_conn(&state, &conn)
+            }
+        });
+
+        let mut conn = interprocess::local_socket::Stream::connect(name).expect("connect");
+        (&conn)
+            .write_all(b"not json\n")
+            .expect("write malformed line");
+        let resp: Response = read_json_line(BufReader::new(&conn)).expect("error response");
+        assert!(matches!(resp, Response::Err(_)), "got {resp:?}");
+
+        let explain = Request::Explain(ExplainRequest {
+            process: Some("zen.exe".to_owned()),
+            ..ExplainRequest::default()
+        });
+        let resps = client_stream(&mut conn, &[Request::Status, explain, Request::Diagnostics])
+            .expect("pipelined requests");
+        drop(conn);
+
+        assert!(matches!(resps[0], Response::OkStatus(_)));
+        assert!(matches!(resps[1], Response::OkExplain(_)));
+        assert!(matches!(resps[2], Response::OkDiagnostics(_)));
+
+        server.join().expect("server thread").expect("conn handled");
+        assert_eq!(state.ipc_requests.load(Ordering::SeqCst), 3);
+
+        let _ = std::fs::remove_file(sock);
+    }
+
+    #[test]
+    fn conn_rejects_oversized_request_and_hangs_up() {
+        use std::io::{Read, Write};
+
+        use policy_router_rs::ipc::socket_name_with_override;
+
+        let sock = tmp_path("oversized").with_extension("sock");
+        let sock_raw = sock.display().to_string();
+        let (name, _) = socket_name_with_override(Some(&sock_raw)).expect("socket name");
+        let listener = ListenerOptions::new()
+            .name(name.clone())
+            .create_sync()
+            .expect("create listener");
+
+        let mut state = make_state(PathBuf::from("config.toml"), load_example_config());
+        state.max_request_bytes = 64;
+        let state = Arc::new(state);
+        let server = thread::spawn({
+            let state = Arc::clone(&state);
+            move || {
+                let conn = listener.accept().expect("accept");
+                handle_conn(&state, &conn)
+            }
+        });
+
+        let conn = interprocess::local_socket::Stream::connect(name).expect("connect");
+        let line = format!("{{\"type\":\"status\",\"pad\":\"{}\"}}\n", "x".repeat(256));
+        (&conn)
+            .write_all(line.as_bytes())
+            .expect("write oversized line");
+        let mut reader = BufReader::new(&conn);
+        let resp: Response = read_json_line(&mut reader).expect("error response");
+        let Response::Err(err) = resp else {
+            panic!("oversized request must be refused, got {resp:?}");
+        };
+        assert_eq!(err.code, ErrorCode::InvalidRequest);
+        assert!(
+            err.message
+                .contains("request too large: line exceeds 64 bytes"),
+            "{}",
+            err.message
+        );
+
+        server.join().expect("server thread").expect("conn handled");
+        let mut trailing = Vec::new();
+        reader
+            .read_to_end(&mut trailing)
+            .expect("read after hang up");
+        assert_eq!(trailing.len(), 0);
+        assert_eq!(state.ipc_requests.load(Ordering::SeqCst), 0);
+
+        let _ = std::fs::remove_file(sock);
+    }
+
+    #[test]
+    fn pid_file_replaces_stale_pid_and_is_removed_on_drop() {
+        let path = tmp_path("pid").with_extension("pid");
+        write_file(&path, "4294967\n");
+
+        let pid_file = PidFile::create(&path).expect("stale pid file is replaced");
+        let raw = fs::read_to_string(&path).expect("pid file written");
+        assert_eq!(raw.trim(), std::process::id().to_string());
+
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pid_file_naming_live_process_refuses_start() {
+        let path = tmp_path("pid-live").with_extension("pid");
+        write_file(&path, "1\n");
+
+        let err = PidFile::create(&path).expect_err("live pid must be refused");
+        assert!(err.to_string().contains("already running"), "{err:#}");
+        assert_eq!(fs::read_to_string(&path).expect("pid file kept"), "1\n");
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn drain_joins_finished_workers_and_reports_stuck_ones() {
+        let state = make_state(PathBuf::from("config.toml"), load_example_config());
+        let (release, stuck) = mpsc::channel::<()>();
+
+        track_worker(
+            &state,
+            thread::spawn(|| thread::sleep(Duration::from_millis(20))),
+        );
+        track_worker(
+            &state,
+            thread::spawn(move || {
+                let _ = stuck.recv();
+            }),
+        );
+
+        let (drained, abandoned) = drain_workers(&state, Duration::from_millis(300));
+        assert_eq!((drained, abandoned), (1, 1));
+        drop(release);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn silent_client_is_released_after_timeout() {
+        use std::io::Read;
+
+        use policy_router_rs::ipc::socket_name_with_override;
+
+        let sock = tmp_path("silent").with_extension("sock");
+        let sock_raw = sock.display().to_string();
+        let (name, _) = socket_name_with_override(Some(&sock_raw)).expect("socket name");
+        let listener = ListenerOptions::new()
+            .name(name.clone())
+            .create_sync()
+            .expect("create listener");
+
+        let state = Arc::new(make_state(
+            PathBuf::from("config.toml"),
+            load_example_config(),
+        ));
+        let server = thread::spawn({
+            let state = Arc::clone(&state);
+            move || {
+                let conn = listener.accept().expect("accept");
+                set_stream_timeout(&conn, Some(Duration::from_millis(100))).expect("timeout");
+                handle_conn(&state, &conn)
+            }
+        });
+
+        let conn = interprocess::local_socket::Stream::connect(name).expect("connect");
+        let started = Instant::now();
+        let err = server
+            .join()
+            .expect("server thread")
+            .expect_err("silent client must time out");
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "took {:?}",
+            started.elapsed()
+        );
+        assert!(
+            err.downcast_ref::<io::Error>().is_some_and(|e| matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            )),
+            "{err:#}"
+        );
+
+        // The server dropped its end, so the client sees EOF.
+        let mut buf = Vec::new();
+        let read = (&conn).read_to_end(&mut buf).expect("read until eof");
+        assert_eq!(read, 0);
+        assert_eq!(state.ipc_requests.load(Ordering::SeqCst), 0);
+
+        let _ = std::fs::remove_file(sock);
+    }
+
+    #[test]
+    fn tcp_listener_serves_requests_without_peer_user() {
+        let listener = bind_tcp("127.0.0.1:0".parse().expect("addr")).expect("bind tcp");
+        let addr = listener.local_addr().expect("local addr");
+
+        let mut cfg = load_example_config();
+        cfg.security.allow_control = Some(vec!["root".to_string()]);
+        let state = Arc::new(make_state(PathBuf::from("config.toml"), cfg));
+        let serving = thread::spawn({
+            let state = Arc::clone(&state);
+            move || {
+                serve_tcp(
+                    &state,
+                    &listener,
+                    1,
+                    1,
+                    Duration::from_secs(5),
+                    Duration::from_millis(20),
+                );
+            }
+        });
+
+        let conn = TcpStream::connect(addr).expect("connect");
+        let mut reader = BufReader::new(&conn);
+        write_json_line(&conn, &Request::Status).expect("send status");
+        let resp: Response = read_json_line(&mut reader).expect("status response");
+        assert!(matches!(resp, Response::OkStatus(_)), "{resp:?}");
+
+        // TCP clients carry no credentials, so guarded requests are refused.
+        write_json_line(&conn, &Request::Stop).expect("send stop");
+        let resp: Response = read_json_line(&mut reader).expect("stop response");
+        let Response::Err(err) = resp else {
+            panic!("unexpected response: {resp:?}");
+        };
+        assert_eq!(err.code, ErrorCode::Unauthorized);
+        drop(reader);
+        drop(conn);
+
+        state.request_stop();
+        serving.join().expect("tcp thread");
+        assert_eq!(state.ipc_requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn quiet_flag_overrides_log_level() {
+        let cli = Cli::parse_from(["policy-routerd", "--log-level", "debug"]);
+        assert_eq!(default_filter(&cli), "debug");
+        let cli = Cli::parse_from(["policy-routerd", "--log-level", "debug", "--quiet"]);
+        assert_eq!(default_filter(&cli), "warn");
+    }
+
+    #[test]
+    fn accept_backoff_speeds_up_after_connection_and_slows_when_idle() {
+        let mut backoff = AcceptBackoff::new(Duration::from_millis(20));
+        assert_eq!(backoff.idle(), Duration::from_millis(20));
+
+        backoff.reset();
+        let pauses: Vec<_> = (0..7).map(|_| backoff.idle().as_millis()).collect();
+        assert_eq!(pauses, [1, 2, 4, 8, 16, 20, 20]);
+
+        let mut backoff = AcceptBackoff::new(Duration::from_millis(1));
+        backoff.reset();
+        assert_eq!(backoff.idle(), Duration::from_millis(1));
+        assert_eq!(backoff.idle(), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn precheck_strict_requires_precheck_egress() {
+        assert!(Cli::try_parse_from(["policy-routerd", "--precheck-strict"]).is_err());
+        let cli = Cli::parse_from(["policy-routerd", "--precheck-egress", "--precheck-strict"]);
+        assert!(cli.precheck.enabled && cli.precheck.strict);
+    }
+
+    #[test]
+    fn tcp_flag_defaults_to_loopback() {
+        assert_eq!(
+            parse_tcp_addr("7000"),
+            Ok("127.0.0.1:7000".parse().expect("addr"))
+        );
+        assert_eq!(
+            parse_tcp_addr("0.0.0.0:7000"),
+            Ok("0.0.0.0:7000".parse().expect("addr"))
+        );
+        assert!(parse_tcp_addr("localhost").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn busy_workers_queue_then_refuse_connections() {
+        use policy_router_rs::ipc::{
+            HelloRequest, client_hello, client_roundtrip, socket_name_with_override,
+        };
+
+        let sock = tmp_path("busy").with_extension("sock");
+        let sock_raw = sock.display().to_string();
+        let (name, _) = socket_name_with_override(Some(&sock_raw)).expect("socket name");
+        let listener = ListenerOptions::new()
+            .name(name.clone())
+            .create_sync()
+            .expect("create listener");
+
+        let state = Arc::new(make_state(
+            PathBuf::from("config.toml"),
+            load_example_config(),
+        ));
+        let queue = spawn_workers(&state, 1, 1, Duration::from_secs(5));
+        let hello = Request::Hello(HelloRequest {
+            version: PROTOCOL_VERSION,
+        });
+
+        // The only worker picks up the first connection and keeps serving it.
+        let mut first = interprocess::local_socket::Stream::connect(name.clone()).expect("connect");
+        queue
+            .try_send(listener.accept().expect("accept"))
+            .expect("queue first");
+        client_roundtrip(&mut first, &hello).expect("first served");
+
+        // The second waits in the queue; the third finds it full.
+        let mut second =
+            interprocess::local_socket::Stream::connect(name.clone()).expect("connect");
+        queue
+            .try_send(listener.accept().expect("accept"))
+            .expect("queue second");
+        let mut third = interprocess::local_socket::Stream::connect(name).expect("connect");
+        let Err(mpsc::TrySendError::Full(conn)) =
+            queue.try_send(listener.accept().expect("accept"))
+        else {
+            panic!("queue must be full");
+        };
+        refuse_connection(&state, &conn);
+        let err = client_hello(&mut third).expect_err("busy daemon must refuse");
+        assert!(err.to_string().contains("busy"), "{err:#}");
+
+        // Once the first client leaves, the queued one is served.
+        drop(first);
+        client_roundtrip(&mut second, &hello).expect("second served");
+        drop(second);
+
+        drop(queue);
+        assert_eq!(drain_workers(&state, Duration::from_secs(5)), (1, 0));
+        assert_eq!(state.refused_connections.load(Ordering::SeqCst), 1);
+
+        let _ = std::fs::remove_file(sock);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn followers_leave_the_worker_pool_free() {
+        use policy_router_rs::ipc::{
+            HelloRequest, HistoryRequest, client_roundtrip, read_json_line,
+            socket_name_with_override,
+        };
+
+        let sock = tmp_path("follow").with_extension("sock");
+        let sock_raw = sock.display().to_string();
+        let (name, _) = socket_name_with_override(Some(&sock_raw)).expect("socket name");
+        let listener = ListenerOptions::new()
+            .name(name.clone())
+            .create_sync()
+            .expect("create listener");
+
+        let state = Arc::new(make_state(
+            PathBuf::from("config.toml"),
+            load_example_config(),
+        ));
+        let queue = spawn_workers(&state, 1, 1, Duration::from_secs(5));
+
+        // The follower moves to its own thread once it asked to follow.
+        let mut follower =
+            interprocess::local_socket::Stream::connect(name.clone()).expect("connect");
+        queue
+            .try_send(listener.accept().expect("accept"))
+            .expect("queue follower");
+        let follow = Request::History(HistoryRequest { follow: true });
+        let snapshot = client_roundtrip(&mut follower, &follow).expect("snapshot");
+        assert!(matches!(snapshot, Response::OkHistory(_)));
+
+        // The only worker still serves other clients.
+        let mut other = interprocess::local_socket::Stream::connect(name).expect("connect");
+        queue
+            .try_send(listener.accept().expect("accept"))
+            .expect("queue other");
+        let hello = Request::Hello(HelloRequest {
+            version: PROTOCOL_VERSION,
+        });
+        client_roundtrip(&mut other, &hello).expect("other served");
+        drop(other);
+
+        explain(
+            &state,
+            &DecideInput {
+                process_name: Some("zen.exe"),
+                cmdline: None,
+                domain: None,
+                port: None,
+                now: None,
+            },
+            false,
+        );
+        let event: Response = read_json_line(&mut BufReader::new(&follower)).expect("event");
+        assert!(matches!(event, Response::Event(_)));
+        assert_eq!(state.followers.load(Ordering::SeqCst), 1);
+
+        // A follower that leaves is noticed without another decision.
+        drop(follower);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while state.followers.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(state.followers.load(Ordering::SeqCst), 0);
+
+        drop(queue);
+        assert_eq!(drain_workers(&state, Duration::from_secs(5)), (2, 0));
+
+        let _ = std::fs::remove_file(sock);
+    }
+
+    #[cfg(all(feature = "tokio", target_os = "linux"))]
+    #[test]
+    fn async_runtime_serves_requests_and_stops() {
+        use policy_router_rs::ipc::{
+            ExplainRequest, client_hello, client_roundtrip, socket_name_with_override,
+        };
+
+        let sock = tmp_path("async").with_extension("sock");
+        let sock_raw = sock.display().to_string();
+        let (name, _) = socket_name_with_override(Some(&sock_raw)).expect("socket name");
+        let server = async_ipc::Server::bind(name.clone(), 2).expect("bind async server");
+
+        let state = Arc::new(make_state(
+            PathBuf::from("config.toml"),
+            load_example_config(),
+        ));
+        let serving = thread::spawn({
+            let state = Arc::clone(&state);
+            move || server.serve(&state, Duration::from_secs(5))
+        });
+
+        let mut conn = interprocess::local_socket::Stream::connect(name).expect("connect");
+        let hello = client_hello(&mut conn).expect("hello");
+        assert_eq!(hello.version, PROTOCOL_VERSION);
+        let resp = client_roundtrip(&mut conn, &Request::Explain(ExplainRequest::default()))
+            .expect("explain");
+        let Response::OkExplain(x) = resp else {
+            panic!("unexpected response: {resp:?}");
+        };
+        assert_eq!(
+            x.client.expect("client must be resolved").pid,
+            std::process::id()
+        );
+
+        let resp = client_roundtrip(&mut conn, &Request::Status).expect("status");
+        assert!(matches!(resp, Response::OkStatus(_)), "{resp:?}");
+        drop(conn);
+
+        state.request_stop();
+        serving.join().expect("server thread");
+        assert_eq!(state.ipc_requests.load(Ordering::SeqCst), 3);
+
+        let _ = std::fs::remove_file(sock);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn explain_without_process_uses_ipc_client() {
+        use policy_router_rs::ipc::{ExplainRequest, client_roundtrip, socket_name_with_override};
+
+        let sock = tmp_path("client").with_extension("sock");
+        let sock_raw = sock.display().to_string();
+        let (name, _) = socket_name_with_override(Some(&sock_raw)).expect("socket name");
+        let listener = ListenerOptions::new()
+            .name(name.clone())
+            .create_sync()
+            .expect("create listener");
+
+        let state = Arc::new(make_state(
+            PathBuf::from("config.toml"),
+            load_example_config(),
+        ));
+        let server = thread::spawn({
+            let state = Arc::clone(&state);
+            move || {
+                let conn = listener.accept().expect("accept");
+                handle_conn(&state, &conn)
+            }
+        });
+
+        let mut conn = interprocess::local_socket::Stream::connect(name).expect("connect");
+        let resp = client_roundtrip(&mut conn, &Request::Explain(ExplainRequest::default()))
+            .expect("explain");
+        drop(conn);
+        server.join().expect("server thread").expect("conn handled");
+
+        let Response::OkExplain(x) = resp else {
+            panic!("unexpected response: {resp:?}");
+        };
+        let client = x.client.expect("client must be resolved");
+        assert_eq!(client.pid, std::process::id());
+        assert_eq!(
+            state.history.snapshot()[0].process.as_deref(),
+            Some(client.exe.as_str())
+        );
+
+        let _ = std::fs::remove_file(sock);
+    }
+
+    #[test]
+    fn security_guards_control_requests_by_user() {
+        let alice = PeerUser {
+            id: "1000".to_string(),
+            name: Some("alice".to_string()),
+        };
+        let bob = PeerUser {
+            id: "1001".to_string(),
+            name: Some("bob".to_string()),
+        };
+        let refused = |security: &Security, req: &Request, user: Option<&PeerUser>| {
+            refuse_unauthorized(security, req, user).map(|resp| match resp {
+                Response::Err(e) => e.code,
+                other => panic!("expected an error, got {other:?}"),
+            })
+        };
+
+        let open = Security::default();
+        assert_eq!(refused(&open, &Request::Stop, None), None);
+
+        for entry in ["1000", "alice"] {
+            let security = Security {
+                allow_control: Some(vec![entry.to_string()]),
+                restrict: Restrict::Control,
+            };
+            assert_eq!(refused(&security, &Request::Stop, Some(&alice)), None);
+            assert_eq!(refused(&security, &Request::Reload, Some(&alice)), None);
+            assert_eq!(
+                refused(&security, &Request::Stop, Some(&bob)),
+                Some(ErrorCode::Unauthorized)
+            );
+            assert_eq!(
+                refused(&security, &Request::Reload, None),
+                Some(ErrorCode::Unauthorized)
+            );
+            assert_eq!(refused(&security, &Request::Status, Some(&bob)), None);
+            assert_eq!(refused(&security, &Request::Diagnostics, None), None);
         }
+
+        let strict = Security {
+            allow_control: Some(vec!["alice".to_string()]),
+            restrict: Restrict::All,
+        };
+        assert_eq!(refused(&strict, &Request::Status, Some(&alice)), None);
+        assert_eq!(
+            refused(&strict, &Request::Status, Some(&bob)),
+            Some(ErrorCode::Unauthorized)
+        );
+        let hello = Request::Hello(HelloRequest {
+            version: PROTOCOL_VERSION,
+        });
+        assert_eq!(refused(&strict, &hello, None), None);
+        assert_eq!(refused(&strict, &Request::Ping, None), None);
+    }
+
+    #[test]
+    fn whois_rejects_malformed_address() {
+        let Response::Err(err) = whois("not-an-address") else {
+            panic!("malformed address must be rejected");
+        };
+        assert_eq!(err.code, ErrorCode::InvalidRequest);
+        assert!(err.message.contains("not-an-address"), "{}", err.message);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn whois_finds_owner_of_local_socket() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind listener");
+        let addr = listener.local_addr().expect("listener addr");
+
+        let Response::OkWhoIs(WhoIsResponse {
+            process: Some(process),
+        }) = whois(&addr.to_string())
+        else {
+            panic!("listener owner must resolve");
+        };
+        assert_eq!(process.pid, std::process::id());
+    }
+
+    #[test]
+    fn audit_log_appends_one_line_per_explain() {
+        use policy_router_rs::ipc::ExplainRequest;
+
+        let path = tmp_path("audit").with_extension("jsonl");
+        let mut state = make_state(PathBuf::from("config.toml"), load_example_config());
+        state.audit = AuditLog::open(&path);
+        assert!(state.audit.is_some());
+
+        let client = ClientInfo {
+            pid: 4242,
+            exe: "C:/Apps/zen.exe".to_owned(),
+        };
+        let req = ExplainRequest {
+            domain: Some("example.com".to_owned()),
+            ..ExplainRequest::default()
+        };
+        let Response::OkExplain(first) = handle_explain(&state, &req, Some(&client)) else {
+            panic!("explain must succeed");
+        };
+        let req = ExplainRequest {
+            process: Some("chrome.exe".to_owned()),
+            ..ExplainRequest::default()
+        };
+        let _ = handle_explain(&state, &req, None);
+
+        let raw = fs::read_to_string(&path).expect("read audit log");
+        let _ = fs::remove_file(&path);
+        let lines: Vec<serde_json::Value> = raw
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("audit line is json"))
+            .collect();
+        assert_eq!(lines.len(), 2, "{raw}");
+
+        assert_eq!(lines[0]["client"]["pid"], 4242);
+        assert_eq!(lines[0]["process"], "C:/Apps/zen.exe");
+        assert_eq!(lines[0]["domain"], "example.com");
+        assert_eq!(lines[0]["egress"], first.decision.egress.as_str());
+        assert_eq!(lines[0]["reason_code"], first.decision.reason_code.as_str());
+        assert!(
+            lines[0]["timestamp"]
+                .as_str()
+                .is_some_and(|t| t.ends_with('Z')),
+            "{raw}"
+        );
+        assert!(lines[1]["client"].is_null());
+        assert_eq!(lines[1]["process"], "chrome.exe");
+        assert!(lines[1]["domain"].is_null());
+    }
+
+    #[test]
+    fn audit_log_that_cannot_be_opened_is_skipped() {
+        let path = tmp_path("audit-missing").join("audit.jsonl");
+        assert!(AuditLog::open(&path).is_none());
+    }
+
+    #[test]
+    fn ping_answers_with_uptime_and_counts_request() {
+        let state = make_state(PathBuf::from("config.toml"), load_example_config());
+        thread::sleep(Duration::from_millis(5));
+
+        let resp = dispatch(&state, Ok(Request::Ping), &Peer::default());
+        let Some(Response::OkPong { server_time_ms }) = resp else {
+            panic!("unexpected response: {resp:?}");
+        };
+        assert!(server_time_ms >= 5, "{server_time_ms}");
+        assert_eq!(state.ipc_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn stop_is_refused_to_users_outside_allow_control() {
+        use policy_router_rs::ipc::{client_roundtrip, socket_name_with_override};
+
+        let serve = |allow_control: Vec<String>| {
+            let sock = tmp_path("security").with_extension("sock");
+            let sock_raw = sock.display().to_string();
+            let (name, _) = socket_name_with_override(Some(&sock_raw)).expect("socket name");
+            let listener = ListenerOptions::new()
+                .name(name.clone())
+                .create_sync()
+                .expect("create listener");
+
+            let mut cfg = load_example_config();
+            cfg.security.allow_control = Some(allow_control);
+            let state = Arc::new(make_state(PathBuf::from("config.toml"), cfg));
+            let server = thread::spawn({
+                let state = Arc::clone(&state);
+                move || {
+                    let conn = listener.accept().expect("accept");
+                    handle_conn(&state, &conn)
+                }
+            });
+
+            let mut conn = interprocess::local_socket::Stream::connect(name).expect("connect");
+            let status = client_roundtrip(&mut conn, &Request::Status).expect("status");
+            let stop = client_roundtrip(&mut conn, &Request::Stop).expect("stop");
+            drop(conn);
+            server.join().expect("server thread").expect("conn handled");
+            let _ = std::fs::remove_file(sock);
+
+            assert!(matches!(status, Response::OkStatus(_)), "{status:?}");
+            (stop, state.running.load(Ordering::SeqCst))
+        };
+
+        let (stop, running) = serve(vec!["nobody-we-know".to_string()]);
+        let Response::Err(e) = stop else {
+            panic!("unexpected response: {stop:?}");
+        };
+        assert_eq!(e.code, ErrorCode::Unauthorized);
+        assert!(running);
+
+        let uid = unsafe { libc::getuid() };
+        let (stop, running) = serve(vec![uid.to_string()]);
+        assert!(matches!(stop, Response::OkStop), "{stop:?}");
+        assert!(!running);
+    }
+
+    #[test]
+    fn metrics_count_decisions_by_source() {
+        let state = make_state(PathBuf::from("config.toml"), load_example_config());
+        explain(
+            &state,
+            &DecideInput {
+                process_name: Some("zen.exe"),
+                cmdline: None,
+                domain: Some("youtube.com"),
+                port: None,
+                now: None,
+            },
+            false,
+        );
+        explain(
+            &state,
+            &DecideInput {
+                process_name: None,
+                cmdline: None,
+                domain: None,
+                port: None,
+                now: None,
+            },
+            false,
+        );
+        explain(
+            &state,
+            &DecideInput {
+                process_name: None,
+                cmdline: None,
+                domain: None,
+                port: None,
+                now: None,
+            },
+            false,
+        );
+        state.accept_errors.fetch_add(1, Ordering::Relaxed);
+
+        let text = render_metrics(&state);
+        assert!(text.contains("# TYPE policy_router_decisions_total counter\n"));
+        assert!(text.contains("policy_router_decisions_total{source=\"domain_rule\"} 1\n"));
+        assert!(text.contains("policy_router_decisions_total{source=\"default\"} 2\n"));
+        assert!(text.contains("policy_router_decisions_total{source=\"cidr_rule\"} 0\n"));
+        assert!(text.contains("policy_router_accept_errors_total 1\n"));
+    }
+
+    #[test]
+    fn get_config_returns_live_config_with_passwords_redacted() {
+        let mut cfg = load_example_config();
+        let proxy = cfg
+            .egress
+            .get_mut(&EgressId("proxy".to_owned()))
+            .expect("example config must declare proxy");
+        proxy.username = Some("alice".to_owned());
+        proxy.password = Some("s3cret".to_owned());
+        let state = make_state(PathBuf::from("config.toml"), cfg.clone());
+
+        let toml = build_config(&state).expect("config must serialize").toml;
+        assert!(!toml.contains("s3cret"));
+        let dumped = toml::from_str::<AppConfig>(&toml).expect("dump must parse");
+        let proxy = &dumped.egress[&EgressId("proxy".to_owned())];
+        assert_eq!(proxy.username.as_deref(), Some("alice"));
+        assert_eq!(proxy.password.as_deref(), Some(REDACTED));
+        assert_eq!(dumped.rules.domain, cfg.rules.domain);
+    }
+
+    #[test]
+    fn priority_lists_rule_egresses_in_evaluation_order() {
+        let state = make_state(PathBuf::from("config.toml"), load_example_config());
+
+        let order = build_priority(&state);
+        assert_eq!(order.block_app, ["block"]);
+        assert_eq!(order.block_domain, ["block"]);
+        assert_eq!(order.app, ["vpn", "proxy", "direct"]);
+        assert_eq!(order.domain[0], "vpn");
+    }
+
+    #[test]
+    fn egress_refs_lists_rules_routing_to_egress() {
+        let mut cfg = load_example_config();
+        cfg.aliases
+            .insert(EgressId("tunnel".to_owned()), EgressId("vpn".to_owned()));
+        let state = make_state(PathBuf::from("config.toml"), cfg);
+
+        let Response::OkEgressRefs(refs) = build_egress_refs(&state, "tunnel") else {
+            panic!("alias must resolve to its target");
+        };
+        assert_eq!(refs.egress, "vpn");
+        assert!(!refs.default);
+        assert_eq!(refs.app, ["zen.exe", "Telegram.exe", "Element.exe"]);
+        assert_eq!(refs.domain, ["chatgpt.com", "discord.com", "github.com"]);
+        assert_eq!(refs.cidr.len(), 0);
+
+        let Response::OkEgressRefs(refs) = build_egress_refs(&state, "direct") else {
+            panic!("declared egress must resolve");
+        };
+        assert!(refs.default);
+        assert_eq!(refs.app, ["ciadpi.exe"]);
+
+        let Response::Err(err) = build_egress_refs(&state, "missing") else {
+            panic!("unknown egress must be rejected");
+        };
+        assert_eq!(err.code, ErrorCode::UnknownEgress);
+    }
+
+    #[test]
+    fn config_files_lists_loaded_entrypoint() {
+        let path = tmp_path("config-files");
+        write_file(&path, include_str!("../../config/config.example.toml"));
+        let cfg = AppConfig::load_from_path(&path).expect("must load config");
+        let state = make_state(path.clone(), cfg);
+
+        let files = build_config_files(&state).files;
+        assert_eq!(files, vec![path.display().to_string()]);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn validate_config_reports_errors_without_swapping() {
+        let path = tmp_path("validate");
+        write_file(&path, include_str!("../../config/config.example.toml"));
+        let original_cfg = AppConfig::load_from_path(&path).expect("must load config");
+        let state = make_state(path.clone(), original_cfg.clone());
+
+        let resp = validate_config(&state);
+        assert!(resp.ok, "example config must pass: {:?}", resp.errors);
+
+        write_file(
+            &path,
+            r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.direct]
+type = "direct"
+
+[rules.app]
+"#,
+        );
+        let resp = validate_config(&state);
+        assert!(resp.ok, "valid replacement must pass: {:?}", resp.errors);
+        assert_eq!(
+            state.router.config().defaults.egress.0,
+            original_cfg.defaults.egress.0
+        );
+
+        write_file(&path, "this = [ is not valid toml");
+        let resp = validate_config(&state);
+        assert!(!resp.ok);
+        assert_eq!(resp.errors.len(), 1);
+        assert_eq!(state.reload_ok.load(Ordering::Relaxed), 0);
+        assert_eq!(state.reload_err.load(Ordering::Relaxed), 0);
+
+        let _ = std::fs::remove_file(path);
     }
 
     #[test]
@@ -450,16 +3090,56 @@ mod tests {
         assert!(err.is_some());
 
         // Config must remain unchanged in memory
-        let current = state.cfg.load();
+        let current = state.router.config();
         assert_eq!(current.defaults.egress.0, original_cfg.defaults.egress.0);
 
         assert_eq!(state.reload_ok.load(Ordering::Relaxed), 0);
         assert_eq!(state.reload_err.load(Ordering::Relaxed), 1);
+        assert_eq!(build_diagnostics(&state).config_generation, 0);
 
         // Best effort cleanup
         let _ = std::fs::remove_file(path);
     }
 
+    #[test]
+    fn reload_from_ipc_needs_the_flag_and_swaps_without_a_config_file() {
+        let mut state = make_state(tmp_path("reload-from-missing"), load_example_config());
+        let pushed = include_str!("../../config/config.example.toml").replacen(
+            "egress = \"direct\"",
+            "egress = \"vpn\"",
+            1,
+        );
+        let reload_from = |state: &State, toml: &str| {
+            handle_request(
+                state,
+                Request::ReloadFrom {
+                    toml: toml.to_owned(),
+                },
+                None,
+            )
+        };
+
+        match reload_from(&state, &pushed) {
+            Response::Err(err) => assert_eq!(err.code, ErrorCode::Unauthorized),
+            other => panic!("expected an error, got {other:?}"),
+        }
+        assert_eq!(state.router.config().defaults.egress.0, "direct");
+        assert_eq!(state.reload_err.load(Ordering::Relaxed), 0);
+
+        state.allow_ipc_reload = true;
+        match reload_from(&state, "this = [ is not valid toml") {
+            Response::Err(err) => assert_eq!(err.code, ErrorCode::ConfigInvalid),
+            other => panic!("expected an error, got {other:?}"),
+        }
+        assert_eq!(state.reload_err.load(Ordering::Relaxed), 1);
+
+        assert!(matches!(reload_from(&state, &pushed), Response::OkReload));
+        assert_eq!(state.router.config().defaults.egress.0, "vpn");
+        assert_eq!(state.reload_ok.load(Ordering::Relaxed), 1);
+        assert_eq!(build_diagnostics(&state).config_path, "<ipc>");
+        assert_eq!(build_diagnostics(&state).config_generation, 1);
+    }
+
     #[test]
     fn reload_valid_config_updates_state() {
         let path = tmp_path("reload-valid");
@@ -473,6 +3153,8 @@ mod tests {
         // Write another valid config with different defaults.egress
         // Minimal toml: keep required sections only
         let next_raw = r#"
+version = 2
+
 [defaults]
 egress = "direct"
 
@@ -492,11 +3174,71 @@ direct = []
         reload_config(&state).expect("reload should succeed");
 
         // Must be updated
-        let current = state.cfg.load();
+        let current = state.router.config();
         assert_eq!(current.defaults.egress.0, "direct");
 
         assert_eq!(state.reload_ok.load(Ordering::Relaxed), 1);
         assert_eq!(state.reload_err.load(Ordering::Relaxed), 0);
+        assert_eq!(build_diagnostics(&state).config_generation, 1);
+        assert_eq!(state.router.status().config_generation, 1);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn reload_diff_reports_changes_without_swapping() {
+        let path = tmp_path("reload-diff");
+        let example = include_str!("../../config/config.example.toml");
+        write_file(&path, example);
+        let original_cfg = AppConfig::load_from_path(&path).expect("must load initial config");
+        let state = make_state(path.clone(), original_cfg.clone());
+
+        write_file(
+            &path,
+            &example
+                .replace("127.0.0.1:1080", "127.0.0.1:1081")
+                .replace(r#""github.com""#, r#""gitlab.com""#),
+        );
+
+        let diff = reload_diff(&state).expect("dry run should succeed");
+        assert_eq!(diff.changed_endpoints.len(), 1);
+        assert_eq!(diff.changed_endpoints[0].egress, "proxy");
+        assert_eq!(diff.added_rules.len(), 1);
+        assert_eq!(diff.added_rules[0].pattern, "gitlab.com");
+        assert_eq!(diff.removed_rules.len(), 1);
+        assert_eq!(diff.removed_rules[0].pattern, "github.com");
+
+        assert_eq!(AppConfig::clone(&state.router.config()), original_cfg);
+        assert_eq!(state.reload_ok.load(Ordering::Relaxed), 0);
+        assert_eq!(state.reload_err.load(Ordering::Relaxed), 0);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn reload_errors_carry_machine_readable_codes() {
+        let path = tmp_path("reload-error-codes");
+        let state = make_state(path.clone(), load_example_config());
+        let reload_code = || match handle_request(&state, Request::Reload, None) {
+            Response::Err(e) => e.code,
+            other => panic!("expected an error, got {other:?}"),
+        };
+
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(reload_code(), ErrorCode::ConfigNotFound);
+
+        write_file(&path, "this = [ is not valid toml");
+        assert_eq!(reload_code(), ErrorCode::ConfigInvalid);
+
+        let example = include_str!("../../config/config.example.toml");
+        write_file(
+            &path,
+            &example.replace(
+                "[rules.domain]\n",
+                "[rules.domain]\nnowhere = [\"example.org\"]\n",
+            ),
+        );
+        assert_eq!(reload_code(), ErrorCode::UnknownEgress);
 
         let _ = std::fs::remove_file(path);
     }
@@ -514,4 +3256,471 @@ direct = []
 
         let _ = std::fs::remove_file(path);
     }
+
+    #[test]
+    fn explain_reports_kind_and_endpoint_of_chosen_egress() {
+        let state = make_state(PathBuf::from("config.toml"), load_example_config());
+        let input = |domain| DecideInput {
+            process_name: None,
+            cmdline: None,
+            domain: Some(domain),
+            port: None,
+            now: None,
+        };
+
+        let routed = explain(&state, &input("youtube.com"), false).decision;
+        assert_eq!(routed.egress, "proxy");
+        assert_eq!(routed.reason_code, "domain_rule");
+        assert_eq!(routed.kind.as_deref(), Some("socks5"));
+        assert_eq!(routed.endpoint.as_deref(), Some("socks5://127.0.0.1:1080"));
+
+        let matcher = explain(&state, &input("i.ytimg.com"), false)
+            .decision
+            .matcher
+            .expect("domain rule matcher");
+        assert_eq!(
+            (matcher.pattern.as_str(), matcher.index),
+            ("ytimg.com", Some(3))
+        );
+
+        let direct = explain(&state, &input("example.org"), false).decision;
+        assert_eq!(direct.egress, "direct");
+        assert_eq!(direct.reason_code, "default");
+        assert_eq!(direct.kind.as_deref(), Some("direct"));
+        assert_eq!(direct.endpoint, None);
+    }
+
+    #[test]
+    fn status_reports_health_of_proxy_egresses() {
+        let open = std::net::TcpListener::bind("127.0.0.1:0").expect("bind listener");
+        let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .expect("bind listener")
+            .port();
+        let raw = format!(
+            r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.direct]
+type = "direct"
+
+[egress.up]
+type = "socks5"
+endpoint = "socks5://{}"
+
+[egress.down]
+type = "http"
+endpoint = "http://127.0.0.1:{closed_port}"
+
+[rules.domain]
+up = ["example.com"]
+"#,
+            open.local_addr().expect("listener addr")
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        cfg.validate().expect("config must validate");
+        let state = make_state(PathBuf::from("config.toml"), cfg);
+
+        let health = |status: &StatusResponse| -> Vec<_> {
+            status
+                .egress
+                .iter()
+                .map(|e| (e.id.clone(), e.healthy, e.last_checked_ms.is_some()))
+                .collect()
+        };
+        assert!(
+            build_status(&state)
+                .egress
+                .iter()
+                .all(|e| e.healthy.is_none())
+        );
+
+        check_egress_health(&state);
+        assert_eq!(
+            health(&build_status(&state)),
+            [
+                ("direct".to_owned(), None, false),
+                ("down".to_owned(), Some(false), true),
+                ("up".to_owned(), Some(true), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn explain_reports_rule_description() {
+        let raw = r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.direct]
+type = "direct"
+
+[egress.tunnel]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[aliases]
+work = "tunnel"
+
+[rules.app]
+tunnel = ["zen.exe"]
+
+[rules.domain]
+tunnel = ["example.com"]
+work = ["intranet.example"]
+
+[rules.domain_meta.work]
+"intranet.example" = "reachable only through the office"
+
+[rules.app_meta.tunnel]
+"zen.exe" = "work browser"
+"#;
+        let mut cfg = toml::from_str::<AppConfig>(raw).expect("config must parse");
+        cfg.validate().expect("config must validate");
+        cfg.resolve_aliases();
+        let state = make_state(PathBuf::from("config.toml"), cfg);
+        let describe = |process: Option<&str>, domain: Option<&str>| {
+            let input = DecideInput {
+                process_name: process,
+                cmdline: None,
+                domain,
+                port: None,
+                now: None,
+            };
+            explain(&state, &input, false).decision.description
+        };
+
+        assert_eq!(
+            describe(None, Some("vpn.intranet.example")).as_deref(),
+            Some("reachable only through the office")
+        );
+        assert_eq!(describe(None, Some("example.com")), None);
+        assert_eq!(
+            describe(Some("zen.exe"), None).as_deref(),
+            Some("work browser")
+        );
+        assert_eq!(describe(Some("other.exe"), None), None);
+    }
+
+    #[test]
+    fn explain_trace_reports_the_pattern_the_decision_matched() {
+        let raw = r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.direct]
+type = "direct"
+
+[egress.block]
+type = "block"
+
+[egress.tunnel]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[rules.domain]
+block = ["example.com", "www.example.com"]
+tunnel = ["example.org", "www.example.org"]
+
+[rules.cidr]
+tunnel = ["10.0.0.0/8", "10.0.0.1/8", "10.1.0.0/16"]
+"#;
+        let cfg = toml::from_str::<AppConfig>(raw).expect("config must parse");
+        cfg.validate().expect("config must validate");
+        let state = make_state(PathBuf::from("config.toml"), cfg);
+
+        for (domain, pattern) in [
+            ("www.example.com", "example.com"),
+            ("www.example.org", "www.example.org"),
+            ("10.1.2.3", "10.1.0.0/16"),
+            ("10.2.3.4", "10.0.0.0/8"),
+        ] {
+            let input = DecideInput {
+                process_name: None,
+                cmdline: None,
+                domain: Some(domain),
+                port: None,
+                now: None,
+            };
+            let decision = explain(&state, &input, true).decision;
+            let matcher = decision.matcher.expect("a rule must match");
+            let step = decision
+                .trace
+                .iter()
+                .find(|step| step.matched)
+                .expect("the trace must show the matching rule");
+            assert_eq!(matcher.pattern, pattern, "domain {domain}");
+            assert_eq!(step.pattern.as_deref(), Some(pattern), "domain {domain}");
+        }
+    }
+
+    #[test]
+    fn strict_precheck_refuses_unreachable_endpoints() {
+        let open = std::net::TcpListener::bind("127.0.0.1:0").expect("bind listener");
+        let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .expect("bind listener")
+            .port();
+        let config = |down: bool| {
+            let down = if down {
+                format!(
+                    "[egress.down]\ntype = \"singbox\"\nendpoint = \"socks5://127.0.0.1:{closed_port}\"\n"
+                )
+            } else {
+                String::new()
+            };
+            let raw = format!(
+                "version = 2\n\n[defaults]\negress = \"direct\"\n\n[egress.direct]\ntype = \"direct\"\n\n[egress.block]\ntype = \"block\"\n\n[egress.up]\ntype = \"socks5\"\nendpoint = \"socks5://{}\"\n\n{down}\n[rules.app]\n",
+                open.local_addr().expect("listener addr")
+            );
+            let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+            cfg.validate().expect("config must validate");
+            make_state(PathBuf::from("config.toml"), cfg)
+        };
+
+        let state = config(false);
+        precheck_egress(&state, true).expect("every endpoint is reachable");
+        let status = build_status(&state);
+        let up = status
+            .egress
+            .iter()
+            .find(|e| e.id == "up")
+            .expect("up egress");
+        assert_eq!(up.healthy, Some(true));
+
+        let state = config(true);
+        precheck_egress(&state, false).expect("lenient precheck only logs");
+        let err = precheck_egress(&state, true).expect_err("down endpoint refuses start");
+        let message = err.to_string();
+        assert!(message.contains("down"), "{message}");
+        assert!(!message.contains("up,"), "{message}");
+    }
+
+    #[test]
+    fn rule_stats_count_hits_and_reset_on_reload() {
+        let path = tmp_path("rule-stats");
+        write_file(&path, include_str!("../../config/config.example.toml"));
+        let state = make_state(path.clone(), load_example_config());
+        let input = DecideInput {
+            process_name: None,
+            cmdline: None,
+            domain: Some("www.youtube.com"),
+            port: None,
+            now: None,
+        };
+        let hits = |state: &State| -> Vec<(String, u64)> {
+            let Response::OkRuleStats(response) = handle_request(state, Request::RuleStats, None)
+            else {
+                panic!("expected rule stats");
+            };
+            assert!(
+                response
+                    .rules
+                    .iter()
+                    .all(|r| r.never_matched == (r.hits == 0))
+            );
+            response
+                .rules
+                .into_iter()
+                .filter(|r| r.hits > 0)
+                .map(|r| (format!("{} {} {}", r.kind, r.egress, r.pattern), r.hits))
+                .collect()
+        };
+
+        assert_eq!(hits(&state), []);
+        explain(&state, &input, false);
+        explain(&state, &input, true);
+        explain(
+            &state,
+            &DecideInput {
+                domain: Some("example.org"),
+                ..input
+            },
+            false,
+        );
+        assert_eq!(hits(&state), [("domain proxy youtube.com".to_owned(), 2)]);
+
+        reload_config(&state).expect("reload should succeed");
+        assert_eq!(hits(&state), []);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn explain_drops_cached_decisions_on_reload() {
+        let path = tmp_path("decision-cache-reload");
+        let example = include_str!("../../config/config.example.toml");
+        write_file(&path, example);
+        let state = make_state(path.clone(), load_example_config());
+        let input = DecideInput {
+            process_name: None,
+            cmdline: None,
+            domain: Some("YouTube.com."),
+            port: None,
+            now: None,
+        };
+
+        assert_eq!(explain(&state, &input, false).decision.egress, "proxy");
+        assert_eq!(explain(&state, &input, false).decision.egress, "proxy");
+        assert_eq!(state.decision_cache.len(), 1);
+
+        write_file(
+            &path,
+            &example.replace(r#""youtube.com""#, r#""example.net""#),
+        );
+        reload_config(&state).expect("reload should succeed");
+
+        assert_eq!(explain(&state, &input, false).decision.egress, "direct");
+        assert_eq!(state.decision_cache.len(), 1);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn explain_records_history_and_notifies_subscribers() {
+        let state = make_state(PathBuf::from("config.toml"), load_example_config());
+        let (rx, _) = state.history.follow();
+
+        explain(
+            &state,
+            &DecideInput {
+                process_name: Some("zen.exe"),
+                cmdline: None,
+                domain: Some("youtube.com"),
+                port: None,
+                now: None,
+            },
+            false,
+        );
+        explain(
+            &state,
+            &DecideInput {
+                process_name: Some("zen.exe"),
+                cmdline: None,
+                domain: None,
+                port: None,
+                now: None,
+            },
+            false,
+        );
+
+        let entries = state.history.snapshot();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].seq, 0);
+        assert_eq!(entries[0].egress, "proxy");
+        assert_eq!(entries[1].seq, 1);
+        assert_eq!(entries[1].egress, "vpn");
+
+        let pushed = rx
+            .try_recv()
+            .expect("subscriber must receive first decision");
+        assert_eq!(pushed.seq, 0);
+        assert_eq!(pushed.domain.as_deref(), Some("youtube.com"));
+    }
+
+    #[test]
+    fn concurrent_decisions_reach_subscribers_in_seq_order() {
+        let state = make_state(PathBuf::from("config.toml"), load_example_config());
+        let (rx, _) = state.history.follow();
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..50 {
+                        explain(
+                            &state,
+                            &DecideInput {
+                                process_name: Some("zen.exe"),
+                                cmdline: None,
+                                domain: None,
+                                port: None,
+                                now: None,
+                            },
+                            false,
+                        );
+                    }
+                });
+            }
+        });
+
+        let seqs: Vec<u64> = rx.try_iter().map(|entry| entry.seq).collect();
+        assert_eq!(seqs, (0..200).collect::<Vec<_>>());
+        let entries = state.history.snapshot();
+        assert!(
+            entries
+                .windows(2)
+                .all(|pair| pair[0].seq + 1 == pair[1].seq)
+        );
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let state = make_state(PathBuf::from("config.toml"), load_example_config());
+
+        for _ in 0..HISTORY_CAPACITY + 5 {
+            explain(
+                &state,
+                &DecideInput {
+                    process_name: Some("zen.exe"),
+                    cmdline: None,
+                    domain: None,
+                    port: None,
+                    now: None,
+                },
+                false,
+            );
+        }
+
+        let entries = state.history.snapshot();
+        assert_eq!(entries.len(), HISTORY_CAPACITY);
+        assert_eq!(entries[0].seq, 5);
+    }
+
+    #[test]
+    fn json_log_format_keeps_fields_as_keys() {
+        #[derive(Clone, Default)]
+        struct Capture(Arc<Mutex<Vec<u8>>>);
+
+        impl io::Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().expect("capture lock").extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let capture = Capture::default();
+        let writer = {
+            let capture = capture.clone();
+            BoxMakeWriter::new(move || capture.clone())
+        };
+        let subscriber = log_subscriber(LogFormat::Json, EnvFilter::new("info"), writer, false);
+        tracing::subscriber::with_default(subscriber, || {
+            warn!(egress = "vpn", error = %"boom", "reload failed");
+            info!(reason = "app rule", "decided");
+        });
+
+        let output = String::from_utf8(capture.0.lock().expect("capture lock").clone())
+            .expect("logs must be UTF-8");
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("each log line must be JSON"))
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["message"], "reload failed");
+        assert_eq!(lines[0]["egress"], "vpn");
+        assert_eq!(lines[0]["error"], "boom");
+        assert_eq!(lines[0]["level"], "WARN");
+        assert_eq!(lines[1]["reason"], "app rule");
+    }
 }
@@ -0,0 +1,202 @@
+//! IPC server running one tokio task per connection, selected with
+//! `--runtime async`.
+
+use std::{
+    sync::{Arc, atomic::Ordering, mpsc::TryRecvError},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use interprocess::local_socket::{
+    ListenerOptions, Name,
+    tokio::{Listener, RecvHalf, SendHalf, Stream, prelude::*},
+};
+use policy_router_rs::ipc::{
+    LineTooLong, Response, decode_json_line_limited, encode_json_line, line_read_cap,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    runtime::Runtime,
+    task::JoinSet,
+    time::timeout,
+};
+use tracing::{info, warn};
+
+#[cfg(unix)]
+use super::lookup_peer;
+use super::{DRAIN_TIMEOUT, Peer, State, dispatch, history_event, start_follow};
+
+/// How often idle loops check whether the daemon is stopping.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A tokio runtime and the listener registered with it.
+pub struct Server {
+    runtime: Runtime,
+    listener: Listener,
+}
+
+impl Server {
+    /// Starts a runtime with `workers` threads and binds `name` on it.
+    pub fn bind(name: Name<'static>, workers: usize) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(workers)
+            .enable_all()
+            .build()
+            .context("failed to start async runtime")?;
+        let listener = {
+            let _guard = runtime.enter();
+            ListenerOptions::new()
+                .name(name)
+                .create_tokio()
+                .context("failed to create IPC listener")?
+        };
+        Ok(Self { runtime, listener })
+    }
+
+    /// Accepts connections until the daemon stops, then waits up to
+    /// [`DRAIN_TIMEOUT`] for open ones to finish.
+    pub fn serve(self, state: &Arc<State>, client_timeout: Duration) {
+        let Self { runtime, listener } = self;
+        runtime.block_on(async {
+            let mut conns = JoinSet::new();
+            while state.running.load(Ordering::SeqCst) {
+                tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok(conn) => {
+                            conns.spawn(serve_conn(Arc::clone(state), conn, client_timeout));
+                        }
+                        Err(e) => {
+                            state.accept_errors.fetch_add(1, Ordering::Relaxed);
+                            warn!(error = %e, "accept error");
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                        }
+                    },
+                    () = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+                while conns.try_join_next().is_some() {}
+            }
+
+            info!("stopping");
+
+            let mut drained = 0_usize;
+            let _ = timeout(DRAIN_TIMEOUT, async {
+                while let Some(joined) = conns.join_next().await {
+                    if joined.is_err() {
+                        warn!("connection task panicked");
+                    }
+                    drained += 1;
+                }
+            })
+            .await;
+            let abandoned = conns.len();
+            if abandoned == 0 {
+                info!(drained, "drained connection tasks");
+            } else {
+                warn!(
+                    drained,
+                    abandoned, "connection tasks still busy after drain timeout"
+                );
+            }
+        });
+    }
+}
+
+async fn serve_conn(state: Arc<State>, conn: Stream, client_timeout: Duration) {
+    let (recv, send) = conn.split();
+    let peer = lookup_async_peer(&recv);
+    if let Err(e) = handle_conn(&state, recv, &send, &peer, client_timeout).await {
+        warn!(error = %format!("{e:#}"), "ipc error");
+    }
+}
+
+/// Like the blocking `handle_conn`, with `client_timeout` bounding every read
+/// and write.
+async fn handle_conn(
+    state: &State,
+    recv: RecvHalf,
+    send: &SendHalf,
+    peer: &Peer,
+    client_timeout: Duration,
+) -> Result<()> {
+    let mut reader = BufReader::new(recv);
+    let mut line = Vec::new();
+    let limit = state.max_request_bytes;
+
+    // One request per line until the client closes its end.
+    loop {
+        line.clear();
+        let mut capped = (&mut reader).take(line_read_cap(limit));
+        let read = timeout(client_timeout, capped.read_until(b'\n', &mut line))
+            .await
+            .context("ipc client sent nothing before the timeout")?
+            .context("failed to read JSON line");
+        let req = match read {
+            Ok(0) => return Ok(()),
+            Ok(_) => decode_json_line_limited(&line, limit),
+            Err(err) => Err(err),
+        };
+        // The rest of an oversized line is still unread; answer and hang up.
+        let overran = req.as_ref().is_err_and(anyhow::Error::is::<LineTooLong>);
+
+        match dispatch(state, req, peer) {
+            Some(resp) => write_line(send, &resp, client_timeout).await?,
+            None => return follow_history(state, send, client_timeout).await,
+        }
+        if overran {
+            return Ok(());
+        }
+    }
+}
+
+async fn follow_history(state: &State, send: &SendHalf, client_timeout: Duration) -> Result<()> {
+    let (rx, snapshot, mut next_seq) = start_follow(state);
+    write_line(send, &snapshot, client_timeout).await?;
+
+    while state.running.load(Ordering::SeqCst) {
+        match rx.try_recv() {
+            Ok(entry) => {
+                if let Some(event) = history_event(entry, &mut next_seq) {
+                    write_line(send, &event, client_timeout).await?;
+                }
+            }
+            // The history channel is blocking, so poll it between sleeps.
+            Err(TryRecvError::Empty) => tokio::time::sleep(POLL_INTERVAL).await,
+            Err(TryRecvError::Disconnected) => break,
+        }
+    }
+
+    Ok(())
+}
+
+async fn write_line(send: &SendHalf, resp: &Response, client_timeout: Duration) -> Result<()> {
+    let line = encode_json_line(resp)?;
+    let mut send = send;
+    timeout(client_timeout, send.write_all(&line))
+        .await
+        .context("ipc client stopped reading before the timeout")?
+        .context("failed to write JSON line")
+}
+
+/// Resolves the peer through the blocking lookup, which only needs the
+/// socket descriptor.
+#[cfg(unix)]
+fn lookup_async_peer(recv: &RecvHalf) -> Peer {
+    use std::{
+        mem::ManuallyDrop,
+        os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+    };
+
+    let RecvHalf::UdSocket(half) = recv;
+    // Borrow the descriptor as a blocking stream without taking ownership.
+    let conn = ManuallyDrop::new(interprocess::local_socket::Stream::UdSocket(
+        unsafe { OwnedFd::from_raw_fd(half.as_fd().as_raw_fd()) }.into(),
+    ));
+    lookup_peer(&conn)
+}
+
+/// Named pipe halves expose no handle to borrow, so on Windows the async
+/// runtime serves every client as unidentified.
+#[cfg(not(unix))]
+fn lookup_async_peer(_recv: &RecvHalf) -> Peer {
+    Peer::default()
+}
@@ -1,6 +1,9 @@
-use std::io::{BufRead, BufReader, Write};
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Read, Write},
+};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use interprocess::local_socket::{GenericFilePath, GenericNamespaced, Name, prelude::*};
 use serde::{Deserialize, Serialize};
 
@@ -8,6 +11,28 @@ pub const SOCKET_PRINT_NAME: &str = "policy-routerd.sock";
 pub const SOCKET_FS_FALLBACK: &str = "/tmp/policy-routerd.sock";
 pub const SOCKET_ENV_VAR: &str = "POLICY_ROUTER_SOCKET";
 
+/// Version of the request/response protocol spoken by this build, bumped
+/// whenever a [`Request`] or [`Response`] variant is added or changed.
+///
+/// Peers that never send [`Request::Hello`] count as version 0.
+pub const PROTOCOL_VERSION: u32 = 9;
+
+/// Oldest peer protocol version this build still talks to.
+pub const MIN_SUPPORTED_PROTOCOL_VERSION: u32 = 0;
+
+/// Longest line [`read_json_line`] accepts, terminator included.
+pub const MAX_LINE_BYTES: usize = 1024 * 1024;
+
+/// A JSON line longer than the reader's limit.
+///
+/// Reading stops at the limit, so the rest of the line is still pending on
+/// the stream; the connection should be dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("request too large: line exceeds {limit} bytes")]
+pub struct LineTooLong {
+    pub limit: usize,
+}
+
 /// Builds the IPC socket name.
 ///
 /// # Errors
@@ -58,37 +83,199 @@ fn looks_like_fs_path(s: &str) -> bool {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Request {
+    /// Announces the client protocol version; sent first on a connection.
+    Hello(HelloRequest),
     Status,
     Reload,
+    /// Make `toml` the live config instead of the config file; refused
+    /// unless the daemon runs with `--allow-ipc-reload`.
+    ReloadFrom {
+        toml: String,
+    },
+    /// Load the on-disk config and diff it against the live one without
+    /// applying it.
+    ReloadDiff,
     Stop,
     Explain(ExplainRequest),
     Diagnostics,
+    History(HistoryRequest),
+    ConfigFiles,
+    /// Load and validate the on-disk config without applying it.
+    ValidateConfig,
+    /// Dump the rule table of the live config.
+    ListRules,
+    Metrics,
+    /// Dump the whole live config, secrets redacted.
+    GetConfig,
+    /// List the rules of the live config routing to one egress.
+    EgressRefs {
+        egress: String,
+    },
+    /// Count how often each rule of the live config decided a flow.
+    RuleStats,
+    /// Liveness probe answered without touching the config.
+    Ping,
+    /// Resolve the local process owning the socket bound to `addr`
+    /// (`ip:port`).
+    WhoIs {
+        addr: String,
+    },
+    /// Report the order in which the live config tries app and domain rule
+    /// egresses.
+    Priority,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HelloRequest {
+    pub version: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ExplainRequest {
     pub process: Option<String>,
+    /// Full command line of the process, for `name::substring` app patterns.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmdline: Option<String>,
     pub domain: Option<String>,
+    /// Destination port, for `rules.port`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Also report every matching rule, not just the winner.
+    #[serde(default)]
+    pub verbose: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryRequest {
+    /// Keep the connection open and push new decisions as [`Response::Event`].
+    #[serde(default)]
+    pub follow: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Response {
+    Hello(HelloResponse),
     OkStatus(StatusResponse),
     OkReload,
+    OkReloadDiff(ReloadDiffResponse),
     OkStop,
-    OkExplain(ExplainResponse),
+    OkExplain(Box<ExplainResponse>),
     OkDiagnostics(DiagnosticsResponse),
+    OkHistory(HistoryResponse),
+    OkConfigFiles(ConfigFilesResponse),
+    OkValidate(ValidateResponse),
+    OkRules(RulesResponse),
+    OkMetrics(MetricsResponse),
+    OkConfig(ConfigResponse),
+    OkEgressRefs(EgressRefsResponse),
+    OkRuleStats(RuleStatsResponse),
+    OkPong {
+        /// Daemon uptime when it answered, in milliseconds.
+        server_time_ms: u64,
+    },
+    OkWhoIs(WhoIsResponse),
+    OkPriority(PriorityResponse),
+    Event(Event),
     Err(ErrorResponse),
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HelloResponse {
+    /// Protocol version spoken by the daemon.
+    pub version: u32,
+    /// Oldest client protocol version the daemon still serves.
+    pub min_supported: u32,
+}
+
+impl HelloResponse {
+    /// What a daemon predating the handshake would have answered.
+    pub const LEGACY: Self = Self {
+        version: 0,
+        min_supported: 0,
+    };
+
+    /// Checks that a client speaking [`PROTOCOL_VERSION`] can talk to this
+    /// daemon.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the side to upgrade when either one is too old
+    /// for the other.
+    #[allow(
+        clippy::absurd_extreme_comparisons,
+        reason = "the supported floor stays 0 until a breaking protocol change"
+    )]
+    pub fn check_compatible(&self) -> Result<()> {
+        if PROTOCOL_VERSION < self.min_supported {
+            bail!(
+                "policy-routerctl speaks protocol v{PROTOCOL_VERSION} but policy-routerd needs at least v{}; upgrade policy-routerctl",
+                self.min_supported
+            );
+        }
+        if self.version < MIN_SUPPORTED_PROTOCOL_VERSION {
+            bail!(
+                "policy-routerd speaks protocol v{} but policy-routerctl needs at least v{MIN_SUPPORTED_PROTOCOL_VERSION}; upgrade policy-routerd",
+                self.version
+            );
+        }
+        Ok(())
+    }
+
+    /// Hint to attach to errors that may stem from the daemon speaking a
+    /// different protocol version, `None` when the versions match.
+    #[must_use]
+    pub fn mismatch_hint(&self) -> Option<String> {
+        match self.version.cmp(&PROTOCOL_VERSION) {
+            std::cmp::Ordering::Equal => None,
+            std::cmp::Ordering::Less => Some(format!(
+                "policy-routerd speaks protocol v{}, older than policy-routerctl (v{PROTOCOL_VERSION}); upgrade policy-routerd",
+                self.version
+            )),
+            std::cmp::Ordering::Greater => Some(format!(
+                "policy-routerd speaks protocol v{}, newer than policy-routerctl (v{PROTOCOL_VERSION}); upgrade policy-routerctl",
+                self.version
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StatusResponse {
     pub uptime_ms: u64,
     pub config_path: String,
+    /// Bumped whenever a reload swaps in a new config; 0 for the config
+    /// loaded at startup.
+    #[serde(default)]
+    pub config_generation: u64,
     pub egress: Vec<EgressInfo>,
 }
 
+/// What a reload would change, by egress id order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReloadDiffResponse {
+    pub added_egress: Vec<String>,
+    pub removed_egress: Vec<String>,
+    pub changed_endpoints: Vec<EndpointChangeInfo>,
+    pub added_rules: Vec<RuleChangeInfo>,
+    pub removed_rules: Vec<RuleChangeInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointChangeInfo {
+    pub egress: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleChangeInfo {
+    /// Rule table: `app`, `domain`, `cidr` or `port`.
+    pub kind: String,
+    pub egress: String,
+    pub pattern: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiagnosticsResponse {
     pub uptime_ms: u64,
@@ -99,6 +286,113 @@ pub struct DiagnosticsResponse {
     pub ipc_requests: u64,
     pub reload_ok: u64,
     pub reload_err: u64,
+    /// Configs swapped in since startup; see
+    /// [`StatusResponse::config_generation`].
+    #[serde(default)]
+    pub config_generation: u64,
+    /// Rule patterns ignored at load because their egress id is unknown.
+    #[serde(default)]
+    pub dropped_rules: usize,
+    /// Non-fatal config warnings from [`AppConfig::lints`].
+    ///
+    /// [`AppConfig::lints`]: crate::policy::config::AppConfig::lints
+    #[serde(default)]
+    pub lints: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryResponse {
+    pub entries: Vec<HistoryEntry>,
+}
+
+/// Live rule table, keyed by egress id in sorted order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RulesResponse {
+    pub app: BTreeMap<String, Vec<String>>,
+    pub domain: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub cidr: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub port: BTreeMap<String, Vec<String>>,
+}
+
+/// Rules of the live config routing to one egress.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EgressRefsResponse {
+    /// The egress asked about, or the target of the alias asked about.
+    pub egress: String,
+    /// Whether `defaults.egress` names this egress.
+    pub default: bool,
+    pub app: Vec<String>,
+    pub domain: Vec<String>,
+    #[serde(default)]
+    pub cidr: Vec<String>,
+    #[serde(default)]
+    pub port: Vec<String>,
+}
+
+/// Hits of every rule of the live config since the daemon started or last
+/// reloaded, in rule table order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleStatsResponse {
+    pub rules: Vec<RuleStat>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleStat {
+    /// Rule table: `app`, `domain`, `cidr` or `port`.
+    pub kind: String,
+    /// Egress id the pattern is listed under.
+    pub egress: String,
+    pub pattern: String,
+    /// Explained flows this rule decided.
+    pub hits: u64,
+    /// Whether `hits` is zero.
+    pub never_matched: bool,
+}
+
+/// Daemon counters in Prometheus text exposition format.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsResponse {
+    pub text: String,
+}
+
+/// The live config serialized as TOML, with egress passwords replaced by
+/// [`REDACTED`].
+///
+/// [`REDACTED`]: crate::policy::config::REDACTED
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigResponse {
+    pub toml: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidateResponse {
+    pub ok: bool,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigFilesResponse {
+    /// Every file that contributed to the effective config, entrypoint first.
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub seq: u64,
+    pub uptime_ms: u64,
+    pub process: Option<String>,
+    pub domain: Option<String>,
+    pub egress: String,
+    pub source: DecisionSource,
+}
+
+/// Server-pushed notifications delivered to subscribed connections.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    DecisionMade(HistoryEntry),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -106,11 +400,58 @@ pub struct EgressInfo {
     pub id: String,
     pub kind: String,
     pub endpoint: Option<String>,
+    /// Interface name of a `tun` egress.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// Whether proxy credentials are configured; they are never exposed.
+    #[serde(default)]
+    pub auth: bool,
+    /// Egress groups listing this egress as a member.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// Operator label from the egress `description`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Whether the last health check reached the endpoint; unset for kinds
+    /// without a proxy endpoint and before the first check.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub healthy: Option<bool>,
+    /// Daemon uptime when `healthy` was last checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_checked_ms: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExplainResponse {
     pub decision: DecisionInfo,
+    /// Process that sent the request, when the platform can resolve it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<ClientInfo>,
+}
+
+/// Owner of the socket asked about by [`Request::WhoIs`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WhoIsResponse {
+    /// `None` when no local process owns the address, or on platforms
+    /// without a process lookup.
+    pub process: Option<ClientInfo>,
+}
+
+/// Egress ids with app and domain rules in the live config, in the order a
+/// decision tries them: `block_app`, then `block_domain`, ahead of every other
+/// rule; `domain` before `app`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriorityResponse {
+    pub block_app: Vec<String>,
+    pub block_domain: Vec<String>,
+    pub app: Vec<String>,
+    pub domain: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientInfo {
+    pub pid: u32,
+    pub exe: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -118,23 +459,117 @@ pub struct DecisionInfo {
     pub egress: String,
     pub reason: String,
 
+    /// Stable key of the reason, such as `domain_rule`, for logs and metric
+    /// labels; `reason` is for humans and may change wording.
+    #[serde(default)]
+    pub reason_code: String,
+
+    /// Kind of the chosen egress, from its `[egress.*]` spec.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+
+    /// Endpoint of the chosen egress; absent for direct and block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+
     pub source: DecisionSource,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rule_egress: Option<String>,
 
+    /// Egress group the winning rule targeted; `egress` is its first member.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub matcher: Option<MatcherInfo>,
+
+    /// Description of the winning rule from `rules.app_meta` or
+    /// `rules.domain_meta`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// Every domain rule that matched, populated for verbose explains.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matchers: Vec<MatcherInfo>,
+
+    /// Priority rank of the winning rule, lower winning: the egress
+    /// `priority` if set, else 0 for singbox and tun, 5 for http, 10 for
+    /// socks5, 20 for direct. Absent for block and default decisions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank: Option<i32>,
+
+    /// Another egress of the same rank also matched and lost on id order.
+    #[serde(default)]
+    pub tie_broken: bool,
+
+    /// Every egress checked on the way to the decision, populated for
+    /// verbose explains.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trace: Vec<TraceStep>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceStep {
+    pub egress: String,
+    pub rule: TraceRule,
+    pub matched: bool,
+    /// The matching pattern, or the first pattern checked when none matched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceRule {
+    BlockApp,
+    BlockDomain,
+    Port,
+    Cidr,
+    Domain,
+    App,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DecisionSource {
     BlockApp,
     BlockDomain,
     DomainRule,
+    CidrRule,
+    PortRule,
     AppRule,
     Default,
+    DefaultBlock,
+}
+
+impl DecisionSource {
+    /// Every source, in declaration order.
+    pub const ALL: [Self; 8] = [
+        Self::BlockApp,
+        Self::BlockDomain,
+        Self::DomainRule,
+        Self::CidrRule,
+        Self::PortRule,
+        Self::AppRule,
+        Self::Default,
+        Self::DefaultBlock,
+    ];
+
+    /// Snake case name, as used on the wire.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::BlockApp => "block_app",
+            Self::BlockDomain => "block_domain",
+            Self::DomainRule => "domain_rule",
+            Self::CidrRule => "cidr_rule",
+            Self::PortRule => "port_rule",
+            Self::AppRule => "app_rule",
+            Self::Default => "default",
+            Self::DefaultBlock => "default_block",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -142,6 +577,16 @@ pub struct MatcherInfo {
     #[serde(rename = "type")]
     pub kind: MatcherKind,
     pub pattern: String,
+
+    /// Zero-based position of `pattern` in its egress's rule list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub egress: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank: Option<i32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -149,36 +594,198 @@ pub struct MatcherInfo {
 pub enum MatcherKind {
     Exact,
     Suffix,
+    Wildcard,
+    Cidr,
+    Port,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ErrorResponse {
+    /// What went wrong, for scripts; older daemons omit it.
+    #[serde(default)]
+    pub code: ErrorCode,
     pub message: String,
 }
 
+/// Machine-readable kind of an [`ErrorResponse`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request line could not be parsed.
+    InvalidRequest,
+    /// The client speaks a protocol version the daemon no longer supports.
+    UnsupportedVersion,
+    /// Every worker was busy; the request may be retried.
+    Busy,
+    /// The config file, or a file it includes, does not exist.
+    ConfigNotFound,
+    /// The config file exists but could not be parsed or validated.
+    ConfigInvalid,
+    /// The config references an egress id it does not declare.
+    UnknownEgress,
+    /// `[security]` does not let the client's user send the request.
+    Unauthorized,
+    /// Any other failure, including codes newer than this client.
+    #[default]
+    #[serde(other)]
+    Internal,
+}
+
 /// Serializes `value` as JSON and writes it as a single line terminated by `\n`.
 ///
 /// # Errors
 ///
 /// Returns an error if JSON serialization fails or the underlying writer fails.
 pub fn write_json_line<W: Write, T: Serialize>(mut w: W, value: &T) -> Result<()> {
-    let mut line = serde_json::to_vec(value).context("failed to serialize JSON")?;
-    line.push(b'\n');
+    let line = encode_json_line(value)?;
     w.write_all(&line).context("failed to write JSON line")?;
     w.flush().ok();
     Ok(())
 }
 
+/// Serializes `value` as a single JSON line terminated by `\n`, for writers
+/// that are not [`Write`], such as async streams.
+///
+/// # Errors
+///
+/// Returns an error if JSON serialization fails.
+pub fn encode_json_line<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(value).context("failed to serialize JSON")?;
+    line.push(b'\n');
+    Ok(line)
+}
+
 /// Reads a single `\n` terminated line and deserializes it from JSON.
 ///
+/// A trailing `\n` or `\r\n` terminator is stripped before parsing, so CRLF
+/// clients are accepted. Lines longer than [`MAX_LINE_BYTES`] are rejected
+/// without being buffered whole.
+///
 /// # Errors
 ///
-/// Returns an error if reading fails or the input is not valid JSON for `T`.
-pub fn read_json_line<R: BufRead, T: for<'de> Deserialize<'de>>(mut r: R) -> Result<T> {
-    let mut line = String::new();
-    r.read_line(&mut line).context("failed to read JSON line")?;
-    let value = serde_json::from_str::<T>(&line).context("failed to deserialize JSON")?;
-    Ok(value)
+/// Returns an error if reading fails, the line is too long ([`LineTooLong`]),
+/// or the input is not valid JSON for `T`.
+pub fn read_json_line<R: BufRead, T: for<'de> Deserialize<'de>>(r: R) -> Result<T> {
+    read_json_line_limited(r, MAX_LINE_BYTES)
+}
+
+/// Like [`read_json_line`], accepting lines of at most `limit` bytes.
+///
+/// # Errors
+///
+/// Returns an error if reading fails, the line is too long ([`LineTooLong`]),
+/// or the input is not valid JSON for `T`.
+pub fn read_json_line_limited<R: BufRead, T: for<'de> Deserialize<'de>>(
+    mut r: R,
+    limit: usize,
+) -> Result<T> {
+    let mut line = Vec::new();
+    (&mut r)
+        .take(line_read_cap(limit))
+        .read_until(b'\n', &mut line)
+        .context("failed to read JSON line")?;
+    decode_json_line_limited(&line, limit)
+}
+
+/// Bytes to read for a line of at most `limit` bytes: one more, to tell a
+/// line of exactly `limit` bytes from a longer one.
+#[must_use]
+pub fn line_read_cap(limit: usize) -> u64 {
+    u64::try_from(limit).unwrap_or(u64::MAX).saturating_add(1)
+}
+
+/// Deserializes a line read with at most [`line_read_cap`] bytes, rejecting
+/// it if it overran `limit`.
+///
+/// # Errors
+///
+/// Returns [`LineTooLong`] if `line` exceeds `limit`, or an error if it is not
+/// UTF-8 or not valid JSON for `T`.
+pub fn decode_json_line_limited<T: for<'de> Deserialize<'de>>(
+    line: &[u8],
+    limit: usize,
+) -> Result<T> {
+    if line.len() > limit {
+        return Err(LineTooLong { limit }.into());
+    }
+    let line = std::str::from_utf8(line).context("JSON line is not valid UTF-8")?;
+    decode_json_line(line)
+}
+
+/// Deserializes one line read by other means, stripping its terminator like
+/// [`read_json_line`].
+///
+/// # Errors
+///
+/// Returns an error if the line is not valid JSON for `T`.
+pub fn decode_json_line<T: for<'de> Deserialize<'de>>(line: &str) -> Result<T> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    serde_json::from_str::<T>(line).context("failed to deserialize JSON")
+}
+
+/// Applies `timeout` to every read and write on `stream`, so a peer that
+/// stops talking makes the blocked call fail instead of hanging. `None`
+/// blocks indefinitely.
+///
+/// Windows named pipes have no per-call timeout; there this is a no-op.
+///
+/// # Errors
+///
+/// Returns an error if the socket rejects the timeout, e.g. a zero duration.
+pub fn set_stream_timeout(
+    stream: &interprocess::local_socket::Stream,
+    timeout: Option<std::time::Duration>,
+) -> Result<()> {
+    set_stream_read_timeout(stream, timeout)?;
+    #[cfg(unix)]
+    with_unix_stream(stream, |socket| {
+        socket
+            .set_write_timeout(timeout)
+            .context("failed to set write timeout")
+    })?;
+
+    Ok(())
+}
+
+/// Like [`set_stream_timeout`], bounding only reads.
+///
+/// # Errors
+///
+/// Returns an error if the socket rejects the timeout, e.g. a zero duration.
+pub fn set_stream_read_timeout(
+    stream: &interprocess::local_socket::Stream,
+    timeout: Option<std::time::Duration>,
+) -> Result<()> {
+    #[cfg(unix)]
+    with_unix_stream(stream, |socket| {
+        socket
+            .set_read_timeout(timeout)
+            .context("failed to set read timeout")
+    })?;
+    #[cfg(not(unix))]
+    let _ = (stream, timeout);
+
+    Ok(())
+}
+
+/// Runs `f` on the descriptor of `stream` borrowed as a std stream.
+#[cfg(unix)]
+fn with_unix_stream<T>(
+    stream: &interprocess::local_socket::Stream,
+    f: impl FnOnce(&std::os::unix::net::UnixStream) -> T,
+) -> T {
+    use std::{
+        mem::ManuallyDrop,
+        os::{
+            fd::{AsFd, AsRawFd, FromRawFd},
+            unix::net::UnixStream,
+        },
+    };
+
+    let interprocess::local_socket::Stream::UdSocket(socket) = stream;
+    // Borrow the descriptor as a std stream without taking ownership.
+    let socket = ManuallyDrop::new(unsafe { UnixStream::from_raw_fd(socket.as_fd().as_raw_fd()) });
+    f(&socket)
 }
 
 /// Sends one request and waits for one response over the same stream.
@@ -194,3 +801,51 @@ pub fn client_roundtrip(
     let reader = BufReader::new(&*stream);
     read_json_line(reader)
 }
+
+/// Opens a session by exchanging protocol versions with the daemon.
+///
+/// A daemon predating the handshake rejects [`Request::Hello`] as an invalid
+/// request and is reported as [`HelloResponse::LEGACY`]; any other error
+/// response, such as a busy daemon refusing the connection, is returned as an
+/// error.
+///
+/// # Errors
+///
+/// Returns an error if the exchange fails or the two sides are incompatible,
+/// see [`HelloResponse::check_compatible`].
+pub fn client_hello(stream: &mut interprocess::local_socket::Stream) -> Result<HelloResponse> {
+    let req = Request::Hello(HelloRequest {
+        version: PROTOCOL_VERSION,
+    });
+    let hello = match client_roundtrip(stream, &req).context("protocol handshake failed")? {
+        Response::Hello(hello) => hello,
+        Response::Err(err) if err.message.starts_with("invalid request") => HelloResponse::LEGACY,
+        Response::Err(err) => bail!("{}", err.message),
+        other => bail!("unexpected response to hello: {other:?}"),
+    };
+    hello.check_compatible()?;
+    Ok(hello)
+}
+
+/// Sends every request in `reqs` over the same stream and collects one
+/// response per request, in order.
+///
+/// Each request is written after the previous response has been read, so
+/// neither side can stall on a full pipe buffer.
+///
+/// # Errors
+///
+/// Returns an error if writing a request fails, reading fails, or JSON parsing fails.
+pub fn client_stream(
+    stream: &mut interprocess::local_socket::Stream,
+    reqs: &[Request],
+) -> Result<Vec<Response>> {
+    let stream = &*stream;
+    let mut reader = BufReader::new(stream);
+    reqs.iter()
+        .map(|req| {
+            write_json_line(stream, req)?;
+            read_json_line(&mut reader)
+        })
+        .collect()
+}
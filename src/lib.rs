@@ -1,3 +1,6 @@
 pub mod ipc;
 pub mod platform;
 pub mod policy;
+pub mod router;
+
+pub use router::PolicyRouter;
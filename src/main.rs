@@ -1,8 +1,15 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
 use anyhow::{Context, Result};
-use clap::Parser;
-use policy_router_rs::policy::{config::AppConfig, engine};
+use clap::{Parser, ValueEnum};
+use policy_router_rs::policy::{
+    config::{AppConfig, EgressKind},
+    engine,
+};
+use serde::Serialize;
 
 #[derive(Debug, Parser)]
 #[command(version, about = "Policy engine CLI (debug tool).")]
@@ -18,17 +25,64 @@ struct Args {
     /// Domain (example: youtube.com)
     #[arg(long)]
     domain: Option<String>,
+
+    /// Only load and validate the config, printing OK and the egress count
+    #[arg(long, conflicts_with_all = ["process", "domain"])]
+    check: bool,
+
+    /// How to report the decision
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Exit with status 3 when the decision blocks the flow, 0 when it routes it
+    #[arg(long, conflicts_with = "check")]
+    exit_status: bool,
 }
 
-fn main() -> Result<()> {
-    tracing_subscriber::fmt().without_time().compact().init();
+/// Exit status of a blocked decision under `--exit-status`; 1 stays reserved
+/// for errors.
+const BLOCKED_EXIT_CODE: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Log the decision like every other message
+    Text,
+    /// Print the decision as one JSON object on stdout, logging to stderr
+    Json,
+}
+
+/// The decision printed by `--format json`, named like the IPC `DecisionInfo`.
+#[derive(Debug, Serialize)]
+struct DecisionOutput<'a> {
+    egress: &'a str,
+    egress_type: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    endpoint: Option<&'a str>,
+    reason: String,
+    /// Stable key of the deciding stage, such as `domain_rule`.
+    source: &'static str,
+}
 
+fn main() -> Result<ExitCode> {
     let args = Args::parse();
+    let logs = tracing_subscriber::fmt().without_time().compact();
+    if args.format == Format::Json {
+        logs.with_writer(std::io::stderr).init();
+    } else {
+        logs.init();
+    }
+
     let config_path = resolve_config_path(args.config.as_deref())?;
 
     tracing::info!(config = %config_path.display(), "using config");
+    // Loading runs `AppConfig::validate` on the merged config.
     let cfg = AppConfig::load_from_path(&config_path)?;
 
+    if args.check {
+        println!("OK: {} egress", cfg.egress.len());
+        return Ok(ExitCode::SUCCESS);
+    }
+
     let decision = engine::decide(&cfg, args.process.as_deref(), args.domain.as_deref());
 
     let egress_id = decision.egress.clone();
@@ -36,6 +90,24 @@ fn main() -> Result<()> {
         .egress
         .get(&egress_id)
         .with_context(|| format!("egress id {egress_id:?} not found in config"))?;
+    let status = if args.exit_status && matches!(spec.kind, EgressKind::Block) {
+        ExitCode::from(BLOCKED_EXIT_CODE)
+    } else {
+        ExitCode::SUCCESS
+    };
+
+    if args.format == Format::Json {
+        let output = DecisionOutput {
+            egress: &egress_id.0,
+            egress_type: spec.kind.as_str(),
+            endpoint: spec.endpoint.as_deref(),
+            reason: decision.reason.to_human(),
+            source: decision.reason.reason_code(),
+        };
+        let json = serde_json::to_string(&output).context("failed to serialize decision")?;
+        println!("{json}");
+        return Ok(status);
+    }
 
     tracing::info!(
         egress = %egress_id.0,
@@ -45,7 +117,7 @@ fn main() -> Result<()> {
         "decision"
     );
 
-    Ok(())
+    Ok(status)
 }
 
 fn resolve_config_path(override_path: Option<&Path>) -> Result<PathBuf> {
@@ -0,0 +1,106 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use interprocess::local_socket::Stream;
+
+use super::{PeerUser, ProcessInfo, ProcessLookup, Protocol};
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(1);
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// Memoizes [`ProcessLookup::lookup_client_process`] results per address and
+/// protocol.
+///
+/// Entries expire after the TTL. Once `capacity` entries are live, the oldest
+/// one is evicted. Errors are never cached, and IPC peer lookups pass through.
+pub struct CachingProcessLookup {
+    inner: Box<dyn ProcessLookup>,
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<(SocketAddr, Protocol), CacheEntry>>,
+}
+
+#[derive(Clone)]
+struct CacheEntry {
+    inserted: Instant,
+    info: Option<ProcessInfo>,
+}
+
+impl CachingProcessLookup {
+    #[must_use]
+    pub fn new(inner: Box<dyn ProcessLookup>, ttl: Duration, capacity: usize) -> Self {
+        Self {
+            inner,
+            ttl,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn fresh_entry(&self, key: (SocketAddr, Protocol), now: Instant) -> Option<CacheEntry> {
+        let entry = self.entries.lock().ok()?.get(&key)?.clone();
+        (now.duration_since(entry.inserted) < self.ttl).then_some(entry)
+    }
+
+    fn insert(&self, key: (SocketAddr, Protocol), info: Option<ProcessInfo>, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, entry| now.duration_since(entry.inserted) < self.ttl);
+        }
+        while entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.inserted)
+                .map(|(key, _)| *key)
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+
+        entries.insert(
+            key,
+            CacheEntry {
+                inserted: now,
+                info,
+            },
+        );
+    }
+}
+
+impl ProcessLookup for CachingProcessLookup {
+    fn lookup_client_process(
+        &self,
+        client_addr: SocketAddr,
+        protocol: Protocol,
+    ) -> Result<Option<ProcessInfo>> {
+        let now = Instant::now();
+        let key = (client_addr, protocol);
+        if let Some(entry) = self.fresh_entry(key, now) {
+            return Ok(entry.info);
+        }
+
+        let info = self.inner.lookup_client_process(client_addr, protocol)?;
+        self.insert(key, info.clone(), now);
+        Ok(info)
+    }
+
+    fn lookup_ipc_peer(&self, conn: &Stream) -> Result<Option<ProcessInfo>> {
+        self.inner.lookup_ipc_peer(conn)
+    }
+
+    fn lookup_ipc_peer_user(&self, conn: &Stream) -> Result<Option<PeerUser>> {
+        self.inner.lookup_ipc_peer_user(conn)
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    ffi::CStr,
+    fs, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::fd::{AsFd, AsRawFd},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use interprocess::local_socket::Stream;
+
+use super::{PeerUser, ProcessInfo, ProcessLookup, Protocol};
+
+const UNKNOWN_EXE: &str = "<unknown>";
+
+pub struct LinuxProcessLookup;
+
+impl LinuxProcessLookup {
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+impl ProcessLookup for LinuxProcessLookup {
+    fn lookup_client_process(
+        &self,
+        client_addr: SocketAddr,
+        protocol: Protocol,
+    ) -> Result<Option<ProcessInfo>> {
+        let Some(inode) = lookup_inode_by_local_endpoint(client_addr, protocol)? else {
+            return Ok(None);
+        };
+
+        let Some(pid) = lookup_pid_by_socket_inode(inode)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(ProcessInfo {
+            pid,
+            exe: query_process_exe(pid)?,
+        }))
+    }
+
+    fn lookup_ipc_peer(&self, conn: &Stream) -> Result<Option<ProcessInfo>> {
+        let Some(pid) = u32::try_from(peer_cred(conn)?.pid)
+            .ok()
+            .filter(|&pid| pid != 0)
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(ProcessInfo {
+            pid,
+            exe: query_process_exe(pid)?,
+        }))
+    }
+
+    fn lookup_ipc_peer_user(&self, conn: &Stream) -> Result<Option<PeerUser>> {
+        let uid = peer_cred(conn)?.uid;
+        Ok(Some(PeerUser {
+            id: uid.to_string(),
+            name: user_name(uid)?,
+        }))
+    }
+}
+
+pub fn process_is_alive(pid: u32) -> bool {
+    pid != 0 && Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Reads the peer credentials of a Unix domain socket via `SO_PEERCRED`.
+fn peer_cred(conn: &Stream) -> Result<libc::ucred> {
+    let Stream::UdSocket(socket) = conn;
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = libc::socklen_t::try_from(size_of::<libc::ucred>())?;
+    let rc = unsafe {
+        libc::getsockopt(
+            socket.as_fd().as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&raw mut cred).cast(),
+            &raw mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error()).context("getsockopt(SO_PEERCRED) failed");
+    }
+
+    Ok(cred)
+}
+
+/// Looks the name of `uid` up in the user database; `None` for uids without
+/// an entry.
+fn user_name(uid: u32) -> Result<Option<String>> {
+    let mut buf = vec![0_u8; 1024];
+    loop {
+        let mut pwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+        let mut found = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwuid_r(
+                uid,
+                pwd.as_mut_ptr(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                &raw mut found,
+            )
+        };
+        if rc == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if rc != 0 {
+            return Err(io::Error::from_raw_os_error(rc))
+                .with_context(|| format!("getpwuid_r failed for uid {uid}"));
+        }
+        if found.is_null() {
+            return Ok(None);
+        }
+        // `found` points at `pwd`, whose strings live in `buf`.
+        let name = unsafe { CStr::from_ptr((*found).pw_name) };
+        return Ok(Some(name.to_string_lossy().into_owned()));
+    }
+}
+
+const SOCKET_TABLES: [(Protocol, &str); 4] = [
+    (Protocol::Tcp, "/proc/net/tcp"),
+    (Protocol::Tcp, "/proc/net/tcp6"),
+    (Protocol::Udp, "/proc/net/udp"),
+    (Protocol::Udp, "/proc/net/udp6"),
+];
+
+fn lookup_inode_by_local_endpoint(client: SocketAddr, protocol: Protocol) -> Result<Option<u64>> {
+    let client_ip = client.ip().to_canonical();
+
+    for (table_protocol, table) in SOCKET_TABLES {
+        if !protocol.includes(table_protocol) {
+            continue;
+        }
+        let raw = match fs::read_to_string(table) {
+            Ok(raw) => raw,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| format!("failed to read {table}")),
+        };
+
+        for line in raw.lines().skip(1) {
+            let Some((addr, inode)) = parse_socket_entry(line) else {
+                continue;
+            };
+            let ip = addr.ip().to_canonical();
+            let ip_matches =
+                ip == client_ip || (table_protocol == Protocol::Udp && ip.is_unspecified());
+            if inode != 0 && addr.port() == client.port() && ip_matches {
+                return Ok(Some(inode));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Parses the local address and inode columns of a `/proc/net/{tcp,udp}{,6}`
+/// row; both tables share the layout.
+fn parse_socket_entry(line: &str) -> Option<(SocketAddr, u64)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let (ip_hex, port_hex) = fields.get(1)?.split_once(':')?;
+    let inode = fields.get(9)?.parse().ok()?;
+
+    let port = u16::from_str_radix(port_hex, 16).ok()?;
+    let ip = match ip_hex.len() {
+        8 => IpAddr::V4(Ipv4Addr::from(
+            u32::from_str_radix(ip_hex, 16).ok()?.to_ne_bytes(),
+        )),
+        32 => {
+            // Four 32-bit words, each in host byte order.
+            let mut octets = [0u8; 16];
+            for (chunk, word) in octets.as_chunks_mut::<4>().0.iter_mut().zip(0..) {
+                let start = word * 8;
+                let value = u32::from_str_radix(ip_hex.get(start..start + 8)?, 16).ok()?;
+                chunk.copy_from_slice(&value.to_ne_bytes());
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+
+    Some((SocketAddr::new(ip, port), inode))
+}
+
+fn lookup_pid_by_socket_inode(inode: u64) -> Result<Option<u32>> {
+    let target = format!("socket:[{inode}]");
+
+    for entry in fs::read_dir("/proc").context("failed to read /proc")? {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u32>().ok())
+        else {
+            continue;
+        };
+
+        // Processes we may not inspect, or that exited meanwhile, are skipped.
+        let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            if fs::read_link(fd.path()).is_ok_and(|link| link == Path::new(&target)) {
+                return Ok(Some(pid));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn query_process_exe(pid: u32) -> Result<String> {
+    match fs::read_link(format!("/proc/{pid}/exe")) {
+        Ok(path) => Ok(path.display().to_string()),
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => Ok(UNKNOWN_EXE.to_string()),
+        Err(err) => {
+            Err(anyhow::Error::new(err)).with_context(|| format!("readlink failed for pid {pid}"))
+        }
+    }
+}
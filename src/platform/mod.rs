@@ -1,4 +1,5 @@
 use anyhow::Result;
+use interprocess::local_socket::Stream;
 
 #[derive(Debug, Clone)]
 pub struct ProcessInfo {
@@ -6,9 +7,40 @@ pub struct ProcessInfo {
     pub exe: String,
 }
 
+/// Account a process runs as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerUser {
+    /// Numeric uid on Unix, SID such as `S-1-5-18` on Windows.
+    pub id: String,
+    /// Account name when it resolves, `DOMAIN\name` on Windows.
+    pub name: Option<String>,
+}
+
+/// Transport of the client socket a lookup searches for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    /// TCP sockets first, then UDP ones.
+    #[default]
+    Any,
+}
+
+impl Protocol {
+    /// Whether sockets of `self` are searched for `candidate`.
+    #[must_use]
+    pub fn includes(self, candidate: Self) -> bool {
+        self == Self::Any || self == candidate
+    }
+}
+
 /// Lookup client process metadata for a given socket address.
 pub trait ProcessLookup: Send + Sync + 'static {
-    /// Returns the process metadata for the client address when available.
+    /// Returns the process metadata for the client address when available,
+    /// searching only sockets of `protocol`.
+    ///
+    /// UDP sockets bound to the unspecified address match any client IP with
+    /// the same port.
     ///
     /// # Errors
     ///
@@ -16,12 +48,50 @@ pub trait ProcessLookup: Send + Sync + 'static {
     fn lookup_client_process(
         &self,
         client_addr: std::net::SocketAddr,
+        protocol: Protocol,
     ) -> Result<Option<ProcessInfo>>;
+
+    /// Returns the process metadata of the peer of an accepted IPC connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the platform-specific lookup fails.
+    fn lookup_ipc_peer(&self, conn: &Stream) -> Result<Option<ProcessInfo>>;
+
+    /// Returns the account the peer of an accepted IPC connection runs as.
+    ///
+    /// Platforms without peer credentials report `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the platform-specific lookup fails.
+    fn lookup_ipc_peer_user(&self, _conn: &Stream) -> Result<Option<PeerUser>> {
+        Ok(None)
+    }
 }
 
+/// Returns the platform lookup wrapped in a [`CachingProcessLookup`] with the
+/// default TTL and capacity.
 #[must_use]
 pub fn process_lookup() -> Box<dyn ProcessLookup> {
-    platform_process_lookup()
+    Box::new(CachingProcessLookup::new(
+        platform_process_lookup(),
+        cache::DEFAULT_TTL,
+        cache::DEFAULT_CAPACITY,
+    ))
+}
+
+/// Reports whether a process with `pid` is currently running.
+///
+/// Platforms without a lookup report every pid as alive.
+#[must_use]
+pub fn process_is_alive(pid: u32) -> bool {
+    #[cfg(all(target_os = "windows", feature = "windows"))]
+    return windows::process_is_alive(pid);
+    #[cfg(target_os = "linux")]
+    return linux::process_is_alive(pid);
+    #[cfg(not(any(all(target_os = "windows", feature = "windows"), target_os = "linux")))]
+    return stub::process_is_alive(pid);
 }
 
 #[cfg(all(target_os = "windows", feature = "windows"))]
@@ -29,13 +99,25 @@ fn platform_process_lookup() -> Box<dyn ProcessLookup> {
     Box::new(windows::WindowsProcessLookup::new())
 }
 
-#[cfg(not(all(target_os = "windows", feature = "windows")))]
+#[cfg(target_os = "linux")]
+fn platform_process_lookup() -> Box<dyn ProcessLookup> {
+    Box::new(linux::LinuxProcessLookup::new())
+}
+
+#[cfg(not(any(all(target_os = "windows", feature = "windows"), target_os = "linux")))]
 fn platform_process_lookup() -> Box<dyn ProcessLookup> {
     Box::new(stub::StubProcessLookup)
 }
 
+pub mod cache;
+
+pub use cache::CachingProcessLookup;
+
 #[cfg(all(target_os = "windows", feature = "windows"))]
 mod windows;
 
-#[cfg(not(all(target_os = "windows", feature = "windows")))]
+#[cfg(target_os = "linux")]
+mod linux;
+
+#[cfg(not(any(all(target_os = "windows", feature = "windows"), target_os = "linux")))]
 mod stub;
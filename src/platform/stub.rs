@@ -1,6 +1,7 @@
 use anyhow::Result;
+use interprocess::local_socket::Stream;
 
-use super::{ProcessInfo, ProcessLookup};
+use super::{ProcessInfo, ProcessLookup, Protocol};
 
 pub struct StubProcessLookup;
 
@@ -8,7 +9,16 @@ impl ProcessLookup for StubProcessLookup {
     fn lookup_client_process(
         &self,
         _client_addr: std::net::SocketAddr,
+        _protocol: Protocol,
     ) -> Result<Option<ProcessInfo>> {
         Ok(None)
     }
+
+    fn lookup_ipc_peer(&self, _conn: &Stream) -> Result<Option<ProcessInfo>> {
+        Ok(None)
+    }
+}
+
+pub const fn process_is_alive(_pid: u32) -> bool {
+    true
 }
@@ -1,19 +1,32 @@
-use std::net::SocketAddr;
+use std::{
+    net::SocketAddr,
+    os::windows::io::{AsHandle, AsRawHandle},
+};
 
 use anyhow::{Context, Result};
+use interprocess::local_socket::Stream;
 use netstat2::{AddressFamilyFlags, ProtocolFlags, ProtocolSocketInfo, get_sockets_info};
 use windows::{
     Win32::{
-        Foundation::{CloseHandle, ERROR_ACCESS_DENIED, ERROR_INSUFFICIENT_BUFFER, HANDLE},
-        System::Threading::{
-            OpenProcess, PROCESS_NAME_FORMAT, PROCESS_QUERY_LIMITED_INFORMATION,
-            QueryFullProcessImageNameW,
+        Foundation::{
+            CloseHandle, ERROR_ACCESS_DENIED, ERROR_INSUFFICIENT_BUFFER, HANDLE, HLOCAL, LocalFree,
+        },
+        Security::{
+            Authorization::ConvertSidToStringSidW, GetTokenInformation, LookupAccountSidW, PSID,
+            SID_NAME_USE, TOKEN_QUERY, TOKEN_USER, TokenUser,
+        },
+        System::{
+            Pipes::GetNamedPipeClientProcessId,
+            Threading::{
+                GetExitCodeProcess, OpenProcess, OpenProcessToken, PROCESS_NAME_FORMAT,
+                PROCESS_QUERY_LIMITED_INFORMATION, QueryFullProcessImageNameW,
+            },
         },
     },
-    core::{Error as WindowsError, HRESULT, PWSTR},
+    core::{Error as WindowsError, HRESULT, PCWSTR, PWSTR},
 };
 
-use super::{ProcessInfo, ProcessLookup};
+use super::{PeerUser, ProcessInfo, ProcessLookup, Protocol};
 
 const UNKNOWN_EXE: &str = "<unknown>";
 
@@ -26,8 +39,12 @@ impl WindowsProcessLookup {
 }
 
 impl ProcessLookup for WindowsProcessLookup {
-    fn lookup_client_process(&self, client_addr: SocketAddr) -> Result<Option<ProcessInfo>> {
-        let Some(pid) = lookup_pid_by_local_endpoint(client_addr)? else {
+    fn lookup_client_process(
+        &self,
+        client_addr: SocketAddr,
+        protocol: Protocol,
+    ) -> Result<Option<ProcessInfo>> {
+        let Some(pid) = lookup_pid_by_local_endpoint(client_addr, protocol)? else {
             return Ok(None);
         };
 
@@ -37,21 +54,138 @@ impl ProcessLookup for WindowsProcessLookup {
 
         Ok(query_process_image_path(pid)?.map(|exe| ProcessInfo { pid, exe }))
     }
+
+    fn lookup_ipc_peer(&self, conn: &Stream) -> Result<Option<ProcessInfo>> {
+        let pid = named_pipe_client_pid(conn)?;
+        if pid == 0 {
+            return Ok(None);
+        }
+
+        Ok(query_process_image_path(pid)?.map(|exe| ProcessInfo { pid, exe }))
+    }
+
+    fn lookup_ipc_peer_user(&self, conn: &Stream) -> Result<Option<PeerUser>> {
+        let pid = named_pipe_client_pid(conn)?;
+        if pid == 0 {
+            return Ok(None);
+        }
+
+        query_process_user(pid).map(Some)
+    }
+}
+
+fn named_pipe_client_pid(conn: &Stream) -> Result<u32> {
+    let Stream::NamedPipe(pipe) = conn;
+    let mut pid = 0;
+    unsafe { GetNamedPipeClientProcessId(HANDLE(pipe.as_handle().as_raw_handle()), &mut pid) }
+        .context("GetNamedPipeClientProcessId failed")?;
+    Ok(pid)
+}
+
+/// Reads the user of the token of process `pid`.
+fn query_process_user(pid: u32) -> Result<PeerUser> {
+    let process = Handle::new(
+        unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }
+            .with_context(|| format!("OpenProcess failed for pid {pid}"))?,
+    );
+    let mut token = HANDLE::default();
+    unsafe { OpenProcessToken(process.handle(), TOKEN_QUERY, &mut token) }
+        .with_context(|| format!("OpenProcessToken failed for pid {pid}"))?;
+    let token = Handle::new(token);
+
+    // The first call fails, reporting the size the token user needs.
+    let mut len = 0;
+    let _ = unsafe { GetTokenInformation(token.handle(), TokenUser, None, 0, &mut len) };
+    // `u64` words keep the buffer aligned for `TOKEN_USER`.
+    let mut buffer = vec![0u64; (len as usize).div_ceil(size_of::<u64>())];
+    unsafe {
+        GetTokenInformation(
+            token.handle(),
+            TokenUser,
+            Some(buffer.as_mut_ptr().cast()),
+            len,
+            &mut len,
+        )
+    }
+    .with_context(|| format!("GetTokenInformation failed for pid {pid}"))?;
+    let sid = unsafe { (*buffer.as_ptr().cast::<TOKEN_USER>()).User.Sid };
+
+    let mut raw = PWSTR::null();
+    unsafe { ConvertSidToStringSidW(sid, &mut raw) }.context("ConvertSidToStringSidW failed")?;
+    let id = unsafe { raw.to_string() };
+    unsafe { LocalFree(Some(HLOCAL(raw.0.cast()))) };
+
+    Ok(PeerUser {
+        id: id.context("SID string is not valid UTF-16")?,
+        name: account_name(sid),
+    })
 }
 
-fn lookup_pid_by_local_endpoint(client: SocketAddr) -> Result<Option<u32>> {
-    let sockets = get_sockets_info(
-        AddressFamilyFlags::IPV4 | AddressFamilyFlags::IPV6,
-        ProtocolFlags::TCP,
-    )
-    .context("get_sockets_info failed")?;
+/// Resolves `sid` to `DOMAIN\name`; `None` when it has no account.
+fn account_name(sid: PSID) -> Option<String> {
+    let (mut name_len, mut domain_len) = (0, 0);
+    let mut kind = SID_NAME_USE::default();
+    // The first call fails, reporting the sizes both names need.
+    let _ = unsafe {
+        LookupAccountSidW(
+            PCWSTR::null(),
+            sid,
+            None,
+            &mut name_len,
+            None,
+            &mut domain_len,
+            &mut kind,
+        )
+    };
+    let mut name = vec![0u16; name_len as usize];
+    let mut domain = vec![0u16; domain_len as usize];
+    unsafe {
+        LookupAccountSidW(
+            PCWSTR::null(),
+            sid,
+            Some(PWSTR(name.as_mut_ptr())),
+            &mut name_len,
+            Some(PWSTR(domain.as_mut_ptr())),
+            &mut domain_len,
+            &mut kind,
+        )
+    }
+    .ok()?;
 
-    for socket in sockets {
-        let ProtocolSocketInfo::Tcp(tcp) = socket.protocol_socket_info else {
+    let name = String::from_utf16_lossy(&name[..name_len as usize]);
+    let domain = String::from_utf16_lossy(&domain[..domain_len as usize]);
+    Some(if domain.is_empty() {
+        name
+    } else {
+        format!("{domain}\\{name}")
+    })
+}
+
+fn lookup_pid_by_local_endpoint(client: SocketAddr, protocol: Protocol) -> Result<Option<u32>> {
+    // Queried one protocol at a time so TCP sockets always win under `Any`.
+    for (table_protocol, flags) in [
+        (Protocol::Tcp, ProtocolFlags::TCP),
+        (Protocol::Udp, ProtocolFlags::UDP),
+    ] {
+        if !protocol.includes(table_protocol) {
             continue;
-        };
-        if tcp.local_addr == client.ip() && tcp.local_port == client.port() {
-            return Ok(socket.associated_pids.into_iter().next());
+        }
+        let sockets = get_sockets_info(AddressFamilyFlags::IPV4 | AddressFamilyFlags::IPV6, flags)
+            .context("get_sockets_info failed")?;
+
+        for socket in sockets {
+            let matches = match &socket.protocol_socket_info {
+                ProtocolSocketInfo::Tcp(tcp) => {
+                    tcp.local_addr == client.ip() && tcp.local_port == client.port()
+                }
+                ProtocolSocketInfo::Udp(udp) => {
+                    (udp.local_addr == client.ip() || udp.local_addr.is_unspecified())
+                        && udp.local_port == client.port()
+                }
+            };
+            if matches {
+                return Ok(socket.associated_pids.into_iter().next());
+            }
         }
     }
 
@@ -102,6 +236,23 @@ fn query_process_image_path(pid: u32) -> Result<Option<String>> {
     }
 }
 
+/// Exit code reported by `GetExitCodeProcess` while a process runs.
+const STILL_ACTIVE: u32 = 259;
+
+pub fn process_is_alive(pid: u32) -> bool {
+    if pid == 0 {
+        return false;
+    }
+    let process = match unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) } {
+        Ok(handle) => Handle::new(handle),
+        // The process exists but belongs to someone we cannot inspect.
+        Err(err) => return is_access_denied(&err),
+    };
+
+    let mut code = 0;
+    unsafe { GetExitCodeProcess(process.handle(), &mut code) }.is_ok_and(|()| code == STILL_ACTIVE)
+}
+
 fn is_access_denied(err: &WindowsError) -> bool {
     err.code() == HRESULT::from_win32(ERROR_ACCESS_DENIED.0)
 }
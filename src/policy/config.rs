@@ -1,16 +1,172 @@
-use std::{collections::BTreeMap, fmt, fs, path::Path};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs,
+    net::IpAddr,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Context, Result, anyhow, bail};
-use serde::{Deserialize, Serialize};
+use globset::{GlobBuilder, GlobMatcher};
+use ipnet::IpNet;
+use percent_encoding::percent_decode_str;
+use regex::{Regex, RegexBuilder};
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{self, DeserializeOwned, MapAccess, Visitor},
+};
+use tracing::warn;
+use url::{Host, Url};
 
-#[derive(Debug, Clone, Deserialize)]
+use super::{engine, schedule::Schedule};
+
+/// A config referencing an egress id that is not declared, kept apart from
+/// other validation errors so callers can tell the two apart by downcasting.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{message}")]
+pub struct UnknownEgressError {
+    /// The undeclared egress id.
+    pub egress: String,
+    message: String,
+}
+
+impl UnknownEgressError {
+    fn new(egress: &impl ToString, message: String) -> Self {
+        Self {
+            egress: egress.to_string(),
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct AppConfig {
+    /// Layout version of the file; older layouts are migrated to
+    /// [`CONFIG_VERSION`] at load.
+    ///
+    /// [`AppConfig::load_from_path`] and [`AppConfig::load_from_str`] take a
+    /// file without one as version 1. Deserializing an `AppConfig` directly
+    /// skips the migration, so it requires `version` to be
+    /// [`CONFIG_VERSION`].
+    #[serde(deserialize_with = "deserialize_current_version")]
+    pub version: u32,
     pub defaults: Defaults,
-    #[serde(default)]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_egress_map",
+        serialize_with = "serialize_egress_map"
+    )]
     pub egress: BTreeMap<EgressId, EgressSpec>,
+    /// Ordered egress lists that rules may target in place of one egress.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub egress_group: BTreeMap<EgressId, EgressGroup>,
     pub rules: Rules,
+    /// Old egress ids mapped to the canonical ids they now stand for.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<EgressId, EgressId>,
+    /// Which IPC clients may send which requests.
+    #[serde(default, skip_serializing_if = "Security::is_open")]
+    pub security: Security,
+    /// Further config files merged into this one, relative to the including
+    /// file.
+    ///
+    /// Emptied by [`AppConfig::load_from_path`] once the files are merged in.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Files that contributed to this config, entrypoint first.
+    ///
+    /// Filled by [`AppConfig::load_from_path`]; empty for configs parsed from
+    /// a string.
+    #[serde(skip)]
+    pub source_files: Vec<PathBuf>,
+    /// Rule patterns dropped at load for pointing at unknown egress ids.
+    #[serde(skip)]
+    pub dropped_rules: usize,
+}
+
+/// Newest config layout this build reads.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Steps upgrading a parsed config one layout at a time: entry `i` turns a
+/// version `i + 1` table into version `i + 2`. Renamed or reshaped fields get
+/// a step here along with a bump of [`CONFIG_VERSION`].
+const MIGRATIONS: &[fn(&mut toml::Table)] = &[spread_egress_priorities];
+
+/// Version 2 spaced the kind ranks out by [`PRIORITY_SPACING`] to fit http
+/// between singbox and socks5; explicit priorities are scaled to match, so
+/// they keep tying and beating the same kinds.
+fn spread_egress_priorities(table: &mut toml::Table) {
+    let Some(toml::Value::Table(egress)) = table.get_mut("egress") else {
+        return;
+    };
+    for (_, spec) in egress.iter_mut() {
+        if let Some(toml::Value::Integer(priority)) = spec.get_mut("priority") {
+            *priority = priority
+                .saturating_mul(i64::from(PRIORITY_SPACING))
+                .clamp(i64::from(i32::MIN), i64::from(i32::MAX));
+        }
+    }
+}
+
+/// Distance between the kind ranks of version 1 configs in the current
+/// scale.
+pub const PRIORITY_SPACING: i32 = 10;
+
+const _: () = assert!(MIGRATIONS.len() + 1 == CONFIG_VERSION as usize);
+
+/// Version of an entrypoint file that declares none, written before versions
+/// existed.
+const UNVERSIONED_CONFIG_VERSION: u32 = 1;
+
+fn deserialize_current_version<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version != CONFIG_VERSION {
+        return Err(de::Error::custom(format!(
+            "config version {version} is not the current version {CONFIG_VERSION}; \
+             load it with AppConfig::load_from_path or AppConfig::load_from_str"
+        )));
+    }
+    Ok(version)
+}
+
+/// Parses one config file, upgrading it first when its `version` predates
+/// [`CONFIG_VERSION`]. A file without one is taken as `unversioned`.
+///
+/// Returns the parsed file and the version it was written in.
+fn parse_config<T: DeserializeOwned>(raw: &str, unversioned: u32) -> Result<(T, u32)> {
+    let mut table: toml::Table = toml::from_str(raw)?;
+    let version = match table.get("version") {
+        None => unversioned,
+        Some(value) => value
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .filter(|&version| version >= 1)
+            .ok_or_else(|| anyhow!("config version must be a positive integer, got {value}"))?,
+    };
+    if version > CONFIG_VERSION {
+        bail!(
+            "config version {version} is newer than the newest supported version \
+             {CONFIG_VERSION}; upgrade policy-router to load it"
+        );
+    }
+    if version == CONFIG_VERSION {
+        // Parse the text itself so errors keep their line and column.
+        return Ok((toml::from_str(raw)?, version));
+    }
+
+    for migrate in MIGRATIONS.iter().skip(version as usize - 1) {
+        migrate(&mut table);
+    }
+    table.insert("version".to_owned(), i64::from(CONFIG_VERSION).into());
+    Ok((table.try_into()?, version))
 }
 
+/// Placeholder written over secrets by [`AppConfig::redact_secrets`].
+pub const REDACTED: &str = "<redacted>";
+
 impl AppConfig {
     /// Loads application configuration from a TOML file.
     ///
@@ -20,40 +176,633 @@ impl AppConfig {
     /// - the file cannot be read
     /// - the file contents are not valid UTF-8
     /// - the TOML cannot be parsed into [`AppConfig`]
+    /// - the file or an included one declares a `version` newer than
+    ///   [`CONFIG_VERSION`]
+    /// - an included file cannot be loaded or includes itself, directly or
+    ///   through other files
+    /// - an egress references an unset `${VAR}`
     pub fn load_from_path(path: &Path) -> Result<Self> {
         let raw = fs::read_to_string(path)
             .with_context(|| format!("failed to read config: {}", path.display()))?;
 
-        let cfg: Self = toml::from_str(&raw)
+        let (cfg, version): (Self, _) = parse_config(&raw, UNVERSIONED_CONFIG_VERSION)
             .with_context(|| format!("failed to parse TOML config: {}", path.display()))?;
 
-        cfg.validate()?;
+        let chain = vec![canonical_path(path)?];
+        cfg.finish_loading(path, version, vec![path.to_path_buf()], chain)
+    }
+
+    /// Loads application configuration from TOML text, such as a config
+    /// pushed over IPC, as though it were the contents of `path`: relative
+    /// includes, denylists and `@file:` entries resolve against its
+    /// directory. `path` itself is never read and need not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error on the same grounds as [`AppConfig::load_from_path`],
+    /// except that `path` is not read.
+    pub fn load_from_str(raw: &str, path: &Path) -> Result<Self> {
+        let (cfg, version): (Self, _) =
+            parse_config(raw, UNVERSIONED_CONFIG_VERSION).context("failed to parse TOML config")?;
+        let chain = canonical_path(path).into_iter().collect();
+        cfg.finish_loading(path, version, Vec::new(), chain)
+    }
+
+    /// Resolves, merges and validates a config parsed from `path`, written in
+    /// layout `version`; `files` and `chain` already hold `path` when it was
+    /// read from disk.
+    fn finish_loading(
+        mut self,
+        path: &Path,
+        version: u32,
+        mut files: Vec<PathBuf>,
+        mut chain: Vec<PathBuf>,
+    ) -> Result<Self> {
+        resolve_denylist_paths(&mut self.egress, path);
+        resolve_domain_files(&mut self.rules.domain, path);
+        let includes = std::mem::take(&mut self.include);
+        self.merge_includes(path, &includes, version, &mut files, &mut chain)?;
+
+        self.expand_env_vars()?;
+        self.expand_domain_files(&mut files);
+        self.append_denylists(&mut files);
+        self.validate()?;
+        self.resolve_aliases();
+        if self.defaults.unknown_egress == UnknownEgress::DropRule {
+            self.drop_unknown_egress_rules();
+        }
+        self.source_files = files;
+
+        Ok(self)
+    }
+
+    /// Loads every file in `includes`, relative to `from`, and merges it in
+    /// before its own includes. Files without a `version` are read as the
+    /// entrypoint's `version`. `chain` holds the files currently being
+    /// loaded, to reject circular includes.
+    fn merge_includes(
+        &mut self,
+        from: &Path,
+        includes: &[String],
+        version: u32,
+        files: &mut Vec<PathBuf>,
+        chain: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let base = from.parent().unwrap_or_else(|| Path::new(""));
+        for include in includes {
+            let path = base.join(include);
+            let canonical = canonical_path(&path)
+                .with_context(|| format!("included from {}", from.display()))?;
+            if chain.contains(&canonical) {
+                let cycle = chain
+                    .iter()
+                    .chain([&canonical])
+                    .map(|file| file.display().to_string())
+                    .collect::<Vec<_>>();
+                bail!("circular include: {}", cycle.join(" -> "));
+            }
+
+            let raw = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read config: {}", path.display()))?;
+            let (mut part, _): (IncludedConfig, _) = parse_config(&raw, version)
+                .with_context(|| format!("failed to parse TOML config: {}", path.display()))?;
+            resolve_denylist_paths(&mut part.egress, &path);
+            resolve_domain_files(&mut part.rules.domain, &path);
+
+            files.push(path.clone());
+            chain.push(canonical);
+            let nested = self.merge_included(part);
+            self.merge_includes(&path, &nested, version, files, chain)?;
+            chain.pop();
+        }
+        Ok(())
+    }
+
+    /// Merges an included file into this config and returns its own
+    /// includes. An egress, group or alias redefined later replaces the
+    /// earlier one, keeping its declaration position; rule patterns are
+    /// appended to those already listed for the egress.
+    fn merge_included(&mut self, part: IncludedConfig) -> Vec<String> {
+        let mut egress: Vec<_> = part.egress.into_iter().collect();
+        egress.sort_by_key(|(_, spec)| spec.declared_index);
+        for (id, mut spec) in egress {
+            spec.declared_index = self
+                .egress
+                .get(&id)
+                .map_or(self.egress.len(), |old| old.declared_index);
+            self.egress.insert(id, spec);
+        }
+        self.egress_group.extend(part.egress_group);
+        self.aliases.extend(part.aliases);
+
+        append_rules(&mut self.rules.app, part.rules.app);
+        append_rules(&mut self.rules.domain, part.rules.domain);
+        append_rules(&mut self.rules.cidr, part.rules.cidr);
+        append_rules(&mut self.rules.port, part.rules.port);
+        append_rules(&mut self.rules.app_meta, part.rules.app_meta);
+        append_rules(&mut self.rules.domain_meta, part.rules.domain_meta);
+
+        part.include
+    }
+
+    /// Replaces `${VAR}` references in egress endpoints and credentials with
+    /// the value of the environment variable `VAR`. Rule patterns are left
+    /// literal.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the egress and field if a referenced variable
+    /// is unset or a `${` is never closed.
+    pub fn expand_env_vars(&mut self) -> Result<()> {
+        for (id, spec) in &mut self.egress {
+            for (field, value) in [
+                ("endpoint", &mut spec.endpoint),
+                ("username", &mut spec.username),
+                ("password", &mut spec.password),
+            ] {
+                if let Some(value) = value {
+                    *value = expand_env(value).with_context(|| format!("egress '{id}' {field}"))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces every `@file:` domain entry whose file is readable by the
+    /// domains listed in it and records the file in `files`. Domains the
+    /// egress already lists are skipped; an unreadable file stays in place for
+    /// [`AppConfig::validate`] to report.
+    fn expand_domain_files(&mut self, files: &mut Vec<PathBuf>) {
+        let case_sensitive = self.defaults.case_sensitive_domains;
+        for patterns in self.rules.domain.values_mut() {
+            if patterns.iter().all(|pattern| pattern.file().is_none()) {
+                continue;
+            }
+            let mut listed: HashSet<String> = patterns
+                .iter()
+                .filter(|pattern| pattern.file().is_none())
+                .map(|pattern| normalize_domain(pattern.as_str(), case_sensitive))
+                .collect();
+
+            let mut expanded = Vec::with_capacity(patterns.len());
+            for pattern in std::mem::take(patterns) {
+                let Some(raw) = pattern
+                    .file()
+                    .and_then(|file| fs::read_to_string(file).ok())
+                else {
+                    expanded.push(pattern);
+                    continue;
+                };
+                files.push(PathBuf::from(pattern.file().unwrap_or_default()));
+                for domain in parse_denylist(&raw) {
+                    if listed.insert(normalize_domain(domain, case_sensitive)) {
+                        expanded.push(DomainPattern(domain.to_owned()));
+                    }
+                }
+            }
+            *patterns = expanded;
+        }
+    }
+
+    /// Appends the domains of every readable `denylist_file` to the domain
+    /// rules of its egress and records the file in `files`. Domains already
+    /// listed for the egress are skipped; an unreadable file is left for
+    /// [`AppConfig::validate`] to report.
+    fn append_denylists(&mut self, files: &mut Vec<PathBuf>) {
+        let case_sensitive = self.defaults.case_sensitive_domains;
+        for (id, spec) in &self.egress {
+            let Some(file) = &spec.denylist_file else {
+                continue;
+            };
+            let Ok(raw) = fs::read_to_string(file) else {
+                continue;
+            };
+            files.push(PathBuf::from(file));
+
+            let patterns = self.rules.domain.entry(id.clone()).or_default();
+            let mut listed: HashSet<String> = patterns
+                .iter()
+                .map(|pattern| normalize_domain(pattern.as_str(), case_sensitive))
+                .collect();
+            for domain in parse_denylist(&raw) {
+                if listed.insert(normalize_domain(domain, case_sensitive)) {
+                    patterns.push(DomainPattern(domain.to_owned()));
+                }
+            }
+        }
+    }
+
+    /// Serializes the config back to TOML.
+    ///
+    /// Load-time bookkeeping such as [`AppConfig::source_files`] is not
+    /// written. Egresses keep their declaration order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the config cannot be represented as TOML.
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).context("failed to serialize config as TOML")
+    }
+
+    /// Replaces every egress password with [`REDACTED`], for configs shown
+    /// outside the daemon.
+    pub fn redact_secrets(&mut self) {
+        for password in self
+            .egress
+            .values_mut()
+            .filter_map(|spec| spec.password.as_mut())
+        {
+            REDACTED.clone_into(password);
+        }
+    }
+
+    /// Rewrites every aliased egress id in defaults and rules to its
+    /// canonical id, appending aliased rule patterns after the canonical ones.
+    ///
+    /// Expects a config that passed [`AppConfig::validate`].
+    pub fn resolve_aliases(&mut self) {
+        if let Some(target) = self.aliases.get(&self.defaults.egress) {
+            self.defaults.egress = target.clone();
+        }
+        merge_aliased_rules(&mut self.rules.app, &self.aliases);
+        merge_aliased_rules(&mut self.rules.domain, &self.aliases);
+        merge_aliased_rules(&mut self.rules.cidr, &self.aliases);
+        merge_aliased_rules(&mut self.rules.port, &self.aliases);
+        merge_aliased_rules(&mut self.rules.app_meta, &self.aliases);
+        merge_aliased_rules(&mut self.rules.domain_meta, &self.aliases);
+    }
+
+    /// Removes rules whose egress id is neither declared nor aliased, logging
+    /// a warning per egress and counting dropped patterns in
+    /// [`AppConfig::dropped_rules`].
+    pub fn drop_unknown_egress_rules(&mut self) {
+        let egress = &self.egress;
+        let aliases = &self.aliases;
+        let groups = &self.egress_group;
+        let is_known = |id: &EgressId| {
+            egress.contains_key(id) || aliases.contains_key(id) || groups.contains_key(id)
+        };
+
+        self.dropped_rules = drop_unknown("app", &mut self.rules.app, is_known)
+            + drop_unknown("domain", &mut self.rules.domain, is_known)
+            + drop_unknown("cidr", &mut self.rules.cidr, is_known)
+            + drop_unknown("port", &mut self.rules.port, is_known);
+        self.rules.app_meta.retain(|id, _| is_known(id));
+        self.rules.domain_meta.retain(|id, _| is_known(id));
+    }
+
+    /// Rejects malformed domain patterns and lists made only of exclusions.
+    fn validate_domain_patterns(&self) -> Result<()> {
+        for (egress_id, patterns) in &self.rules.domain {
+            if !patterns.is_empty() && patterns.iter().all(|p| p.excluded().is_some()) {
+                bail!(
+                    "rules.domain for egress '{egress_id}' lists only '!' exclusions; they need a pattern to exclude from"
+                );
+            }
+            for (index, pattern) in patterns.iter().enumerate() {
+                if let Some(file) = pattern.file() {
+                    if let Err(err) = fs::File::open(file) {
+                        bail!(
+                            "rules.domain file '{file}' for egress '{egress_id}' cannot be read: {err}"
+                        );
+                    }
+                    continue;
+                }
+                let trimmed = pattern.as_str().trim();
+                let body = pattern.excluded().map_or(trimmed, str::trim);
+                if body.is_empty() {
+                    bail!("rules.domain entry at index {index} for egress '{egress_id}' is empty");
+                }
+                if body.contains('!') {
+                    bail!(
+                        "rules.domain pattern '{trimmed}' for egress '{egress_id}' may only use '!' as a leading exclusion"
+                    );
+                }
+                let rest = body.strip_prefix('*').unwrap_or(body);
+                if rest.contains('*') {
+                    bail!(
+                        "rules.domain pattern '{trimmed}' for egress '{egress_id}' may only use '*' as a leading wildcard"
+                    );
+                }
+                if rest.trim_matches('.').is_empty() {
+                    bail!(
+                        "rules.domain pattern '{trimmed}' for egress '{egress_id}' matches every domain"
+                    );
+                }
+                if rest
+                    .trim()
+                    .trim_matches('.')
+                    .split('.')
+                    .any(|label| label_to_ascii(label).is_none())
+                {
+                    bail!(
+                        "rules.domain pattern '{trimmed}' for egress '{egress_id}' is not a valid internationalized domain name"
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rejects `*_meta` descriptions of patterns their egress does not list.
+    fn validate_rule_meta(&self) -> Result<()> {
+        for (egress_id, meta) in &self.rules.app_meta {
+            let listed: Vec<&str> = self
+                .rules
+                .app
+                .get(egress_id)
+                .map_or_else(Vec::new, |patterns| {
+                    patterns.iter().map(AppPattern::as_str).collect()
+                });
+            check_meta("app", egress_id, meta, &listed)?;
+        }
+        for (egress_id, meta) in &self.rules.domain_meta {
+            let listed: Vec<&str> = self
+                .rules
+                .domain
+                .get(egress_id)
+                .map_or_else(Vec::new, |patterns| {
+                    patterns.iter().map(DomainPattern::as_str).collect()
+                });
+            check_meta("domain", egress_id, meta, &listed)?;
+        }
+        Ok(())
+    }
+
+    /// Rejects a pattern listed twice for one egress, comparing patterns the
+    /// way the engine sees them.
+    fn validate_unique_patterns(&self) -> Result<()> {
+        for (egress_id, patterns) in &self.rules.app {
+            if let Some((first, second)) = find_duplicate(patterns, AppPattern::normalized) {
+                bail!(
+                    "rules.app pattern '{}' for egress '{egress_id}' is duplicated at indices {first} and {second}",
+                    patterns[second].as_str().trim()
+                );
+            }
+        }
+
+        let case_sensitive = self.defaults.case_sensitive_domains;
+        for (egress_id, patterns) in &self.rules.domain {
+            if let Some((first, second)) = find_duplicate(patterns, |p: &DomainPattern| {
+                normalize_domain(p.as_str(), case_sensitive)
+            }) {
+                bail!(
+                    "rules.domain pattern '{}' for egress '{egress_id}' is duplicated at indices {first} and {second}",
+                    patterns[second].as_str().trim()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reports non-fatal config smells, one message each.
+    ///
+    /// Currently flags a pattern listed under several egresses, naming the
+    /// egress whose rule wins; the other rules for it never fire.
+    #[must_use]
+    pub fn lints(&self) -> Vec<String> {
+        let mut lints = shadowed_patterns(self, "app", &self.rules.app, |p: &AppPattern| {
+            Some(p.normalized())
+        });
+        lints.extend(shadowed_patterns(
+            self,
+            "domain",
+            &self.rules.domain,
+            |p: &DomainPattern| {
+                p.excluded()
+                    .is_none()
+                    .then(|| normalize_domain(p.as_str(), self.defaults.case_sensitive_domains))
+            },
+        ));
+        lints
+    }
+
+    fn is_known_egress(&self, id: &EgressId) -> bool {
+        self.egress.contains_key(id) || self.aliases.contains_key(id)
+    }
+
+    /// Resolves a rule egress id to the egress it routes to: the first
+    /// member for a group, the id itself otherwise.
+    #[must_use]
+    pub fn resolve_group<'a>(&'a self, id: &'a EgressId) -> &'a EgressId {
+        self.egress_group
+            .get(id)
+            .and_then(|group| group.members.first())
+            .unwrap_or(id)
+    }
+
+    /// Returns the spec of the egress `id` routes to, resolving groups.
+    #[must_use]
+    pub fn resolved_spec(&self, id: &EgressId) -> Option<&EgressSpec> {
+        self.egress.get(self.resolve_group(id))
+    }
+
+    fn validate_egress_ids(&self) -> Result<()> {
+        let ids = self
+            .egress
+            .keys()
+            .chain(self.egress_group.keys())
+            .chain(self.aliases.keys())
+            .chain(std::iter::once(&self.defaults.egress))
+            .chain(self.rules.app.keys())
+            .chain(self.rules.domain.keys())
+            .chain(self.rules.cidr.keys())
+            .chain(self.rules.port.keys());
+        for id in ids {
+            if !id.is_valid() {
+                bail!("egress id '{id}' may only contain ASCII letters, digits, '-' and '_'");
+            }
+        }
+        Ok(())
+    }
 
-        Ok(cfg)
+    fn validate_egress_groups(&self) -> Result<()> {
+        for (group_id, group) in &self.egress_group {
+            if self.is_known_egress(group_id) {
+                bail!("egress_group '{group_id}' shadows an egress id or alias");
+            }
+            if group.members.is_empty() {
+                bail!("egress_group '{group_id}' has no members");
+            }
+            for member in &group.members {
+                if self.egress_group.contains_key(member) {
+                    bail!(
+                        "egress_group '{group_id}' member '{member}' is a group; groups cannot be nested"
+                    );
+                }
+                if !self.egress.contains_key(member) {
+                    return Err(UnknownEgressError::new(
+                        member,
+                        format!(
+                            "egress_group '{group_id}' member '{member}' is not declared under [egress.*]"
+                        ),
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(())
     }
 
     /// Validates configuration invariants.
     ///
     /// # Errors
     ///
-    /// Returns an error if defaults or rules reference unknown egress ids.
+    /// Returns an error if defaults or rules reference unknown egress ids, or
+    /// if an alias shadows a declared egress or targets an undeclared one.
     pub fn validate(&self) -> Result<()> {
-        if !self.egress.contains_key(&self.defaults.egress) {
-            bail!(
-                "defaults.egress '{}' is not declared under [egress.*]",
-                self.defaults.egress
-            );
+        self.validate_egress_ids()?;
+
+        for (alias, target) in &self.aliases {
+            if self.egress.contains_key(alias) {
+                bail!("alias '{alias}' shadows an egress declared under [egress.*]");
+            }
+            if !self.egress.contains_key(target) {
+                return Err(UnknownEgressError::new(
+                    target,
+                    format!("alias '{alias}' targets unknown egress id '{target}'"),
+                )
+                .into());
+            }
+        }
+
+        if !self.is_known_egress(&self.defaults.egress) {
+            return Err(UnknownEgressError::new(
+                &self.defaults.egress,
+                format!(
+                    "defaults.egress '{}' is not declared under [egress.*]",
+                    self.defaults.egress
+                ),
+            )
+            .into());
+        }
+
+        let rule_egresses = self.rules.app.keys().chain(self.rules.domain.keys());
+        if self.defaults.on_no_match == OnNoMatch::Block
+            && !self
+                .egress
+                .values()
+                .any(|spec| matches!(spec.kind, EgressKind::Block))
+        {
+            bail!("defaults.on_no_match = \"block\" requires an egress of type \"block\"");
+        }
+
+        self.validate_egress_groups()?;
+
+        for egress_id in rule_egresses
+            .chain(self.rules.cidr.keys())
+            .chain(self.rules.port.keys())
+        {
+            if self.defaults.unknown_egress == UnknownEgress::Error
+                && !self.is_known_egress(egress_id)
+                && !self.egress_group.contains_key(egress_id)
+            {
+                return Err(UnknownEgressError::new(
+                    egress_id,
+                    format!(
+                        "rules reference unknown egress id '{egress_id}' (missing under [egress.*])"
+                    ),
+                )
+                .into());
+            }
+        }
+
+        self.validate_egress_specs()?;
+
+        self.validate_app_patterns()?;
+        self.validate_domain_patterns()?;
+        self.validate_unique_patterns()?;
+        self.validate_rule_meta()?;
+
+        for (egress_id, patterns) in &self.rules.cidr {
+            for pattern in patterns {
+                if let Err(err) = pattern.parse() {
+                    bail!(
+                        "rules.cidr pattern '{}' for egress '{egress_id}' is not a valid CIDR: {err}",
+                        pattern.as_str().trim()
+                    );
+                }
+            }
+        }
+
+        self.validate_port_patterns()?;
+
+        if self.defaults.use_psl {
+            self.validate_psl_patterns()?;
+        }
+
+        self.security.validate()?;
+
+        Ok(())
+    }
+
+    fn validate_port_patterns(&self) -> Result<()> {
+        for (egress_id, patterns) in &self.rules.port {
+            for pattern in patterns {
+                if let Err(err) = pattern.parse() {
+                    bail!(
+                        "rules.port pattern '{}' for egress '{egress_id}' is not a valid port or range: {err}",
+                        pattern.as_str().trim()
+                    );
+                }
+            }
         }
+        Ok(())
+    }
 
-        for egress_id in self.rules.app.keys().chain(self.rules.domain.keys()) {
-            if !self.egress.contains_key(egress_id) {
-                bail!("rules reference unknown egress id '{egress_id}' (missing under [egress.*])");
+    fn validate_app_patterns(&self) -> Result<()> {
+        for (egress_id, patterns) in &self.rules.app {
+            for (index, pattern) in patterns.iter().enumerate() {
+                if pattern.as_str().trim().is_empty() {
+                    bail!("rules.app entry at index {index} for egress '{egress_id}' is empty");
+                }
+                let (name, cmdline) = pattern.parts();
+                if name.is_empty() || cmdline.is_some_and(str::is_empty) {
+                    bail!(
+                        "rules.app pattern '{}' for egress '{egress_id}' needs both a name and a command-line substring around '::'",
+                        pattern.as_str().trim()
+                    );
+                }
+                if pattern.regex_source().is_some_and(str::is_empty) {
+                    bail!("rules.app regex at index {index} for egress '{egress_id}' is empty");
+                }
+                if pattern
+                    .dir()
+                    .is_some_and(|dir| normalize_process_dir(dir).is_empty())
+                {
+                    bail!(
+                        "rules.app dir pattern at index {index} for egress '{egress_id}' names no directory"
+                    );
+                }
+                if pattern.extension().is_some_and(|ext| ext.trim().is_empty()) {
+                    bail!(
+                        "rules.app extension pattern at index {index} for egress '{egress_id}' names no extension"
+                    );
+                }
+                if let Some(Err(err)) = pattern.compile_regex() {
+                    bail!(
+                        "rules.app regex '{}' for egress '{egress_id}' is invalid: {err}",
+                        pattern.as_str().trim()
+                    );
+                }
+                if let Some(Err(err)) = pattern.compile_glob() {
+                    bail!(
+                        "rules.app glob '{}' for egress '{egress_id}' is invalid: {err}",
+                        pattern.as_str().trim()
+                    );
+                }
             }
         }
 
+        Ok(())
+    }
+
+    fn validate_egress_specs(&self) -> Result<()> {
         for (egress_id, spec) in &self.egress {
             match spec.kind {
-                EgressKind::Singbox | EgressKind::Socks5 => {
+                EgressKind::Singbox | EgressKind::Http | EgressKind::Socks5 => {
                     let endpoint = spec.endpoint.as_deref().ok_or_else(|| {
                         anyhow!(
                             "egress '{egress_id}' ({}) requires endpoint",
@@ -73,13 +822,36 @@ impl AppConfig {
                             spec.kind.as_str()
                         )
                     })?;
-                    if scheme != "socks5" {
+                    let schemes = spec.kind.endpoint_schemes();
+                    if !schemes.contains(&scheme.as_str()) {
+                        bail!(
+                            "egress '{egress_id}' ({}) must use {} scheme, got '{scheme}'",
+                            spec.kind.as_str(),
+                            schemes.join(" or ")
+                        );
+                    }
+                    if spec.username.is_some() != spec.password.is_some() {
                         bail!(
-                            "egress '{egress_id}' ({}) must use socks5 scheme, got '{scheme}'",
+                            "egress '{egress_id}' ({}) requires both username and password",
                             spec.kind.as_str()
                         );
                     }
                 }
+                EgressKind::Tun => {
+                    let interface = spec
+                        .interface
+                        .as_deref()
+                        .ok_or_else(|| anyhow!("egress '{egress_id}' (tun) requires interface"))?;
+                    if interface.trim().is_empty() {
+                        bail!("egress '{egress_id}' (tun) has empty interface");
+                    }
+                    if spec.endpoint.is_some() {
+                        bail!("egress '{egress_id}' (tun) must not define endpoint");
+                    }
+                    if spec.username.is_some() || spec.password.is_some() {
+                        bail!("egress '{egress_id}' (tun) must not define credentials");
+                    }
+                }
                 EgressKind::Direct | EgressKind::Block => {
                     if spec.endpoint.is_some() {
                         bail!(
@@ -87,30 +859,249 @@ impl AppConfig {
                             spec.kind.as_str()
                         );
                     }
+                    if spec.username.is_some() || spec.password.is_some() {
+                        bail!(
+                            "egress '{egress_id}' ({}) must not define credentials",
+                            spec.kind.as_str()
+                        );
+                    }
+                    if matches!(spec.kind, EgressKind::Block) && spec.priority.is_some() {
+                        bail!("egress '{egress_id}' (block) must not define priority");
+                    }
                 }
             }
-        }
-
-        for (egress_id, patterns) in &self.rules.app {
-            for (index, pattern) in patterns.iter().enumerate() {
-                if pattern.as_str().trim().is_empty() {
-                    bail!("rules.app entry at index {index} for egress '{egress_id}' is empty");
-                }
+            if spec.interface.is_some() && spec.kind != EgressKind::Tun {
+                bail!(
+                    "egress '{egress_id}' ({}) must not define interface",
+                    spec.kind.as_str()
+                );
+            }
+            if let Some(active) = &spec.active {
+                active.windows().with_context(|| {
+                    format!("egress '{egress_id}' has an invalid active schedule")
+                })?;
+            }
+            if let Some(file) = &spec.denylist_file
+                && let Err(err) = fs::File::open(file)
+            {
+                bail!("egress '{egress_id}' denylist_file '{file}' cannot be read: {err}");
             }
         }
 
+        Ok(())
+    }
+
+    #[cfg(feature = "psl")]
+    fn validate_psl_patterns(&self) -> Result<()> {
         for (egress_id, patterns) in &self.rules.domain {
-            for (index, pattern) in patterns.iter().enumerate() {
-                if pattern.as_str().trim().is_empty() {
-                    bail!("rules.domain entry at index {index} for egress '{egress_id}' is empty");
+            for pattern in patterns {
+                let normalized = normalize_domain(
+                    pattern.excluded().unwrap_or_else(|| pattern.as_str()),
+                    self.defaults.case_sensitive_domains,
+                );
+                let normalized = normalized.trim_start_matches('*');
+                let normalized = normalized.strip_prefix('.').unwrap_or(normalized);
+                if super::psl::is_public_suffix(normalized) {
+                    bail!(
+                        "rules.domain pattern '{}' for egress '{egress_id}' is a public suffix",
+                        pattern.as_str()
+                    );
                 }
             }
         }
 
         Ok(())
     }
+
+    #[cfg(not(feature = "psl"))]
+    fn validate_psl_patterns(&self) -> Result<()> {
+        let _ = self;
+        bail!("defaults.use_psl requires building with the 'psl' feature");
+    }
+}
+
+/// Lists patterns of `rules` shared by several egresses, in evaluation order.
+/// Patterns `normalize` maps to `None` are not compared.
+fn shadowed_patterns<T>(
+    cfg: &AppConfig,
+    section: &str,
+    rules: &BTreeMap<EgressId, Vec<T>>,
+    normalize: impl Fn(&T) -> Option<String>,
+) -> Vec<String> {
+    let mut owners: BTreeMap<String, Vec<&EgressId>> = BTreeMap::new();
+    for egress in engine::ordered_rule_egresses(cfg, rules) {
+        for pattern in rules[egress].iter().filter_map(&normalize) {
+            let holders = owners.entry(pattern).or_default();
+            if !holders.contains(&egress) {
+                holders.push(egress);
+            }
+        }
+    }
+
+    owners
+        .into_iter()
+        .filter(|(_, holders)| holders.len() > 1)
+        .map(|(pattern, holders)| {
+            let listed = holders
+                .iter()
+                .map(|id| format!("'{id}'"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                "rules.{section} pattern '{pattern}' is listed under egresses {listed}; only '{}' can win",
+                holders[0]
+            )
+        })
+        .collect()
+}
+
+/// Returns the indices of the first pair of patterns with equal normalized
+/// forms.
+fn find_duplicate<T>(patterns: &[T], normalize: impl Fn(&T) -> String) -> Option<(usize, usize)> {
+    let mut seen = HashMap::new();
+    for (index, pattern) in patterns.iter().enumerate() {
+        if let Some(first) = seen.insert(normalize(pattern), index) {
+            return Some((first, index));
+        }
+    }
+    None
+}
+
+/// Reduces a process path, or a command line starting with one, to its
+/// lowercase basename.
+pub(crate) fn normalize_process_name(raw: &str) -> String {
+    let normalized_path = normalize_process_path(raw);
+    let base_name = normalized_path
+        .rsplit('/')
+        .find(|segment| !segment.is_empty())
+        .unwrap_or("");
+    base_name.to_string()
+}
+
+/// Reduces a process path, or a command line starting with one, to the
+/// lowercase executable path with forward slashes.
+pub(crate) fn normalize_process_path(raw: &str) -> String {
+    executable_path(raw).replace('\\', "/").to_ascii_lowercase()
+}
+
+/// Normalizes the directory of a `dir:` app pattern like a process path,
+/// without trailing slashes.
+pub(crate) fn normalize_process_dir(raw: &str) -> String {
+    let path = raw.trim().replace('\\', "/").to_ascii_lowercase();
+    path.trim_end_matches('/').to_string()
+}
+
+/// Strips the arguments from a command line, leaving the executable path.
+///
+/// A quoted path ends at its closing quote and may contain spaces. An unquoted
+/// one ends at the first space after `.exe`; without one the whole string is
+/// taken as the path, since its spaces may well be part of it.
+fn executable_path(raw: &str) -> &str {
+    let trimmed = raw.trim();
+    if let Some(quoted) = trimmed.strip_prefix('"') {
+        return quoted.split_once('"').map_or(quoted, |(path, _)| path);
+    }
+
+    trimmed
+        .match_indices(char::is_whitespace)
+        .map(|(at, _)| &trimmed[..at])
+        .find(|path| {
+            path.len()
+                .checked_sub(4)
+                .and_then(|start| path.get(start..))
+                .is_some_and(|ext| ext.eq_ignore_ascii_case(".exe"))
+        })
+        .unwrap_or(trimmed)
+}
+
+fn drop_unknown<T>(
+    section: &str,
+    rules: &mut BTreeMap<EgressId, Vec<T>>,
+    is_known: impl Fn(&EgressId) -> bool,
+) -> usize {
+    let mut dropped = 0;
+    rules.retain(|egress_id, patterns| {
+        if is_known(egress_id) {
+            return true;
+        }
+        warn!(
+            egress = %egress_id,
+            count = patterns.len(),
+            "dropping rules.{section} entries for unknown egress id"
+        );
+        dropped += patterns.len();
+        false
+    });
+    dropped
+}
+
+fn merge_aliased_rules<V: Default + IntoIterator + Extend<V::Item>>(
+    rules: &mut BTreeMap<EgressId, V>,
+    aliases: &BTreeMap<EgressId, EgressId>,
+) {
+    for (alias, target) in aliases {
+        if let Some(patterns) = rules.remove(alias) {
+            rules.entry(target.clone()).or_default().extend(patterns);
+        }
+    }
+}
+
+/// Substitutes every `${VAR}` in `raw` from the environment.
+fn expand_env(raw: &str) -> Result<String> {
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| anyhow!("unterminated '${{' in '{raw}'"))?;
+        let name = &after[..end];
+        let value =
+            std::env::var(name).map_err(|_| anyhow!("environment variable '{name}' is not set"))?;
+        out.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Moves `user:pass@` credentials out of the endpoint into the spec fields,
+/// percent-decoded, so the stored endpoint never carries a password.
+fn take_endpoint_credentials(spec: &mut EgressSpec) -> Result<(), &'static str> {
+    let Some(url) = spec
+        .endpoint
+        .as_deref()
+        .and_then(|endpoint| Url::parse(endpoint).ok())
+        .filter(|url| !url.username().is_empty() || url.password().is_some())
+    else {
+        return Ok(());
+    };
+
+    if spec.username.is_some() || spec.password.is_some() {
+        return Err("credentials must be given either in endpoint or as username/password");
+    }
+
+    let decode = |raw: &str| {
+        percent_decode_str(raw)
+            .decode_utf8()
+            .map(Cow::into_owned)
+            .map_err(|_| "endpoint credentials must be valid UTF-8 once percent-decoded")
+    };
+    spec.username = Some(decode(url.username())?);
+    spec.password = url.password().map(decode).transpose()?;
+    spec.endpoint = spec.endpoint.as_deref().and_then(|endpoint| {
+        let (scheme, rest) = endpoint.split_once("://")?;
+        let (_, host) = rest.rsplit_once('@')?;
+        Some(format!("{scheme}://{host}"))
+    });
+
+    Ok(())
 }
 
+/// Splits an endpoint into its lowercased scheme, host (IPv6 without
+/// brackets) and port, which must be spelled out even where the scheme has a
+/// default.
 fn parse_endpoint(endpoint: &str) -> Result<(String, String, u16)> {
     let (scheme, rest) = endpoint
         .split_once("://")
@@ -119,50 +1110,317 @@ fn parse_endpoint(endpoint: &str) -> Result<(String, String, u16)> {
         bail!("endpoint has empty scheme");
     }
 
-    let (host, port_str) = if let Some(rest) = rest.strip_prefix('[') {
-        let close = rest
-            .find(']')
-            .ok_or_else(|| anyhow!("endpoint IPv6 host must have closing ']'"))?;
-        let host = &rest[..close];
-        let after = &rest[close + 1..];
-        let port_str = after
-            .strip_prefix(':')
-            .ok_or_else(|| anyhow!("endpoint IPv6 host must include port after ']'"))?;
-        (host, port_str)
-    } else {
-        let (host, port_str) = rest
-            .split_once(':')
-            .ok_or_else(|| anyhow!("endpoint must include port after host"))?;
-        (host, port_str)
+    let url = Url::parse(endpoint).map_err(|err| anyhow!("endpoint is not a valid URL: {err}"))?;
+    let host = match url.host() {
+        Some(Host::Ipv6(addr)) => addr.to_string(),
+        Some(host) => host.to_string(),
+        None => bail!("endpoint has empty host"),
     };
-
-    if host.trim().is_empty() {
+    if host.is_empty() {
         bail!("endpoint has empty host");
     }
+    if !matches!(url.path(), "" | "/") || url.query().is_some() || url.fragment().is_some() {
+        bail!("endpoint must not have a path, query or fragment");
+    }
 
-    let port: u16 = port_str
-        .parse()
-        .map_err(|_| anyhow!("endpoint port must be a number, got '{port_str}'"))?;
+    // `Url` drops a port equal to the scheme default, so look for it in the
+    // authority as written.
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let port = url
+        .port()
+        .or_else(|| {
+            url.port_or_known_default()
+                .filter(|port| authority.ends_with(&format!(":{port}")))
+        })
+        .ok_or_else(|| anyhow!("endpoint must include port after host"))?;
     if port == 0 {
         bail!("endpoint port must be between 1 and 65535, got {port}");
     }
 
-    Ok((scheme.to_string(), host.to_string(), port))
+    Ok((url.scheme().to_string(), host, port))
+}
+
+/// Restricts IPC requests to listed users, identified by the peer
+/// credentials of their connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Security {
+    /// Users allowed to send control requests (`stop`, `reload`): numeric
+    /// uids or user names on Unix, SIDs or `DOMAIN\name` accounts on Windows.
+    /// Unset lets every client through; empty lets none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_control: Option<Vec<String>>,
+    /// Which requests `allow_control` guards.
+    #[serde(default)]
+    pub restrict: Restrict,
+}
+
+impl Security {
+    /// Whether every client may send every request.
+    #[must_use]
+    pub const fn is_open(&self) -> bool {
+        self.allow_control.is_none()
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.restrict == Restrict::All && self.is_open() {
+            bail!("security.restrict = \"all\" requires security.allow_control");
+        }
+        let allowed = self.allow_control.iter().flatten();
+        for (index, user) in allowed.enumerate() {
+            if user.trim().is_empty() {
+                bail!("security.allow_control entry at index {index} is empty");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Selects which requests [`Security::allow_control`] guards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Restrict {
+    /// Control requests only; read-only requests stay open to everyone.
+    #[default]
+    Control,
+    /// Every request but the version handshake and `ping`.
+    All,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Defaults {
     pub egress: EgressId,
+    #[serde(default)]
+    pub block_select: BlockSelect,
+    /// Reject domain patterns that are a public suffix, such as `co.uk`.
+    /// Matching stays plain suffix matching.
+    #[serde(default)]
+    pub use_psl: bool,
+    #[serde(default)]
+    pub reason_domain_form: ReasonDomainForm,
+    #[serde(default)]
+    pub unknown_egress: UnknownEgress,
+    #[serde(default)]
+    pub on_no_match: OnNoMatch,
+    /// Compare domains and domain patterns without folding ASCII case.
+    #[serde(default)]
+    pub case_sensitive_domains: bool,
+    #[serde(default)]
+    pub port_priority: PortPriority,
+}
+
+/// Selects the rule stage `rules.port` is evaluated in; block rules always
+/// come first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PortPriority {
+    /// Before cidr and domain rules.
+    #[default]
+    BeforeDomain,
+    /// After cidr and domain rules, before app rules.
+    AfterDomain,
+    /// After app rules, right before the default.
+    AfterApp,
+}
+
+/// Selects what happens to flows that no rule matches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnNoMatch {
+    /// Route through `defaults.egress`.
+    #[default]
+    Route,
+    /// Fail closed through a block egress, picked per `block_select`.
+    Block,
+}
+
+/// Selects how rules referencing undeclared egress ids are handled at load.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownEgress {
+    /// Reject the whole config.
+    #[default]
+    Error,
+    /// Log a warning and ignore the offending rules.
+    DropRule,
+}
+
+/// Selects how matched domain patterns are rendered in decision reasons.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasonDomainForm {
+    /// Pattern is shown exactly as written in the config.
+    #[default]
+    AsWritten,
+    /// Punycode labels are decoded to Unicode.
+    Unicode,
+    /// Non-ASCII labels are encoded to punycode.
+    Ascii,
+}
+
+/// Selects which block egress is reported when several block rules match.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockSelect {
+    /// Lexicographically smallest egress id wins.
+    #[default]
+    FirstId,
+    /// Egress declared first under `[egress.*]` wins.
+    FirstDeclared,
+}
+
+/// An `[egress_group.<id>]` section.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct EgressGroup {
+    /// Egresses in failover order; decisions currently use the first.
+    pub members: Vec<EgressId>,
+}
+
+/// The tables a file listed in [`AppConfig::include`] may contribute.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IncludedConfig {
+    /// Checked and migrated by [`parse_config`]; the merged config carries
+    /// the entrypoint's.
+    #[serde(default, rename = "version")]
+    _version: u32,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default, deserialize_with = "deserialize_egress_map")]
+    egress: BTreeMap<EgressId, EgressSpec>,
+    #[serde(default)]
+    egress_group: BTreeMap<EgressId, EgressGroup>,
+    #[serde(default)]
+    rules: Rules,
+    #[serde(default)]
+    aliases: BTreeMap<EgressId, EgressId>,
+}
+
+/// Makes every relative `denylist_file` in `egress` relative to the directory
+/// of `file`, the config declaring it.
+fn resolve_denylist_paths(egress: &mut BTreeMap<EgressId, EgressSpec>, file: &Path) {
+    let base = file.parent().unwrap_or_else(|| Path::new(""));
+    for spec in egress.values_mut() {
+        if let Some(denylist) = &mut spec.denylist_file {
+            *denylist = base.join(&*denylist).display().to_string();
+        }
+    }
+}
+
+/// Makes the path of every relative `@file:` entry in `domain` relative to
+/// the directory of `file`, the config declaring it.
+fn resolve_domain_files(domain: &mut BTreeMap<EgressId, Vec<DomainPattern>>, file: &Path) {
+    let base = file.parent().unwrap_or_else(|| Path::new(""));
+    for pattern in domain.values_mut().flatten() {
+        if let Some(path) = pattern.file() {
+            pattern.0 = format!("@file:{}", base.join(path).display());
+        }
+    }
+}
+
+/// Domains of a denylist: one per line, or hosts-file entries whose leading
+/// address is dropped (`0.0.0.0 ads.example.com`). `#` starts a comment.
+fn parse_denylist(raw: &str) -> impl Iterator<Item = &str> {
+    raw.lines().flat_map(|line| {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace().peekable();
+        if fields
+            .peek()
+            .is_some_and(|field| field.parse::<IpAddr>().is_ok())
+        {
+            fields.next();
+        }
+        fields
+    })
+}
+
+fn canonical_path(path: &Path) -> Result<PathBuf> {
+    fs::canonicalize(path).with_context(|| format!("failed to read config: {}", path.display()))
 }
 
-#[derive(Debug, Clone, Deserialize)]
+fn append_rules<V: Default + IntoIterator + Extend<V::Item>>(
+    rules: &mut BTreeMap<EgressId, V>,
+    more: BTreeMap<EgressId, V>,
+) {
+    for (id, patterns) in more {
+        rules.entry(id).or_default().extend(patterns);
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Rules {
     #[serde(default)]
     pub app: BTreeMap<EgressId, Vec<AppPattern>>,
     #[serde(default)]
     pub domain: BTreeMap<EgressId, Vec<DomainPattern>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cidr: BTreeMap<EgressId, Vec<CidrPattern>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub port: BTreeMap<EgressId, Vec<PortPattern>>,
+    /// Descriptions of `app` patterns, keyed by egress and then by pattern as
+    /// written in the list.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub app_meta: BTreeMap<EgressId, BTreeMap<String, String>>,
+    /// Descriptions of `domain` patterns, keyed like [`Rules::app_meta`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub domain_meta: BTreeMap<EgressId, BTreeMap<String, String>>,
+}
+
+impl Rules {
+    /// The `app_meta` description of the pattern at `index` of the `egress`
+    /// app list.
+    #[must_use]
+    pub fn app_description(&self, egress: &EgressId, index: usize) -> Option<&str> {
+        let pattern = self.app.get(egress)?.get(index)?;
+        describe(self.app_meta.get(egress)?, pattern.as_str())
+    }
+
+    /// The `domain_meta` description of the pattern at `index` of the
+    /// `egress` domain list.
+    #[must_use]
+    pub fn domain_description(&self, egress: &EgressId, index: usize) -> Option<&str> {
+        let pattern = self.domain.get(egress)?.get(index)?;
+        describe(self.domain_meta.get(egress)?, pattern.as_str())
+    }
+}
+
+fn check_meta(
+    section: &str,
+    egress_id: &EgressId,
+    meta: &BTreeMap<String, String>,
+    listed: &[&str],
+) -> Result<()> {
+    for pattern in meta.keys() {
+        if !listed.iter().any(|p| p.trim() == pattern.trim()) {
+            bail!(
+                "rules.{section}_meta for egress '{egress_id}' describes pattern '{pattern}', which rules.{section} does not list for that egress"
+            );
+        }
+    }
+    Ok(())
+}
+
+fn describe<'a>(meta: &'a BTreeMap<String, String>, pattern: &str) -> Option<&'a str> {
+    meta.iter()
+        .find(|(key, _)| key.trim() == pattern.trim())
+        .map(|(_, description)| description.as_str())
 }
 
+/// An app rule pattern.
+///
+/// Plain patterns are compared against the process basename, ignoring ASCII
+/// case. A plain pattern containing `*` or `?`, such as `chrome*.exe`, is a
+/// glob matched against the whole basename, also ignoring case. A pattern
+/// written as `*.ext`, such as `*.exe`, is an extension pattern instead: it
+/// matches basenames whose last extension is `ext`, ignoring case, without
+/// going through the glob engine. A pattern
+/// written as `/regex/` is a case-insensitive regular
+/// expression searched for in the lowercased basename; anchor it with `^` and
+/// `$` to match the whole name. A pattern prefixed with `dir:`, such as
+/// `dir:C:/Tools`, matches any executable under that directory, comparing the
+/// full path instead of the basename. Appending `::substring` to any form
+/// also requires the process command line to contain `substring`,
+/// case-sensitively.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(transparent)]
 pub struct AppPattern(pub String);
@@ -172,8 +1430,114 @@ impl AppPattern {
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    /// Splits a `name::substring` pattern into its name part and the
+    /// substring the command line must contain. A pattern that is a regex as
+    /// a whole is never split.
+    #[must_use]
+    pub fn parts(&self) -> (&str, Option<&str>) {
+        let trimmed = self.0.trim();
+        if regex_body(trimmed).is_some() {
+            return (trimmed, None);
+        }
+        match trimmed.split_once("::") {
+            Some((name, cmdline)) => (name.trim(), Some(cmdline)),
+            None => (trimmed, None),
+        }
+    }
+
+    /// The pattern as the engine compares it: the lowercase basename for
+    /// plain names, the trimmed name for regexes, `dir:` and the normalized
+    /// directory for directories, followed by `::substring` when a
+    /// command-line substring is required.
+    #[must_use]
+    pub fn normalized(&self) -> String {
+        let (name, cmdline) = self.parts();
+        let name = if regex_body(name).is_some() {
+            name.to_string()
+        } else if let Some(dir) = self.dir() {
+            format!("dir:{}", normalize_process_dir(dir))
+        } else {
+            normalize_process_name(name)
+        };
+        match cmdline {
+            Some(cmdline) => format!("{name}::{cmdline}"),
+            None => name,
+        }
+    }
+
+    /// Returns the directory after the `dir:` prefix of the name part.
+    #[must_use]
+    pub fn dir(&self) -> Option<&str> {
+        let (name, _) = self.parts();
+        if regex_body(name).is_some() {
+            return None;
+        }
+        name.strip_prefix("dir:").map(str::trim)
+    }
+
+    /// Returns the expression between the slashes of a `/regex/` name part.
+    #[must_use]
+    pub fn regex_source(&self) -> Option<&str> {
+        regex_body(self.parts().0)
+    }
+
+    /// Compiles a `/regex/` pattern; `None` for plain patterns.
+    #[must_use]
+    pub fn compile_regex(&self) -> Option<Result<Regex, regex::Error>> {
+        self.regex_source()
+            .map(|source| RegexBuilder::new(source).case_insensitive(true).build())
+    }
+
+    /// Returns the extension of a `*.ext` pattern, which holds no further
+    /// `*`, `?` or `.`; empty for a bare `*.`.
+    #[must_use]
+    pub fn extension(&self) -> Option<&str> {
+        let (name, _) = self.parts();
+        if regex_body(name).is_some() {
+            return None;
+        }
+        name.strip_prefix("*.")
+            .filter(|ext| !ext.contains(['*', '?', '.']))
+    }
+
+    /// Returns the name part of a plain pattern holding `*` or `?`, unless it
+    /// is an extension pattern.
+    #[must_use]
+    pub fn glob(&self) -> Option<&str> {
+        let (name, _) = self.parts();
+        if regex_body(name).is_some() || self.dir().is_some() || self.extension().is_some() {
+            return None;
+        }
+        name.contains(['*', '?']).then_some(name)
+    }
+
+    /// Compiles a glob pattern against normalized basenames; `None` for
+    /// patterns without `*` or `?`.
+    #[must_use]
+    pub fn compile_glob(&self) -> Option<Result<GlobMatcher, globset::Error>> {
+        self.glob().map(|glob| {
+            GlobBuilder::new(&normalize_process_name(glob))
+                .case_insensitive(true)
+                .build()
+                .map(|glob| glob.compile_matcher())
+        })
+    }
+}
+
+fn regex_body(pattern: &str) -> Option<&str> {
+    pattern.strip_prefix('/')?.strip_suffix('/')
 }
 
+/// A domain rule pattern.
+///
+/// Plain patterns match the domain itself and any subdomain. A pattern
+/// starting with `*.` matches one or more labels below the suffix but not the
+/// apex, and a leading `*` without a dot matches any domain ending in the
+/// rest of the pattern. `*` is not allowed anywhere else.
+///
+/// A pattern prefixed with `!` is an exclusion: when it matches, the egress
+/// listing it skips the domain altogether, whatever its other patterns say.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(transparent)]
 pub struct DomainPattern(pub String);
@@ -183,33 +1547,253 @@ impl DomainPattern {
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    /// The pattern an `!` exclusion applies to; `None` for inclusions.
+    #[must_use]
+    pub fn excluded(&self) -> Option<&str> {
+        self.0.trim().strip_prefix('!')
+    }
+
+    /// The path of an `@file:path` entry, which loading replaces by the
+    /// domains listed in that file.
+    #[must_use]
+    pub fn file(&self) -> Option<&str> {
+        self.0.trim().strip_prefix("@file:").map(str::trim)
+    }
+
+    /// The pattern lowercased, with surrounding whitespace and trailing dots
+    /// removed.
+    #[must_use]
+    pub fn normalized(&self) -> String {
+        normalize_domain(&self.0, false)
+    }
+}
+
+/// Trims whitespace and trailing dots, lowercasing unless `case_sensitive`.
+///
+/// Non-ASCII labels are converted to their lowercase `xn--` punycode form, so
+/// Unicode and ASCII spellings of a domain compare equal; a label that fails
+/// IDNA conversion is kept as written.
+#[must_use]
+pub fn normalize_domain(raw: &str, case_sensitive: bool) -> String {
+    let trimmed = raw.trim().trim_end_matches('.');
+    if !trimmed.is_ascii() {
+        return trimmed
+            .split('.')
+            .map(|label| {
+                let ascii = label_to_ascii(label).unwrap_or_else(|| label.to_string());
+                if case_sensitive {
+                    ascii
+                } else {
+                    ascii.to_ascii_lowercase()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(".");
+    }
+    if case_sensitive {
+        trimmed.to_string()
+    } else {
+        trimmed.to_ascii_lowercase()
+    }
+}
+
+/// IDNA (UTS #46) ASCII form of one domain label, keeping a leading `*` or
+/// `!`; `None` if the label cannot be converted.
+fn label_to_ascii(label: &str) -> Option<String> {
+    if label.is_ascii() {
+        return Some(label.to_string());
+    }
+    let body = label.trim_start_matches(['*', '!']);
+    let ascii = idna::domain_to_ascii_cow(body.as_bytes(), idna::AsciiDenyList::URL).ok()?;
+    if ascii.is_empty() || ascii.contains('.') {
+        return None;
+    }
+    Some(format!("{}{ascii}", &label[..label.len() - body.len()]))
+}
+
+/// A CIDR rule pattern such as `10.0.0.0/8` or `fd00::/8`, matched against
+/// destinations given as IP literals.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct CidrPattern(pub String);
+
+impl CidrPattern {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Parses the pattern as a network.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the pattern is not a valid CIDR.
+    pub fn parse(&self) -> Result<IpNet, ipnet::AddrParseError> {
+        self.0.trim().parse()
+    }
+}
+
+/// A destination port rule pattern, either one port such as `22` or an
+/// inclusive range such as `8000-8100`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct PortPattern(pub String);
+
+impl PortPattern {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Parses the pattern as an inclusive port range; a single port is a
+    /// range of one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a port is not a number from 1 to 65535 or the
+    /// range ends before it starts.
+    pub fn parse(&self) -> Result<RangeInclusive<u16>> {
+        let raw = self.0.trim();
+        let (start, end) = raw.split_once('-').unwrap_or((raw, raw));
+        let port = |raw: &str| -> Result<u16> {
+            match raw.trim().parse::<u16>() {
+                Ok(port) if port > 0 => Ok(port),
+                _ => bail!(
+                    "port must be a number from 1 to 65535, got '{}'",
+                    raw.trim()
+                ),
+            }
+        };
+        let (start, end) = (port(start)?, port(end)?);
+        if start > end {
+            bail!("range start {start} is above its end {end}");
+        }
+        Ok(start..=end)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 #[serde(transparent)]
 pub struct EgressId(pub String);
 
+impl EgressId {
+    /// Whether the id is non-empty and made of ASCII alphanumerics, `-` and
+    /// `_`, so it is safe as a TOML key and in IPC output.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        !self.0.is_empty()
+            && self
+                .0
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+    }
+}
+
 impl fmt::Display for EgressId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.0.fmt(f)
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct EgressSpec {
     #[serde(rename = "type")]
     pub kind: EgressKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub endpoint: Option<String>,
+    /// Interface name of a `tun` egress.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// Proxy username, also taken from a `user:pass@` endpoint prefix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Proxy password, also taken from a `user:pass@` endpoint prefix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Rule priority rank overriding the kind rank; lower wins.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    /// Free-form label for operators, shown in status output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// When the rules routing to this egress apply; always when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active: Option<Schedule>,
+    /// File of domains appended to this egress's domain rules at load, one
+    /// per line or as hosts-file entries, relative to the file declaring it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub denylist_file: Option<String>,
+    /// Position of this egress in the `[egress.*]` declaration order.
+    #[serde(skip)]
+    pub declared_index: usize,
+}
+
+impl EgressSpec {
+    /// Host and port of the endpoint, if it has a valid one.
+    #[must_use]
+    pub fn endpoint_host_port(&self) -> Option<(String, u16)> {
+        let (_scheme, host, port) = parse_endpoint(self.endpoint.as_deref()?).ok()?;
+        Some((host, port))
+    }
+}
+
+/// Writes egresses in declaration order so `declared_index` survives a round
+/// trip through [`AppConfig::to_toml`].
+fn serialize_egress_map<S>(
+    egress: &BTreeMap<EgressId, EgressSpec>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut ordered: Vec<_> = egress.iter().collect();
+    ordered.sort_by_key(|(_, spec)| spec.declared_index);
+    serializer.collect_map(ordered)
+}
+
+fn deserialize_egress_map<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<EgressId, EgressSpec>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct DeclaredOrder;
+
+    impl<'de> Visitor<'de> for DeclaredOrder {
+        type Value = BTreeMap<EgressId, EgressSpec>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a table of egress specs")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut out = BTreeMap::new();
+            while let Some((id, mut spec)) = map.next_entry::<EgressId, EgressSpec>()? {
+                take_endpoint_credentials(&mut spec)
+                    .map_err(|msg| de::Error::custom(format!("egress '{id}': {msg}")))?;
+                spec.declared_index = out.len();
+                out.insert(id, spec);
+            }
+            Ok(out)
+        }
+    }
+
+    deserializer.deserialize_map(DeclaredOrder)
 }
 
 use strum_macros::{Display, IntoStaticStr};
 
-#[derive(Debug, Clone, Copy, Deserialize, IntoStaticStr, Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, IntoStaticStr, Display)]
 #[serde(rename_all = "lowercase")]
 #[strum(serialize_all = "lowercase")]
 pub enum EgressKind {
     Singbox,
+    /// HTTP proxy reached with `CONNECT`.
+    Http,
     Socks5,
+    /// System TUN interface, e.g. a wireguard tunnel, named by `interface`.
+    Tun,
     Direct,
     Block,
 }
@@ -219,4 +1803,15 @@ impl EgressKind {
     pub fn as_str(self) -> &'static str {
         self.into()
     }
+
+    /// Endpoint schemes accepted for this kind; empty for kinds without an
+    /// endpoint.
+    #[must_use]
+    pub const fn endpoint_schemes(self) -> &'static [&'static str] {
+        match self {
+            Self::Singbox | Self::Socks5 => &["socks5"],
+            Self::Http => &["http", "https"],
+            Self::Tun | Self::Direct | Self::Block => &[],
+        }
+    }
 }
@@ -0,0 +1,160 @@
+//! Differences between two configs, as reported by a dry-run reload.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::config::{AppConfig, AppPattern, CidrPattern, DomainPattern, EgressId, PortPattern};
+
+/// What applying `next` over `live` would change.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+    pub added_egress: Vec<EgressId>,
+    pub removed_egress: Vec<EgressId>,
+    /// Egresses present in both configs whose endpoint differs.
+    pub changed_endpoints: Vec<EndpointChange>,
+    pub added_rules: Vec<RuleChange>,
+    pub removed_rules: Vec<RuleChange>,
+}
+
+impl ConfigDiff {
+    /// Compares `live` against `next`. Egresses and rules are listed in egress
+    /// id order, patterns in the order they are written.
+    #[must_use]
+    pub fn between(live: &AppConfig, next: &AppConfig) -> Self {
+        let mut diff = Self {
+            added_egress: missing_keys(&next.egress, &live.egress),
+            removed_egress: missing_keys(&live.egress, &next.egress),
+            changed_endpoints: live
+                .egress
+                .iter()
+                .filter_map(|(id, old)| {
+                    let new = next.egress.get(id)?;
+                    (old.endpoint != new.endpoint).then(|| EndpointChange {
+                        egress: id.clone(),
+                        old: old.endpoint.clone(),
+                        new: new.endpoint.clone(),
+                    })
+                })
+                .collect(),
+            ..Self::default()
+        };
+
+        diff.diff_rules(
+            RuleKind::App,
+            &live.rules.app,
+            &next.rules.app,
+            AppPattern::as_str,
+        );
+        diff.diff_rules(
+            RuleKind::Domain,
+            &live.rules.domain,
+            &next.rules.domain,
+            DomainPattern::as_str,
+        );
+        diff.diff_rules(
+            RuleKind::Cidr,
+            &live.rules.cidr,
+            &next.rules.cidr,
+            CidrPattern::as_str,
+        );
+        diff.diff_rules(
+            RuleKind::Port,
+            &live.rules.port,
+            &next.rules.port,
+            PortPattern::as_str,
+        );
+        diff
+    }
+
+    /// Whether the two configs agree on everything this diff tracks.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.added_egress.is_empty()
+            && self.removed_egress.is_empty()
+            && self.changed_endpoints.is_empty()
+            && self.added_rules.is_empty()
+            && self.removed_rules.is_empty()
+    }
+
+    fn diff_rules<T>(
+        &mut self,
+        kind: RuleKind,
+        live: &BTreeMap<EgressId, Vec<T>>,
+        next: &BTreeMap<EgressId, Vec<T>>,
+        as_str: fn(&T) -> &str,
+    ) {
+        self.added_rules
+            .extend(missing_patterns(kind, next, live, as_str));
+        self.removed_rules
+            .extend(missing_patterns(kind, live, next, as_str));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointChange {
+    pub egress: EgressId,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleChange {
+    pub kind: RuleKind,
+    /// Egress id the pattern is listed under.
+    pub egress: EgressId,
+    pub pattern: String,
+}
+
+/// Rule table a [`RuleChange`] belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RuleKind {
+    App,
+    Domain,
+    Cidr,
+    Port,
+}
+
+impl RuleKind {
+    /// Name of the `[rules.*]` table.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::App => "app",
+            Self::Domain => "domain",
+            Self::Cidr => "cidr",
+            Self::Port => "port",
+        }
+    }
+}
+
+/// Keys of `from` absent from `other`.
+fn missing_keys<V>(from: &BTreeMap<EgressId, V>, other: &BTreeMap<EgressId, V>) -> Vec<EgressId> {
+    from.keys()
+        .filter(|id| !other.contains_key(id))
+        .cloned()
+        .collect()
+}
+
+/// Patterns listed in `from` but not under the same egress in `other`.
+fn missing_patterns<T>(
+    kind: RuleKind,
+    from: &BTreeMap<EgressId, Vec<T>>,
+    other: &BTreeMap<EgressId, Vec<T>>,
+    as_str: fn(&T) -> &str,
+) -> Vec<RuleChange> {
+    from.iter()
+        .flat_map(|(id, patterns)| {
+            let known: BTreeSet<&str> = other.get(id).map_or_else(BTreeSet::new, |patterns| {
+                patterns.iter().map(as_str).collect()
+            });
+            patterns
+                .iter()
+                .map(as_str)
+                .filter(move |pattern| !known.contains(pattern))
+                .map(move |pattern| RuleChange {
+                    kind,
+                    egress: id.clone(),
+                    pattern: pattern.to_string(),
+                })
+        })
+        .collect()
+}
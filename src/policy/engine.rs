@@ -1,17 +1,57 @@
-use std::collections::BTreeMap;
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    ops::RangeInclusive,
+};
 
-use super::config::{AppConfig, AppPattern, DomainPattern, EgressId, EgressKind};
+use chrono::NaiveDateTime;
+use globset::GlobMatcher;
+use ipnet::IpNet;
+use regex::Regex;
+
+use super::{
+    config::{
+        AppConfig, AppPattern, BlockSelect, Defaults, DomainPattern, EgressId, EgressKind,
+        OnNoMatch, PRIORITY_SPACING, PortPriority, ReasonDomainForm, normalize_domain,
+        normalize_process_dir, normalize_process_name, normalize_process_path,
+    },
+    idn,
+};
 
 #[derive(Debug, Clone)]
 pub struct Decision {
     pub egress: EgressId,
     pub reason: DecisionReason,
+    /// Priority rank of a non-block rule winner; `None` for block and
+    /// default decisions.
+    pub priority: Option<Priority>,
+}
+
+impl Decision {
+    /// See [`DecisionReason::specificity`].
+    #[must_use]
+    pub fn specificity(&self) -> u32 {
+        self.reason.specificity()
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Where a non-block rule winner sits in the egress priority ordering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Priority {
+    /// Rank as returned by [`egress_rank`]; lower wins.
+    pub rank: i32,
+    /// Another egress of the same rank also matched and lost on id order.
+    pub tie_broken: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MatchKind {
     Exact,
     Suffix,
+    /// Pattern starting with `*`, see [`DomainPattern`].
+    Wildcard,
 }
 
 impl MatchKind {
@@ -20,40 +60,171 @@ impl MatchKind {
         match self {
             Self::Exact => "exact",
             Self::Suffix => "suffix",
+            Self::Wildcard => "wildcard",
         }
     }
 }
 
+/// Why a decision picked its egress.
+///
+/// For rule variants, `group` is the egress group the rule targeted when
+/// `egress` was resolved from one.
 #[derive(Debug, Clone)]
 pub enum DecisionReason {
     BlockByApp {
         egress: EgressId,
+        group: Option<EgressId>,
         pattern: String,
+        /// Zero-based position of `pattern` in the egress's rule list.
+        index: usize,
     },
     BlockByDomain {
         egress: EgressId,
+        group: Option<EgressId>,
         pattern: String,
+        /// Zero-based position of `pattern` in the egress's rule list.
+        index: usize,
         match_kind: MatchKind,
     },
     AppRule {
         egress: EgressId,
+        group: Option<EgressId>,
         pattern: String,
+        /// Zero-based position of `pattern` in the egress's rule list.
+        index: usize,
     },
     DomainRule {
         egress: EgressId,
+        group: Option<EgressId>,
         pattern: String,
+        /// Zero-based position of `pattern` in the egress's rule list.
+        index: usize,
         match_kind: MatchKind,
     },
+    /// The destination IP literal fell in a `rules.cidr` network.
+    CidrRule {
+        egress: EgressId,
+        group: Option<EgressId>,
+        pattern: String,
+        /// Zero-based position of `pattern` in the egress's rule list.
+        index: usize,
+    },
+    /// The destination port fell in a `rules.port` port or range.
+    PortRule {
+        egress: EgressId,
+        group: Option<EgressId>,
+        pattern: String,
+        /// Zero-based position of `pattern` in the egress's rule list.
+        index: usize,
+    },
     Default {
         egress: EgressId,
     },
+    /// Nothing matched and the default egress is a block egress, either
+    /// because `defaults.on_no_match` is `block` or `defaults.egress` is one.
+    DefaultBlock {
+        egress: EgressId,
+    },
 }
 
 impl DecisionReason {
+    /// The egress group the winning rule targeted, if any.
+    #[must_use]
+    pub const fn group(&self) -> Option<&EgressId> {
+        match self {
+            Self::BlockByApp { group, .. }
+            | Self::BlockByDomain { group, .. }
+            | Self::AppRule { group, .. }
+            | Self::DomainRule { group, .. }
+            | Self::CidrRule { group, .. }
+            | Self::PortRule { group, .. } => group.as_ref(),
+            Self::Default { .. } | Self::DefaultBlock { .. } => None,
+        }
+    }
+
+    /// Stable `snake_case` key of the variant, for logs and metric labels;
+    /// unlike [`Self::to_human`] it never changes wording.
+    #[must_use]
+    pub const fn reason_code(&self) -> &'static str {
+        match self {
+            Self::BlockByApp { .. } => "block_app",
+            Self::BlockByDomain { .. } => "block_domain",
+            Self::AppRule { .. } => "app_rule",
+            Self::DomainRule { .. } => "domain_rule",
+            Self::CidrRule { .. } => "cidr_rule",
+            Self::PortRule { .. } => "port_rule",
+            Self::Default { .. } => "default",
+            Self::DefaultBlock { .. } => "default_block",
+        }
+    }
+
+    /// How specific the deciding match was, for ranking decisions outside
+    /// the engine; [`decide`] never consults it. Higher is more specific:
+    ///
+    /// | reason                                | score                    |
+    /// |---------------------------------------|--------------------------|
+    /// | app rule, blocking or not             | 5000                     |
+    /// | exact domain rule, blocking or not    | 4000 + pattern labels    |
+    /// | suffix domain rule, blocking or not   | 3000 + pattern labels    |
+    /// | wildcard domain rule, blocking or not | 2000 + pattern labels    |
+    /// | cidr rule                             | 1000 + prefix length     |
+    /// | port rule                             | 500                      |
+    /// | default, blocking or not              | 0                        |
+    ///
+    /// Labels are counted without the `*.` or `.` prefix and capped at 999,
+    /// so `*.a.example.com` scores 2003 and tiers never overlap.
+    #[must_use]
+    pub fn specificity(&self) -> u32 {
+        match self {
+            Self::BlockByApp { .. } | Self::AppRule { .. } => 5000,
+            Self::BlockByDomain {
+                pattern,
+                match_kind,
+                ..
+            }
+            | Self::DomainRule {
+                pattern,
+                match_kind,
+                ..
+            } => {
+                let tier = match match_kind {
+                    MatchKind::Exact => 4000,
+                    MatchKind::Suffix => 3000,
+                    MatchKind::Wildcard => 2000,
+                };
+                let labels = pattern
+                    .trim_start_matches('*')
+                    .trim_start_matches('.')
+                    .split('.')
+                    .filter(|label| !label.is_empty())
+                    .count();
+                tier + u32::try_from(labels).unwrap_or(u32::MAX).min(999)
+            }
+            Self::CidrRule { pattern, .. } => {
+                1000 + pattern
+                    .trim()
+                    .parse::<IpNet>()
+                    .map_or(0, |net| u32::from(net.prefix_len()))
+            }
+            Self::PortRule { .. } => 500,
+            Self::Default { .. } | Self::DefaultBlock { .. } => 0,
+        }
+    }
+
     #[must_use]
     pub fn to_human(&self) -> String {
+        let human = self.to_human_ungrouped();
+        match self.group() {
+            Some(group) => format!("{human} via group '{group}'"),
+            None => human,
+        }
+    }
+
+    fn to_human_ungrouped(&self) -> String {
         match self {
-            Self::BlockByApp { pattern, egress } => {
+            Self::BlockByApp {
+                pattern, egress, ..
+            } => {
                 format!(
                     "blocked: app exact match '{pattern}' -> egress '{egress}' has highest priority"
                 )
@@ -62,26 +233,43 @@ impl DecisionReason {
                 pattern,
                 match_kind,
                 egress,
+                ..
             } => {
                 let mk = match_kind_to_str(*match_kind);
                 format!(
                     "blocked: domain {mk} match '{pattern}' -> egress '{egress}' has highest priority"
                 )
             }
-            Self::AppRule { egress, pattern } => {
+            Self::AppRule {
+                egress, pattern, ..
+            } => {
                 format!("app rule: exact match '{pattern}' -> egress '{egress}'")
             }
             Self::DomainRule {
                 egress,
                 pattern,
                 match_kind,
+                ..
             } => {
                 let mk = match_kind_to_str(*match_kind);
                 format!("domain rule: {mk} match '{pattern}' -> egress '{egress}'")
             }
+            Self::CidrRule {
+                egress, pattern, ..
+            } => {
+                format!("cidr rule: address in '{pattern}' -> egress '{egress}'")
+            }
+            Self::PortRule {
+                egress, pattern, ..
+            } => {
+                format!("port rule: port in '{pattern}' -> egress '{egress}'")
+            }
             Self::Default { egress } => {
                 format!("default: egress '{egress}' (no rules matched)")
             }
+            Self::DefaultBlock { egress } => {
+                format!("fail-closed default: blocked via egress '{egress}' (no rules matched)")
+            }
         }
     }
 }
@@ -90,141 +278,1432 @@ const fn match_kind_to_str(k: MatchKind) -> &'static str {
     match k {
         MatchKind::Exact => "exact",
         MatchKind::Suffix => "suffix",
+        MatchKind::Wildcard => "wildcard",
     }
 }
 
+/// Rule stage a [`TraceStep`] was evaluated in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceRule {
+    BlockApp,
+    BlockDomain,
+    Port,
+    Cidr,
+    Domain,
+    App,
+}
+
+/// One egress checked while deciding, as reported by [`decide_traced`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceStep {
+    /// Egress id the rules are listed under, before group resolution.
+    pub egress: EgressId,
+    pub rule: TraceRule,
+    pub matched: bool,
+    /// The matching pattern, or the first pattern checked when none matched.
+    pub pattern: Option<String>,
+}
+
+/// A single domain rule hit, as reported by [`domain_matches`].
+#[derive(Debug, Clone)]
+pub struct DomainMatch {
+    pub egress: EgressId,
+    pub pattern: String,
+    /// Zero-based position of `pattern` in the egress's rule list.
+    pub index: usize,
+    pub match_kind: MatchKind,
+}
+
 #[derive(Debug, Clone)]
 struct DomainSuffixMatch {
     pattern: String,
+    /// Position in the egress's pattern list.
+    index: usize,
     match_kind: MatchKind,
 }
 
+/// Inputs describing one flow.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DecideInput<'a> {
+    pub process_name: Option<&'a str>,
+    /// Full command line of the process, checked by `name::substring` app
+    /// patterns.
+    pub cmdline: Option<&'a str>,
+    pub domain: Option<&'a str>,
+    /// Destination port, matched by `rules.port`.
+    pub port: Option<u16>,
+    /// Local wall-clock time of the flow. Rules routing to an egress with an
+    /// `active` schedule are skipped unless it falls in one of its windows;
+    /// `None` applies every rule regardless of schedules.
+    pub now: Option<NaiveDateTime>,
+}
+
+/// Decides the egress for a flow.
+///
+/// Empty or whitespace-only inputs are treated as absent, so matching never
+/// sees an empty process name or domain. App patterns are compiled for each
+/// call; callers deciding many flows against one config should build a
+/// [`CompiledConfig`] and use [`decide_compiled`].
 #[must_use]
 pub fn decide(cfg: &AppConfig, process_name: Option<&str>, domain: Option<&str>) -> Decision {
-    decide_block(cfg, process_name, domain)
-        .or_else(|| decide_domain(cfg, domain))
-        .or_else(|| decide_app(cfg, process_name))
-        .unwrap_or_else(|| decide_default(cfg))
+    decide_with(
+        cfg,
+        &DecideInput {
+            process_name,
+            cmdline: None,
+            domain,
+            port: None,
+            now: None,
+        },
+    )
+}
+
+/// Like [`decide`], also taking the process command line into account.
+#[must_use]
+pub fn decide_with(cfg: &AppConfig, input: &DecideInput<'_>) -> Decision {
+    Plan::new(cfg, input.now).decide(input)
 }
 
-fn decide_block(
+/// Like [`decide`], returning only the chosen egress.
+///
+/// Rules are looked up in `compiled`, which must have been built from `cfg`
+/// once and reused across calls; its rule order is borrowed rather than
+/// copied. No [`DecisionReason`] is built, so matched patterns are never
+/// copied into strings. What is still allocated per call is the normalized
+/// process path and domain, the domain pattern hits of each egress checked
+/// and the returned id. The result always equals
+/// `decide(cfg, process_name, domain).egress`.
+#[must_use]
+pub fn decide_egress(
     cfg: &AppConfig,
+    compiled: &CompiledConfig,
     process_name: Option<&str>,
     domain: Option<&str>,
-) -> Option<Decision> {
-    if let Some(name) = process_name
-        && let Some((egress, pattern)) = choose_block_app(cfg, name)
-    {
-        return Some(Decision {
-            egress: egress.clone(),
-            reason: DecisionReason::BlockByApp { egress, pattern },
-        });
+) -> EgressId {
+    let input = DecideInput {
+        process_name,
+        cmdline: None,
+        domain,
+        port: None,
+        now: None,
+    };
+    let plan = Plan::with_index(cfg, compiled, None);
+    with_normalized(
+        &input,
+        cfg.defaults.case_sensitive_domains,
+        |process, domain| plan.egress_normalized(process, domain, None).clone(),
+    )
+}
+
+/// Like [`decide`], for a domain the caller already normalized with
+/// [`normalize_domain`] under the config's `defaults.case_sensitive_domains`.
+///
+/// The domain is matched as given and rules are looked up in `compiled`,
+/// which must have been built from `cfg`, so pipelines normalizing and
+/// compiling once can decide many times without repeating that work.
+/// Decisions equal those of [`decide`] on the raw domain.
+#[must_use]
+pub fn decide_normalized(
+    cfg: &AppConfig,
+    compiled: &CompiledConfig,
+    process_name: Option<&str>,
+    normalized_domain: Option<&str>,
+) -> Decision {
+    let input = DecideInput {
+        process_name,
+        cmdline: None,
+        domain: None,
+        port: None,
+        now: None,
+    };
+    let domain = normalized_domain.filter(|domain| !domain.is_empty());
+    let plan = Plan::with_index(cfg, compiled, None);
+    with_normalized(&input, cfg.defaults.case_sensitive_domains, |process, _| {
+        plan.decide_normalized(process, domain, None)
+    })
+}
+
+/// Normalized `(process path, domain, port)` of a flow, for caching
+/// decisions.
+///
+/// Two flows with equal keys get equal decisions from `cfg` unless a command
+/// line or an egress `active` schedule is involved. IP literals are keyed by
+/// their canonical address.
+#[must_use]
+pub fn flow_key(
+    cfg: &AppConfig,
+    input: &DecideInput<'_>,
+) -> (Option<String>, Option<String>, Option<u16>) {
+    let domain = non_empty(input.domain).map(|domain| {
+        parse_ip_literal(domain).map_or_else(
+            || normalize_domain(domain, cfg.defaults.case_sensitive_domains),
+            |ip| ip.to_string(),
+        )
+    });
+    (
+        non_empty(input.process_name).map(normalize_process_path),
+        domain,
+        input.port,
+    )
+}
+
+/// Like [`decide_with`], looking app and domain rules up in `compiled`
+/// instead of scanning every pattern.
+///
+/// `compiled` must have been built from `cfg`; without one, it is built for
+/// this call. Decisions equal those of [`decide_with`].
+#[must_use]
+pub fn decide_compiled(
+    cfg: &AppConfig,
+    compiled: Option<&CompiledConfig>,
+    input: &DecideInput<'_>,
+) -> Decision {
+    compiled.map_or_else(
+        || Plan::with_index(cfg, &CompiledConfig::new(cfg), input.now).decide(input),
+        |compiled| Plan::with_index(cfg, compiled, input.now).decide(input),
+    )
+}
+
+/// Like [`decide_with`], also returning every rule evaluated on the way.
+///
+/// Steps follow evaluation order and end with the stage that decided; within
+/// the domain and cidr stages every egress is listed, since the most specific
+/// match wins across egresses. [`decide`] itself never records a trace.
+#[must_use]
+pub fn decide_traced(cfg: &AppConfig, input: &DecideInput<'_>) -> (Decision, Vec<TraceStep>) {
+    let plan = Plan::new(cfg, input.now);
+    (plan.decide(input), plan.trace(input))
+}
+
+/// Decides every `(process, domain)` pair in `inputs` against one config.
+///
+/// Egress ordering and normalized app patterns are computed once and shared
+/// by all inputs. Results follow input order and equal calling [`decide`] on
+/// each pair.
+#[must_use]
+pub fn decide_batch(cfg: &AppConfig, inputs: &[(Option<&str>, Option<&str>)]) -> Vec<Decision> {
+    let plan = Plan::new(cfg, None);
+    inputs
+        .iter()
+        .map(|&(process_name, domain)| {
+            plan.decide(&DecideInput {
+                process_name,
+                cmdline: None,
+                domain,
+                port: None,
+                now: None,
+            })
+        })
+        .collect()
+}
+
+/// Lists every domain rule matching `domain`, in evaluation order.
+///
+/// Block egresses come first, followed by non-block egresses in priority
+/// order. Within an egress, hits follow the pattern list order. IP literals,
+/// bracketed or not, match no domain rule.
+#[must_use]
+pub fn domain_matches(cfg: &AppConfig, domain: &str) -> Vec<DomainMatch> {
+    let domain = normalize_domain(domain, cfg.defaults.case_sensitive_domains);
+    if domain.is_empty() || parse_ip_literal(&domain).is_some() {
+        return Vec::new();
     }
 
-    if let Some(d) = domain
-        && let Some((egress, m)) = choose_block_domain(cfg, d)
-    {
-        return Some(Decision {
-            egress: egress.clone(),
-            reason: DecisionReason::BlockByDomain {
-                egress,
+    let rules = &cfg.rules.domain;
+    ordered_rule_egresses(cfg, rules)
+        .into_iter()
+        .filter_map(|egress| rules.get(egress).map(|patterns| (egress, patterns)))
+        .flat_map(|(egress, patterns)| {
+            domain_matches_iter(patterns, &domain, &cfg.defaults).map(|m| DomainMatch {
+                egress: egress.clone(),
                 pattern: m.pattern,
+                index: m.index,
                 match_kind: m.match_kind,
-            },
+            })
+        })
+        .collect()
+}
+
+fn non_empty(value: Option<&str>) -> Option<&str> {
+    value.filter(|v| !v.trim().is_empty())
+}
+
+/// Records egresses of one stage up to the first match; returns whether
+/// one matched.
+fn trace_first_match(
+    trace: &mut Vec<TraceStep>,
+    rule: TraceRule,
+    egresses: &[EgressId],
+    check: impl Fn(&EgressId) -> (bool, Option<String>),
+) -> bool {
+    for egress in egresses {
+        let (matched, pattern) = check(egress);
+        trace.push(TraceStep {
+            egress: egress.clone(),
+            rule,
+            matched,
+            pattern,
         });
+        if matched {
+            return true;
+        }
     }
+    false
+}
 
-    None
+/// Runs `f` on the normalized process and domain of `input`; the domain is
+/// normalized once here so matching never repeats it per egress.
+fn with_normalized<R>(
+    input: &DecideInput<'_>,
+    case_sensitive: bool,
+    f: impl FnOnce(Option<&AppInput<'_>>, Option<&str>) -> R,
+) -> R {
+    let path = non_empty(input.process_name).map(normalize_process_path);
+    let process = path.as_deref().map(|path| AppInput {
+        name: path
+            .rsplit('/')
+            .find(|segment| !segment.is_empty())
+            .unwrap_or(""),
+        path,
+        cmdline: non_empty(input.cmdline),
+    });
+    let domain = non_empty(input.domain)
+        .map(|domain| normalize_domain(domain, case_sensitive))
+        .filter(|domain| !domain.is_empty());
+    f(process.as_ref(), domain.as_deref())
 }
 
-fn decide_domain(cfg: &AppConfig, domain: Option<&str>) -> Option<Decision> {
-    let d = domain?;
+/// Rule evaluation order and lookup tables derived from one config, leaving
+/// out egresses whose schedule excludes the time the plan was made for.
+struct Plan<'a> {
+    cfg: &'a AppConfig,
+    now: Option<NaiveDateTime>,
+    /// Borrowed from a [`CompiledConfig`] unless a schedule leaves some
+    /// egresses out.
+    order: Cow<'a, RuleOrder>,
+    lookup: Lookup<'a>,
+}
 
-    choose_domain(d, cfg)
+/// How a [`Plan`] finds the app and domain patterns matching a flow.
+enum Lookup<'a> {
+    /// App patterns per egress as `(matcher, as written)` pairs in pattern
+    /// order; domain patterns are scanned straight from the config.
+    Scan(BTreeMap<&'a EgressId, Vec<(AppMatcher, &'a str)>>),
+    Index(&'a CompiledConfig),
 }
 
-fn choose_domain(domain: &str, cfg: &AppConfig) -> Option<Decision> {
-    let rules = &cfg.rules.domain;
-    for egress in ordered_non_block_rule_egresses(cfg, rules) {
-        let Some(patterns) = rules.get(egress) else {
-            continue;
+impl<'a> Plan<'a> {
+    fn new(cfg: &'a AppConfig, now: Option<NaiveDateTime>) -> Self {
+        let app_patterns = cfg
+            .rules
+            .app
+            .iter()
+            .map(|(egress, patterns)| {
+                let compiled = patterns
+                    .iter()
+                    .map(|p| (AppMatcher::new(p), p.as_str()))
+                    .collect();
+                (egress, compiled)
+            })
+            .collect();
+        let mut order = RuleOrder::new(cfg);
+        order.retain_active(cfg, now);
+
+        Self {
+            cfg,
+            now,
+            order: Cow::Owned(order),
+            lookup: Lookup::Scan(app_patterns),
+        }
+    }
+
+    fn with_index(
+        cfg: &'a AppConfig,
+        compiled: &'a CompiledConfig,
+        now: Option<NaiveDateTime>,
+    ) -> Self {
+        let order = if schedules_apply(cfg, now) {
+            let mut order = compiled.order.clone();
+            order.retain_active(cfg, now);
+            Cow::Owned(order)
+        } else {
+            Cow::Borrowed(&compiled.order)
+        };
+
+        Self {
+            cfg,
+            now,
+            order,
+            lookup: Lookup::Index(compiled),
+        }
+    }
+
+    fn decide(&self, input: &DecideInput<'_>) -> Decision {
+        with_normalized(
+            input,
+            self.cfg.defaults.case_sensitive_domains,
+            |process, domain| self.decide_normalized(process, domain, input.port),
+        )
+    }
+
+    fn decide_normalized(
+        &self,
+        process: Option<&AppInput<'_>>,
+        domain: Option<&str>,
+        port: Option<u16>,
+    ) -> Decision {
+        // IP literals only go through cidr rules; domain patterns never match them.
+        let ip = domain.and_then(parse_ip_literal);
+        let host = domain.filter(|_| ip.is_none());
+        let port_rule = |stage| {
+            port.filter(|_| self.cfg.defaults.port_priority == stage)
+                .and_then(|port| self.choose_port(port))
+        };
+        self.decide_block(process, host)
+            .or_else(|| port_rule(PortPriority::BeforeDomain))
+            .or_else(|| ip.and_then(|ip| self.choose_cidr(ip)))
+            .or_else(|| host.and_then(|d| self.choose_domain(d)))
+            .or_else(|| port_rule(PortPriority::AfterDomain))
+            .or_else(|| process.and_then(|process| self.choose_app(process)))
+            .or_else(|| port_rule(PortPriority::AfterApp))
+            .unwrap_or_else(|| decide_default(self.cfg))
+    }
+
+    /// The egress [`Self::decide_normalized`] picks, found the same way
+    /// without building a reason.
+    fn egress_normalized(
+        &self,
+        process: Option<&AppInput<'_>>,
+        domain: Option<&str>,
+        port: Option<u16>,
+    ) -> &EgressId {
+        let ip = domain.and_then(parse_ip_literal);
+        let host = domain.filter(|_| ip.is_none());
+        let block_app = || {
+            let process = process?;
+            self.order
+                .block_app
+                .iter()
+                .find(|egress| self.app_matches(egress, process))
+        };
+        let block_domain = || {
+            let host = host?;
+            self.order
+                .block_domain
+                .iter()
+                .find(|egress| self.domain_labels(egress, host).is_some())
+        };
+        let domain_rule = || {
+            let host = host?;
+            self.order
+                .domain
+                .iter()
+                .filter_map(|egress| Some((egress, self.domain_labels(egress, host)?)))
+                .min_by_key(|(_, labels)| Reverse(*labels))
+                .map(|(egress, _)| egress)
+        };
+        let app_rule = || {
+            let process = process?;
+            self.order
+                .app
+                .iter()
+                .find(|egress| self.app_matches(egress, process))
+        };
+
+        let port_rule = |stage| {
+            port.filter(|_| self.cfg.defaults.port_priority == stage)
+                .and_then(|port| self.port_winner(port))
+                .map(|winner| &self.order.port[winner].0)
+        };
+
+        block_app()
+            .or_else(block_domain)
+            .or_else(|| port_rule(PortPriority::BeforeDomain))
+            .or_else(|| {
+                ip.and_then(|ip| self.cidr_winner(ip))
+                    .map(|winner| &self.order.cidr[winner].0)
+            })
+            .or_else(domain_rule)
+            .or_else(|| port_rule(PortPriority::AfterDomain))
+            .or_else(app_rule)
+            .or_else(|| port_rule(PortPriority::AfterApp))
+            .map_or_else(
+                || default_egress(self.cfg),
+                |egress| self.cfg.resolve_group(egress),
+            )
+    }
+
+    fn trace(&self, input: &DecideInput<'_>) -> Vec<TraceStep> {
+        with_normalized(
+            input,
+            self.cfg.defaults.case_sensitive_domains,
+            |process, domain| {
+                let mut trace = Vec::new();
+                self.trace_stages(&mut trace, process, domain, input.port);
+                trace
+            },
+        )
+    }
+
+    /// Records the stages of [`Self::decide_normalized`] in order up to the
+    /// one that decided; returns whether a rule did.
+    fn trace_stages(
+        &self,
+        trace: &mut Vec<TraceStep>,
+        process: Option<&AppInput<'_>>,
+        domain: Option<&str>,
+        port: Option<u16>,
+    ) -> bool {
+        let ip = domain.and_then(parse_ip_literal);
+        let host = domain.filter(|_| ip.is_none());
+        let port_rule = |trace: &mut Vec<TraceStep>, stage| {
+            self.cfg.defaults.port_priority == stage
+                && port.is_some_and(|port| self.trace_port(trace, port))
+        };
+
+        process.is_some_and(|process| {
+            trace_first_match(trace, TraceRule::BlockApp, &self.order.block_app, |e| {
+                self.app_trace(e, process)
+            })
+        }) || host.is_some_and(|d| {
+            trace_first_match(
+                trace,
+                TraceRule::BlockDomain,
+                &self.order.block_domain,
+                |e| self.domain_trace(e, self.domain_match(e, d)),
+            )
+        }) || port_rule(trace, PortPriority::BeforeDomain)
+            || ip.is_some_and(|ip| self.trace_cidr(trace, ip))
+            || host.is_some_and(|d| self.trace_domain(trace, d))
+            || port_rule(trace, PortPriority::AfterDomain)
+            || process.is_some_and(|process| {
+                trace_first_match(trace, TraceRule::App, &self.order.app, |e| {
+                    self.app_trace(e, process)
+                })
+            })
+            || port_rule(trace, PortPriority::AfterApp)
+    }
+
+    fn trace_port(&self, trace: &mut Vec<TraceStep>, port: u16) -> bool {
+        let start = trace.len();
+        for egress in ordered_rule_egresses(self.cfg, &self.cfg.rules.port)
+            .into_iter()
+            .filter(|egress| is_active(self.cfg, egress, self.now))
+        {
+            let ranges = self.order.port.iter().filter(|(id, _, _, _)| id == egress);
+            let hit = ranges
+                .clone()
+                .filter(|(_, ports, _, _)| ports.contains(&port))
+                .min_by_key(|(_, ports, _, _)| ports.end() - ports.start());
+            trace.push(TraceStep {
+                egress: egress.clone(),
+                rule: TraceRule::Port,
+                matched: hit.is_some(),
+                pattern: hit
+                    .or_else(|| ranges.clone().next())
+                    .map(|(_, _, pattern, _)| pattern.trim().to_string()),
+            });
+        }
+        trace[start..].iter().any(|step| step.matched)
+    }
+
+    fn trace_cidr(&self, trace: &mut Vec<TraceStep>, ip: IpAddr) -> bool {
+        let start = trace.len();
+        for egress in ordered_rule_egresses(self.cfg, &self.cfg.rules.cidr)
+            .into_iter()
+            .filter(|egress| is_active(self.cfg, egress, self.now))
+        {
+            let nets = self.order.cidr.iter().filter(|(id, _, _, _)| id == egress);
+            let hit = nets
+                .clone()
+                .filter(|(_, net, _, _)| net.contains(&ip))
+                .min_by_key(|(_, net, _, _)| Reverse(net.prefix_len()));
+            trace.push(TraceStep {
+                egress: egress.clone(),
+                rule: TraceRule::Cidr,
+                matched: hit.is_some(),
+                pattern: hit
+                    .or_else(|| nets.clone().next())
+                    .map(|(_, _, pattern, _)| pattern.trim().to_string()),
+            });
+        }
+        trace[start..].iter().any(|step| step.matched)
+    }
+
+    fn trace_domain(&self, trace: &mut Vec<TraceStep>, domain: &str) -> bool {
+        let start = trace.len();
+        for egress in &self.order.domain {
+            let (matched, pattern) =
+                self.domain_trace(egress, self.most_specific_domain_match(egress, domain));
+            trace.push(TraceStep {
+                egress: egress.clone(),
+                rule: TraceRule::Domain,
+                matched,
+                pattern,
+            });
+        }
+        trace[start..].iter().any(|step| step.matched)
+    }
+
+    fn app_trace(&self, egress: &EgressId, process: &AppInput<'_>) -> (bool, Option<String>) {
+        let first = || {
+            Some(
+                self.cfg
+                    .rules
+                    .app
+                    .get(egress)?
+                    .first()?
+                    .as_str()
+                    .to_string(),
+            )
+        };
+        self.app_match(egress, process)
+            .map_or_else(|| (false, first()), |(pattern, _)| (true, Some(pattern)))
+    }
+
+    /// The step for `egress` given the pattern its stage matched, if any; the
+    /// block and domain stages pick that pattern differently.
+    fn domain_trace(
+        &self,
+        egress: &EgressId,
+        hit: Option<DomainSuffixMatch>,
+    ) -> (bool, Option<String>) {
+        let first = || {
+            Some(
+                self.cfg
+                    .rules
+                    .domain
+                    .get(egress)?
+                    .first()?
+                    .as_str()
+                    .to_string(),
+            )
         };
-        if let Some(m) = domain_matches_any(patterns, domain) {
+        hit.map_or_else(|| (false, first()), |m| (true, Some(m.pattern)))
+    }
+
+    fn decide_block(
+        &self,
+        process: Option<&AppInput<'_>>,
+        domain: Option<&str>,
+    ) -> Option<Decision> {
+        if let Some(process) = process
+            && let Some((egress, (pattern, index))) = self
+                .order
+                .block_app
+                .iter()
+                .find_map(|egress| Some((egress, self.app_match(egress, process)?)))
+        {
+            let (egress, group) = self.target(egress);
             return Some(Decision {
                 egress: egress.clone(),
-                reason: DecisionReason::DomainRule {
+                reason: DecisionReason::BlockByApp {
+                    egress,
+                    group,
+                    pattern,
+                    index,
+                },
+                priority: None,
+            });
+        }
+
+        if let Some(d) = domain
+            && let Some((egress, m)) = self
+                .order
+                .block_domain
+                .iter()
+                .find_map(|egress| Some((egress, self.domain_match(egress, d)?)))
+        {
+            let (egress, group) = self.target(egress);
+            return Some(Decision {
+                egress: egress.clone(),
+                reason: DecisionReason::BlockByDomain {
+                    egress,
+                    group,
                     pattern: m.pattern,
+                    index: m.index,
                     match_kind: m.match_kind,
-                    egress: egress.clone(),
                 },
+                priority: None,
             });
         }
+
+        None
     }
 
-    None
+    /// Picks the most specific matching pattern across non-block egresses,
+    /// falling back to egress priority order among equally specific ones.
+    fn choose_domain(&self, domain: &str) -> Option<Decision> {
+        let candidates: Vec<(&EgressId, DomainSuffixMatch, usize)> = self
+            .order
+            .domain
+            .iter()
+            .filter_map(|egress| {
+                let m = self.most_specific_domain_match(egress, domain)?;
+                let labels = pattern_label_count(&m.pattern);
+                Some((egress, m, labels))
+            })
+            .collect();
+
+        let best = candidates.iter().map(|(_, _, labels)| *labels).max()?;
+        let winner = candidates
+            .iter()
+            .position(|(_, _, labels)| *labels == best)?;
+        let (egress, m, _) = &candidates[winner];
+        let rank = egress_rank(self.cfg, egress);
+        let tie_broken = candidates[winner + 1..]
+            .iter()
+            .any(|(other, _, labels)| *labels == best && egress_rank(self.cfg, other) == rank);
+
+        let (egress, group) = self.target(egress);
+        Some(Decision {
+            egress: egress.clone(),
+            reason: DecisionReason::DomainRule {
+                pattern: m.pattern.clone(),
+                index: m.index,
+                match_kind: m.match_kind,
+                egress,
+                group,
+            },
+            priority: rank.map(|rank| Priority { rank, tie_broken }),
+        })
+    }
+
+    /// Picks the longest matching prefix across all egresses, falling back to
+    /// evaluation order among equally long ones.
+    fn choose_cidr(&self, ip: IpAddr) -> Option<Decision> {
+        let winner = self.cidr_winner(ip)?;
+        let (egress, best, pattern, index) = &self.order.cidr[winner];
+        let priority = egress_rank(self.cfg, egress).map(|rank| Priority {
+            rank,
+            tie_broken: self.order.cidr[winner + 1..]
+                .iter()
+                .any(|(other, net, _, _)| {
+                    net.contains(&ip)
+                        && net.prefix_len() == best.prefix_len()
+                        && other != egress
+                        && egress_rank(self.cfg, other) == Some(rank)
+                }),
+        });
+
+        let (egress, group) = self.target(egress);
+        Some(Decision {
+            egress: egress.clone(),
+            reason: DecisionReason::CidrRule {
+                egress,
+                group,
+                pattern: pattern.trim().to_string(),
+                index: *index,
+            },
+            priority,
+        })
+    }
+
+    /// Position in `self.cidr` of the longest prefix containing `ip`, the
+    /// first in evaluation order among equally long ones.
+    fn cidr_winner(&self, ip: IpAddr) -> Option<usize> {
+        self.order
+            .cidr
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, net, _, _))| net.contains(&ip))
+            .min_by_key(|(_, (_, net, _, _))| Reverse(net.prefix_len()))
+            .map(|(position, _)| position)
+    }
+
+    /// Picks the narrowest port range containing `port` across all egresses,
+    /// falling back to evaluation order among equally narrow ones.
+    fn choose_port(&self, port: u16) -> Option<Decision> {
+        let winner = self.port_winner(port)?;
+        let (egress, best, pattern, index) = &self.order.port[winner];
+        let width = best.end() - best.start();
+        let priority = egress_rank(self.cfg, egress).map(|rank| Priority {
+            rank,
+            tie_broken: self.order.port[winner + 1..]
+                .iter()
+                .any(|(other, ports, _, _)| {
+                    ports.contains(&port)
+                        && ports.end() - ports.start() == width
+                        && other != egress
+                        && egress_rank(self.cfg, other) == Some(rank)
+                }),
+        });
+
+        let (egress, group) = self.target(egress);
+        Some(Decision {
+            egress: egress.clone(),
+            reason: DecisionReason::PortRule {
+                egress,
+                group,
+                pattern: pattern.trim().to_string(),
+                index: *index,
+            },
+            priority,
+        })
+    }
+
+    /// Position in `self.port` of the narrowest range containing `port`, the
+    /// first in evaluation order among equally narrow ones.
+    fn port_winner(&self, port: u16) -> Option<usize> {
+        self.order
+            .port
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, ports, _, _))| ports.contains(&port))
+            .min_by_key(|(_, (_, ports, _, _))| ports.end() - ports.start())
+            .map(|(position, _)| position)
+    }
+
+    fn choose_app(&self, process: &AppInput<'_>) -> Option<Decision> {
+        for (position, egress) in self.order.app.iter().enumerate() {
+            if let Some((pattern, index)) = self.app_match(egress, process) {
+                let (resolved, group) = self.target(egress);
+                return Some(Decision {
+                    egress: resolved.clone(),
+                    reason: DecisionReason::AppRule {
+                        pattern,
+                        index,
+                        egress: resolved,
+                        group,
+                    },
+                    priority: winner_priority(self.cfg, &self.order.app, position, |id| {
+                        self.app_match(id, process).is_some()
+                    }),
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Resolves a rule egress to `(egress, group)`, `group` being set when
+    /// the rule targeted an egress group.
+    fn target(&self, rule_egress: &EgressId) -> (EgressId, Option<EgressId>) {
+        let egress = self.cfg.resolve_group(rule_egress);
+        let group = (egress != rule_egress).then(|| rule_egress.clone());
+        (egress.clone(), group)
+    }
+
+    /// The first pattern of `egress` matching `process`, as written, with its
+    /// position in the list.
+    fn app_match(&self, egress: &EgressId, process: &AppInput<'_>) -> Option<(String, usize)> {
+        match &self.lookup {
+            Lookup::Scan(app_patterns) => app_patterns
+                .get(egress)?
+                .iter()
+                .position(|(matcher, _)| matcher.is_match(process))
+                .map(|index| (app_patterns[egress][index].1.to_string(), index)),
+            Lookup::Index(compiled) => compiled.app.get(egress)?.first_match(process),
+        }
+    }
+
+    /// Whether some pattern of `egress` matches `process`.
+    fn app_matches(&self, egress: &EgressId, process: &AppInput<'_>) -> bool {
+        match &self.lookup {
+            Lookup::Scan(app_patterns) => app_patterns.get(egress).is_some_and(|patterns| {
+                patterns
+                    .iter()
+                    .any(|(matcher, _)| matcher.is_match(process))
+            }),
+            Lookup::Index(compiled) => compiled
+                .app
+                .get(egress)
+                .and_then(|index| index.first_position(process))
+                .is_some(),
+        }
+    }
+
+    /// Label count of the most specific pattern of `egress` matching
+    /// `domain`, which is what ranks domain rules across egresses.
+    fn domain_labels(&self, egress: &EgressId, domain: &str) -> Option<usize> {
+        match &self.lookup {
+            Lookup::Scan(_) => {
+                let patterns = self.cfg.rules.domain.get(egress)?;
+                let form = self.cfg.defaults.reason_domain_form;
+                domain_hits(patterns, domain, &self.cfg.defaults)
+                    .map(|(index, _)| rendered_label_count(patterns[index].as_str(), form))
+                    .max()
+            }
+            Lookup::Index(compiled) => {
+                let index = compiled.domain.get(egress)?;
+                index
+                    .hits(domain)
+                    .iter()
+                    .map(|(position, _)| index.rendered[*position].1)
+                    .max()
+            }
+        }
+    }
+
+    fn most_specific_domain_match(
+        &self,
+        egress: &EgressId,
+        domain: &str,
+    ) -> Option<DomainSuffixMatch> {
+        match &self.lookup {
+            Lookup::Scan(_) => {
+                let patterns = self.cfg.rules.domain.get(egress)?;
+                domain_matches_iter(patterns, domain, &self.cfg.defaults)
+                    .min_by_key(|m| Reverse(pattern_label_count(&m.pattern)))
+            }
+            Lookup::Index(compiled) => {
+                let index = compiled.domain.get(egress)?;
+                index.most_specific(&index.hits(domain))
+            }
+        }
+    }
+
+    fn domain_match(&self, egress: &EgressId, domain: &str) -> Option<DomainSuffixMatch> {
+        match &self.lookup {
+            Lookup::Scan(_) => {
+                let patterns = self.cfg.rules.domain.get(egress)?;
+                domain_matches_any(patterns, domain, &self.cfg.defaults)
+            }
+            Lookup::Index(compiled) => {
+                let index = compiled.domain.get(egress)?;
+                index.first(&index.hits(domain))
+            }
+        }
+    }
+}
+
+/// App and domain rules of one config, indexed so lookups do not grow with
+/// the number of patterns.
+///
+/// Plain app names are keyed by basename and domain patterns sit in a
+/// reversed-label trie. Only regex, glob and `dir:` app patterns and
+/// `*suffix` wildcards not starting at a dot are still checked one by one.
+/// Build one per config and pass it to [`decide_compiled`],
+/// [`decide_egress`] or [`decide_normalized`].
+#[derive(Debug)]
+pub struct CompiledConfig {
+    app: BTreeMap<EgressId, AppIndex>,
+    domain: BTreeMap<EgressId, DomainIndex>,
+    order: RuleOrder,
 }
 
-fn decide_app(cfg: &AppConfig, process_name: Option<&str>) -> Option<Decision> {
-    let name = process_name?;
+/// Rule egresses in evaluation order and parsed cidr and port rules.
+#[derive(Debug, Clone)]
+struct RuleOrder {
+    block_app: Vec<EgressId>,
+    block_domain: Vec<EgressId>,
+    app: Vec<EgressId>,
+    domain: Vec<EgressId>,
+    /// Parsed CIDR rules as `(egress, network, as written, position)`, block
+    /// egresses first, then non-block egresses in priority order.
+    cidr: Vec<(EgressId, IpNet, String, usize)>,
+    /// Parsed port rules as `(egress, ports, as written, position)`, in the
+    /// same order as `cidr`.
+    port: Vec<(EgressId, RangeInclusive<u16>, String, usize)>,
+}
+
+impl RuleOrder {
+    fn new(cfg: &AppConfig) -> Self {
+        let owned = |egresses: Vec<&EgressId>| egresses.into_iter().cloned().collect();
+        Self {
+            block_app: owned(ordered_block_rule_egresses(cfg, &cfg.rules.app)),
+            block_domain: owned(ordered_block_rule_egresses(cfg, &cfg.rules.domain)),
+            app: owned(ordered_non_block_rule_egresses(cfg, &cfg.rules.app)),
+            domain: owned(ordered_non_block_rule_egresses(cfg, &cfg.rules.domain)),
+            cidr: parsed_rules(cfg, &cfg.rules.cidr, |p| {
+                Some((p.parse().ok()?, p.as_str()))
+            })
+            .map(|(egress, net, raw, index)| (egress.clone(), net, raw.to_string(), index))
+            .collect(),
+            port: parsed_rules(cfg, &cfg.rules.port, |p| {
+                Some((p.parse().ok()?, p.as_str()))
+            })
+            .map(|(egress, ports, raw, index)| (egress.clone(), ports, raw.to_string(), index))
+            .collect(),
+        }
+    }
+
+    /// Leaves out the rules of egresses whose schedule excludes `now`.
+    fn retain_active(&mut self, cfg: &AppConfig, now: Option<NaiveDateTime>) {
+        if !schedules_apply(cfg, now) {
+            return;
+        }
+        let active = |egress: &EgressId| is_active(cfg, egress, now);
+        self.block_app.retain(active);
+        self.block_domain.retain(active);
+        self.app.retain(active);
+        self.domain.retain(active);
+        self.cidr.retain(|(egress, ..)| active(egress));
+        self.port.retain(|(egress, ..)| active(egress));
+    }
+}
 
-    choose_app(name, cfg)
+impl CompiledConfig {
+    #[must_use]
+    pub fn new(cfg: &AppConfig) -> Self {
+        Self {
+            app: cfg
+                .rules
+                .app
+                .iter()
+                .map(|(egress, patterns)| (egress.clone(), AppIndex::new(patterns)))
+                .collect(),
+            domain: cfg
+                .rules
+                .domain
+                .iter()
+                .map(|(egress, patterns)| {
+                    (egress.clone(), DomainIndex::new(patterns, &cfg.defaults))
+                })
+                .collect(),
+            order: RuleOrder::new(cfg),
+        }
+    }
 }
 
-fn normalize_process_name(raw: &str) -> String {
-    let trimmed = raw.trim();
-    let normalized_path = trimmed.replace('\\', "/");
-    let base_name = normalized_path
-        .rsplit('/')
-        .find(|segment| !segment.is_empty())
-        .unwrap_or("");
-    base_name.to_ascii_lowercase()
+/// App patterns of one egress.
+#[derive(Debug)]
+struct AppIndex {
+    /// `(matcher, as written)` pairs in pattern order.
+    patterns: Vec<(AppMatcher, String)>,
+    /// Positions in `patterns` of plain names, keyed by normalized basename.
+    by_name: HashMap<String, Vec<usize>>,
+    /// Positions in `patterns` of `*.ext` patterns, keyed by extension.
+    by_extension: HashMap<String, Vec<usize>>,
+    /// Positions in `patterns` of valid regexes, globs and directories,
+    /// checked one by one.
+    scanned: Vec<usize>,
 }
 
-fn choose_app(process_name: &str, cfg: &AppConfig) -> Option<Decision> {
-    let normalized = normalize_process_name(process_name);
-    let rules = &cfg.rules.app;
-    for egress in ordered_non_block_rule_egresses(cfg, rules) {
-        let Some(patterns) = rules.get(egress) else {
-            continue;
+impl AppIndex {
+    fn new(patterns: &[AppPattern]) -> Self {
+        let mut index = Self {
+            patterns: Vec::with_capacity(patterns.len()),
+            by_name: HashMap::new(),
+            by_extension: HashMap::new(),
+            scanned: Vec::new(),
         };
-        if let Some(pattern) = find_matching_app_pattern(patterns, &normalized) {
-            return Some(Decision {
-                egress: egress.clone(),
-                reason: DecisionReason::AppRule {
-                    pattern,
-                    egress: egress.clone(),
-                },
-            });
+        for (position, pattern) in patterns.iter().enumerate() {
+            let matcher = AppMatcher::new(pattern);
+            match &matcher.name {
+                NameMatcher::Exact(name) => {
+                    index
+                        .by_name
+                        .entry(name.clone())
+                        .or_default()
+                        .push(position);
+                }
+                NameMatcher::Extension(ext) => {
+                    index
+                        .by_extension
+                        .entry(ext.clone())
+                        .or_default()
+                        .push(position);
+                }
+                NameMatcher::Regex(_) | NameMatcher::Glob(_) | NameMatcher::Dir(_) => {
+                    index.scanned.push(position);
+                }
+                NameMatcher::Invalid => {}
+            }
+            index.patterns.push((matcher, pattern.as_str().to_string()));
         }
+        index
     }
 
-    None
+    /// The first pattern in pattern order matching `process`, as written,
+    /// with its position.
+    fn first_match(&self, process: &AppInput<'_>) -> Option<(String, usize)> {
+        let position = self.first_position(process)?;
+        Some((self.patterns[position].1.clone(), position))
+    }
+
+    /// Position of the first pattern matching `process`.
+    fn first_position(&self, process: &AppInput<'_>) -> Option<usize> {
+        let is_match = |position: &&usize| self.patterns[**position].0.is_match(process);
+        let by_name = self
+            .by_name
+            .get(process.name)
+            .and_then(|positions| positions.iter().find(is_match));
+        let by_extension = process_extension(process.name)
+            .and_then(|ext| self.by_extension.get(ext))
+            .and_then(|positions| positions.iter().find(is_match));
+        let scanned = self.scanned.iter().find(is_match);
+        by_name
+            .into_iter()
+            .chain(by_extension)
+            .chain(scanned)
+            .min()
+            .copied()
+    }
+}
+
+/// Domain patterns of one egress.
+#[derive(Debug, Default)]
+struct DomainIndex {
+    /// Per pattern in pattern order: the pattern as reasons show it, and its
+    /// label count.
+    rendered: Vec<(String, usize)>,
+    /// Per pattern in pattern order: whether it is an `!` exclusion. The
+    /// pattern it excludes is indexed like any other.
+    excluded: Vec<bool>,
+    /// Plain patterns and `*.suffix` wildcards.
+    trie: LabelTrie,
+    /// `*suffix` wildcards without a dot after the `*` as `(position,
+    /// suffix)`; these match inside a label and cannot live in the trie.
+    unaligned: Vec<(usize, String)>,
+}
+
+/// Domain patterns keyed label by label, starting from the rightmost one.
+#[derive(Debug, Default)]
+struct LabelTrie {
+    children: HashMap<String, Self>,
+    /// Patterns ending at this node as `(position, wildcard)`; a wildcard
+    /// needs at least one more label in the domain.
+    patterns: Vec<(usize, bool)>,
+}
+
+impl LabelTrie {
+    fn insert(&mut self, suffix: &str, position: usize, wildcard: bool) {
+        let node = suffix.rsplit('.').fold(self, |node, label| {
+            node.children.entry(label.to_string()).or_default()
+        });
+        node.patterns.push((position, wildcard));
+    }
+}
+
+impl DomainIndex {
+    /// Sorts each pattern into the structure whose lookup agrees with
+    /// [`domain_matches_pattern`] for it.
+    fn new(patterns: &[DomainPattern], defaults: &Defaults) -> Self {
+        let case_sensitive = defaults.case_sensitive_domains;
+        let mut index = Self::default();
+        for (position, pattern) in patterns.iter().enumerate() {
+            index.excluded.push(pattern.excluded().is_some());
+            let raw = pattern.excluded().unwrap_or_else(|| pattern.as_str());
+            let rendered =
+                render_domain_pattern(raw.trim().to_string(), defaults.reason_domain_form);
+            let labels = pattern_label_count(&rendered);
+            index.rendered.push((rendered, labels));
+
+            let normalized = normalize_domain(raw, case_sensitive);
+            if raw.trim().starts_with('*') {
+                match normalized.strip_prefix('*') {
+                    None | Some("" | ".") => {}
+                    Some(rest) => match rest.strip_prefix('.') {
+                        Some(suffix) => index.trie.insert(suffix, position, true),
+                        None => index.unaligned.push((position, rest.to_string())),
+                    },
+                }
+                continue;
+            }
+
+            if !normalized.is_empty() {
+                let suffix = normalized.strip_prefix('.').unwrap_or(&normalized);
+                index.trie.insert(suffix, position, false);
+            }
+        }
+        index
+    }
+
+    /// Every pattern matching the normalized `domain` as `(position, match
+    /// kind)`, in no particular order; none when an exclusion matches.
+    fn hits(&self, domain: &str) -> Vec<(usize, MatchKind)> {
+        let mut hits = Vec::new();
+
+        let mut node = &self.trie;
+        let mut labels = domain.rsplit('.').peekable();
+        while let Some(label) = labels.next() {
+            let Some(child) = node.children.get(label) else {
+                break;
+            };
+            node = child;
+            let apex = labels.peek().is_none();
+            hits.extend(node.patterns.iter().filter_map(|&(position, wildcard)| {
+                let kind = match (wildcard, apex) {
+                    (true, true) => return None,
+                    (true, false) => MatchKind::Wildcard,
+                    (false, true) => MatchKind::Exact,
+                    (false, false) => MatchKind::Suffix,
+                };
+                Some((position, kind))
+            }));
+        }
+
+        hits.extend(
+            self.unaligned
+                .iter()
+                .filter(|(_, suffix)| domain.ends_with(suffix.as_str()))
+                .map(|(position, _)| (*position, MatchKind::Wildcard)),
+        );
+
+        if hits.iter().any(|&(position, _)| self.excluded[position]) {
+            hits.clear();
+        }
+        hits
+    }
+
+    /// The hit listed first among the patterns.
+    fn first(&self, hits: &[(usize, MatchKind)]) -> Option<DomainSuffixMatch> {
+        hits.iter()
+            .min_by_key(|(position, _)| *position)
+            .map(|&(position, kind)| self.suffix_match(position, kind))
+    }
+
+    /// The hit with the most labels, the first listed among equals.
+    fn most_specific(&self, hits: &[(usize, MatchKind)]) -> Option<DomainSuffixMatch> {
+        hits.iter()
+            .min_by_key(|(position, _)| (Reverse(self.rendered[*position].1), *position))
+            .map(|&(position, kind)| self.suffix_match(position, kind))
+    }
+
+    fn suffix_match(&self, position: usize, match_kind: MatchKind) -> DomainSuffixMatch {
+        DomainSuffixMatch {
+            pattern: self.rendered[position].0.clone(),
+            index: position,
+            match_kind,
+        }
+    }
+}
+
+/// Parses `domain` as an IP literal, accepting bracketed IPv6.
+fn parse_ip_literal(domain: &str) -> Option<IpAddr> {
+    let trimmed = domain.trim();
+    let unbracketed = trimmed
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .unwrap_or(trimmed);
+    unbracketed
+        .parse::<IpAddr>()
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
+
+/// The process side of a flow, as app patterns see it.
+struct AppInput<'a> {
+    /// Normalized basename.
+    name: &'a str,
+    /// Normalized full path, for `dir:` patterns.
+    path: &'a str,
+    cmdline: Option<&'a str>,
+}
+
+/// A compiled app pattern.
+#[derive(Debug)]
+struct AppMatcher {
+    name: NameMatcher,
+    /// Substring the command line must contain.
+    cmdline: Option<String>,
+}
+
+#[derive(Debug)]
+enum NameMatcher {
+    /// Normalized basename compared for equality.
+    Exact(String),
+    Regex(Regex),
+    /// Glob over the normalized basename.
+    Glob(GlobMatcher),
+    /// Lowercase last extension of the normalized basename, from `*.ext`.
+    Extension(String),
+    /// Normalized directory the full path must lie under.
+    Dir(String),
+    /// A regex or glob that failed to compile; never matches.
+    Invalid,
+}
+
+impl AppMatcher {
+    fn new(pattern: &AppPattern) -> Self {
+        let (name, cmdline) = pattern.parts();
+        let name = match (pattern.compile_regex(), pattern.compile_glob()) {
+            (Some(Ok(regex)), _) => NameMatcher::Regex(regex),
+            (_, Some(Ok(glob))) => NameMatcher::Glob(glob),
+            (Some(Err(_)), _) | (_, Some(Err(_))) => NameMatcher::Invalid,
+            (None, None) => match (pattern.extension(), pattern.dir()) {
+                (Some(ext), _) => NameMatcher::Extension(ext.trim().to_ascii_lowercase()),
+                (None, Some(dir)) => NameMatcher::Dir(normalize_process_dir(dir)),
+                (None, None) => NameMatcher::Exact(normalize_process_name(name)),
+            },
+        };
+        Self {
+            name,
+            cmdline: cmdline.map(str::to_owned),
+        }
+    }
+
+    fn is_match(&self, process: &AppInput<'_>) -> bool {
+        let name_matches = match &self.name {
+            NameMatcher::Exact(name) => name == process.name,
+            NameMatcher::Regex(regex) => regex.is_match(process.name),
+            NameMatcher::Glob(glob) => glob.is_match(process.name),
+            NameMatcher::Extension(ext) => process_extension(process.name) == Some(ext),
+            NameMatcher::Dir(dir) => process
+                .path
+                .strip_prefix(dir.as_str())
+                .is_some_and(|rest| rest.starts_with('/')),
+            NameMatcher::Invalid => false,
+        };
+        name_matches
+            && self.cmdline.as_deref().is_none_or(|required| {
+                process
+                    .cmdline
+                    .is_some_and(|cmdline| cmdline.contains(required))
+            })
+    }
+}
+
+/// Last extension of a normalized basename, which is already lowercase.
+fn process_extension(name: &str) -> Option<&str> {
+    name.rsplit_once('.').map(|(_, ext)| ext)
 }
 
+/// Number of labels in a domain pattern, ignoring wildcards and outer dots.
+fn pattern_label_count(pattern: &str) -> usize {
+    pattern
+        .trim()
+        .trim_start_matches('*')
+        .trim_matches('.')
+        .split('.')
+        .filter(|label| !label.is_empty())
+        .count()
+}
+
+/// Whether rules routing to `egress` apply at `now`, per the `active`
+/// schedule of the egress it resolves to.
+/// Whether some egress has a schedule that `now` is checked against.
+fn schedules_apply(cfg: &AppConfig, now: Option<NaiveDateTime>) -> bool {
+    now.is_some() && cfg.egress.values().any(|spec| spec.active.is_some())
+}
+
+fn is_active(cfg: &AppConfig, egress: &EgressId, now: Option<NaiveDateTime>) -> bool {
+    let schedule = cfg
+        .resolved_spec(egress)
+        .and_then(|spec| spec.active.as_ref());
+    match (schedule, now) {
+        (Some(schedule), Some(now)) => schedule.includes(now),
+        _ => true,
+    }
+}
+
+/// Builds the [`Priority`] of `ordered[winner]`, checking whether any later
+/// egress of the same rank also matched.
+fn winner_priority(
+    cfg: &AppConfig,
+    ordered: &[EgressId],
+    winner: usize,
+    matches: impl Fn(&EgressId) -> bool,
+) -> Option<Priority> {
+    let rank = egress_rank(cfg, &ordered[winner])?;
+    let tie_broken = ordered[winner + 1..]
+        .iter()
+        .take_while(|id| egress_rank(cfg, id) == Some(rank))
+        .any(matches);
+
+    Some(Priority { rank, tie_broken })
+}
+
+/// The egress flows matching no rule go to.
+fn default_egress(cfg: &AppConfig) -> &EgressId {
+    match cfg.defaults.on_no_match {
+        OnNoMatch::Block => default_block_egress(cfg).unwrap_or(&cfg.defaults.egress),
+        OnNoMatch::Route => &cfg.defaults.egress,
+    }
+}
+
+/// Decision for a flow no rule matched; a block egress as the default,
+/// chosen by `on_no_match` or named by `defaults.egress`, reads as a deny.
 fn decide_default(cfg: &AppConfig) -> Decision {
+    let egress = default_egress(cfg).clone();
+    let reason = if is_block_egress(cfg, &egress) {
+        DecisionReason::DefaultBlock {
+            egress: egress.clone(),
+        }
+    } else {
+        DecisionReason::Default {
+            egress: egress.clone(),
+        }
+    };
+
     Decision {
-        egress: cfg.defaults.egress.clone(),
-        reason: DecisionReason::Default {
-            egress: cfg.defaults.egress.clone(),
-        },
+        egress,
+        reason,
+        priority: None,
     }
 }
 
-fn find_matching_app_pattern(list: &[AppPattern], normalized_value: &str) -> Option<String> {
-    list.iter()
-        .find(|pattern| normalize_process_name(pattern.as_str()) == normalized_value)
-        .map(|pattern| pattern.as_str().to_string())
+fn domain_matches_any(
+    suffixes: &[DomainPattern],
+    domain: &str,
+    defaults: &Defaults,
+) -> Option<DomainSuffixMatch> {
+    domain_matches_iter(suffixes, domain, defaults).next()
 }
 
-fn domain_matches_any(suffixes: &[DomainPattern], domain: &str) -> Option<DomainSuffixMatch> {
-    let d = domain.trim().trim_end_matches('.').to_ascii_lowercase();
-    suffixes
+/// Inclusion patterns of one egress matching the normalized `domain`, in
+/// pattern order; nothing when one of its `!` exclusions matches.
+fn domain_matches_iter<'a>(
+    suffixes: &'a [DomainPattern],
+    domain: &'a str,
+    defaults: &Defaults,
+) -> impl Iterator<Item = DomainSuffixMatch> + 'a {
+    let form = defaults.reason_domain_form;
+    domain_hits(suffixes, domain, defaults).map(move |(index, match_kind)| DomainSuffixMatch {
+        pattern: render_domain_pattern(suffixes[index].as_str().trim().to_string(), form),
+        index,
+        match_kind,
+    })
+}
+
+/// Like [`domain_matches_iter`], yielding only `(position, match kind)`.
+fn domain_hits<'a>(
+    suffixes: &'a [DomainPattern],
+    domain: &'a str,
+    defaults: &Defaults,
+) -> impl Iterator<Item = (usize, MatchKind)> + 'a {
+    let case_sensitive = defaults.case_sensitive_domains;
+    let excluded = suffixes
         .iter()
-        .find_map(|raw| domain_matches_suffix(&d, raw.as_str()))
+        .filter_map(DomainPattern::excluded)
+        .any(|raw| domain_matches_pattern(domain, raw, case_sensitive).is_some());
+    let suffixes = if excluded { &[] } else { suffixes };
+    suffixes.iter().enumerate().filter_map(move |(index, raw)| {
+        if raw.excluded().is_some() {
+            return None;
+        }
+        domain_matches_pattern(domain, raw.as_str(), case_sensitive)
+            .map(|match_kind| (index, match_kind))
+    })
+}
+
+/// [`pattern_label_count`] of `raw` as reasons render it, only rendering
+/// when the form could change it.
+fn rendered_label_count(raw: &str, form: ReasonDomainForm) -> usize {
+    match form {
+        ReasonDomainForm::AsWritten => pattern_label_count(raw),
+        ReasonDomainForm::Unicode | ReasonDomainForm::Ascii => {
+            pattern_label_count(&render_domain_pattern(raw.trim().to_string(), form))
+        }
+    }
+}
+
+fn render_domain_pattern(pattern: String, form: ReasonDomainForm) -> String {
+    match form {
+        ReasonDomainForm::AsWritten => pattern,
+        ReasonDomainForm::Unicode => idn::domain_to_unicode(&pattern),
+        ReasonDomainForm::Ascii => idn::domain_to_ascii(&pattern).unwrap_or(pattern),
+    }
+}
+
+/// How `raw` matches `domain`, if it does.
+///
+/// `domain` must already be normalized with the same `case_sensitive`.
+fn domain_matches_pattern(domain: &str, raw: &str, case_sensitive: bool) -> Option<MatchKind> {
+    if raw.trim().starts_with('*') {
+        return domain_matches_wildcard(domain, raw, case_sensitive);
+    }
+
+    domain_matches_suffix(domain, raw, case_sensitive)
 }
 
-fn domain_matches_suffix(domain: &str, raw_suffix: &str) -> Option<DomainSuffixMatch> {
-    let suffix_raw = raw_suffix.trim().trim_end_matches('.').to_ascii_lowercase();
+fn domain_matches_suffix(
+    domain: &str,
+    raw_suffix: &str,
+    case_sensitive: bool,
+) -> Option<MatchKind> {
+    let suffix_raw = normalize_domain(raw_suffix, case_sensitive);
     if suffix_raw.is_empty() {
         return None;
     }
@@ -232,77 +1711,147 @@ fn domain_matches_suffix(domain: &str, raw_suffix: &str) -> Option<DomainSuffixM
     let suffix = suffix_raw.strip_prefix('.').unwrap_or(suffix_raw.as_str());
 
     if domain == suffix {
-        return Some(DomainSuffixMatch {
-            pattern: raw_suffix.trim().to_string(),
-            match_kind: MatchKind::Exact,
-        });
+        return Some(MatchKind::Exact);
     }
 
     if domain.ends_with(&format!(".{suffix}")) {
-        return Some(DomainSuffixMatch {
-            pattern: raw_suffix.trim().to_string(),
-            match_kind: MatchKind::Suffix,
-        });
+        return Some(MatchKind::Suffix);
     }
 
     None
 }
 
-fn choose_block_app(cfg: &AppConfig, process_name: &str) -> Option<(EgressId, String)> {
-    let normalized = normalize_process_name(process_name);
-    for (egress, patterns) in cfg
-        .rules
-        .app
-        .iter()
-        .filter(|(id, _)| is_block_egress(cfg, id))
-    {
-        if let Some(pattern) = find_matching_app_pattern(patterns, &normalized) {
-            return Some((egress.clone(), pattern));
-        }
+fn domain_matches_wildcard(
+    domain: &str,
+    raw_pattern: &str,
+    case_sensitive: bool,
+) -> Option<MatchKind> {
+    let pattern = normalize_domain(raw_pattern, case_sensitive);
+    let rest = pattern.strip_prefix('*')?;
+    if rest.is_empty() || rest == "." {
+        return None;
     }
 
-    None
+    // For `*.example.com` the rest keeps its leading dot, so the apex never
+    // matches; a bare `*example.com` is a plain string suffix.
+    domain.ends_with(rest).then_some(MatchKind::Wildcard)
 }
 
-fn choose_block_domain(cfg: &AppConfig, domain: &str) -> Option<(EgressId, DomainSuffixMatch)> {
-    for (egress, patterns) in cfg
-        .rules
-        .domain
-        .iter()
-        .filter(|(id, _)| is_block_egress(cfg, id))
-    {
-        if let Some(m) = domain_matches_any(patterns, domain) {
-            return Some((egress.clone(), m));
-        }
+fn is_block_egress(cfg: &AppConfig, id: &EgressId) -> bool {
+    cfg.resolved_spec(id)
+        .is_some_and(|spec| matches!(spec.kind, EgressKind::Block))
+}
+
+/// Block egress used for fail-closed defaults, chosen per `block_select`.
+fn default_block_egress(cfg: &AppConfig) -> Option<&EgressId> {
+    let mut blocks = cfg.egress.keys().filter(|id| is_block_egress(cfg, id));
+    match cfg.defaults.block_select {
+        BlockSelect::FirstId => blocks.next(),
+        BlockSelect::FirstDeclared => blocks.min_by_key(|id| cfg.egress[*id].declared_index),
     }
+}
 
-    None
+/// Egresses of `rules` in evaluation order: block egresses first, then
+/// non-block egresses by priority.
+pub(crate) fn ordered_rule_egresses<'a, T>(
+    cfg: &'a AppConfig,
+    rules: &'a BTreeMap<EgressId, Vec<T>>,
+) -> Vec<&'a EgressId> {
+    let mut ordered = ordered_block_rule_egresses(cfg, rules);
+    ordered.extend(ordered_non_block_rule_egresses(cfg, rules));
+    ordered
 }
 
-fn is_block_egress(cfg: &AppConfig, id: &EgressId) -> bool {
-    cfg.egress
-        .get(id)
-        .is_some_and(|spec| matches!(spec.kind, EgressKind::Block))
+/// Parsed rules of `rules` as `(egress, value, as written, position)`, block
+/// egresses first, then non-block egresses in priority order. Patterns
+/// `parse` rejects are left out.
+fn parsed_rules<'a, T, V>(
+    cfg: &'a AppConfig,
+    rules: &'a BTreeMap<EgressId, Vec<T>>,
+    parse: impl Fn(&'a T) -> Option<(V, &'a str)> + Copy + 'a,
+) -> impl Iterator<Item = (&'a EgressId, V, &'a str, usize)> + 'a {
+    ordered_rule_egresses(cfg, rules)
+        .into_iter()
+        .flat_map(move |egress| {
+            rules[egress]
+                .iter()
+                .enumerate()
+                .filter_map(move |(index, p)| {
+                    let (value, raw) = parse(p)?;
+                    Some((egress, value, raw, index))
+                })
+        })
+}
+
+fn ordered_block_rule_egresses<'a, T>(
+    cfg: &'a AppConfig,
+    rules: &'a BTreeMap<EgressId, Vec<T>>,
+) -> Vec<&'a EgressId> {
+    let mut ordered: Vec<&EgressId> = rules.keys().filter(|id| is_block_egress(cfg, id)).collect();
+
+    if cfg.defaults.block_select == BlockSelect::FirstDeclared {
+        ordered.sort_by_key(|id| cfg.resolved_spec(id).map(|spec| spec.declared_index));
+    }
+
+    ordered
+}
+
+/// Egresses with app and domain rules, in the order [`decide`] tries them.
+/// Egresses whose `active` schedule excludes the time of a decision are
+/// skipped then, but still listed here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RulePriority<'a> {
+    /// Block egresses with app rules, tried before any other rule.
+    pub block_app: Vec<&'a EgressId>,
+    /// Block egresses with domain rules, tried right after `block_app`.
+    pub block_domain: Vec<&'a EgressId>,
+    /// Non-block egresses with app rules; the first match wins.
+    pub app: Vec<&'a EgressId>,
+    /// Non-block egresses with domain rules; the most specific match wins,
+    /// ties going to the earlier egress.
+    pub domain: Vec<&'a EgressId>,
+}
+
+/// Returns the evaluation order of the app and domain rules of `cfg`.
+#[must_use]
+pub fn rule_priority(cfg: &AppConfig) -> RulePriority<'_> {
+    RulePriority {
+        block_app: ordered_block_rule_egresses(cfg, &cfg.rules.app),
+        block_domain: ordered_block_rule_egresses(cfg, &cfg.rules.domain),
+        app: ordered_non_block_rule_egresses(cfg, &cfg.rules.app),
+        domain: ordered_non_block_rule_egresses(cfg, &cfg.rules.domain),
+    }
+}
+
+/// Returns the non-block priority rank of egress `id`, lower winning.
+///
+/// An explicit `priority` on the egress is used as is; otherwise the kind
+/// rank applies: 0 for singbox and tun, 5 for http, 10 for socks5, 20 for
+/// direct. Block and undeclared egresses have no rank.
+#[must_use]
+pub fn egress_rank(cfg: &AppConfig, id: &EgressId) -> Option<i32> {
+    let spec = cfg.resolved_spec(id)?;
+    let kind_rank = match spec.kind {
+        EgressKind::Singbox | EgressKind::Tun => 0,
+        EgressKind::Http => PRIORITY_SPACING / 2,
+        EgressKind::Socks5 => PRIORITY_SPACING,
+        EgressKind::Direct => 2 * PRIORITY_SPACING,
+        EgressKind::Block => return None,
+    };
+    Some(spec.priority.unwrap_or(kind_rank))
 }
 
 fn ordered_non_block_rule_egresses<'a, T>(
     cfg: &'a AppConfig,
     rules: &'a BTreeMap<EgressId, Vec<T>>,
 ) -> Vec<&'a EgressId> {
-    let mut ordered: Vec<(&EgressId, usize)> = rules
+    let mut ordered: Vec<(&EgressId, i32)> = rules
         .keys()
-        .filter_map(|id| {
-            let spec = cfg.egress.get(id)?;
-            let rank = match spec.kind {
-                EgressKind::Singbox => 0,
-                EgressKind::Socks5 => 1,
-                EgressKind::Direct => 2,
-                EgressKind::Block => return None,
-            };
-            Some((id, rank))
-        })
+        .filter_map(|id| Some((id, egress_rank(cfg, id)?)))
         .collect();
 
+    // Explicit priorities and kind ranks share one scale, so a priority of 10
+    // ties with every unprioritized socks5 egress. Ties break on egress id.
     ordered.sort_by(|(left_id, left_rank), (right_id, right_rank)| {
         left_rank
             .cmp(right_rank)
@@ -0,0 +1,183 @@
+//! Conversion between Unicode and ASCII (punycode) forms of domain names.
+//!
+//! Implements the punycode codec from RFC 3492 applied label by label, with
+//! the `xn--` ACE prefix. Labels are lowercased but no further IDNA mapping
+//! is performed, which is enough for rendering configured patterns.
+
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+const ACE_PREFIX: &str = "xn--";
+
+/// Converts every non-ASCII label of `domain` to its `xn--` punycode form.
+///
+/// Returns `None` if a label cannot be encoded.
+#[must_use]
+pub fn domain_to_ascii(domain: &str) -> Option<String> {
+    domain
+        .split('.')
+        .map(|label| {
+            if label.is_ascii() {
+                Some(label.to_ascii_lowercase())
+            } else {
+                let chars: Vec<char> = label.to_lowercase().chars().collect();
+                encode(&chars).map(|encoded| format!("{ACE_PREFIX}{encoded}"))
+            }
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|labels| labels.join("."))
+}
+
+/// Converts every `xn--` label of `domain` back to Unicode.
+///
+/// Labels that are not valid punycode are kept in their ASCII form.
+#[must_use]
+pub fn domain_to_unicode(domain: &str) -> String {
+    domain
+        .split('.')
+        .map(|label| {
+            let lower = label.to_lowercase();
+            lower
+                .strip_prefix(ACE_PREFIX)
+                .and_then(decode)
+                .unwrap_or(lower)
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn encode(input: &[char]) -> Option<String> {
+    let mut output: String = input.iter().filter(|c| c.is_ascii()).collect();
+    let basic_len = u32::try_from(output.len()).ok()?;
+    let total_len = u32::try_from(input.len()).ok()?;
+    if basic_len > 0 {
+        output.push('-');
+    }
+
+    let mut code_point = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut handled = basic_len;
+
+    while handled < total_len {
+        let next = input
+            .iter()
+            .map(|&c| u32::from(c))
+            .filter(|&c| c >= code_point)
+            .min()?;
+        delta = delta.checked_add((next - code_point).checked_mul(handled + 1)?)?;
+        code_point = next;
+
+        for &c in input {
+            let c = u32::from(c);
+            if c < code_point {
+                delta = delta.checked_add(1)?;
+            }
+            if c == code_point {
+                let mut rest = delta;
+                let mut k = BASE;
+                loop {
+                    let t = threshold(k, bias);
+                    if rest < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + (rest - t) % (BASE - t)));
+                    rest = (rest - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(encode_digit(rest));
+                bias = adapt(delta, handled + 1, handled == basic_len);
+                delta = 0;
+                handled += 1;
+            }
+        }
+
+        delta = delta.checked_add(1)?;
+        code_point += 1;
+    }
+
+    Some(output)
+}
+
+fn decode(input: &str) -> Option<String> {
+    let (basic, extended) = input.rsplit_once('-').unwrap_or(("", input));
+    if !basic.is_ascii() {
+        return None;
+    }
+
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut code_point = INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut digits = extended.bytes();
+
+    while digits.len() > 0 {
+        let old_i = i;
+        let mut weight: u32 = 1;
+        let mut k = BASE;
+        loop {
+            let digit = decode_digit(digits.next()?)?;
+            i = i.checked_add(digit.checked_mul(weight)?)?;
+            let t = threshold(k, bias);
+            if digit < t {
+                break;
+            }
+            weight = weight.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+
+        let len = u32::try_from(output.len()).ok()? + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        code_point = code_point.checked_add(i / len)?;
+        i %= len;
+        output.insert(usize::try_from(i).ok()?, char::from_u32(code_point)?);
+        i += 1;
+    }
+
+    Some(output.into_iter().collect())
+}
+
+const fn threshold(k: u32, bias: u32) -> u32 {
+    if k <= bias {
+        T_MIN
+    } else if k >= bias + T_MAX {
+        T_MAX
+    } else {
+        k - bias
+    }
+}
+
+const fn adapt(delta: u32, num_points: u32, first_time: bool) -> u32 {
+    let mut delta = if first_time { delta / DAMP } else { delta / 2 };
+    delta += delta / num_points;
+
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+
+    k + ((BASE - T_MIN + 1) * delta) / (delta + SKEW)
+}
+
+fn encode_digit(d: u32) -> char {
+    let d = u8::try_from(d).unwrap_or(0);
+    if d < 26 {
+        char::from(b'a' + d)
+    } else {
+        char::from(b'0' + d - 26)
+    }
+}
+
+const fn decode_digit(b: u8) -> Option<u32> {
+    match b {
+        b'a'..=b'z' => Some((b - b'a') as u32),
+        b'A'..=b'Z' => Some((b - b'A') as u32),
+        b'0'..=b'9' => Some((b - b'0') as u32 + 26),
+        _ => None,
+    }
+}
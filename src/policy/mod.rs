@@ -1,2 +1,7 @@
 pub mod config;
+pub mod diff;
 pub mod engine;
+pub mod idn;
+#[cfg(feature = "psl")]
+pub mod psl;
+pub mod schedule;
@@ -0,0 +1,21 @@
+//! Public suffix awareness for `defaults.use_psl` pattern validation.
+//!
+//! Thin wrappers around the [`psl`] crate, which embeds the full public
+//! suffix list (ICANN and private sections). Names under an unknown TLD fall
+//! back to the list's implicit `*` rule.
+
+/// Returns `true` if `domain` is itself a public suffix.
+///
+/// `domain` must already be normalized and stripped of a trailing dot.
+#[must_use]
+pub fn is_public_suffix(domain: &str) -> bool {
+    ::psl::suffix_str(domain).is_none_or(|suffix| suffix == domain)
+}
+
+/// Returns the registrable domain (public suffix plus one label) of `domain`.
+///
+/// Returns `None` when `domain` is empty or is itself a public suffix.
+#[must_use]
+pub fn registrable_domain(domain: &str) -> Option<&str> {
+    ::psl::domain_str(domain)
+}
@@ -0,0 +1,147 @@
+//! Weekly time windows gating when an egress's rules apply.
+
+use anyhow::{Context, Result, bail};
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// When an egress's rules apply, as a list of windows like
+/// `"mon-fri 18:00-23:59"`; a single window may be written as a plain string.
+///
+/// Times are wall-clock local time, both ends inclusive to the minute. A
+/// window ending before it starts runs past midnight into the next day.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Schedule(pub Vec<String>);
+
+impl<'de> Deserialize<'de> for Schedule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            One(String),
+            Many(Vec<String>),
+        }
+
+        Ok(match Raw::deserialize(deserializer)? {
+            Raw::One(window) => Self(vec![window]),
+            Raw::Many(windows) => Self(windows),
+        })
+    }
+}
+
+impl Schedule {
+    /// Parses every window.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first malformed window, or if there are
+    /// none.
+    pub fn windows(&self) -> Result<Vec<Window>> {
+        if self.0.is_empty() {
+            bail!("schedule lists no windows");
+        }
+        self.0.iter().map(|raw| Window::parse(raw)).collect()
+    }
+
+    /// Whether some window includes `at`; malformed windows never do.
+    #[must_use]
+    pub fn includes(&self, at: NaiveDateTime) -> bool {
+        self.0
+            .iter()
+            .filter_map(|raw| Window::parse(raw).ok())
+            .any(|window| window.includes(at))
+    }
+}
+
+/// One `<days> <HH:MM>-<HH:MM>` window of a [`Schedule`].
+///
+/// Days are `mon` through `sun`, as a comma-separated list of single days
+/// and ranges such as `mon-fri,sun`; ranges may wrap, as in `fri-mon`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    /// One bit per weekday, Monday in the lowest bit.
+    days: u8,
+    /// Minutes since midnight.
+    start: u32,
+    end: u32,
+}
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+impl Window {
+    /// Parses one window.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the days or times are malformed.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let parse = || -> Result<Self> {
+            let Some((days, times)) = raw.trim().split_once(char::is_whitespace) else {
+                bail!("expected '<days> <HH:MM>-<HH:MM>'");
+            };
+            let Some((start, end)) = times.trim().split_once('-') else {
+                bail!("expected a time range like 18:00-23:59");
+            };
+            Ok(Self {
+                days: parse_days(days)?,
+                start: parse_time(start)?,
+                end: parse_time(end)?,
+            })
+        };
+        parse().with_context(|| format!("invalid schedule window '{}'", raw.trim()))
+    }
+
+    /// Whether `at` falls in this window.
+    #[must_use]
+    pub fn includes(&self, at: NaiveDateTime) -> bool {
+        let day = at.weekday().num_days_from_monday();
+        let minute = at.hour() * 60 + at.minute();
+        if self.start <= self.end {
+            return self.has_day(day) && (self.start..=self.end).contains(&minute);
+        }
+        // Past midnight the window still belongs to the day it started on.
+        (self.has_day(day) && minute >= self.start)
+            || (self.has_day((day + 6) % 7) && minute <= self.end)
+    }
+
+    const fn has_day(self, day: u32) -> bool {
+        self.days & (1 << day) != 0
+    }
+}
+
+fn parse_days(raw: &str) -> Result<u8> {
+    let mut days = 0_u8;
+    for part in raw.split(',') {
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let (first, last) = (parse_day(first)?, parse_day(last)?);
+        let mut day = first;
+        loop {
+            days |= 1 << day;
+            if day == last {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Ok(days)
+}
+
+fn parse_day(raw: &str) -> Result<usize> {
+    let raw = raw.trim().to_ascii_lowercase();
+    DAYS.iter()
+        .position(|day| *day == raw)
+        .with_context(|| format!("unknown day '{raw}', expected one of {}", DAYS.join(", ")))
+}
+
+fn parse_time(raw: &str) -> Result<u32> {
+    let raw = raw.trim();
+    let parsed = raw.split_once(':').and_then(|(hour, minute)| {
+        let two_digits = |s: &str| s.len() == 2 && s.bytes().all(|b| b.is_ascii_digit());
+        if !two_digits(hour) || !two_digits(minute) {
+            return None;
+        }
+        let (hour, minute) = (hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?);
+        (hour < 24 && minute < 60).then_some(hour * 60 + minute)
+    });
+    parsed.with_context(|| format!("invalid time '{raw}', expected HH:MM between 00:00 and 23:59"))
+}
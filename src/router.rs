@@ -0,0 +1,249 @@
+//! A single handle on a live config for embedding the policy engine.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use arc_swap::{ArcSwap, Guard};
+
+use crate::{
+    ipc::{EgressInfo, StatusResponse},
+    policy::{
+        config::AppConfig,
+        engine::{self, CompiledConfig, DecideInput, Decision},
+    },
+};
+
+/// Config path reported by [`PolicyRouter::status`] while the live config
+/// came from [`PolicyRouter::reload_from_str`] rather than the config file.
+pub const INLINE_CONFIG_PATH: &str = "<ipc>";
+
+/// The active config of a [`PolicyRouter`] and its rule index, swapped
+/// together on reload.
+#[derive(Debug)]
+pub struct LiveConfig {
+    cfg: AppConfig,
+    compiled: CompiledConfig,
+    generation: u64,
+    inline: bool,
+}
+
+impl LiveConfig {
+    #[must_use]
+    pub fn new(cfg: AppConfig) -> Self {
+        Self::with_generation(cfg, 0, false)
+    }
+
+    fn with_generation(cfg: AppConfig, generation: u64, inline: bool) -> Self {
+        let compiled = CompiledConfig::new(&cfg);
+        Self {
+            cfg,
+            compiled,
+            generation,
+            inline,
+        }
+    }
+
+    /// Rule index built from this config.
+    #[must_use]
+    pub const fn compiled(&self) -> &CompiledConfig {
+        &self.compiled
+    }
+
+    /// Reloads that had completed when this config was swapped in; 0 for the
+    /// config a [`PolicyRouter`] started with.
+    #[must_use]
+    pub const fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Whether this config was loaded from text instead of the config file.
+    #[must_use]
+    pub const fn is_inline(&self) -> bool {
+        self.inline
+    }
+}
+
+impl std::ops::Deref for LiveConfig {
+    type Target = AppConfig;
+
+    fn deref(&self) -> &AppConfig {
+        &self.cfg
+    }
+}
+
+/// A config file loaded for deciding flows, reloadable while in use.
+///
+/// Decisions and reloads may run concurrently from any thread; a decision
+/// sees either the old or the new config, never a mix.
+#[derive(Debug)]
+pub struct PolicyRouter {
+    config_path: PathBuf,
+    started_at: Instant,
+    live: ArcSwap<LiveConfig>,
+    /// Generation of the latest config handed to `live`.
+    generation: AtomicU64,
+}
+
+impl PolicyRouter {
+    /// Loads the config at `path`, which [`PolicyRouter::reload`] re-reads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the config cannot be loaded; see
+    /// [`AppConfig::load_from_path`].
+    pub fn from_path(path: impl Into<PathBuf>) -> Result<Self> {
+        let config_path = path.into();
+        let cfg = AppConfig::load_from_path(&config_path)?;
+        Ok(Self::with_config(config_path, cfg))
+    }
+
+    /// Serves `cfg`, already loaded from `path`.
+    #[must_use]
+    pub fn with_config(path: impl Into<PathBuf>, cfg: AppConfig) -> Self {
+        Self {
+            config_path: path.into(),
+            started_at: Instant::now(),
+            live: ArcSwap::from_pointee(LiveConfig::new(cfg)),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    #[must_use]
+    pub fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+
+    /// Where the live config came from: the config path, or
+    /// [`INLINE_CONFIG_PATH`] after [`PolicyRouter::reload_from_str`].
+    #[must_use]
+    pub fn config_source(&self) -> String {
+        if self.config().is_inline() {
+            INLINE_CONFIG_PATH.to_string()
+        } else {
+            self.config_path.display().to_string()
+        }
+    }
+
+    /// Time since the router was created.
+    #[must_use]
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Number of configs swapped in by [`PolicyRouter::reload`] and
+    /// [`PolicyRouter::reload_from_str`]; failed reloads leave it unchanged.
+    /// Read it from [`PolicyRouter::config`] instead to get it together with
+    /// the config it numbers.
+    #[must_use]
+    pub fn generation(&self) -> u64 {
+        self.config().generation()
+    }
+
+    /// The config live at call time; a reload does not change it.
+    #[must_use]
+    pub fn config(&self) -> Guard<Arc<LiveConfig>> {
+        self.live.load()
+    }
+
+    /// Loads the config file again without making it live.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the config path if it cannot be loaded.
+    pub fn load_config(&self) -> Result<AppConfig> {
+        AppConfig::load_from_path(&self.config_path)
+            .with_context(|| format!("failed to load config {}", self.config_path.display()))
+    }
+
+    /// Loads the config file again and makes it live; on error the previous
+    /// config stays in place.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the config path if it cannot be loaded.
+    pub fn reload(&self) -> Result<()> {
+        let next = self.load_config()?;
+        self.swap(next, false);
+        Ok(())
+    }
+
+    /// Parses and validates `raw` as a config and makes it live, without
+    /// reading the config file; relative paths in it resolve against the
+    /// config file's directory. On error the previous config stays in place.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `raw` is not a valid config; see
+    /// [`AppConfig::load_from_str`].
+    pub fn reload_from_str(&self, raw: &str) -> Result<()> {
+        let next = AppConfig::load_from_str(raw, &self.config_path)?;
+        self.swap(next, true);
+        Ok(())
+    }
+
+    fn swap(&self, next: AppConfig, inline: bool) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.live.store(Arc::new(LiveConfig::with_generation(
+            next, generation, inline,
+        )));
+    }
+
+    /// Decides the egress for a flow with the live config, skipping rules
+    /// whose egress `active` schedule excludes the current local time.
+    #[must_use]
+    pub fn decide(&self, process_name: Option<&str>, domain: Option<&str>) -> Decision {
+        let cfg = self.config();
+        engine::decide_compiled(
+            &cfg,
+            Some(cfg.compiled()),
+            &DecideInput {
+                process_name,
+                cmdline: None,
+                domain,
+                port: None,
+                now: Some(chrono::Local::now().naive_local()),
+            },
+        )
+    }
+
+    /// Uptime, config source, and the generation and egresses of the live
+    /// config.
+    #[must_use]
+    pub fn status(&self) -> StatusResponse {
+        let cfg = self.config();
+        let egress = cfg
+            .egress
+            .iter()
+            .map(|(id, spec)| EgressInfo {
+                id: id.to_string(),
+                kind: spec.kind.to_string(),
+                endpoint: spec.endpoint.clone(),
+                interface: spec.interface.clone(),
+                auth: spec.username.is_some(),
+                groups: cfg
+                    .egress_group
+                    .iter()
+                    .filter(|(_, group)| group.members.contains(id))
+                    .map(|(group_id, _)| group_id.to_string())
+                    .collect(),
+                description: spec.description.clone(),
+                healthy: None,
+                last_checked_ms: None,
+            })
+            .collect();
+
+        StatusResponse {
+            uptime_ms: u64::try_from(self.uptime().as_millis()).unwrap_or(u64::MAX),
+            config_path: self.config_source(),
+            config_generation: cfg.generation(),
+            egress,
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use policy_router_rs::policy::{
+    config::{AppConfig, EgressId},
+    diff::{ConfigDiff, RuleChange, RuleKind},
+};
+
+const LIVE: &str = r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.vpn]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.old]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1090"
+
+[egress.direct]
+type = "direct"
+
+[rules.app]
+vpn = ["zen.exe"]
+old = []
+direct = []
+
+[rules.domain]
+vpn = ["example.com", "example.org"]
+old = []
+direct = []
+"#;
+
+fn parse(raw: &str) -> AppConfig {
+    toml::from_str(raw).expect("config must parse")
+}
+
+fn id(raw: &str) -> EgressId {
+    EgressId(raw.to_owned())
+}
+
+fn rule(kind: RuleKind, egress: &str, pattern: &str) -> RuleChange {
+    RuleChange {
+        kind,
+        egress: id(egress),
+        pattern: pattern.to_owned(),
+    }
+}
+
+#[test]
+fn identical_configs_have_empty_diff() {
+    let diff = ConfigDiff::between(&parse(LIVE), &parse(LIVE));
+    assert!(diff.is_empty(), "got {diff:?}");
+}
+
+#[test]
+fn diff_reports_added_and_removed_egresses() {
+    let next = LIVE
+        .replace("[egress.old]", "[egress.new]")
+        .replace("old = []", "new = []");
+    let diff = ConfigDiff::between(&parse(LIVE), &parse(&next));
+
+    assert_eq!(diff.added_egress, [id("new")]);
+    assert_eq!(diff.removed_egress, [id("old")]);
+    assert!(diff.changed_endpoints.is_empty(), "got {diff:?}");
+    assert!(diff.added_rules.is_empty(), "got {diff:?}");
+}
+
+#[test]
+fn diff_reports_changed_endpoints_only() {
+    let next = LIVE.replace("127.0.0.1:1080", "127.0.0.1:1081");
+    let diff = ConfigDiff::between(&parse(LIVE), &parse(&next));
+
+    assert_eq!(diff.changed_endpoints.len(), 1);
+    let change = &diff.changed_endpoints[0];
+    assert_eq!(change.egress, id("vpn"));
+    assert_eq!(change.old.as_deref(), Some("socks5://127.0.0.1:1080"));
+    assert_eq!(change.new.as_deref(), Some("socks5://127.0.0.1:1081"));
+    assert!(diff.added_egress.is_empty() && diff.removed_egress.is_empty());
+}
+
+#[test]
+fn diff_reports_added_and_removed_rule_patterns() {
+    let next = LIVE
+        .replace(
+            r#"vpn = ["example.com", "example.org"]"#,
+            r#"vpn = ["example.org", "example.net"]"#,
+        )
+        .replacen("direct = []", r#"direct = ["ciadpi.exe"]"#, 1);
+    let diff = ConfigDiff::between(&parse(LIVE), &parse(&next));
+
+    assert_eq!(
+        diff.added_rules,
+        [
+            rule(RuleKind::App, "direct", "ciadpi.exe"),
+            rule(RuleKind::Domain, "vpn", "example.net"),
+        ]
+    );
+    assert_eq!(
+        diff.removed_rules,
+        [rule(RuleKind::Domain, "vpn", "example.com")]
+    );
+}
+
+#[test]
+fn diff_treats_pattern_moved_between_egresses_as_remove_and_add() {
+    let next = LIVE.replace(r#"vpn = ["zen.exe"]"#, "vpn = []").replace(
+        "[rules.app]\nvpn = []\nold = []\ndirect = []",
+        "[rules.app]\nvpn = []\nold = []\ndirect = [\"zen.exe\"]",
+    );
+    let diff = ConfigDiff::between(&parse(LIVE), &parse(&next));
+
+    assert_eq!(diff.added_rules, [rule(RuleKind::App, "direct", "zen.exe")]);
+    assert_eq!(diff.removed_rules, [rule(RuleKind::App, "vpn", "zen.exe")]);
+}
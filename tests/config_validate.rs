@@ -2,7 +2,9 @@ use policy_router_rs::policy::config::AppConfig;
 
 fn base_config(egress_block: &str, rules_block: &str) -> String {
     format!(
-        r#"[defaults]
+        r#"version = 2
+
+[defaults]
 egress = "main"
 
 {egress_block}
@@ -126,3 +128,702 @@ main = ["   "]
     let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
     assert!(cfg.validate().is_err());
 }
+
+#[cfg(not(feature = "psl"))]
+#[test]
+fn validate_rejects_use_psl_without_feature() {
+    let raw = r#"version = 2
+
+[defaults]
+egress = "main"
+use_psl = true
+
+[egress.main]
+type = "direct"
+
+[rules.app]
+main = []
+"#;
+    let cfg = toml::from_str::<AppConfig>(raw).expect("config must parse");
+    assert!(cfg.validate().is_err());
+}
+
+#[test]
+fn validate_rejects_alias_shadowing_declared_egress() {
+    let raw = base_config(
+        r#"[egress.main]
+type = "direct"
+
+[egress.old]
+type = "direct"
+
+[aliases]
+old = "main"
+"#,
+        "[rules.app]\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    assert!(cfg.validate().is_err());
+}
+
+#[test]
+fn validate_rejects_alias_to_unknown_egress() {
+    let raw = base_config(
+        r#"[egress.main]
+type = "direct"
+
+[aliases]
+old = "missing"
+"#,
+        "[rules.app]\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    assert!(cfg.validate().is_err());
+}
+
+#[test]
+fn validate_accepts_unknown_rule_egress_with_drop_rule() {
+    let raw = r#"version = 2
+
+[defaults]
+egress = "main"
+unknown_egress = "drop_rule"
+
+[egress.main]
+type = "direct"
+
+[rules.app]
+main = ["zen.exe"]
+gone = ["curl.exe", "wget.exe"]
+
+[rules.domain]
+gone = ["example.com"]
+"#;
+    let mut cfg = toml::from_str::<AppConfig>(raw).expect("config must parse");
+    cfg.validate().expect("drop_rule config must validate");
+
+    cfg.drop_unknown_egress_rules();
+    assert_eq!(cfg.dropped_rules, 3);
+    assert_eq!(cfg.rules.app.len(), 1);
+    assert!(cfg.rules.domain.is_empty());
+}
+
+#[test]
+fn validate_rejects_inner_or_bare_domain_wildcards() {
+    for pattern in ["a.*.b.com", "*.a*.com", "*", "*."] {
+        let raw = base_config(
+            r#"[egress.main]
+type = "direct"
+"#,
+            &format!("[rules.domain]\nmain = [\"{pattern}\"]\n"),
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        assert!(cfg.validate().is_err(), "{pattern} must be rejected");
+    }
+}
+
+#[test]
+fn validate_checks_internationalized_domain_patterns() {
+    for pattern in ["пример.рф", "*.bücher.example", "!cdn.münchen.de"] {
+        let raw = base_config(
+            "[egress.main]\ntype = \"direct\"\n",
+            &format!("[rules.domain]\nmain = [\"example.com\", \"{pattern}\"]\n"),
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        cfg.validate()
+            .expect("internationalized pattern must validate");
+    }
+
+    for pattern in ["пример<>.рф", "bü cher.example"] {
+        let raw = base_config(
+            "[egress.main]\ntype = \"direct\"\n",
+            &format!("[rules.domain]\nmain = [\"{pattern}\"]\n"),
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        let message = cfg
+            .validate()
+            .expect_err("unconvertible pattern must be rejected")
+            .to_string();
+        assert!(
+            message.contains("not a valid internationalized domain name"),
+            "{message}"
+        );
+    }
+}
+
+#[test]
+fn validate_accepts_domain_exclusions_next_to_inclusions() {
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n",
+        "[rules.domain]\nmain = [\"example.com\", \"!internal.example.com\", \"!*.lab.example.com\"]\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    cfg.validate().expect("config must validate");
+}
+
+#[test]
+fn validate_rejects_exclusion_only_or_malformed_exclusions() {
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n",
+        "[rules.domain]\nmain = [\"!internal.example.com\"]\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    let message = cfg
+        .validate()
+        .expect_err("exclusion-only list must be rejected")
+        .to_string();
+    assert!(message.contains("only '!' exclusions"), "{message}");
+
+    for pattern in ["!", "!!a.com", "a!.com", "!*"] {
+        let raw = base_config(
+            "[egress.main]\ntype = \"direct\"\n",
+            &format!("[rules.domain]\nmain = [\"example.com\", \"{pattern}\"]\n"),
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        assert!(cfg.validate().is_err(), "{pattern} must be rejected");
+    }
+}
+
+#[test]
+fn validate_rejects_credentials_on_direct() {
+    let raw = base_config(
+        r#"[egress.main]
+type = "direct"
+username = "user"
+password = "secret"
+"#,
+        "[rules.app]\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    assert!(cfg.validate().is_err());
+}
+
+#[test]
+fn validate_accepts_http_endpoints() {
+    for endpoint in [
+        "http://127.0.0.1:3128",
+        "https://proxy.example.com:443",
+        "http://alice:s3cret@[::1]:8080",
+    ] {
+        let raw = base_config(
+            &format!("[egress.main]\ntype = \"http\"\nendpoint = \"{endpoint}\"\n"),
+            "[rules.app]\n",
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        cfg.validate()
+            .unwrap_or_else(|err| panic!("{endpoint} must be accepted: {err:#}"));
+    }
+}
+
+#[test]
+fn validate_accepts_mixed_case_schemes_and_ipv6_hosts() {
+    for egress in [
+        "type = \"socks5\"\nendpoint = \"SOCKS5://127.0.0.1:1080\"\n",
+        "type = \"singbox\"\nendpoint = \"Socks5://[::1]:1080\"\n",
+        "type = \"http\"\nendpoint = \"HTTPS://proxy.example.com:443\"\n",
+    ] {
+        let raw = base_config(&format!("[egress.main]\n{egress}"), "[rules.app]\n");
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        cfg.validate()
+            .unwrap_or_else(|err| panic!("{egress} must be accepted: {err:#}"));
+    }
+}
+
+#[test]
+fn validate_rejects_endpoints_with_path_or_bad_ipv6() {
+    for endpoint in [
+        "socks5://127.0.0.1:1080/tunnel",
+        "socks5://127.0.0.1:1080?x=1",
+        "socks5://[::1:1080",
+        "socks5://[::1]",
+        "socks5://127.0.0.1:0",
+        "socks5://:1080",
+    ] {
+        let raw = base_config(
+            &format!("[egress.main]\ntype = \"socks5\"\nendpoint = \"{endpoint}\"\n"),
+            "[rules.app]\n",
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        assert!(cfg.validate().is_err(), "{endpoint} must be rejected");
+    }
+}
+
+#[test]
+fn validate_rejects_bad_http_endpoints() {
+    for egress in [
+        "type = \"http\"\n",
+        "type = \"http\"\nendpoint = \"socks5://127.0.0.1:1080\"\n",
+        "type = \"http\"\nendpoint = \"http://127.0.0.1\"\n",
+        "type = \"http\"\nendpoint = \"127.0.0.1:3128\"\n",
+        "type = \"socks5\"\nendpoint = \"http://127.0.0.1:3128\"\n",
+    ] {
+        let raw = base_config(&format!("[egress.main]\n{egress}"), "[rules.app]\n");
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        assert!(cfg.validate().is_err(), "{egress} must be rejected");
+    }
+}
+
+#[test]
+fn validate_rejects_username_without_password() {
+    for egress in [
+        "type = \"socks5\"\nendpoint = \"socks5://127.0.0.1:1080\"\nusername = \"user\"\n",
+        "type = \"socks5\"\nendpoint = \"socks5://user@127.0.0.1:1080\"\n",
+    ] {
+        let raw = base_config(&format!("[egress.main]\n{egress}"), "[rules.app]\n");
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        assert!(cfg.validate().is_err(), "{egress} must be rejected");
+    }
+}
+
+#[test]
+fn validate_rejects_invalid_app_regex() {
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n",
+        "[rules.app]\nmain = [\"/python3\\\\.(1[12/\"]\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    let err = cfg.validate().expect_err("invalid regex must be rejected");
+    let message = err.to_string();
+    assert!(message.contains("'main'"), "{message}");
+    assert!(message.contains("/python3\\.(1[12/"), "{message}");
+}
+
+#[test]
+fn validate_rejects_app_extension_pattern_without_extension() {
+    for pattern in ["*.", "*. ::--flag"] {
+        let raw = base_config(
+            "[egress.main]\ntype = \"direct\"\n",
+            &format!("[rules.app]\nmain = [\"{pattern}\"]\n"),
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        let err = cfg
+            .validate()
+            .expect_err("empty extension must be rejected");
+        assert!(err.to_string().contains("names no extension"), "{err}");
+    }
+}
+
+#[test]
+fn validate_rejects_incomplete_app_cmdline_pattern() {
+    for pattern in ["node.exe::", "::script.js"] {
+        let raw = base_config(
+            "[egress.main]\ntype = \"direct\"\n",
+            &format!("[rules.app]\nmain = [\"{pattern}\"]\n"),
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        assert!(cfg.validate().is_err(), "{pattern} must be rejected");
+    }
+}
+
+#[test]
+fn validate_rejects_app_dir_pattern_without_directory() {
+    for pattern in ["dir:", "dir:  ", "dir:/", "dir:\\\\::tool"] {
+        let raw = base_config(
+            "[egress.main]\ntype = \"direct\"\n",
+            &format!("[rules.app]\nmain = [\"{pattern}\"]\n"),
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        let err = cfg.validate().expect_err("empty dir must be rejected");
+        assert!(
+            err.to_string().contains("names no directory"),
+            "{pattern}: {err}"
+        );
+    }
+
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n",
+        "[rules.app]\nmain = [\"dir:C:/Tools\", \"dir:/opt/tools/::--fast\"]\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    cfg.validate().expect("dir patterns must validate");
+}
+
+#[test]
+fn validate_rejects_invalid_app_glob() {
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n",
+        "[rules.app]\nmain = [\"chrome*[.exe\"]\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    let err = cfg.validate().expect_err("invalid glob must be rejected");
+    assert!(
+        err.to_string()
+            .contains("rules.app glob 'chrome*[.exe' for egress 'main' is invalid"),
+        "{err}"
+    );
+
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n",
+        "[rules.app]\nmain = [\"chrome*.exe\", \"tool?.exe::--fast\"]\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    cfg.validate().expect("glob patterns must validate");
+}
+
+#[test]
+fn validate_rejects_invalid_cidr() {
+    for pattern in ["10.0.0.0", "10.0.0.0/33", "example.com/8"] {
+        let raw = base_config(
+            "[egress.main]\ntype = \"direct\"\n",
+            &format!("[rules.cidr]\nmain = [\"{pattern}\"]\n"),
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        assert!(cfg.validate().is_err(), "{pattern} must be rejected");
+    }
+}
+
+#[test]
+fn validate_accepts_ports_and_port_ranges() {
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n",
+        "[rules.port]\nmain = [\"22\", \"8000-8100\", \"65535\", \"443-443\"]\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    cfg.validate().expect("ports and ranges must validate");
+}
+
+#[test]
+fn validate_rejects_invalid_port_patterns() {
+    for pattern in [
+        "0",
+        "65536",
+        "ssh",
+        "8100-8000",
+        "8000-",
+        "-22",
+        "1-2-3",
+        "",
+    ] {
+        let raw = base_config(
+            "[egress.main]\ntype = \"direct\"\n",
+            &format!("[rules.port]\nmain = [\"{pattern}\"]\n"),
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        let err = cfg.validate().expect_err("malformed port must be rejected");
+        assert!(err.to_string().contains("rules.port"), "{pattern}: {err}");
+    }
+}
+
+#[test]
+fn validate_rejects_on_no_match_block_without_block_egress() {
+    let raw = r#"version = 2
+
+[defaults]
+egress = "main"
+on_no_match = "block"
+
+[egress.main]
+type = "direct"
+
+[rules.app]
+"#;
+    let cfg = toml::from_str::<AppConfig>(raw).expect("config must parse");
+    assert!(cfg.validate().is_err());
+}
+
+#[test]
+fn validate_rejects_duplicate_app_patterns() {
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n",
+        "[rules.app]\nmain = [\"zen.exe\", \"curl.exe\", 'C:\\Apps\\ZEN.EXE']\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    let message = cfg
+        .validate()
+        .expect_err("duplicate app pattern must be rejected")
+        .to_string();
+    assert!(message.contains("'main'"), "{message}");
+    assert!(message.contains("indices 0 and 2"), "{message}");
+}
+
+#[test]
+fn validate_rejects_duplicate_domain_patterns() {
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n",
+        "[rules.app]\n\n[rules.domain]\nmain = [\"youtube.com\", \"YouTube.com.\"]\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    let message = cfg
+        .validate()
+        .expect_err("duplicate domain pattern must be rejected")
+        .to_string();
+    assert!(message.contains("'YouTube.com.'"), "{message}");
+    assert!(message.contains("indices 0 and 1"), "{message}");
+}
+
+#[test]
+fn validate_allows_same_pattern_under_different_egresses() {
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n\n[egress.other]\ntype = \"direct\"\n",
+        "[rules.app]\n\n[rules.domain]\nmain = [\"youtube.com\"]\nother = [\"youtube.com\"]\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    cfg.validate().expect("config must validate");
+}
+
+#[test]
+fn lints_report_pattern_shared_across_egresses() {
+    let raw = base_config(
+        r#"[egress.proxy]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.main]
+type = "direct"
+"#,
+        r#"[rules.app]
+main = ["curl.exe"]
+proxy = ["CURL.EXE"]
+
+[rules.domain]
+main = ["example.com", "only-direct.example"]
+proxy = ["Example.com."]
+"#,
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    cfg.validate()
+        .expect("shared patterns must not fail validation");
+
+    let lints = cfg.lints();
+    assert_eq!(lints.len(), 2, "{lints:?}");
+    assert!(
+        lints[0].starts_with("rules.app pattern 'curl.exe'"),
+        "{lints:?}"
+    );
+    assert!(lints[1].contains("'example.com'"), "{lints:?}");
+    assert!(lints[1].ends_with("only 'proxy' can win"), "{lints:?}");
+}
+
+#[test]
+fn validate_rejects_bad_egress_groups() {
+    for (group, message) in [
+        ("[egress_group.g]\nmembers = [\"missing\"]\n", "'missing'"),
+        ("[egress_group.g]\nmembers = []\n", "no members"),
+        (
+            "[egress_group.g]\nmembers = [\"inner\"]\n[egress_group.inner]\nmembers = [\"main\"]\n",
+            "nested",
+        ),
+        ("[egress_group.main]\nmembers = [\"main\"]\n", "shadows"),
+    ] {
+        let raw = base_config(
+            &format!("[egress.main]\ntype = \"direct\"\n{group}"),
+            "[rules.app]\ng = [\"zen.exe\"]\n",
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        let err = cfg.validate().expect_err("bad group must be rejected");
+        assert!(err.to_string().contains(message), "{err}");
+    }
+}
+
+#[test]
+fn validate_accepts_active_schedules() {
+    for active in [
+        r#""mon-fri 18:00-23:59""#,
+        r#"["mon-fri 18:00-23:59", "sat,sun 00:00-23:59"]"#,
+        r#""fri-mon 22:00-02:00""#,
+    ] {
+        let raw = base_config(
+            &format!("[egress.main]\ntype = \"direct\"\nactive = {active}\n"),
+            "[rules.app]\nmain = [\"zen.exe\"]\n",
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        cfg.validate().expect("schedule must validate");
+    }
+}
+
+#[test]
+fn validate_rejects_malformed_active_schedules() {
+    for (active, message) in [
+        ("[]", "no windows"),
+        (r#""18:00-23:59""#, "expected '<days> <HH:MM>-<HH:MM>'"),
+        (r#""mon-fri 18:00""#, "time range"),
+        (r#""mon-fry 18:00-23:59""#, "unknown day 'fry'"),
+        (r#""mon 24:00-23:59""#, "invalid time '24:00'"),
+        (r#""mon 8:00-09:00""#, "invalid time '8:00'"),
+    ] {
+        let raw = base_config(
+            &format!("[egress.main]\ntype = \"direct\"\nactive = {active}\n"),
+            "[rules.app]\nmain = [\"zen.exe\"]\n",
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        let err = cfg.validate().expect_err("bad schedule must be rejected");
+        let err = format!("{err:#}");
+        assert!(
+            err.contains("egress 'main' has an invalid active schedule"),
+            "{err}"
+        );
+        assert!(err.contains(message), "{err}");
+    }
+}
+
+#[test]
+fn validate_checks_security_allow_control() {
+    let with_security = |security: &str| {
+        let raw = base_config(
+            "[egress.main]\ntype = \"direct\"\n",
+            &format!("[rules.app]\n\n[security]\n{security}"),
+        );
+        toml::from_str::<AppConfig>(&raw).expect("config must parse")
+    };
+
+    for security in [
+        "allow_control = [\"1000\", \"alice\"]\n",
+        "allow_control = []\n",
+        "allow_control = ['BUILTIN\\Administrators']\nrestrict = \"all\"\n",
+    ] {
+        with_security(security)
+            .validate()
+            .unwrap_or_else(|err| panic!("{security} must validate: {err:#}"));
+    }
+
+    for (security, expected) in [
+        ("restrict = \"all\"\n", "requires security.allow_control"),
+        ("allow_control = [\"alice\", \" \"]\n", "index 1 is empty"),
+    ] {
+        let err = with_security(security)
+            .validate()
+            .expect_err("security must be rejected");
+        assert!(err.to_string().contains(expected), "{security}: {err}");
+    }
+
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n",
+        "[rules.app]\n\n[security]\nallow_stop = [\"alice\"]\n",
+    );
+    assert!(toml::from_str::<AppConfig>(&raw).is_err());
+}
+
+#[test]
+fn validate_accepts_tun_egress_with_interface() {
+    let raw = base_config(
+        "[egress.main]\ntype = \"tun\"\ninterface = \"wg0\"\n",
+        "[rules.app]\nmain = [\"zen.exe\"]\n",
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    cfg.validate().expect("tun egress must validate");
+}
+
+#[test]
+fn validate_rejects_tun_egress_without_interface() {
+    for (egress, expected) in [
+        ("type = \"tun\"\n", "requires interface"),
+        ("type = \"tun\"\ninterface = \" \"\n", "empty interface"),
+        (
+            "type = \"tun\"\ninterface = \"wg0\"\nendpoint = \"socks5://127.0.0.1:1080\"\n",
+            "must not define endpoint",
+        ),
+        (
+            "type = \"direct\"\ninterface = \"wg0\"\n",
+            "must not define interface",
+        ),
+    ] {
+        let raw = base_config(
+            &format!("[egress.main]\n{egress}"),
+            "[rules.app]\nmain = [\"zen.exe\"]\n",
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        let err = cfg.validate().expect_err("egress must be rejected");
+        assert!(err.to_string().contains(expected), "{egress}: {err}");
+    }
+}
+
+#[test]
+fn validate_accepts_egress_id_with_dash_and_underscore() {
+    let raw = base_config(
+        r#"[egress.main]
+type = "direct"
+
+[egress.proxy-eu_2]
+type = "block"
+"#,
+        r#"[rules.domain]
+proxy-eu_2 = ["ads.example.com"]
+"#,
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    cfg.validate().expect("config must validate");
+}
+
+#[test]
+fn validate_rejects_egress_id_with_space_or_dot() {
+    for id in ["my proxy", "proxy.eu"] {
+        let raw = base_config(
+            &format!(
+                r#"[egress.main]
+type = "direct"
+
+[egress."{id}"]
+type = "block"
+"#
+            ),
+            "[rules.app]",
+        );
+        let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+        let err = cfg.validate().expect_err("unsafe egress id");
+        let message = err.to_string();
+        assert!(message.contains(&format!("egress id '{id}'")), "{message}");
+    }
+}
+
+#[test]
+fn validate_rejects_unsafe_egress_id_in_rule_keys() {
+    let raw = base_config(
+        r#"[egress.main]
+type = "direct"
+"#,
+        r#"[rules.app]
+"main.exe" = ["chrome.exe"]
+"#,
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    let err = cfg.validate().expect_err("unsafe rule key");
+    assert!(err.to_string().contains("'main.exe'"), "{err}");
+}
+
+#[test]
+fn validate_checks_rule_meta_references_listed_patterns() {
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n",
+        r#"[rules.app]
+main = ["zen.exe"]
+
+[rules.domain]
+main = ["example.com"]
+
+[rules.app_meta.main]
+"zen.exe" = "browser used for work"
+
+[rules.domain_meta.main]
+"example.com" = "vendor portal"
+"#,
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    cfg.validate().expect("config must validate");
+
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n",
+        r#"[rules.domain]
+main = ["example.com"]
+
+[rules.domain_meta.main]
+"example.org" = "typo"
+"#,
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    let message = cfg
+        .validate()
+        .expect_err("meta for an unlisted pattern")
+        .to_string();
+    assert!(message.contains("rules.domain_meta"), "{message}");
+    assert!(message.contains("'example.org'"), "{message}");
+
+    let raw = base_config(
+        "[egress.main]\ntype = \"direct\"\n",
+        r#"[rules.app_meta.main]
+"zen.exe" = "no app rules at all"
+"#,
+    );
+    let cfg = toml::from_str::<AppConfig>(&raw).expect("config must parse");
+    let message = cfg.validate().expect_err("meta without a list").to_string();
+    assert!(message.contains("rules.app_meta"), "{message}");
+}
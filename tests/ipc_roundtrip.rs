@@ -1,13 +1,22 @@
-use std::{io::BufReader, thread};
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader},
+    thread,
+};
 
 use anyhow::{Context, Result};
 use interprocess::local_socket::{
     GenericFilePath, GenericNamespaced, ListenerOptions, Stream, prelude::*,
 };
 use policy_router_rs::ipc::{
-    DecisionInfo, DecisionSource, DiagnosticsResponse, EgressInfo, ExplainRequest, ExplainResponse,
-    MatcherInfo, MatcherKind, Request, Response, StatusResponse, client_roundtrip, read_json_line,
-    write_json_line,
+    ClientInfo, ConfigFilesResponse, ConfigResponse, DecisionInfo, DecisionSource,
+    DiagnosticsResponse, EgressInfo, EgressRefsResponse, EndpointChangeInfo, ErrorCode,
+    ErrorResponse, Event, ExplainRequest, ExplainResponse, HelloResponse, HistoryEntry,
+    HistoryRequest, HistoryResponse, LineTooLong, MAX_LINE_BYTES, MIN_SUPPORTED_PROTOCOL_VERSION,
+    MatcherInfo, MatcherKind, MetricsResponse, PROTOCOL_VERSION, PriorityResponse,
+    ReloadDiffResponse, Request, Response, RuleChangeInfo, RuleStat, RuleStatsResponse,
+    RulesResponse, StatusResponse, ValidateResponse, WhoIsResponse, client_hello, client_roundtrip,
+    read_json_line, read_json_line_limited, write_json_line,
 };
 
 fn unique_tag() -> String {
@@ -20,8 +29,7 @@ fn unique_tag() -> String {
 
     let nanos = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_nanos())
-        .unwrap_or(0);
+        .map_or(0, |d| d.as_nanos());
 
     format!("{pid}-{nanos}-{seq}")
 }
@@ -60,6 +68,169 @@ impl TestServer {
     }
 }
 
+fn fake_explain(req: ExplainRequest) -> Response {
+    let proc = req.process.unwrap_or_else(|| "<none>".to_owned());
+    let dom = req.domain.unwrap_or_else(|| "<none>".to_owned());
+
+    Response::OkExplain(Box::new(ExplainResponse {
+        decision: DecisionInfo {
+            egress: "vpn".to_owned(),
+            reason: format!("process={proc} domain={dom}"),
+            reason_code: "default".to_owned(),
+            kind: Some("singbox".to_owned()),
+            endpoint: Some("socks5://127.0.0.1:1488".to_owned()),
+            source: DecisionSource::Default,
+            rule_egress: Some("vpn".to_owned()),
+            group: None,
+            matcher: Some(MatcherInfo {
+                kind: MatcherKind::Exact,
+                pattern: "example".to_owned(),
+                index: Some(2),
+                egress: None,
+                rank: None,
+            }),
+            description: None,
+            matchers: Vec::new(),
+            rank: None,
+            tie_broken: false,
+            trace: Vec::new(),
+        },
+        client: None,
+    }))
+}
+
+fn fake_status(kind: &str) -> Response {
+    Response::OkStatus(StatusResponse {
+        uptime_ms: 123,
+        config_path: "config.toml".to_owned(),
+        config_generation: 3,
+        egress: vec![
+            EgressInfo {
+                id: "vpn".to_owned(),
+                kind: kind.to_owned(),
+                endpoint: Some("127.0.0.1:1080".to_owned()),
+                interface: None,
+                auth: true,
+                groups: vec!["failover".to_owned()],
+                description: Some("sing-box core".to_owned()),
+                healthy: Some(true),
+                last_checked_ms: Some(100),
+            },
+            EgressInfo {
+                id: "direct".to_owned(),
+                kind: "direct".to_owned(),
+                endpoint: None,
+                interface: None,
+                auth: false,
+                groups: Vec::new(),
+                description: None,
+                healthy: None,
+                last_checked_ms: None,
+            },
+        ],
+    })
+}
+
+#[test]
+fn status_response_serializes_to_yaml_matching_json() {
+    let resp = fake_status("socks5");
+    let yaml = serde_yaml::to_string(&resp).expect("status must serialize to YAML");
+
+    let doc: serde_yaml::Value = serde_yaml::from_str(&yaml).expect("YAML must parse");
+    assert_eq!(doc["type"].as_str(), Some("ok_status"), "{yaml}");
+    assert_eq!(
+        serde_yaml::from_str::<serde_json::Value>(&yaml).expect("YAML must parse"),
+        serde_json::to_value(&resp).expect("status must serialize to JSON"),
+    );
+    assert!(matches!(
+        serde_yaml::from_str::<Response>(&yaml),
+        Ok(Response::OkStatus(_))
+    ));
+}
+
+fn fake_whois(addr: &str) -> Response {
+    Response::OkWhoIs(WhoIsResponse {
+        process: (addr == "127.0.0.1:50123").then(|| ClientInfo {
+            pid: 4242,
+            exe: "C:/Apps/chrome.exe".to_owned(),
+        }),
+    })
+}
+
+fn fake_egress_refs(egress: String) -> Response {
+    if egress != "proxy" {
+        return Response::Err(ErrorResponse {
+            code: ErrorCode::UnknownEgress,
+            message: format!("unknown egress id '{egress}'"),
+        });
+    }
+    Response::OkEgressRefs(EgressRefsResponse {
+        egress,
+        default: false,
+        app: vec!["chrome.exe".to_owned()],
+        domain: vec!["example.com".to_owned()],
+        cidr: Vec::new(),
+        port: Vec::new(),
+    })
+}
+
+fn fake_priority() -> Response {
+    Response::OkPriority(PriorityResponse {
+        block_app: vec!["block".to_owned()],
+        block_domain: Vec::new(),
+        app: vec!["vpn".to_owned(), "direct".to_owned()],
+        domain: vec!["proxy".to_owned()],
+    })
+}
+
+fn fake_rule_stats() -> Response {
+    Response::OkRuleStats(RuleStatsResponse {
+        rules: vec![RuleStat {
+            kind: "domain".to_owned(),
+            egress: "proxy".to_owned(),
+            pattern: "example.com".to_owned(),
+            hits: 0,
+            never_matched: true,
+        }],
+    })
+}
+
+fn fake_config() -> Response {
+    Response::OkConfig(ConfigResponse {
+        toml: "[defaults]\negress = \"direct\"\n".to_owned(),
+    })
+}
+
+fn fake_reload_diff() -> Response {
+    Response::OkReloadDiff(ReloadDiffResponse {
+        added_egress: vec!["proxy".to_owned()],
+        removed_egress: Vec::new(),
+        changed_endpoints: vec![EndpointChangeInfo {
+            egress: "vpn".to_owned(),
+            old: Some("127.0.0.1:1080".to_owned()),
+            new: Some("127.0.0.1:1081".to_owned()),
+        }],
+        added_rules: vec![RuleChangeInfo {
+            kind: "domain".to_owned(),
+            egress: "proxy".to_owned(),
+            pattern: "example.com".to_owned(),
+        }],
+        removed_rules: Vec::new(),
+    })
+}
+
+fn fake_rules() -> Response {
+    Response::OkRules(RulesResponse {
+        app: BTreeMap::from([("proxy".to_owned(), vec!["chrome.exe".to_owned()])]),
+        domain: BTreeMap::from([
+            ("direct".to_owned(), vec!["lan".to_owned()]),
+            ("proxy".to_owned(), vec!["example.com".to_owned()]),
+        ]),
+        cidr: BTreeMap::from([("direct".to_owned(), vec!["10.0.0.0/8".to_owned()])]),
+        port: BTreeMap::from([("proxy".to_owned(), vec!["8000-8100".to_owned()])]),
+    })
+}
+
 fn spawn_stateful_server(
     name: interprocess::local_socket::Name<'static>,
     max_accepts: usize,
@@ -86,52 +257,22 @@ fn spawn_stateful_server(
                 read_json_line(BufReader::new(&mut conn)).expect("failed to read request");
 
             let resp = match req {
-                Request::Status => {
-                    let kind = if state == 0 { "socks5" } else { "direct" };
-
-                    Response::OkStatus(StatusResponse {
-                        uptime_ms: 123,
-                        config_path: "config.toml".to_owned(),
-                        egress: vec![
-                            EgressInfo {
-                                id: "vpn".to_owned(),
-                                kind: kind.to_owned(),
-                                endpoint: Some("127.0.0.1:1080".to_owned()),
-                            },
-                            EgressInfo {
-                                id: "direct".to_owned(),
-                                kind: "direct".to_owned(),
-                                endpoint: None,
-                            },
-                        ],
-                    })
-                }
-                Request::Reload => {
+                Request::Hello(_) => Response::Hello(HelloResponse {
+                    version: PROTOCOL_VERSION + 1,
+                    min_supported: MIN_SUPPORTED_PROTOCOL_VERSION,
+                }),
+                Request::Status => fake_status(if state == 0 { "socks5" } else { "direct" }),
+                Request::Reload | Request::ReloadFrom { .. } => {
                     state = 1;
                     Response::OkReload
                 }
+                Request::ReloadDiff => fake_reload_diff(),
                 Request::Stop => {
                     write_json_line(&mut conn, &Response::OkStop)
                         .expect("failed to write response");
                     return;
                 }
-                Request::Explain(x) => {
-                    let proc = x.process.unwrap_or_else(|| "<none>".to_owned());
-                    let dom = x.domain.unwrap_or_else(|| "<none>".to_owned());
-
-                    Response::OkExplain(ExplainResponse {
-                        decision: DecisionInfo {
-                            egress: "vpn".to_owned(),
-                            reason: format!("process={proc} domain={dom}"),
-                            source: DecisionSource::Default,
-                            rule_egress: Some("vpn".to_owned()),
-                            matcher: Some(MatcherInfo {
-                                kind: MatcherKind::Exact,
-                                pattern: "example".to_owned(),
-                            }),
-                        },
-                    })
-                }
+                Request::Explain(x) => fake_explain(x),
                 Request::Diagnostics => Response::OkDiagnostics(DiagnosticsResponse {
                     uptime_ms: 123,
                     config_path: "config.toml".to_owned(),
@@ -141,7 +282,47 @@ fn spawn_stateful_server(
                     ipc_requests: 1,
                     reload_ok: 0,
                     reload_err: 0,
+                    config_generation: 0,
+                    dropped_rules: 0,
+                    lints: Vec::new(),
+                }),
+                Request::History(x) => {
+                    let entry = |seq| HistoryEntry {
+                        seq,
+                        uptime_ms: 10 * seq,
+                        process: Some("zen.exe".to_owned()),
+                        domain: None,
+                        egress: "vpn".to_owned(),
+                        source: DecisionSource::AppRule,
+                    };
+
+                    let resp = Response::OkHistory(HistoryResponse {
+                        entries: vec![entry(0)],
+                    });
+                    if x.follow {
+                        write_json_line(&mut conn, &resp).expect("failed to write response");
+                        Response::Event(Event::DecisionMade(entry(1)))
+                    } else {
+                        resp
+                    }
+                }
+                Request::ConfigFiles => Response::OkConfigFiles(ConfigFilesResponse {
+                    files: vec!["config.toml".to_owned(), "rules/extra.toml".to_owned()],
                 }),
+                Request::ValidateConfig => Response::OkValidate(ValidateResponse {
+                    ok: false,
+                    errors: vec!["failed to parse TOML config: config.toml".to_owned()],
+                }),
+                Request::ListRules => fake_rules(),
+                Request::Metrics => Response::OkMetrics(MetricsResponse {
+                    text: "policy_router_ipc_requests_total 1\n".to_owned(),
+                }),
+                Request::GetConfig => fake_config(),
+                Request::EgressRefs { egress } => fake_egress_refs(egress),
+                Request::RuleStats => fake_rule_stats(),
+                Request::Ping => Response::OkPong { server_time_ms: 42 },
+                Request::WhoIs { addr } => fake_whois(&addr),
+                Request::Priority => fake_priority(),
             };
 
             write_json_line(&mut conn, &resp).expect("failed to write response");
@@ -167,9 +348,19 @@ fn ipc_status_roundtrip() -> Result<()> {
         Response::OkStatus(s) => {
             assert_eq!(s.uptime_ms, 123);
             assert_eq!(s.config_path, "config.toml");
+            assert_eq!(s.config_generation, 3);
             assert_eq!(s.egress.len(), 2);
             assert_eq!(s.egress[0].id, "vpn");
             assert_eq!(s.egress[0].endpoint.as_deref(), Some("127.0.0.1:1080"));
+            assert!(s.egress[0].auth);
+            assert!(!s.egress[1].auth);
+            assert_eq!(s.egress[0].groups, ["failover"]);
+            assert_eq!(s.egress[1].groups.len(), 0);
+            assert_eq!(s.egress[0].description.as_deref(), Some("sing-box core"));
+            assert_eq!(s.egress[1].description, None);
+            assert_eq!(s.egress[0].healthy, Some(true));
+            assert_eq!(s.egress[0].last_checked_ms, Some(100));
+            assert_eq!(s.egress[1].healthy, None);
         }
         other => anyhow::bail!("unexpected response: {other:?}"),
     }
@@ -187,6 +378,7 @@ fn ipc_explain_roundtrip() -> Result<()> {
     let req = Request::Explain(ExplainRequest {
         process: Some("chrome.exe".to_owned()),
         domain: Some("youtube.com".to_owned()),
+        ..ExplainRequest::default()
     });
 
     let resp = client_roundtrip(&mut conn, &req)?;
@@ -196,8 +388,14 @@ fn ipc_explain_roundtrip() -> Result<()> {
             assert_eq!(x.decision.egress, "vpn");
             assert!(x.decision.reason.contains("process=chrome.exe"));
             assert!(x.decision.reason.contains("domain=youtube.com"));
+            assert_eq!(x.decision.reason_code, "default");
             assert_eq!(x.decision.rule_egress.as_deref(), Some("vpn"));
-            assert!(x.decision.matcher.is_some());
+            assert_eq!(x.decision.kind.as_deref(), Some("singbox"));
+            assert_eq!(
+                x.decision.endpoint.as_deref(),
+                Some("socks5://127.0.0.1:1488")
+            );
+            assert_eq!(x.decision.matcher.and_then(|m| m.index), Some(2));
         }
         other => anyhow::bail!("unexpected response: {other:?}"),
     }
@@ -240,6 +438,32 @@ fn ipc_reload_roundtrip() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn ipc_reload_diff_roundtrip() -> Result<()> {
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 1).wait_ready();
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    let resp = client_roundtrip(&mut conn, &Request::ReloadDiff)?;
+
+    match resp {
+        Response::OkReloadDiff(d) => {
+            assert_eq!(d.added_egress, ["proxy"]);
+            assert_eq!(d.removed_egress.len(), 0);
+            assert_eq!(d.changed_endpoints[0].egress, "vpn");
+            assert_eq!(
+                d.changed_endpoints[0].new.as_deref(),
+                Some("127.0.0.1:1081")
+            );
+            assert_eq!(d.added_rules[0].kind, "domain");
+            assert_eq!(d.added_rules[0].pattern, "example.com");
+        }
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
+
 #[test]
 fn ipc_stop_roundtrip() -> Result<()> {
     let name = make_name()?;
@@ -285,3 +509,376 @@ fn ipc_diagnostics_roundtrip() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn ipc_history_follow_streams_events() -> Result<()> {
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 1).wait_ready();
+
+    let conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    write_json_line(&conn, &Request::History(HistoryRequest { follow: true }))?;
+
+    let mut reader = BufReader::new(&conn);
+    match read_json_line(&mut reader)? {
+        Response::OkHistory(h) => {
+            assert_eq!(h.entries.len(), 1);
+            assert_eq!(h.entries[0].seq, 0);
+        }
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    match read_json_line(&mut reader)? {
+        Response::Event(Event::DecisionMade(entry)) => {
+            assert_eq!(entry.seq, 1);
+            assert_eq!(entry.egress, "vpn");
+        }
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    assert!(
+        reader.fill_buf()?.is_empty(),
+        "stream must end after server closes"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn ipc_config_files_roundtrip() -> Result<()> {
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 1).wait_ready();
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    let resp = client_roundtrip(&mut conn, &Request::ConfigFiles)?;
+
+    match resp {
+        Response::OkConfigFiles(c) => {
+            assert_eq!(c.files, vec!["config.toml", "rules/extra.toml"]);
+        }
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ipc_get_config_roundtrip() -> Result<()> {
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 1).wait_ready();
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    let resp = client_roundtrip(&mut conn, &Request::GetConfig)?;
+
+    match resp {
+        Response::OkConfig(c) => {
+            assert_eq!(c.toml, "[defaults]\negress = \"direct\"\n");
+        }
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ipc_egress_refs_roundtrip() -> Result<()> {
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 2).wait_ready();
+
+    let mut conn = Stream::connect(name.clone()).context("failed to connect to test IPC server")?;
+    let req = Request::EgressRefs {
+        egress: "proxy".to_owned(),
+    };
+    match client_roundtrip(&mut conn, &req)? {
+        Response::OkEgressRefs(r) => {
+            assert_eq!(r.egress, "proxy");
+            assert!(!r.default);
+            assert_eq!(r.app, vec!["chrome.exe"]);
+            assert_eq!(r.domain, vec!["example.com"]);
+        }
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    let req = Request::EgressRefs {
+        egress: "missing".to_owned(),
+    };
+    match client_roundtrip(&mut conn, &req)? {
+        Response::Err(e) => assert_eq!(e.code, ErrorCode::UnknownEgress),
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ipc_rule_stats_roundtrip() -> Result<()> {
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 1).wait_ready();
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    match client_roundtrip(&mut conn, &Request::RuleStats)? {
+        Response::OkRuleStats(r) => {
+            assert_eq!(r.rules.len(), 1);
+            assert_eq!(r.rules[0].kind, "domain");
+            assert_eq!(r.rules[0].pattern, "example.com");
+            assert_eq!(r.rules[0].hits, 0);
+            assert!(r.rules[0].never_matched);
+        }
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ipc_ping_roundtrip() -> Result<()> {
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 1).wait_ready();
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    match client_roundtrip(&mut conn, &Request::Ping)? {
+        Response::OkPong { server_time_ms } => assert_eq!(server_time_ms, 42),
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ipc_whois_roundtrip() -> Result<()> {
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 2).wait_ready();
+
+    let mut conn = Stream::connect(name.clone()).context("failed to connect to test IPC server")?;
+    let req = Request::WhoIs {
+        addr: "127.0.0.1:50123".to_owned(),
+    };
+    match client_roundtrip(&mut conn, &req)? {
+        Response::OkWhoIs(WhoIsResponse {
+            process: Some(process),
+        }) => {
+            assert_eq!(process.pid, 4242);
+            assert_eq!(process.exe, "C:/Apps/chrome.exe");
+        }
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    let req = Request::WhoIs {
+        addr: "127.0.0.1:1".to_owned(),
+    };
+    match client_roundtrip(&mut conn, &req)? {
+        Response::OkWhoIs(WhoIsResponse { process: None }) => {}
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ipc_priority_roundtrip() -> Result<()> {
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 1).wait_ready();
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    match client_roundtrip(&mut conn, &Request::Priority)? {
+        Response::OkPriority(order) => {
+            assert_eq!(order.block_app, ["block"]);
+            assert_eq!(order.block_domain.len(), 0);
+            assert_eq!(order.app, ["vpn", "direct"]);
+            assert_eq!(order.domain, ["proxy"]);
+        }
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ipc_validate_config_roundtrip() -> Result<()> {
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 1).wait_ready();
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    let resp = client_roundtrip(&mut conn, &Request::ValidateConfig)?;
+
+    match resp {
+        Response::OkValidate(v) => {
+            assert!(!v.ok);
+            assert_eq!(v.errors.len(), 1);
+        }
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn read_json_line_rejects_lines_over_the_limit() {
+    let line = b"{\"type\":\"status\"}\n";
+
+    let req: Request =
+        read_json_line_limited(&line[..], line.len()).expect("line at the limit must decode");
+    assert!(matches!(req, Request::Status));
+
+    let err = read_json_line_limited::<_, Request>(&line[..], line.len() - 1)
+        .expect_err("line over the limit must be rejected");
+    assert_eq!(
+        err.downcast_ref::<LineTooLong>(),
+        Some(&LineTooLong {
+            limit: line.len() - 1
+        })
+    );
+    assert!(err.to_string().contains("request too large"), "{err}");
+
+    let huge = format!("\"{}\"\n", "x".repeat(MAX_LINE_BYTES));
+    let err = read_json_line::<_, Request>(huge.as_bytes()).expect_err("huge line must fail");
+    assert!(err.is::<LineTooLong>(), "{err:#}");
+}
+
+#[test]
+fn ipc_accepts_crlf_terminated_request() -> Result<()> {
+    use std::io::Write;
+
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 1).wait_ready();
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    conn.write_all(b"{\"type\":\"status\"}\r\n")
+        .context("failed to write CRLF request")?;
+
+    match read_json_line(BufReader::new(&conn))? {
+        Response::OkStatus(s) => assert_eq!(s.config_path, "config.toml"),
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ipc_list_rules_roundtrip() -> Result<()> {
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 1).wait_ready();
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    let resp = client_roundtrip(&mut conn, &Request::ListRules)?;
+
+    match resp {
+        Response::OkRules(r) => {
+            assert_eq!(r.app["proxy"], vec!["chrome.exe"]);
+            let egresses: Vec<&str> = r.domain.keys().map(String::as_str).collect();
+            assert_eq!(egresses, vec!["direct", "proxy"]);
+            assert_eq!(r.cidr["direct"], vec!["10.0.0.0/8"]);
+            assert_eq!(r.port["proxy"], vec!["8000-8100"]);
+        }
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ipc_metrics_roundtrip() -> Result<()> {
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 1).wait_ready();
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    let resp = client_roundtrip(&mut conn, &Request::Metrics)?;
+
+    match resp {
+        Response::OkMetrics(m) => {
+            assert_eq!(m.text, "policy_router_ipc_requests_total 1\n");
+        }
+        other => anyhow::bail!("unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ipc_hello_reports_daemon_version() -> Result<()> {
+    let name = make_name()?;
+    let _server = spawn_stateful_server(name.clone(), 1).wait_ready();
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    let hello = client_hello(&mut conn)?;
+
+    assert_eq!(hello.version, PROTOCOL_VERSION + 1);
+    let hint = hello.mismatch_hint().expect("newer daemon yields a hint");
+    assert!(hint.contains("upgrade policy-routerctl"), "got {hint}");
+
+    Ok(())
+}
+
+#[test]
+fn ipc_hello_treats_rejecting_daemon_as_legacy() -> Result<()> {
+    let name = make_name()?;
+    let listener = ListenerOptions::new()
+        .name(name.clone())
+        .create_sync()
+        .context("failed to create test IPC listener")?;
+    let server = thread::spawn(move || {
+        let mut conn = listener.accept().expect("failed to accept IPC connection");
+        let mut line = String::new();
+        BufReader::new(&mut conn)
+            .read_line(&mut line)
+            .expect("failed to read request");
+        let resp = Response::Err(ErrorResponse {
+            code: ErrorCode::InvalidRequest,
+            message: "invalid request: unknown variant `hello`".to_owned(),
+        });
+        write_json_line(&mut conn, &resp).expect("failed to write response");
+    });
+
+    let mut conn = Stream::connect(name).context("failed to connect to test IPC server")?;
+    let hello = client_hello(&mut conn)?;
+    server.join().expect("server thread");
+
+    assert_eq!(hello.version, 0);
+    let hint = hello.mismatch_hint().expect("legacy daemon yields a hint");
+    assert!(hint.contains("upgrade policy-routerd"), "got {hint}");
+
+    Ok(())
+}
+
+#[test]
+fn hello_rejects_daemon_requiring_newer_client() {
+    let hello = HelloResponse {
+        version: PROTOCOL_VERSION + 2,
+        min_supported: PROTOCOL_VERSION + 1,
+    };
+    let err = hello.check_compatible().expect_err("client too old");
+    assert!(
+        err.to_string().contains("upgrade policy-routerctl"),
+        "got {err}"
+    );
+
+    let current = HelloResponse {
+        version: PROTOCOL_VERSION,
+        min_supported: MIN_SUPPORTED_PROTOCOL_VERSION,
+    };
+    current
+        .check_compatible()
+        .expect("same version is compatible");
+    assert_eq!(current.mismatch_hint(), None);
+}
+
+#[test]
+fn error_codes_roundtrip_and_default_to_internal() -> Result<()> {
+    let err = ErrorResponse {
+        code: ErrorCode::UnknownEgress,
+        message: "rules reference unknown egress id 'vpn'".to_owned(),
+    };
+    let json = serde_json::to_value(&err)?;
+    assert_eq!(json["code"], "unknown_egress");
+
+    let back: ErrorResponse = serde_json::from_value(json)?;
+    assert_eq!(back.code, ErrorCode::UnknownEgress);
+
+    // Older daemons send no code, newer ones may send codes we do not know.
+    let legacy: ErrorResponse = serde_json::from_str(r#"{"message":"boom"}"#)?;
+    assert_eq!(legacy.code, ErrorCode::Internal);
+    let newer: ErrorResponse = serde_json::from_str(r#"{"code":"disk_on_fire","message":"boom"}"#)?;
+    assert_eq!(newer.code, ErrorCode::Internal);
+    Ok(())
+}
@@ -1,10 +1,17 @@
 use policy_router_rs::policy::{
-    config::{AppConfig, EgressId},
-    engine::{DecisionReason, decide},
+    config::{AppConfig, AppPattern, EgressId, OnNoMatch, normalize_domain},
+    engine::{
+        CompiledConfig, DecideInput, Decision, DecisionReason, MatchKind, Priority, TraceRule,
+        TraceStep, decide, decide_batch, decide_compiled, decide_egress, decide_normalized,
+        decide_traced, decide_with, domain_matches, rule_priority,
+    },
+    idn,
 };
 
 fn cfg_minimal() -> AppConfig {
     let toml = r#"
+version = 2
+
 [defaults]
 egress = "vpn"
 
@@ -70,7 +77,12 @@ fn app_used_when_no_domain_match() {
     assert_eq!(d.egress, eid("vpn"));
 
     match d.reason {
-        DecisionReason::AppRule { pattern, egress } => {
+        DecisionReason::AppRule {
+            pattern,
+            index: 0,
+            egress,
+            group: None,
+        } => {
             assert_eq!(pattern, "zen.exe");
             assert_eq!(egress, eid("vpn"));
         }
@@ -103,7 +115,12 @@ fn block_by_app_has_top_priority() {
     assert_eq!(d.egress, eid("block"));
 
     match d.reason {
-        DecisionReason::BlockByApp { pattern, egress } => {
+        DecisionReason::BlockByApp {
+            pattern,
+            index: 0,
+            egress,
+            group: None,
+        } => {
             assert_eq!(pattern, "bad.exe");
             assert_eq!(egress, eid("block"));
         }
@@ -164,6 +181,8 @@ fn app_matching_case_insensitive() {
 #[test]
 fn app_rule_matches_full_windows_path() {
     let toml = r#"
+version = 2
+
 [defaults]
 egress = "vpn"
 
@@ -186,7 +205,12 @@ vpn = ["zen.exe"]
     assert_eq!(d.egress, eid("vpn"));
 
     match d.reason {
-        DecisionReason::AppRule { pattern, egress } => {
+        DecisionReason::AppRule {
+            pattern,
+            index: 0,
+            egress,
+            group: None,
+        } => {
             assert_eq!(pattern, "zen.exe");
             assert_eq!(egress, eid("vpn"));
         }
@@ -194,9 +218,253 @@ vpn = ["zen.exe"]
     }
 }
 
+#[test]
+fn app_rule_matches_command_lines_and_bare_names() {
+    let cfg = cfg_minimal();
+    cfg.validate().expect("config must validate");
+
+    for process in [
+        r#""C:\Program Files\Zen\zen.exe" --flag"#,
+        r#""C:\Program Files\Zen\zen.exe""#,
+        r#"  "C:/Program Files/Zen/zen.exe"   -P "work profile""#,
+        r"C:\Program Files\Zen\zen.exe",
+        r"C:\Tools\zen.exe --new-window https://example.com",
+        "/opt/zen/zen.exe -private",
+        "zen.exe",
+        "ZEN.EXE --flag",
+    ] {
+        let d = decide(&cfg, Some(process), Some("unknown.example"));
+        assert_eq!(d.egress, eid("vpn"), "process {process}");
+    }
+
+    let d = decide(&cfg, Some("/usr/bin/curl.exe -s"), Some("unknown.example"));
+    assert_eq!(d.egress, eid("proxy"));
+}
+
+#[test]
+fn app_rule_keeps_spaces_in_unquoted_paths() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.direct]
+type = "direct"
+
+[rules.app]
+vpn = ["code - insiders.exe", "tool.exe"]
+"#;
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+
+    for process in [
+        r"C:\Program Files\VS Code Insiders\Code - Insiders.exe",
+        r"C:\Program Files\VS Code Insiders\Code - Insiders.exe --new-window",
+        r#""C:\Program Files\VS Code Insiders\Code - Insiders.exe" -n"#,
+        r"C:\App v1.2 beta\tool.exe",
+        r"C:\App v1.2 beta\tool.exe -v",
+    ] {
+        let d = decide(&cfg, Some(process), None);
+        assert_eq!(d.egress, eid("vpn"), "process {process}");
+    }
+}
+
+#[test]
+fn app_dir_patterns_match_full_paths_only() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "vpn"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.direct]
+type = "direct"
+
+[rules.app]
+direct = ['dir:C:\Tools\', "dir:/opt/bin::--local"]
+vpn = ["tools"]
+"#;
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+    let compiled = CompiledConfig::new(&cfg);
+
+    let cases = [
+        (r"C:\Tools\app.exe", None, "direct"),
+        (r"c:/tools/nested/other.exe", None, "direct"),
+        (r#""C:\TOOLS\App With Spaces.exe" --flag"#, None, "direct"),
+        (r"C:\ToolsExtra\app.exe", None, "vpn"),
+        // A bare name has no directory to match.
+        ("app.exe", None, "vpn"),
+        // Basename patterns still ignore the directory.
+        (r"C:\Tools", None, "vpn"),
+        ("/opt/bin/tool", Some("tool --local"), "direct"),
+        ("/opt/bin/tool", Some("tool"), "vpn"),
+    ];
+    for (process, cmdline, expected) in cases {
+        let input = DecideInput {
+            process_name: Some(process),
+            cmdline,
+            domain: None,
+            port: None,
+            now: None,
+        };
+        let d = decide_with(&cfg, &input);
+        assert_eq!(d.egress, eid(expected), "process {process}");
+        assert_eq!(
+            format!("{d:?}"),
+            format!("{:?}", decide_compiled(&cfg, Some(&compiled), &input)),
+            "process {process}"
+        );
+    }
+
+    let d = decide(&cfg, Some(r"C:\Tools\app.exe"), None);
+    match d.reason {
+        DecisionReason::AppRule { pattern, index, .. } => {
+            assert_eq!(pattern, r"dir:C:\Tools\");
+            assert_eq!(index, 0);
+        }
+        other => panic!("unexpected reason: {other:?}"),
+    }
+}
+
+#[test]
+fn app_glob_patterns_match_whole_basenames_ignoring_case() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "vpn"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.direct]
+type = "direct"
+
+[rules.app]
+direct = ["chrome*.exe", "tool?.exe::--local"]
+vpn = ["chrome.exe.bak"]
+"#;
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+    let compiled = CompiledConfig::new(&cfg);
+
+    let cases = [
+        ("chrome.exe", None, "direct"),
+        ("chromedriver.exe", None, "direct"),
+        ("Chrome.EXE", None, "direct"),
+        (r"C:\Program Files\Google\ChromeDriver.exe", None, "direct"),
+        // The glob must cover the whole basename.
+        ("chrome.exe.bak", None, "vpn"),
+        ("mychrome.exe", None, "vpn"),
+        ("chrome.com", None, "vpn"),
+        ("tool1.exe", Some("tool1.exe --local"), "direct"),
+        ("tool12.exe", Some("tool12.exe --local"), "vpn"),
+        ("tool1.exe", None, "vpn"),
+    ];
+    for (process, cmdline, expected) in cases {
+        let input = DecideInput {
+            process_name: Some(process),
+            cmdline,
+            domain: None,
+            port: None,
+            now: None,
+        };
+        let d = decide_with(&cfg, &input);
+        assert_eq!(d.egress, eid(expected), "process {process}");
+        assert_eq!(
+            format!("{d:?}"),
+            format!("{:?}", decide_compiled(&cfg, Some(&compiled), &input)),
+            "process {process}"
+        );
+    }
+
+    let d = decide(&cfg, Some("ChromeDriver.exe"), None);
+    match d.reason {
+        DecisionReason::AppRule { pattern, index, .. } => {
+            assert_eq!(pattern, "chrome*.exe");
+            assert_eq!(index, 0);
+        }
+        other => panic!("unexpected reason: {other:?}"),
+    }
+}
+
+#[test]
+fn app_extension_patterns_match_the_last_extension() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "vpn"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.direct]
+type = "direct"
+
+[egress.proxy]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[rules.app]
+direct = ["*.exe"]
+proxy = ["*.COM::--proxy"]
+"#;
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+    let compiled = CompiledConfig::new(&cfg);
+
+    let cases = [
+        ("zen.exe", None, "direct"),
+        ("ZEN.EXE", None, "direct"),
+        (r"C:\Tools\setup.msi.exe", None, "direct"),
+        ("zen.com", None, "vpn"),
+        ("zen.com", Some("zen.com --proxy"), "proxy"),
+        ("zen.exe.bak", None, "vpn"),
+        ("zen", None, "vpn"),
+    ];
+    for (process, cmdline, expected) in cases {
+        let input = DecideInput {
+            process_name: Some(process),
+            cmdline,
+            ..DecideInput::default()
+        };
+        let d = decide_with(&cfg, &input);
+        assert_eq!(d.egress, eid(expected), "process {process}");
+        assert_eq!(
+            format!("{d:?}"),
+            format!("{:?}", decide_compiled(&cfg, Some(&compiled), &input)),
+            "process {process}"
+        );
+    }
+
+    let d = decide(&cfg, Some("zen.exe"), None);
+    match d.reason {
+        DecisionReason::AppRule { pattern, index, .. } => {
+            assert_eq!(pattern, "*.exe");
+            assert_eq!(index, 0);
+        }
+        other => panic!("unexpected reason: {other:?}"),
+    }
+}
+
 #[test]
 fn block_app_matches_full_windows_path() {
     let toml = r#"
+version = 2
+
 [defaults]
 egress = "block"
 
@@ -214,7 +482,12 @@ block = ["bad.exe"]
     assert_eq!(d.egress, eid("block"));
 
     match d.reason {
-        DecisionReason::BlockByApp { pattern, egress } => {
+        DecisionReason::BlockByApp {
+            pattern,
+            index: 0,
+            egress,
+            group: None,
+        } => {
             assert_eq!(pattern, "bad.exe");
             assert_eq!(egress, eid("block"));
         }
@@ -268,6 +541,8 @@ fn explicit_direct_app_rule() {
 #[test]
 fn validate_rejects_unknown_rule_egress() {
     let toml = r#"
+version = 2
+
 [defaults]
 egress = "vpn"
 
@@ -290,6 +565,8 @@ vpn = ["example.com"]
 #[test]
 fn domain_priority_prefers_singbox_over_direct() {
     let toml = r#"
+version = 2
+
 [defaults]
 egress = "direct"
 
@@ -314,11 +591,20 @@ vpn = []
 
     let d = decide(&cfg, Some("zen.exe"), Some("example.com"));
     assert_eq!(d.egress, eid("vpn"));
+    assert_eq!(
+        d.priority,
+        Some(Priority {
+            rank: 0,
+            tie_broken: false
+        })
+    );
 }
 
 #[test]
 fn app_priority_prefers_singbox_over_direct() {
     let toml = r#"
+version = 2
+
 [defaults]
 egress = "direct"
 
@@ -344,3 +630,1932 @@ vpn = ["zen.exe"]
     let d = decide(&cfg, Some("zen.exe"), Some("example.com"));
     assert_eq!(d.egress, eid("vpn"));
 }
+
+fn cfg_two_blocks(block_select: &str) -> AppConfig {
+    let toml = format!(
+        r#"
+version = 2
+
+[defaults]
+egress = "direct"
+block_select = "{block_select}"
+
+[egress.direct]
+type = "direct"
+
+[egress.zblock]
+type = "block"
+
+[egress.ablock]
+type = "block"
+
+[rules.app]
+zblock = ["bad.exe"]
+ablock = ["bad.exe"]
+
+[rules.domain]
+zblock = ["blocked.example"]
+ablock = ["blocked.example"]
+"#
+    );
+
+    let cfg = toml::from_str::<AppConfig>(&toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+    cfg
+}
+
+#[test]
+fn block_select_first_id_picks_smallest_id() {
+    let cfg = cfg_two_blocks("first_id");
+
+    let d = decide(&cfg, Some("bad.exe"), None);
+    assert_eq!(d.egress, eid("ablock"));
+
+    let d = decide(&cfg, None, Some("blocked.example"));
+    assert_eq!(d.egress, eid("ablock"));
+}
+
+#[test]
+fn block_select_first_declared_picks_declaration_order() {
+    let cfg = cfg_two_blocks("first_declared");
+
+    let d = decide(&cfg, Some("bad.exe"), None);
+    assert_eq!(d.egress, eid("zblock"));
+
+    let d = decide(&cfg, None, Some("blocked.example"));
+    assert_eq!(d.egress, eid("zblock"));
+}
+
+#[cfg(feature = "psl")]
+fn cfg_psl(domain_rules: &str) -> AppConfig {
+    let toml = format!(
+        r#"
+version = 2
+
+[defaults]
+egress = "direct"
+use_psl = true
+
+[egress.direct]
+type = "direct"
+
+[egress.proxy]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[rules.domain]
+proxy = [{domain_rules}]
+"#
+    );
+
+    toml::from_str::<AppConfig>(&toml).expect("test config TOML must parse")
+}
+
+#[cfg(feature = "psl")]
+#[test]
+fn psl_matches_subdomains_at_any_depth() {
+    let cfg = cfg_psl(r#""example.co.uk""#);
+    cfg.validate().expect("config must validate");
+
+    let d = decide(&cfg, None, Some("a.b.example.co.uk"));
+    assert_eq!(d.egress, eid("proxy"));
+
+    let d = decide(&cfg, None, Some("example.co.uk"));
+    assert_eq!(d.egress, eid("proxy"));
+
+    let d = decide(&cfg, None, Some("other.co.uk"));
+    assert_eq!(d.egress, eid("direct"));
+}
+
+#[cfg(feature = "psl")]
+#[test]
+fn psl_deeper_pattern_matches_neither_sibling_nor_parent() {
+    let cfg = cfg_psl(r#""mail.example.co.uk""#);
+    cfg.validate().expect("config must validate");
+    let compiled = CompiledConfig::new(&cfg);
+
+    for (domain, expected) in [
+        ("mail.example.co.uk", "proxy"),
+        ("a.mail.example.co.uk", "proxy"),
+        ("www.example.co.uk", "direct"),
+        ("example.co.uk", "direct"),
+    ] {
+        let input = DecideInput {
+            process_name: None,
+            cmdline: None,
+            domain: Some(domain),
+            port: None,
+            now: None,
+        };
+        let d = decide_with(&cfg, &input);
+        assert_eq!(d.egress, eid(expected), "domain {domain}");
+        assert_eq!(
+            format!("{d:?}"),
+            format!("{:?}", decide_compiled(&cfg, Some(&compiled), &input)),
+            "domain {domain}"
+        );
+    }
+}
+
+#[cfg(feature = "psl")]
+#[test]
+fn psl_rejects_public_suffix_patterns() {
+    for pattern in [
+        r#""co.uk""#,
+        r#""com""#,
+        r#"".github.io""#,
+        r#""co.at""#,
+        r#""gov.au""#,
+    ] {
+        let cfg = cfg_psl(pattern);
+        assert!(cfg.validate().is_err(), "{pattern} must be rejected");
+    }
+}
+
+#[test]
+fn no_inputs_use_default() {
+    let cfg = cfg_minimal();
+    cfg.validate().expect("config must validate");
+
+    let d = decide(&cfg, None, None);
+    assert!(matches!(d.reason, DecisionReason::Default { .. }));
+}
+
+#[test]
+fn empty_inputs_are_treated_as_absent() {
+    let cfg = cfg_minimal();
+    cfg.validate().expect("config must validate");
+
+    for (process, domain) in [
+        (Some(""), Some("")),
+        (Some("   "), None),
+        (None, Some(" \t")),
+    ] {
+        let d = decide(&cfg, process, domain);
+        assert!(
+            matches!(d.reason, DecisionReason::Default { .. }),
+            "{process:?}/{domain:?} must fall back to default, got {:?}",
+            d.reason
+        );
+    }
+
+    let d = decide(&cfg, Some("zen.exe"), Some(""));
+    assert_eq!(d.egress, eid("vpn"));
+    assert!(matches!(d.reason, DecisionReason::AppRule { .. }));
+
+    let d = decide(&cfg, Some(""), Some("youtube.com"));
+    assert_eq!(d.egress, eid("proxy"));
+}
+
+#[test]
+fn domain_matches_lists_every_hit_across_egresses() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.direct]
+type = "direct"
+
+[rules.domain]
+direct = ["example.com"]
+vpn = ["www.example.com", "other.example"]
+
+[rules.app]
+"#;
+
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+
+    let hits = domain_matches(&cfg, "www.example.com");
+    let hits: Vec<_> = hits
+        .iter()
+        .map(|m| (m.egress.0.as_str(), m.pattern.as_str(), m.match_kind))
+        .collect();
+    assert_eq!(
+        hits,
+        vec![
+            ("vpn", "www.example.com", MatchKind::Exact),
+            ("direct", "example.com", MatchKind::Suffix),
+        ]
+    );
+
+    assert!(domain_matches(&cfg, "unrelated.test").is_empty());
+    assert!(domain_matches(&cfg, " ").is_empty());
+}
+
+fn cfg_reason_form(form: &str) -> AppConfig {
+    let toml = format!(
+        r#"
+version = 2
+
+[defaults]
+egress = "direct"
+reason_domain_form = "{form}"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.direct]
+type = "direct"
+
+[rules.domain]
+vpn = ["münchen.de", "xn--bcher-kva.example"]
+
+[rules.app]
+"#
+    );
+
+    toml::from_str::<AppConfig>(&toml).expect("test config TOML must parse")
+}
+
+fn domain_rule_pattern(cfg: &AppConfig, domain: &str) -> String {
+    match decide(cfg, None, Some(domain)).reason {
+        DecisionReason::DomainRule { pattern, .. } => pattern,
+        other => panic!("unexpected reason: {other:?}"),
+    }
+}
+
+#[test]
+fn reason_domain_form_as_written_keeps_config_spelling() {
+    let cfg = cfg_reason_form("as_written");
+    cfg.validate().expect("config must validate");
+
+    assert_eq!(domain_rule_pattern(&cfg, "münchen.de"), "münchen.de");
+    assert_eq!(
+        domain_rule_pattern(&cfg, "xn--bcher-kva.example"),
+        "xn--bcher-kva.example"
+    );
+}
+
+#[test]
+fn reason_domain_form_ascii_renders_punycode() {
+    let cfg = cfg_reason_form("ascii");
+    cfg.validate().expect("config must validate");
+
+    assert_eq!(
+        domain_rule_pattern(&cfg, "www.münchen.de"),
+        "xn--mnchen-3ya.de"
+    );
+    let d = decide(&cfg, None, Some("xn--bcher-kva.example"));
+    assert!(d.reason.to_human().contains("'xn--bcher-kva.example'"));
+}
+
+#[test]
+fn reason_domain_form_unicode_decodes_punycode() {
+    let cfg = cfg_reason_form("unicode");
+    cfg.validate().expect("config must validate");
+
+    assert_eq!(domain_rule_pattern(&cfg, "münchen.de"), "münchen.de");
+    let d = decide(&cfg, None, Some("xn--bcher-kva.example"));
+    assert!(d.reason.to_human().contains("'bücher.example'"));
+}
+
+#[test]
+fn idn_roundtrips_known_punycode_labels() {
+    for (unicode, ascii) in [
+        ("münchen.de", "xn--mnchen-3ya.de"),
+        ("bücher.example", "xn--bcher-kva.example"),
+        ("пример.рф", "xn--e1afmkfd.xn--p1ai"),
+        ("example.com", "example.com"),
+    ] {
+        assert_eq!(idn::domain_to_ascii(unicode).as_deref(), Some(ascii));
+        assert_eq!(idn::domain_to_unicode(ascii), unicode);
+    }
+}
+
+#[test]
+fn unicode_domain_matches_punycode_rule() {
+    let cfg = cfg_reason_form("as_written");
+    cfg.validate().expect("config must validate");
+
+    for domain in ["bücher.example", "www.BÜCHER.example"] {
+        let d = decide(&cfg, None, Some(domain));
+        assert_eq!(d.egress, eid("vpn"), "{domain}");
+    }
+}
+
+#[test]
+fn punycode_domain_matches_unicode_rule() {
+    let cfg = cfg_reason_form("as_written");
+    cfg.validate().expect("config must validate");
+
+    for domain in ["xn--mnchen-3ya.de", "cdn.XN--MNCHEN-3YA.de", "München.de"] {
+        let d = decide(&cfg, None, Some(domain));
+        assert_eq!(d.egress, eid("vpn"), "{domain}");
+    }
+    let d = decide(&cfg, None, Some("xn--e1afmkfd.xn--p1ai"));
+    assert_eq!(d.egress, eid("direct"));
+}
+
+#[test]
+fn aliased_rules_route_to_canonical_egress() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "vpn"
+
+[egress.vpn-primary]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.direct]
+type = "direct"
+
+[aliases]
+vpn = "vpn-primary"
+
+[rules.domain]
+vpn = ["chatgpt.com"]
+vpn-primary = ["openai.com"]
+
+[rules.app]
+vpn = ["zen.exe"]
+"#;
+
+    let mut cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+    cfg.resolve_aliases();
+
+    assert_eq!(cfg.defaults.egress, eid("vpn-primary"));
+
+    let d = decide(&cfg, None, Some("chatgpt.com"));
+    assert_eq!(d.egress, eid("vpn-primary"));
+    match d.reason {
+        DecisionReason::DomainRule { egress, .. } => assert_eq!(egress, eid("vpn-primary")),
+        other => panic!("unexpected reason: {other:?}"),
+    }
+
+    let d = decide(&cfg, Some("zen.exe"), Some("openai.com"));
+    assert_eq!(d.egress, eid("vpn-primary"));
+
+    let d = decide(&cfg, Some("zen.exe"), None);
+    assert_eq!(d.egress, eid("vpn-primary"));
+}
+
+#[test]
+fn priority_reports_tie_broken_between_same_rank_egresses() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "direct-b"
+
+[egress.direct-a]
+type = "direct"
+
+[egress.direct-b]
+type = "direct"
+
+[rules.domain]
+direct-b = ["example.com"]
+direct-a = ["example.com"]
+
+[rules.app]
+direct-a = ["zen.exe"]
+"#;
+
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+
+    let d = decide(&cfg, None, Some("example.com"));
+    assert_eq!(d.egress, eid("direct-a"));
+    assert_eq!(
+        d.priority,
+        Some(Priority {
+            rank: 20,
+            tie_broken: true
+        })
+    );
+
+    let d = decide(&cfg, Some("zen.exe"), None);
+    assert_eq!(
+        d.priority,
+        Some(Priority {
+            rank: 20,
+            tie_broken: false
+        })
+    );
+
+    assert_eq!(decide(&cfg, None, None).priority, None);
+}
+
+#[test]
+fn wildcard_domain_patterns_exclude_apex() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.direct]
+type = "direct"
+
+[rules.domain]
+vpn = ["*.cdn.example.com", "*video.test"]
+
+[rules.app]
+"#;
+
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+
+    for domain in [
+        "a.cdn.example.com",
+        "a.b.cdn.example.com",
+        "myvideo.test",
+        "video.test",
+    ] {
+        let d = decide(&cfg, None, Some(domain));
+        assert_eq!(d.egress, eid("vpn"), "{domain} must match");
+        match d.reason {
+            DecisionReason::DomainRule { match_kind, .. } => {
+                assert_eq!(match_kind, MatchKind::Wildcard);
+            }
+            other => panic!("unexpected reason: {other:?}"),
+        }
+    }
+
+    for domain in ["cdn.example.com", "example.com", "video.test.org"] {
+        let d = decide(&cfg, None, Some(domain));
+        assert_eq!(d.egress, eid("direct"), "{domain} must not match");
+    }
+
+    let d = decide(&cfg, None, Some("x.cdn.example.com"));
+    assert!(
+        d.reason
+            .to_human()
+            .contains("wildcard match '*.cdn.example.com'")
+    );
+}
+
+#[test]
+fn domain_exclusion_skips_egress_for_excluded_domains() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.proxy]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.direct]
+type = "direct"
+
+[rules.domain]
+vpn = ["example.com", "!internal.example.com"]
+proxy = ["*.example.com", "!*.lab.internal.example.com"]
+
+[rules.app]
+"#;
+
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+    let compiled = CompiledConfig::new(&cfg);
+
+    for (domain, egress) in [
+        ("example.com", "vpn"),
+        ("www.example.com", "vpn"),
+        // Excluded from vpn, so the lower ranked proxy takes over.
+        ("internal.example.com", "proxy"),
+        ("a.internal.example.com", "proxy"),
+        // Excluded from proxy as well; falls through to the default.
+        ("a.lab.internal.example.com", "direct"),
+    ] {
+        let input = DecideInput {
+            process_name: None,
+            cmdline: None,
+            domain: Some(domain),
+            port: None,
+            now: None,
+        };
+        assert_eq!(decide_with(&cfg, &input).egress, eid(egress), "{domain}");
+        assert_eq!(
+            decide_compiled(&cfg, Some(&compiled), &input).egress,
+            eid(egress),
+            "{domain} (compiled)"
+        );
+    }
+
+    let hits = domain_matches(&cfg, "internal.example.com");
+    assert_eq!(hits.len(), 1, "{hits:?}");
+    assert_eq!(hits[0].egress, eid("proxy"));
+}
+
+#[test]
+fn decide_batch_matches_individual_decisions_in_order() {
+    let cfg = cfg_minimal();
+    cfg.validate().expect("config must validate");
+
+    let inputs = [
+        (Some("zen.exe"), Some("youtube.com")),
+        (Some("bad.exe"), Some("chatgpt.com")),
+        (Some("curl.exe"), None),
+        (None, Some("blocked.example")),
+        (Some(""), Some("yandex.ru")),
+        (None, None),
+    ];
+
+    let batch = decide_batch(&cfg, &inputs);
+    assert_eq!(batch.len(), inputs.len());
+
+    for (d, (process, domain)) in batch.iter().zip(inputs) {
+        let single = decide(&cfg, process, domain);
+        assert_eq!(d.egress, single.egress, "{process:?}/{domain:?}");
+        assert_eq!(d.reason.to_human(), single.reason.to_human());
+        assert_eq!(d.priority, single.priority);
+    }
+
+    assert_eq!(batch[0].egress, eid("proxy"));
+    assert_eq!(batch[1].egress, eid("block"));
+    assert_eq!(batch[4].egress, eid("direct"));
+}
+
+fn cfg_priorities(egress: &str) -> AppConfig {
+    let toml = format!(
+        r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+{egress}
+
+[egress.direct]
+type = "direct"
+
+[rules.domain]
+socks-a = ["example.com"]
+socks-b = ["example.com"]
+vpn = ["example.com"]
+
+[rules.app]
+"#
+    );
+
+    toml::from_str::<AppConfig>(&toml).expect("test config TOML must parse")
+}
+
+#[test]
+fn explicit_priority_overrides_kind_rank() {
+    let cfg = cfg_priorities(
+        r#"
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.socks-a]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.socks-b]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1081"
+priority = -1
+"#,
+    );
+    cfg.validate().expect("config must validate");
+
+    let d = decide(&cfg, None, Some("example.com"));
+    assert_eq!(d.egress, eid("socks-b"));
+    assert_eq!(
+        d.priority,
+        Some(Priority {
+            rank: -1,
+            tie_broken: false
+        })
+    );
+}
+
+#[test]
+fn unset_priority_falls_back_to_kind_rank() {
+    let cfg = cfg_priorities(
+        r#"
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.socks-a]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.socks-b]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1081"
+priority = 50
+"#,
+    );
+    cfg.validate().expect("config must validate");
+
+    // vpn keeps kind rank 0, socks-a kind rank 10, socks-b explicit 50.
+    let d = decide(&cfg, None, Some("example.com"));
+    assert_eq!(d.egress, eid("vpn"));
+}
+
+#[test]
+fn rule_priority_lists_egresses_in_evaluation_order() {
+    let cfg = cfg_minimal();
+    cfg.validate().expect("config must validate");
+    let order = rule_priority(&cfg);
+    assert_eq!(order.block_app, [&eid("block")]);
+    assert_eq!(order.block_domain, [&eid("block")]);
+    assert_eq!(order.app, [&eid("vpn"), &eid("proxy"), &eid("direct")]);
+    assert_eq!(order.domain, [&eid("vpn"), &eid("proxy"), &eid("direct")]);
+
+    let cfg = cfg_priorities(
+        r#"
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.socks-a]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.socks-b]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1081"
+priority = -1
+"#,
+    );
+    cfg.validate().expect("config must validate");
+    let order = rule_priority(&cfg);
+    assert_eq!(order.block_domain.len(), 0);
+    assert_eq!(order.app.len(), 0);
+    assert_eq!(
+        order.domain,
+        [&eid("socks-b"), &eid("vpn"), &eid("socks-a")]
+    );
+}
+
+#[test]
+fn http_egress_ranks_between_singbox_and_socks5() {
+    let cfg = cfg_priorities(
+        r#"
+[egress.vpn]
+type = "http"
+endpoint = "http://127.0.0.1:3128"
+
+[egress.socks-a]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.socks-b]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1081"
+"#,
+    );
+    cfg.validate().expect("config must validate");
+
+    // The http egress (kind rank 5) beats both socks5 egresses (rank 10).
+    let d = decide(&cfg, None, Some("example.com"));
+    assert_eq!(d.egress, eid("vpn"));
+    assert_eq!(
+        d.priority,
+        Some(Priority {
+            rank: 5,
+            tie_broken: false
+        })
+    );
+}
+
+#[test]
+fn equal_priorities_break_ties_on_egress_id() {
+    let cfg = cfg_priorities(
+        r#"
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+priority = 30
+
+[egress.socks-a]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+priority = 10
+
+[egress.socks-b]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1081"
+"#,
+    );
+    cfg.validate().expect("config must validate");
+
+    let d = decide(&cfg, None, Some("example.com"));
+    assert_eq!(d.egress, eid("socks-a"));
+    assert_eq!(
+        d.priority,
+        Some(Priority {
+            rank: 10,
+            tie_broken: true
+        })
+    );
+}
+
+#[test]
+fn longest_domain_suffix_wins_across_egresses() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.proxy]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.direct]
+type = "direct"
+
+[rules.domain]
+vpn = ["example.com", "img.example.com"]
+proxy = ["cdn.example.com"]
+direct = ["example.com", "static.cdn.example.com"]
+
+[rules.app]
+"#;
+
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+
+    let pattern_of = |domain: &str| match decide(&cfg, None, Some(domain)) {
+        Decision {
+            egress,
+            reason: DecisionReason::DomainRule { pattern, .. },
+            ..
+        } => (egress, pattern),
+        other => panic!("unexpected decision: {other:?}"),
+    };
+
+    assert_eq!(
+        pattern_of("a.cdn.example.com"),
+        (eid("proxy"), "cdn.example.com".to_owned())
+    );
+    assert_eq!(
+        pattern_of("x.static.cdn.example.com"),
+        (eid("direct"), "static.cdn.example.com".to_owned())
+    );
+    assert_eq!(
+        pattern_of("img.example.com"),
+        (eid("vpn"), "img.example.com".to_owned())
+    );
+    // Equally specific apex matches fall back to kind order.
+    assert_eq!(
+        pattern_of("www.example.com"),
+        (eid("vpn"), "example.com".to_owned())
+    );
+}
+
+#[test]
+fn app_regex_pattern_matches_basename() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "vpn"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.direct]
+type = "direct"
+
+[rules.app]
+direct = ['/^python3\.\d+$/', "python3"]
+"#;
+
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+
+    for process in ["python3.11", "/usr/bin/python3.12", "PYTHON3.12"] {
+        let d = decide(&cfg, Some(process), None);
+        assert_eq!(d.egress, eid("direct"), "{process}");
+        match d.reason {
+            DecisionReason::AppRule { pattern, .. } => assert_eq!(pattern, r"/^python3\.\d+$/"),
+            other => panic!("expected AppRule, got {other:?}"),
+        }
+    }
+
+    let d = decide(&cfg, Some("python3"), None);
+    match d.reason {
+        DecisionReason::AppRule { pattern, .. } => assert_eq!(pattern, "python3"),
+        other => panic!("expected AppRule, got {other:?}"),
+    }
+
+    let d = decide(&cfg, Some("python3.11-config"), None);
+    assert_eq!(d.egress, eid("vpn"));
+}
+
+#[test]
+fn app_rules_edited_after_a_decision_take_effect() {
+    let mut cfg = cfg_minimal();
+    cfg.validate().expect("config must validate");
+
+    assert_eq!(decide(&cfg, Some("curl.exe"), None).egress, eid("proxy"));
+
+    cfg.rules.app.get_mut(&eid("proxy")).expect("proxy rules")[0] =
+        AppPattern("/^wget/".to_string());
+    assert_eq!(decide(&cfg, Some("curl.exe"), None).egress, eid("vpn"));
+    assert_eq!(decide(&cfg, Some("wget.exe"), None).egress, eid("proxy"));
+}
+
+#[test]
+fn app_cmdline_pattern_requires_substring() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.proxy]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.direct]
+type = "direct"
+
+[rules.app]
+vpn = ["node.exe::script-a.js"]
+proxy = ["Node.exe::script-b.js"]
+"#;
+
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+
+    let input = |cmdline| DecideInput {
+        process_name: Some(r"C:\Tools\node.exe"),
+        cmdline,
+        domain: None,
+        port: None,
+        now: None,
+    };
+
+    let d = decide_with(&cfg, &input(Some("node.exe C:\\app\\script-a.js --port 1")));
+    assert_eq!(d.egress, eid("vpn"));
+    match d.reason {
+        DecisionReason::AppRule { pattern, .. } => assert_eq!(pattern, "node.exe::script-a.js"),
+        other => panic!("expected AppRule, got {other:?}"),
+    }
+
+    let d = decide_with(&cfg, &input(Some("node.exe script-b.js")));
+    assert_eq!(d.egress, eid("proxy"));
+
+    // The substring is case-sensitive and a missing cmdline never matches.
+    let d = decide_with(&cfg, &input(Some("node.exe SCRIPT-A.JS")));
+    assert_eq!(d.egress, eid("direct"));
+    let d = decide_with(&cfg, &input(None));
+    assert_eq!(d.egress, eid("direct"));
+    let d = decide(&cfg, Some("node.exe"), None);
+    assert_eq!(d.egress, eid("direct"));
+}
+
+fn cfg_cidr() -> AppConfig {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "vpn"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.direct]
+type = "direct"
+
+[egress.block]
+type = "block"
+
+[rules.domain]
+vpn = ["10.1.2.3"]
+
+[rules.cidr]
+vpn = ["10.0.0.0/8", "fd00::/8"]
+direct = ["10.1.0.0/16"]
+block = ["10.1.2.0/24"]
+"#;
+
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+    cfg
+}
+
+#[test]
+fn cidr_rule_prefers_longest_prefix() {
+    let cfg = cfg_cidr();
+
+    let d = decide(&cfg, None, Some("10.9.9.9"));
+    assert_eq!(d.egress, eid("vpn"));
+    match d.reason {
+        DecisionReason::CidrRule { pattern, .. } => assert_eq!(pattern, "10.0.0.0/8"),
+        other => panic!("expected CidrRule, got {other:?}"),
+    }
+
+    assert_eq!(decide(&cfg, None, Some("10.1.9.9")).egress, eid("direct"));
+    assert_eq!(decide(&cfg, None, Some("10.1.2.9")).egress, eid("block"));
+    assert_eq!(decide(&cfg, None, Some("[fd00::1]")).egress, eid("vpn"));
+    assert_eq!(
+        decide(&cfg, None, Some("::ffff:10.1.9.9")).egress,
+        eid("direct")
+    );
+}
+
+#[test]
+fn scheduled_egress_rules_apply_only_inside_their_windows() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "vpn"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.streaming]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+active = ["mon-fri 18:00-23:59", "sat,sun 00:00-23:59"]
+
+[egress.night]
+type = "direct"
+active = "mon 22:00-02:00"
+
+[rules.app]
+streaming = ["mpv.exe"]
+
+[rules.domain]
+streaming = ["youtube.com"]
+night = ["backup.example"]
+"#;
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+    let compiled = CompiledConfig::new(&cfg);
+
+    // 2026-10-12 is a Monday.
+    let at = |day: u32, hour: u32, minute: u32| {
+        chrono::NaiveDate::from_ymd_opt(2026, 10, day)
+            .and_then(|date| date.and_hms_opt(hour, minute, 0))
+            .expect("valid test time")
+    };
+    let egress = |process: Option<&str>, domain: Option<&str>, now| {
+        let input = DecideInput {
+            process_name: process,
+            cmdline: None,
+            domain,
+            port: None,
+            now,
+        };
+        let scanned = decide_with(&cfg, &input).egress;
+        assert_eq!(
+            decide_compiled(&cfg, Some(&compiled), &input).egress,
+            scanned
+        );
+        assert_eq!(decide_traced(&cfg, &input).0.egress, scanned);
+        scanned
+    };
+
+    let work = Some(at(12, 10, 0));
+    let evening = Some(at(12, 19, 30));
+    let saturday = Some(at(17, 10, 0));
+    assert_eq!(egress(None, Some("youtube.com"), work), eid("vpn"));
+    assert_eq!(egress(Some("mpv.exe"), None, work), eid("vpn"));
+    assert_eq!(egress(None, Some("youtube.com"), evening), eid("streaming"));
+    assert_eq!(egress(Some("mpv.exe"), None, saturday), eid("streaming"));
+    assert_eq!(egress(None, Some("youtube.com"), None), eid("streaming"));
+
+    // A window ending before it starts runs into the next day.
+    assert_eq!(
+        egress(None, Some("backup.example"), Some(at(12, 23, 0))),
+        eid("night")
+    );
+    assert_eq!(
+        egress(None, Some("backup.example"), Some(at(13, 1, 59))),
+        eid("night")
+    );
+    assert_eq!(
+        egress(None, Some("backup.example"), Some(at(13, 2, 1))),
+        eid("vpn")
+    );
+    assert_eq!(
+        egress(None, Some("backup.example"), Some(at(12, 1, 0))),
+        eid("vpn")
+    );
+}
+
+#[test]
+fn reasons_report_index_of_matched_pattern() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.direct]
+type = "direct"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.block]
+type = "block"
+
+[rules.app]
+vpn = ["zen.exe", "/^python3\\.\\d+$/", "curl.exe"]
+block = ["a.exe", "bad.exe"]
+
+[rules.domain]
+vpn = ["chatgpt.com", "*.example.com", "youtube.com"]
+block = ["ads.example", "!ok.tracker.example", "tracker.example"]
+
+[rules.cidr]
+vpn = ["10.0.0.0/8", "fd00::/8"]
+"#;
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+    let compiled = CompiledConfig::new(&cfg);
+
+    let index = |process: Option<&str>, domain: Option<&str>| {
+        let input = DecideInput {
+            process_name: process,
+            cmdline: None,
+            domain,
+            port: None,
+            now: None,
+        };
+        let scanned = reason_index(&decide_with(&cfg, &input).reason);
+        assert_eq!(
+            reason_index(&decide_compiled(&cfg, Some(&compiled), &input).reason),
+            scanned,
+            "{process:?} {domain:?}"
+        );
+        scanned
+    };
+
+    assert_eq!(index(Some("curl.exe"), None), Some(2));
+    assert_eq!(index(Some("python3.12"), None), Some(1));
+    assert_eq!(index(Some("bad.exe"), None), Some(1));
+    assert_eq!(index(None, Some("www.youtube.com")), Some(2));
+    assert_eq!(index(None, Some("a.example.com")), Some(1));
+    assert_eq!(index(None, Some("x.tracker.example")), Some(2));
+    assert_eq!(index(None, Some("[fd00::1]")), Some(1));
+    assert_eq!(index(None, Some("unknown.test")), None);
+
+    let matches = domain_matches(&cfg, "youtube.com");
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].index, 2);
+}
+
+fn reason_index(reason: &DecisionReason) -> Option<usize> {
+    match reason {
+        DecisionReason::BlockByApp { index, .. }
+        | DecisionReason::BlockByDomain { index, .. }
+        | DecisionReason::AppRule { index, .. }
+        | DecisionReason::DomainRule { index, .. }
+        | DecisionReason::CidrRule { index, .. }
+        | DecisionReason::PortRule { index, .. } => Some(*index),
+        DecisionReason::Default { .. } | DecisionReason::DefaultBlock { .. } => None,
+    }
+}
+
+#[test]
+fn ip_literals_skip_domain_rules() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.direct]
+type = "direct"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.block]
+type = "block"
+
+[rules.app]
+vpn = ["curl"]
+
+[rules.domain]
+block = ["1"]
+direct = ["3.4", "db8::1"]
+
+[rules.cidr]
+vpn = ["fd00::/8"]
+"#;
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+
+    for ip in ["1.2.3.4", "2001:db8::1", "[2001:db8::1]", " [2001:db8::1] "] {
+        let d = decide(&cfg, None, Some(ip));
+        assert!(
+            matches!(d.reason, DecisionReason::Default { .. }),
+            "{ip}: {d:?}"
+        );
+
+        let d = decide(&cfg, Some("curl"), Some(ip));
+        assert!(
+            matches!(d.reason, DecisionReason::AppRule { .. }),
+            "{ip}: {d:?}"
+        );
+
+        assert_eq!(domain_matches(&cfg, ip).len(), 0, "{ip}");
+    }
+
+    for ip in ["fd00::1", "[fd00::1]"] {
+        let d = decide(&cfg, Some("curl"), Some(ip));
+        assert!(
+            matches!(d.reason, DecisionReason::CidrRule { .. }),
+            "{ip}: {d:?}"
+        );
+    }
+
+    // Hostnames still go through domain rules.
+    let d = decide(&cfg, None, Some("host.3.4"));
+    assert!(
+        matches!(d.reason, DecisionReason::DomainRule { .. }),
+        "{d:?}"
+    );
+}
+
+#[test]
+fn cidr_rule_checked_before_domain_rules() {
+    let cfg = cfg_cidr();
+
+    let d = decide(&cfg, None, Some("10.1.2.3"));
+    assert!(matches!(d.reason, DecisionReason::CidrRule { .. }), "{d:?}");
+
+    let d = decide(&cfg, None, Some("192.168.0.1"));
+    assert!(matches!(d.reason, DecisionReason::Default { .. }), "{d:?}");
+}
+
+#[test]
+fn on_no_match_block_fails_closed() {
+    let mut cfg = cfg_minimal();
+    cfg.defaults.on_no_match = OnNoMatch::Block;
+    cfg.validate().expect("config must validate");
+
+    let d = decide(&cfg, Some("unknown.exe"), Some("unknown.example"));
+    assert_eq!(d.egress, eid("block"));
+    assert!(
+        matches!(d.reason, DecisionReason::DefaultBlock { .. }),
+        "{d:?}"
+    );
+    assert!(d.reason.to_human().contains("fail-closed"));
+
+    let d = decide(&cfg, Some("zen.exe"), None);
+    assert_eq!(d.egress, eid("vpn"));
+}
+
+#[test]
+fn block_default_egress_reports_default_block() {
+    let mut cfg = cfg_minimal();
+    cfg.defaults.egress = eid("block");
+    cfg.validate().expect("config must validate");
+
+    let d = decide(&cfg, Some("unknown.exe"), Some("unknown.example"));
+    assert_eq!(d.egress, eid("block"));
+    assert!(
+        matches!(d.reason, DecisionReason::DefaultBlock { .. }),
+        "{d:?}"
+    );
+    assert!(d.reason.to_human().contains("default: blocked"), "{d:?}");
+
+    let d = decide(&cfg, Some("zen.exe"), None);
+    assert_eq!(d.egress, eid("vpn"));
+}
+
+#[test]
+fn rule_targeting_group_routes_to_first_member() {
+    let toml = r#"
+version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.primary]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.secondary]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1081"
+
+[egress.direct]
+type = "direct"
+
+[egress_group.proxies]
+members = ["primary", "secondary"]
+
+[rules.app]
+proxies = ["zen.exe"]
+direct = ["zen.exe"]
+"#;
+
+    let cfg = toml::from_str::<AppConfig>(toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+
+    // The group ranks as its first member (socks5), ahead of direct.
+    let d = decide(&cfg, Some("zen.exe"), None);
+    assert_eq!(d.egress, eid("primary"));
+    match &d.reason {
+        DecisionReason::AppRule { egress, group, .. } => {
+            assert_eq!(egress, &eid("primary"));
+            assert_eq!(group.as_ref(), Some(&eid("proxies")));
+        }
+        other => panic!("expected AppRule, got {other:?}"),
+    }
+    assert!(d.reason.to_human().contains("group 'proxies'"));
+    assert_eq!(d.priority.map(|p| p.rank), Some(10));
+}
+
+#[test]
+fn traced_decision_lists_evaluated_rules() {
+    let cfg = cfg_minimal();
+    cfg.validate().expect("config must validate");
+
+    let step = |egress: &str, rule, matched, pattern: &str| TraceStep {
+        egress: eid(egress),
+        rule,
+        matched,
+        pattern: Some(pattern.to_owned()),
+    };
+
+    let input = DecideInput {
+        process_name: Some("zen.exe"),
+        domain: Some("unknown.example"),
+        ..DecideInput::default()
+    };
+    let (d, trace) = decide_traced(&cfg, &input);
+    assert_eq!(d.egress, decide_with(&cfg, &input).egress);
+    assert_eq!(
+        trace,
+        [
+            step("block", TraceRule::BlockApp, false, "bad.exe"),
+            step("block", TraceRule::BlockDomain, false, "blocked.example"),
+            step("vpn", TraceRule::Domain, false, "chatgpt.com"),
+            step("proxy", TraceRule::Domain, false, "youtube.com"),
+            step("direct", TraceRule::Domain, false, "ru"),
+            step("vpn", TraceRule::App, true, "zen.exe"),
+        ]
+    );
+
+    // A domain match decides, so app rules are never evaluated.
+    let (d, trace) = decide_traced(
+        &cfg,
+        &DecideInput {
+            domain: Some("www.youtube.com"),
+            ..input
+        },
+    );
+    assert_eq!(d.egress, eid("proxy"));
+    assert_eq!(trace.last().map(|s| s.rule), Some(TraceRule::Domain));
+    assert!(trace.contains(&step("proxy", TraceRule::Domain, true, "youtube.com")));
+}
+
+fn port_rules_config(port_priority: &str) -> AppConfig {
+    let toml = format!(
+        r#"
+version = 2
+
+[defaults]
+egress = "direct"
+port_priority = "{port_priority}"
+
+[egress.direct]
+type = "direct"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.proxy]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.block]
+type = "block"
+
+[rules.app]
+vpn = ["curl"]
+block = ["bad.exe"]
+
+[rules.domain]
+vpn = ["example.com"]
+
+[rules.port]
+vpn = ["22"]
+proxy = ["8000-8100", " 443 "]
+direct = ["8080"]
+"#
+    );
+    let cfg = toml::from_str::<AppConfig>(&toml).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+    cfg
+}
+
+/// Decides with every entry point, checking they agree.
+fn decide_port(
+    cfg: &AppConfig,
+    process_name: Option<&str>,
+    domain: Option<&str>,
+    port: u16,
+) -> Decision {
+    let input = DecideInput {
+        process_name,
+        domain,
+        port: Some(port),
+        ..DecideInput::default()
+    };
+    let d = decide_with(cfg, &input);
+    for other in [
+        decide_compiled(cfg, None, &input),
+        decide_traced(cfg, &input).0,
+    ] {
+        assert_eq!(other.egress, d.egress);
+        assert_eq!(other.reason.to_human(), d.reason.to_human());
+    }
+    d
+}
+
+#[test]
+fn port_rules_match_single_ports_and_ranges() {
+    let cfg = port_rules_config("before_domain");
+
+    let d = decide_port(&cfg, None, None, 22);
+    assert_eq!(d.egress, eid("vpn"));
+    match &d.reason {
+        DecisionReason::PortRule { pattern, index, .. } => {
+            assert_eq!((pattern.as_str(), *index), ("22", 0));
+        }
+        other => panic!("expected PortRule, got {other:?}"),
+    }
+    assert!(d.reason.to_human().starts_with("port rule"), "{d:?}");
+
+    for port in [8000, 8050, 8100, 443] {
+        assert_eq!(decide_port(&cfg, None, None, port).egress, eid("proxy"));
+    }
+    // The narrowest range containing the port wins.
+    assert_eq!(decide_port(&cfg, None, None, 8080).egress, eid("direct"));
+
+    for port in [21, 7999, 8101] {
+        let d = decide_port(&cfg, None, None, port);
+        assert!(matches!(d.reason, DecisionReason::Default { .. }), "{d:?}");
+    }
+    let d = decide(&cfg, None, None);
+    assert!(matches!(d.reason, DecisionReason::Default { .. }), "{d:?}");
+}
+
+#[test]
+fn port_priority_orders_port_rules_among_stages() {
+    let cfg = port_rules_config("before_domain");
+    assert_eq!(
+        decide_port(&cfg, None, Some("example.com"), 8050).egress,
+        eid("proxy")
+    );
+    assert_eq!(
+        decide_port(&cfg, Some("bad.exe"), None, 8050).egress,
+        eid("block")
+    );
+
+    let cfg = port_rules_config("after_domain");
+    assert_eq!(
+        decide_port(&cfg, None, Some("example.com"), 8050).egress,
+        eid("vpn")
+    );
+    assert_eq!(
+        decide_port(&cfg, Some("curl"), None, 8050).egress,
+        eid("proxy")
+    );
+
+    let cfg = port_rules_config("after_app");
+    assert_eq!(
+        decide_port(&cfg, Some("curl"), None, 8050).egress,
+        eid("vpn")
+    );
+    assert_eq!(
+        decide_port(&cfg, Some("other"), None, 8050).egress,
+        eid("proxy")
+    );
+}
+
+#[test]
+fn traced_decision_lists_port_rules() {
+    let cfg = port_rules_config("before_domain");
+    let (d, trace) = decide_traced(
+        &cfg,
+        &DecideInput {
+            domain: Some("example.com"),
+            port: Some(8080),
+            ..DecideInput::default()
+        },
+    );
+    assert_eq!(d.egress, eid("direct"));
+    let port_steps: Vec<_> = trace
+        .iter()
+        .filter(|step| step.rule == TraceRule::Port)
+        .map(|step| {
+            (
+                step.egress.0.as_str(),
+                step.matched,
+                step.pattern.as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        port_steps,
+        [
+            ("vpn", false, Some("22")),
+            ("proxy", true, Some("8000-8100")),
+            ("direct", true, Some("8080")),
+        ]
+    );
+    assert_eq!(trace.last().map(|s| s.rule), Some(TraceRule::Port));
+}
+
+#[test]
+fn reason_codes_name_the_deciding_stage() {
+    let cfg = port_rules_config("before_domain");
+    let code = |process: Option<&str>, domain: Option<&str>, port: Option<u16>| {
+        decide_with(
+            &cfg,
+            &DecideInput {
+                process_name: process,
+                domain,
+                port,
+                ..DecideInput::default()
+            },
+        )
+        .reason
+        .reason_code()
+    };
+
+    assert_eq!(
+        code(Some("bad.exe"), Some("example.com"), None),
+        "block_app"
+    );
+    assert_eq!(code(None, None, Some(22)), "port_rule");
+    assert_eq!(code(None, Some("example.com"), None), "domain_rule");
+    assert_eq!(code(Some("curl"), None, None), "app_rule");
+    assert_eq!(code(None, None, None), "default");
+
+    let mut cfg = cfg_minimal();
+    cfg.defaults.on_no_match = OnNoMatch::Block;
+    let d = decide(&cfg, None, Some("blocked.example"));
+    assert_eq!(d.reason.reason_code(), "block_domain");
+    let d = decide(&cfg, None, Some("unknown.example"));
+    assert_eq!(d.reason.reason_code(), "default_block");
+}
+
+#[test]
+fn specificity_ranks_app_over_domains_over_default() {
+    let domain = |pattern: &str, match_kind| DecisionReason::DomainRule {
+        egress: eid("vpn"),
+        group: None,
+        pattern: pattern.to_owned(),
+        index: 0,
+        match_kind,
+    };
+    let ranked = [
+        DecisionReason::AppRule {
+            egress: eid("vpn"),
+            group: None,
+            pattern: "zen.exe".to_owned(),
+            index: 0,
+        },
+        domain("a.b.example.com", MatchKind::Exact),
+        domain("example.com", MatchKind::Exact),
+        domain("a.example.com", MatchKind::Suffix),
+        domain("example.com", MatchKind::Suffix),
+        domain("*.example.com", MatchKind::Wildcard),
+        DecisionReason::CidrRule {
+            egress: eid("vpn"),
+            group: None,
+            pattern: "10.1.0.0/16".to_owned(),
+            index: 0,
+        },
+        DecisionReason::CidrRule {
+            egress: eid("vpn"),
+            group: None,
+            pattern: "10.0.0.0/8".to_owned(),
+            index: 0,
+        },
+        DecisionReason::PortRule {
+            egress: eid("vpn"),
+            group: None,
+            pattern: "22".to_owned(),
+            index: 0,
+        },
+        DecisionReason::Default {
+            egress: eid("direct"),
+        },
+    ];
+    let scores: Vec<u32> = ranked.iter().map(DecisionReason::specificity).collect();
+    assert!(
+        scores.windows(2).all(|pair| pair[0] > pair[1]),
+        "{scores:?}"
+    );
+    assert_eq!(scores[1], 4004);
+    assert_eq!(scores[5], 2002);
+    assert_eq!(scores[6], 1016);
+    assert_eq!(scores.last(), Some(&0));
+}
+
+#[test]
+fn specificity_of_block_matches_equals_their_rule_kind() {
+    let cfg = cfg_minimal();
+    let app = decide(&cfg, Some("zen.exe"), None);
+    assert_eq!(app.reason.reason_code(), "app_rule");
+    assert_eq!(app.specificity(), 5000);
+
+    let mut cfg = cfg_minimal();
+    cfg.defaults.on_no_match = OnNoMatch::Block;
+    let blocked = decide(&cfg, None, Some("blocked.example"));
+    assert_eq!(blocked.reason.reason_code(), "block_domain");
+    assert_eq!(blocked.specificity(), 4002);
+    assert_eq!(decide(&cfg, None, Some("unknown.example")).specificity(), 0);
+}
+
+#[test]
+fn case_sensitive_domains_opt_in() {
+    let toml = |case_sensitive: bool| {
+        format!(
+            r#"
+version = 2
+
+[defaults]
+egress = "direct"
+case_sensitive_domains = {case_sensitive}
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.direct]
+type = "direct"
+
+[rules.domain]
+vpn = ["example.com", "*.Internal.Corp"]
+
+[rules.app]
+"#
+        )
+    };
+
+    let cfg = toml::from_str::<AppConfig>(&toml(false)).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+    assert_eq!(decide(&cfg, None, Some("Example.com")).egress, eid("vpn"));
+    assert_eq!(
+        decide(&cfg, None, Some("host.internal.corp")).egress,
+        eid("vpn")
+    );
+
+    let cfg = toml::from_str::<AppConfig>(&toml(true)).expect("test config TOML must parse");
+    cfg.validate().expect("config must validate");
+    assert_eq!(
+        decide(&cfg, None, Some("Example.com")).egress,
+        eid("direct")
+    );
+    assert_eq!(
+        decide(&cfg, None, Some("host.internal.corp")).egress,
+        eid("direct")
+    );
+
+    let d = decide(&cfg, None, Some("www.example.com"));
+    assert_eq!(d.egress, eid("vpn"));
+    assert!(matches!(
+        d.reason,
+        DecisionReason::DomainRule {
+            match_kind: MatchKind::Suffix,
+            ..
+        }
+    ));
+    let d = decide(&cfg, None, Some("Host.Internal.Corp"));
+    assert!(matches!(
+        d.reason,
+        DecisionReason::DomainRule {
+            match_kind: MatchKind::Wildcard,
+            ..
+        }
+    ));
+}
+
+/// Deterministic xorshift generator, enough to spread fuzz inputs.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        let len = u64::try_from(items.len()).expect("pool size fits u64");
+        let index = usize::try_from(self.next() % len).expect("index fits usize");
+        items[index]
+    }
+
+    fn one_in(&mut self, n: u64) -> bool {
+        self.next().is_multiple_of(n)
+    }
+}
+
+fn cfg_fuzz(case_sensitive: bool, use_psl: bool, form: &str) -> AppConfig {
+    let toml = format!(
+        r#"
+version = 2
+
+[defaults]
+egress = "direct"
+case_sensitive_domains = {case_sensitive}
+use_psl = {use_psl}
+reason_domain_form = "{form}"
+
+[egress.vpn]
+type = "singbox"
+endpoint = "socks5://127.0.0.1:1488"
+
+[egress.proxy]
+type = "http"
+endpoint = "http://127.0.0.1:3128"
+
+[egress.socks]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.direct]
+type = "direct"
+
+[egress.block]
+type = "block"
+
+[rules.app]
+block = ["malware.exe", '/^evil\d+\.exe$/']
+vpn = ["zen.exe", "node.exe::bot.js", '/^python3\.\d+$/', "Telegram.exe"]
+proxy = ["node.exe", "zen.exe", "curl.exe::--proxy"]
+socks = ["/cu.l/", "app.exe"]
+direct = ["app.exe", "curl.exe"]
+
+[rules.domain]
+block = ["*.ads.example.com", "ads.example.com", "*.tracker.net", "*bad.org", "!not.ads.example.com"]
+vpn = ["example.com", "*.example.com", ".github.com", "*.cdn.example.com", "Mixed.Case.io", "co.uk"]
+proxy = ["sub.example.com", "*video.com", "github.com", "xn--80ak6aa92e.com", "shop.example.co.uk"]
+socks = ["example.com", "*.example.com", "video.com", "tracker.net", "*.Case.io", "!my.example.com", "!*shop.example.com"]
+direct = ["cdn.example.com", "*.io", "net"]
+"#
+    );
+    toml::from_str::<AppConfig>(&toml).expect("test config TOML must parse")
+}
+
+#[test]
+fn compiled_index_matches_linear_engine() {
+    const TAILS: &[&str] = &[
+        "example.com",
+        "github.com",
+        "tracker.net",
+        "video.com",
+        "bad.org",
+        "co.uk",
+        "example.co.uk",
+        "Case.io",
+        "case.io",
+        "xn--80ak6aa92e.com",
+        "com",
+        "io",
+        "net",
+    ];
+    const LABELS: &[&str] = &[
+        "www", "sub", "cdn", "ads", "my", "not", "Mixed", "shop", "a",
+    ];
+    const PROCESSES: &[&str] = &[
+        "zen.exe",
+        "ZEN.EXE",
+        r"C:\Apps\node.exe",
+        "node.exe",
+        "curl.exe",
+        "/usr/bin/cUrl.exe",
+        "python3.12",
+        "evil42.exe",
+        "malware.exe",
+        "app.exe",
+        "telegram.exe",
+        "other.exe",
+    ];
+    const CMDLINES: &[&str] = &["", "node bot.js", "curl --proxy x", "zen"];
+
+    let mut psl_modes = vec![false];
+    if cfg!(feature = "psl") {
+        psl_modes.push(true);
+    }
+
+    let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+    for case_sensitive in [false, true] {
+        for &use_psl in &psl_modes {
+            for form in ["as_written", "unicode", "ascii"] {
+                let cfg = cfg_fuzz(case_sensitive, use_psl, form);
+                let compiled = CompiledConfig::new(&cfg);
+
+                for _ in 0..200 {
+                    let mut domain = rng.pick(TAILS).to_string();
+                    for _ in 0..rng.next() % 3 {
+                        let joiner = if rng.one_in(4) { "" } else { "." };
+                        domain = format!("{}{joiner}{domain}", rng.pick(LABELS));
+                    }
+                    if rng.one_in(8) {
+                        domain.push('.');
+                    }
+                    let process = rng.pick(PROCESSES);
+                    let input = DecideInput {
+                        process_name: (!rng.one_in(3)).then_some(process),
+                        cmdline: Some(rng.pick(CMDLINES)),
+                        domain: (!rng.one_in(5)).then_some(domain.as_str()),
+                        port: None,
+                        now: None,
+                    };
+
+                    let linear = format!("{:?}", decide_with(&cfg, &input));
+                    let indexed = format!("{:?}", decide_compiled(&cfg, Some(&compiled), &input));
+                    assert_eq!(linear, indexed, "input {input:?}");
+                }
+            }
+        }
+    }
+
+    let cfg = cfg_fuzz(false, false, "as_written");
+    let input = DecideInput {
+        process_name: Some("zen.exe"),
+        cmdline: None,
+        domain: Some("www.example.com"),
+        port: None,
+        now: None,
+    };
+    assert_eq!(
+        format!("{:?}", decide_compiled(&cfg, None, &input)),
+        format!("{:?}", decide_with(&cfg, &input))
+    );
+}
+
+#[test]
+fn decide_egress_agrees_with_decide() {
+    const DOMAINS: &[&str] = &[
+        "example.com",
+        "www.example.com",
+        "sub.example.com",
+        "cdn.example.com",
+        "ads.example.com",
+        "not.ads.example.com",
+        "my.example.com",
+        "shop.example.com",
+        "github.com",
+        "tracker.net",
+        "bad.org",
+        "myvideo.com",
+        "shop.example.co.uk",
+        "Mixed.Case.io",
+        "a.io",
+        "10.1.2.3",
+        "10.9.9.9",
+        "192.168.1.1",
+        "[fd00::1]",
+        "",
+    ];
+    const PROCESSES: &[&str] = &[
+        "zen.exe",
+        r#""C:\Program Files\Zen\zen.exe" --flag"#,
+        "node.exe",
+        "curl.exe",
+        "python3.12",
+        "evil42.exe",
+        "malware.exe",
+        "app.exe",
+        "other.exe",
+        "",
+    ];
+
+    let grouped = r#"
+version = 2
+
+[defaults]
+egress = "direct"
+on_no_match = "block"
+
+[egress.primary]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[egress.secondary]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1081"
+
+[egress.direct]
+type = "direct"
+
+[egress.block]
+type = "block"
+
+[egress_group.proxies]
+members = ["secondary", "primary"]
+
+[rules.app]
+proxies = ["zen.exe"]
+direct = ["zen.exe", "curl.exe"]
+
+[rules.domain]
+proxies = ["example.com"]
+direct = ["www.example.com"]
+
+[rules.cidr]
+proxies = ["10.0.0.0/8"]
+direct = ["10.1.0.0/16"]
+block = ["192.168.0.0/16"]
+"#;
+    let mut configs = vec![
+        cfg_minimal(),
+        toml::from_str::<AppConfig>(grouped).expect("test config TOML must parse"),
+    ];
+    configs.push(cfg_fuzz(false, false, "as_written"));
+    configs.push(cfg_fuzz(true, false, "unicode"));
+    configs.push(cfg_fuzz(false, cfg!(feature = "psl"), "ascii"));
+
+    for cfg in &configs {
+        let compiled = CompiledConfig::new(cfg);
+        for process in PROCESSES {
+            for domain in DOMAINS {
+                let (process, domain) = (Some(*process), Some(*domain));
+                assert_eq!(
+                    decide_egress(cfg, &compiled, process, domain),
+                    decide(cfg, process, domain).egress,
+                    "process {process:?}, domain {domain:?}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn decide_normalized_agrees_with_decide() {
+    let mut cfg = cfg_minimal();
+    cfg.validate().expect("config must validate");
+    let domains = [
+        None,
+        Some(""),
+        Some("  YouTube.COM. "),
+        Some("r1.googlevideo.com"),
+        Some("blocked.example"),
+        Some("ChatGPT.com"),
+        Some("example.ru."),
+        Some("10.0.0.1"),
+        Some("unrelated.test"),
+    ];
+
+    for case_sensitive in [false, true] {
+        cfg.defaults.case_sensitive_domains = case_sensitive;
+        let compiled = CompiledConfig::new(&cfg);
+        for process in [None, Some("zen.exe"), Some("bad.exe")] {
+            for domain in domains {
+                let normalized = domain.map(|d| normalize_domain(d, case_sensitive));
+                let fast = decide_normalized(&cfg, &compiled, process, normalized.as_deref());
+                let single = decide(&cfg, process, domain);
+                let context = format!("{process:?}/{domain:?} case_sensitive={case_sensitive}");
+                assert_eq!(fast.egress, single.egress, "{context}");
+                assert_eq!(
+                    fast.reason.to_human(),
+                    single.reason.to_human(),
+                    "{context}"
+                );
+                assert_eq!(fast.priority, single.priority, "{context}");
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use policy_router_rs::{PolicyRouter, router::INLINE_CONFIG_PATH};
+
+const PROXY_CONFIG: &str = r#"version = 2
+
+[defaults]
+egress = "direct"
+
+[egress.direct]
+type = "direct"
+
+[egress.proxy]
+type = "socks5"
+endpoint = "socks5://127.0.0.1:1080"
+
+[rules.domain]
+proxy = ["youtube.com"]
+"#;
+
+fn write_tmp_config(tag: &str, contents: &str) -> std::path::PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("policy-router-{tag}-{}.toml", std::process::id()));
+    std::fs::write(&path, contents).expect("failed to write temp config");
+    path
+}
+
+#[test]
+fn router_decides_and_reloads_from_its_path() {
+    let path = write_tmp_config("router-reload", PROXY_CONFIG);
+    let router = PolicyRouter::from_path(&path).expect("config must load");
+
+    assert_eq!(router.config_path(), path);
+    assert_eq!(router.generation(), 0);
+    assert_eq!(
+        router.decide(None, Some("www.youtube.com")).egress.0,
+        "proxy"
+    );
+    assert_eq!(router.decide(None, Some("example.com")).egress.0, "direct");
+
+    std::fs::write(&path, PROXY_CONFIG.replace("youtube.com", "example.com"))
+        .expect("failed to rewrite temp config");
+    router.reload().expect("reload must succeed");
+    assert_eq!(router.generation(), 1);
+    assert_eq!(router.status().config_generation, 1);
+    assert_eq!(
+        router.decide(None, Some("www.youtube.com")).egress.0,
+        "direct"
+    );
+    assert_eq!(router.decide(None, Some("example.com")).egress.0, "proxy");
+
+    std::fs::write(&path, "this = [ is not valid toml").expect("failed to break temp config");
+    let err = router.reload().expect_err("broken config must not reload");
+    assert!(
+        err.to_string().contains(&path.display().to_string()),
+        "{err}"
+    );
+    assert_eq!(router.decide(None, Some("example.com")).egress.0, "proxy");
+    assert_eq!(router.generation(), 1);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn router_status_lists_live_egresses() {
+    let path = write_tmp_config("router-status", PROXY_CONFIG);
+    let router = PolicyRouter::from_path(&path).expect("config must load");
+    let _ = std::fs::remove_file(&path);
+
+    let status = router.status();
+    assert_eq!(status.config_path, path.display().to_string());
+    let egress: Vec<_> = status
+        .egress
+        .iter()
+        .map(|e| (e.id.as_str(), e.endpoint.as_deref()))
+        .collect();
+    assert_eq!(
+        egress,
+        [("direct", None), ("proxy", Some("socks5://127.0.0.1:1080"))]
+    );
+}
+
+#[test]
+fn router_reloads_from_text_without_reading_its_path() {
+    let dir = std::env::temp_dir().join(format!("policy-router-inline-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("failed to create temp dir");
+    let path = dir.join("config.toml");
+    std::fs::write(&path, PROXY_CONFIG).expect("failed to write temp config");
+    std::fs::write(dir.join("extra.txt"), "example.org\n").expect("failed to write domain file");
+    let router = PolicyRouter::from_path(&path).expect("config must load");
+
+    let pushed = PROXY_CONFIG.replace("\"youtube.com\"", "\"example.com\", \"@file:extra.txt\"");
+    router
+        .reload_from_str(&pushed)
+        .expect("reload from text must succeed");
+    assert_eq!(router.generation(), 1);
+    assert_eq!(router.status().config_path, INLINE_CONFIG_PATH);
+    assert_eq!(router.decide(None, Some("example.com")).egress.0, "proxy");
+    assert_eq!(router.decide(None, Some("example.org")).egress.0, "proxy");
+    assert_eq!(
+        router.decide(None, Some("www.youtube.com")).egress.0,
+        "direct"
+    );
+
+    router
+        .reload_from_str("this = [ is not valid toml")
+        .expect_err("broken text must not reload");
+    assert_eq!(router.generation(), 1);
+    assert_eq!(router.decide(None, Some("example.com")).egress.0, "proxy");
+
+    router.reload().expect("reload must succeed");
+    assert_eq!(router.generation(), 2);
+    assert_eq!(router.status().config_path, path.display().to_string());
+    assert_eq!(
+        router.decide(None, Some("www.youtube.com")).egress.0,
+        "proxy"
+    );
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,125 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread::sleep,
+    time::Duration,
+};
+
+use anyhow::Result;
+use interprocess::local_socket::Stream;
+use policy_router_rs::platform::{CachingProcessLookup, ProcessInfo, ProcessLookup, Protocol};
+
+struct CountingLookup {
+    calls: Arc<AtomicUsize>,
+}
+
+impl ProcessLookup for CountingLookup {
+    fn lookup_client_process(
+        &self,
+        client_addr: SocketAddr,
+        _protocol: Protocol,
+    ) -> Result<Option<ProcessInfo>> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        Ok(Some(ProcessInfo {
+            pid: u32::from(client_addr.port()),
+            exe: "client.exe".to_owned(),
+        }))
+    }
+
+    fn lookup_ipc_peer(&self, _conn: &Stream) -> Result<Option<ProcessInfo>> {
+        Ok(None)
+    }
+}
+
+fn caching(ttl: Duration, capacity: usize) -> (CachingProcessLookup, Arc<AtomicUsize>) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let inner = Box::new(CountingLookup {
+        calls: Arc::clone(&calls),
+    });
+    (CachingProcessLookup::new(inner, ttl, capacity), calls)
+}
+
+fn addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+#[test]
+fn cache_serves_repeated_lookups_within_ttl() {
+    let (lookup, calls) = caching(Duration::from_secs(60), 16);
+
+    for _ in 0..3 {
+        let info = lookup
+            .lookup_client_process(addr(4000), Protocol::Tcp)
+            .expect("lookup")
+            .expect("process info");
+        assert_eq!(info.pid, 4000);
+    }
+
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn cache_entries_expire_after_ttl() {
+    let (lookup, calls) = caching(Duration::from_millis(20), 16);
+
+    lookup
+        .lookup_client_process(addr(4000), Protocol::Tcp)
+        .expect("lookup");
+    sleep(Duration::from_millis(40));
+    lookup
+        .lookup_client_process(addr(4000), Protocol::Tcp)
+        .expect("lookup");
+
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn cache_evicts_oldest_entry_beyond_capacity() {
+    let (lookup, calls) = caching(Duration::from_secs(60), 2);
+
+    lookup
+        .lookup_client_process(addr(1), Protocol::Tcp)
+        .expect("lookup");
+    sleep(Duration::from_millis(2));
+    lookup
+        .lookup_client_process(addr(2), Protocol::Tcp)
+        .expect("lookup");
+    sleep(Duration::from_millis(2));
+    lookup
+        .lookup_client_process(addr(3), Protocol::Tcp)
+        .expect("lookup");
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    lookup
+        .lookup_client_process(addr(3), Protocol::Tcp)
+        .expect("lookup");
+    lookup
+        .lookup_client_process(addr(2), Protocol::Tcp)
+        .expect("lookup");
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    lookup
+        .lookup_client_process(addr(1), Protocol::Tcp)
+        .expect("lookup");
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn cache_keeps_protocols_apart() {
+    let (lookup, calls) = caching(Duration::from_secs(60), 16);
+
+    lookup
+        .lookup_client_process(addr(4000), Protocol::Tcp)
+        .expect("lookup");
+    lookup
+        .lookup_client_process(addr(4000), Protocol::Udp)
+        .expect("lookup");
+    lookup
+        .lookup_client_process(addr(4000), Protocol::Udp)
+        .expect("lookup");
+
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}